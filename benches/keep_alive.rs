@@ -0,0 +1,183 @@
+//! Allocations and throughput of keep-alive connections
+//!
+//! Serializes responses the old way, with `to_string`, and into buffers of
+//! the pool, then has clients send requests over keep-alive connections to
+//! a server running in the process. Allocations are counted by the global
+//! allocator, the clients' included.
+//!
+//! ```text
+//! cargo bench --bench keep_alive -- [connections] [requests per connection]
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use localhost::http::body::Body;
+use localhost::http::header::Header;
+use localhost::http::request::{HttpMethod, Request};
+use localhost::http::response::Response;
+use localhost::http::status::HttpStatusCode;
+use localhost::server::buffers;
+use localhost::server::handlers::handlers::RouteHandler;
+use localhost::server::listen::ListenAddr;
+use localhost::server::logger::{LogLevel, LogSettings};
+use localhost::server::route::Route;
+use localhost::{HostBuilder, ServerBuilder};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const BODY: &str = "Hello from localhost!";
+const SERIALIZED: usize = 100_000;
+
+fn main() {
+    // `cargo bench` passes `--bench` along
+    let mut args = std::env::args().skip(1).filter(|arg| !arg.starts_with("--"));
+    let connections = args.next().and_then(|arg| arg.parse().ok()).unwrap_or(8);
+    let requests = args.next().and_then(|arg| arg.parse().ok()).unwrap_or(5_000);
+
+    serialization();
+    keep_alive(connections, requests);
+}
+
+fn response() -> Response {
+    Response::new(
+        HttpStatusCode::Ok,
+        vec![
+            Header::from_str("content-type", "text/plain"),
+            Header::from_str("content-length", &BODY.len().to_string()),
+            Header::from_str("connection", "keep-alive"),
+        ],
+        Some(Body::text(BODY)),
+    )
+}
+
+fn serialization() {
+    let response = response();
+    let (allocations, elapsed) = measure(|| {
+        for _ in 0..SERIALIZED {
+            let serialized = response.clone().to_string();
+            std::hint::black_box(serialized.as_bytes());
+        }
+    });
+    // The clone `to_string` consumes is counted too, as the server paid for it
+    report("to_string", allocations, elapsed);
+
+    let (allocations, elapsed) = measure(|| {
+        for _ in 0..SERIALIZED {
+            let mut buffer = buffers::RESPONSES.take();
+            response.write_to(&mut buffer);
+            std::hint::black_box(&buffer);
+            buffers::RESPONSES.give(buffer);
+        }
+    });
+    report("write_to, pooled", allocations, elapsed);
+}
+
+fn report(name: &str, allocations: usize, elapsed: Duration) {
+    println!(
+        "{:<18} {:>6.2} allocations, {:>6} ns per response",
+        name,
+        allocations as f64 / SERIALIZED as f64,
+        elapsed.as_nanos() / SERIALIZED as u128,
+    );
+}
+
+fn keep_alive(connections: usize, requests: usize) {
+    let host = HostBuilder::new("bench.local")
+        .listen("127.0.0.1:0")
+        .handler("/hello", vec![HttpMethod::GET], RouteHandler::from_fn(|_: &Request, _: &Route| Ok(response())))
+        .build()
+        .expect("the bench host builds");
+    // Requests aren't logged, writing them would be most of what's measured
+    let server = ServerBuilder::new()
+        .host(host)
+        .logging(LogSettings { level: LogLevel::WARN, ..LogSettings::default() })
+        .spawn()
+        .expect("the bench server starts");
+    let addr = match server.local_addrs() {
+        [ListenAddr::Tcp(addr), ..] => *addr,
+        _ => panic!("the bench server listens on no TCP port"),
+    };
+
+    let (allocations, elapsed) = measure(|| {
+        let clients: Vec<_> = (0..connections)
+            .map(|_| thread::spawn(move || client(addr, requests)))
+            .collect();
+        for client in clients {
+            client.join().expect("the client succeeds");
+        }
+    });
+
+    let total = connections * requests;
+    println!(
+        "{} connections x {} requests: {:.0} req/s, {:.2} allocations per request",
+        connections,
+        requests,
+        total as f64 / elapsed.as_secs_f64(),
+        allocations as f64 / total as f64,
+    );
+    server.shutdown();
+    server.wait().expect("the bench server stops");
+}
+
+/// Sends `requests` over one connection, reading each response before the next
+fn client(addr: SocketAddr, requests: usize) {
+    let stream = TcpStream::connect(addr).expect("the client connects");
+    stream.set_nodelay(true).unwrap();
+    let request = format!("GET /hello HTTP/1.1\r\nHost: {}\r\n\r\n", addr);
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let mut body = [0; BODY.len()];
+
+    for _ in 0..requests {
+        writer.write_all(request.as_bytes()).unwrap();
+        let mut length = 0;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        assert_eq!(length, BODY.len(), "the server answers {}", line);
+        reader.read_exact(&mut body).unwrap();
+    }
+}
+
+fn measure(run: impl FnOnce()) -> (usize, Duration) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    run();
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, started.elapsed())
+}
@@ -0,0 +1,14 @@
+//! Feeds arbitrary bytes to the request parser, which must reject what it
+//! can't read instead of panicking. Run with `cargo fuzz run parse_request`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use localhost::http::request::parse_request;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(request) = parse_request(data) {
+        // Every part of what was parsed is readable
+        let _ = format!("{:?}", request);
+    }
+});
@@ -8,29 +8,43 @@
 //! 🛠️ Usage examples:
 //! ```sh
 //! localhost-cli create mysite    # Creates the 'sites/mysite' directory
+//! localhost-cli create --template spa # Starts the site from a skeleton
 //! localhost-cli list             # Lists all configured sites
 //! localhost-cli config           # Displays the config.json file
+//! localhost-cli route add mysite # Adds a route to the 'mysite' server
+//! localhost-cli delete mysite    # Removes 'mysite', and its directory if asked
+//! localhost-cli start            # Runs the server in the background
+//! localhost-cli status           # Tells whether each host answers
+//! localhost-cli logs -f --level warn # Follows the warnings and errors
 //! ```
 //!
 //! 🎯 Make local site management easier with **localhost-cli**! 🚀
 
 
-use clap::{Parser, Subcommand, Args};
+use clap::{Parser, Subcommand, Args, ValueEnum};
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 
-use dialoguer::{Input, Confirm};
+use dialoguer::{Input, Confirm, MultiSelect, Select};
 use indicatif::{ProgressBar, ProgressStyle};
-use std::{thread, time::Duration};
+use std::{thread, time::{Duration, Instant}};
 use tabwriter::TabWriter;
 use colored::*;
-use serde_json::Value;
+use serde_json::{json, Map, Value};
 use std::{fs, io::{self, Write}};
 
 use std::{
     env,
-    path::PathBuf,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
 };
+use localhost::config::config::ALLOWED_HTTP_METHODS;
+use localhost::server::hosts_file::{HostsFile, DEFAULT_HOSTS_FILE};
+use localhost::server::listen::ListenAddr;
+use localhost::server::logger::LogLevel;
 
 /// 🚀 A simple CLI to manage local sites 🌍
 #[derive(Parser)]
@@ -38,6 +52,10 @@ use std::{
 #[command(version = "1.0")]
 #[command(about = "Create and manage local sites effortlessly", long_about = None)]
 struct Cli {
+    /// Configuration file, defaults to ~/.cargo/localhost-cli/config.json
+    #[arg(long, global = true, env = "LOCALHOST_CONFIG")]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -57,19 +75,112 @@ struct CreateArgs {
     /// Enable `cgi-bin` directory
     #[arg(long)]
     cgi_bin: bool,
+    /// Skeleton the site directory and its routes start from
+    #[arg(short, long, value_enum)]
+    template: Option<SiteTemplate>,
+    /// Take the defaults of the settings not given instead of asking for them
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// 🧱 Working sites `create` can start from
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum SiteTemplate {
+    /// An index page and its stylesheet
+    Static,
+    /// A single-page app, every path without an extension answered by its index page
+    Spa,
+    /// A JSON endpoint run by a CGI script, and an upload endpoint
+    Api,
+    /// A form answered by a CGI script, and an upload form
+    Cgi,
+}
+
+impl SiteTemplate {
+    /// The files of the skeleton, relative to the site directory.
+    /// `{{server_name}}` is replaced by the name of the server.
+    fn files(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            SiteTemplate::Static => &[
+                ("index.html", include_str!("scaffold/static/index.html")),
+                ("style.css", include_str!("scaffold/static/style.css")),
+            ],
+            SiteTemplate::Spa => &[
+                ("index.html", include_str!("scaffold/spa/index.html")),
+                ("app.js", include_str!("scaffold/spa/app.js")),
+                ("style.css", include_str!("scaffold/spa/style.css")),
+            ],
+            SiteTemplate::Api => &[
+                ("index.html", include_str!("scaffold/api/index.html")),
+                ("cgi-bin/api.py", include_str!("scaffold/api/cgi-bin/api.py")),
+            ],
+            SiteTemplate::Cgi => &[
+                ("index.html", include_str!("scaffold/cgi/index.html")),
+                ("cgi-bin/form.py", include_str!("scaffold/cgi/cgi-bin/form.py")),
+            ],
+        }
+    }
+
+    /// The routes serving the skeleton of the site called `name`
+    fn routes(self, name: &str) -> Vec<Map<String, Value>> {
+        let index = json!({ "path": "/", "methods": ["GET"], "root": name, "default_page": "index.html" });
+        let cgi = |path: &str, script: &str| json!({
+            "path": path,
+            "methods": ["GET"],
+            "root": name,
+            "cgi": { "script_file_name": script, "extensions": ["py"] },
+        });
+        let upload = json!({
+            "path": "/api/files/upload",
+            "methods": ["POST"],
+            "root": name,
+            "upload_dir": format!("uploads/{}", name),
+            "content_types": ["multipart/form-data"],
+        });
+        let routes = match self {
+            SiteTemplate::Static | SiteTemplate::Spa => vec![index],
+            SiteTemplate::Api => vec![index, cgi("/api/hello", "api.py"), upload],
+            SiteTemplate::Cgi => vec![index, cgi("/form", "form.py"), upload],
+        };
+        routes.into_iter().filter_map(|route| route.as_object().cloned()).collect()
+    }
+
+    /// Server settings the skeleton needs besides its routes
+    fn settings(self) -> Map<String, Value> {
+        let mut settings = Map::new();
+        if self == SiteTemplate::Spa {
+            // Paths of the app are answered by the index page, which renders them
+            settings.insert("rewrite".to_string(), json!([{ "pattern": "^/[^.]+$", "replacement": "/", "flag": "last" }]));
+        }
+        settings
+    }
+
+    fn has_cgi_bin(self) -> bool {
+        self.files().iter().any(|(path, _)| path.starts_with("cgi-bin/"))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ServerConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     server_address: String,
     server_name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     ports: Vec<String>,
+    /// Routes are edited field by field, the ones the CLI doesn't prompt for kept
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    routes: Vec<Map<String, Value>>,
+    /// Settings the CLI doesn't manage, written back as they were
+    #[serde(flatten)]
+    other: Map<String, Value>,
 }
 
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Config {
     servers: Vec<ServerConfig>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
 }
 
 #[derive(Subcommand)]
@@ -85,8 +196,94 @@ enum Commands {
 
     // 🗑️ Clean up config.json
     Clean,
+
+    /// 🗑️ Delete a server, the hosts file entry the server added and optionally its site directory
+    Delete {
+        /// Server name
+        name: String,
+    },
+
+    /// 🛣️ Manage the routes of a server
+    Route {
+        #[command(subcommand)]
+        command: RouteCommands,
+    },
+
+    /// ▶️ Run the server in the background
+    Start,
+
+    /// ⏹️ Stop the server, letting it finish the requests in progress
+    Stop,
+
+    /// 🔎 Show whether the server runs and which hosts answer
+    Status,
+
+    /// 🔁 Stop the server and start it again, to apply configuration changes
+    Restart,
+
+    /// 📜 Show the last lines of the server's logs
+    Logs(LogsArgs),
 }
 
+#[derive(Args)]
+struct LogsArgs {
+    /// Only the lines of this server
+    #[arg(long)]
+    host: Option<String>,
+    /// Only the lines of this level or a more severe one
+    #[arg(long)]
+    level: Option<String>,
+    /// Keep printing the lines as they are written
+    #[arg(short, long)]
+    follow: bool,
+    /// Last lines shown of each file, before following
+    #[arg(short = 'n', long, default_value_t = 20)]
+    lines: usize,
+}
+
+#[derive(Subcommand)]
+enum RouteCommands {
+    /// ➕ Add a route to a server
+    Add {
+        /// Server name
+        server: String,
+    },
+
+    /// ✏️ Change a route of a server
+    Edit {
+        /// Server name
+        server: String,
+        /// Path of the route, picked from a list when left out
+        path: Option<String>,
+    },
+
+    /// ➖ Remove a route from a server
+    Remove {
+        /// Server name
+        server: String,
+        /// Path of the route, picked from a list when left out
+        path: Option<String>,
+    },
+}
+
+/// The binary started in the background, looked up next to the CLI first
+const SERVER_BINARY: &str = "localhost-server";
+
+/// How long the server may take to stop: its shutdown timeout, and some more
+const STOP_TIMEOUT: Duration = Duration::from_secs(35);
+
+/// How long a host may take to accept a connection when checking its status
+const STATUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Largest request body of the servers created, as the server defaults to
+const DEFAULT_BODY_SIZE: &str = "10M";
+
+/// The 404 page of the sites asking for one, relative to their directory
+const ERROR_PAGE: &str = "error/404.html";
+
+/// How often followed log files are checked for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
 
 fn get_config_dir() -> PathBuf {
     let home_dir = env::var("HOME").expect("❌ Failed to get home directory");
@@ -100,7 +297,13 @@ fn get_config_dir() -> PathBuf {
     config_dir
 }
 
+/// Set from `--config` or `LOCALHOST_CONFIG` before any command runs
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
 fn get_config_path() -> PathBuf {
+    if let Some(config_path) = CONFIG_PATH.get() {
+        return config_path.clone();
+    }
     let mut config_path = get_config_dir();
     config_path.push("config.json");
     config_path
@@ -109,9 +312,13 @@ fn get_config_path() -> PathBuf {
 
 /// 📜 Save config.json
 fn save_config(config: &Config) {
-    let config_path = get_config_path();
+    write_config(config, &get_config_path()).expect("❌ Failed to write config.json");
+}
+
+/// 📜 Write `config` to `path`
+fn write_config(config: &Config, path: &Path) -> io::Result<()> {
     let json_data = to_string_pretty(config).expect("❌ Failed to format JSON");
-    fs::write(config_path, json_data).expect("❌ Failed to write config.json");
+    fs::write(path, json_data)
 }
 
 /// 🎤 Prompt user input with a nice UI
@@ -133,14 +340,14 @@ fn prompt_user(prompt: &str, default: Option<&str>) -> String {
 }
 
 /// 🚀 Create a new server with an interactive CLI
-fn create_server(args: CreateArgs) {
+fn create_server(mut args: CreateArgs) {
     let mut config = load_config();
 
     println!("\n🌍 Let's set up your new server!\n");
 
-    // 🌟 Demander le nom du serveur
+    // 🌟 Demander le nom du serveur, sauf s'il est donné
     let server_name = loop {
-        let name = prompt_user("📛 Enter server name:", None);
+        let name = args.name.take().unwrap_or_else(|| prompt_user("📛 Enter server name:", None));
         if name.is_empty() {
             println!("❌ Server name cannot be empty!");
             continue;
@@ -158,7 +365,7 @@ fn create_server(args: CreateArgs) {
     // 🌐 Demander l'adresse IP
     let default_address = format!("127.0.0.{}", config.servers.len() + 2);
     let server_address = loop {
-        let address = prompt_user("📡 Enter server IP address:", Some(&default_address));
+        let address = args.address.take().unwrap_or_else(|| prompt_user("📡 Enter server IP address:", Some(&default_address)));
 
         // 🛑 Vérifier l'unicité de l'adresse
         if config.servers.iter().any(|s| s.server_address == address) {
@@ -171,23 +378,54 @@ fn create_server(args: CreateArgs) {
 
     // 🎯 Demander les ports
     let default_ports = "8080".to_string();
-    let ports_input = prompt_user("🛠️ Enter ports (comma-separated):", Some(&default_ports));
-    let ports: Vec<String> = ports_input.split(',').map(|p| p.trim().to_string()).collect();
+    let ports = loop {
+        let ports_input = args.ports.take().unwrap_or_else(|| prompt_user("🛠️ Enter ports (comma-separated):", Some(&default_ports)));
+        let ports: Vec<String> = ports_input.split(',').map(|p| p.trim().to_string()).collect();
+        // 🔌 Vérifier que chaque port est libre, en proposant le suivant sinon
+        match check_ports(&config, &server_address, ports) {
+            Some(ports) => break ports,
+            None => continue,
+        }
+    };
+
+    // 📂 Demander si `cgi-bin` est nécessaire, sauf si le modèle en a un
+    let template = args.template;
+    let has_cgi_bin = args.cgi_bin
+        || template.is_some_and(SiteTemplate::has_cgi_bin)
+        || (!args.yes && confirm("📂 Do you need a `cgi-bin` folder?", false));
 
-    // 📂 Demander si `cgi-bin` est nécessaire
-    let has_cgi_bin = Confirm::new()
-        .with_prompt("📂 Do you need a `cgi-bin` folder?")
-        .default(false)
-        .interact()
-        .unwrap();
+    let mut site_path = get_config_dir();
+    site_path.push("sites");
+    site_path.push(&server_name);
 
-    let server = ServerConfig {
+    let mut server = ServerConfig {
         server_address: server_address.clone(),
         server_name: server_name.clone(),
         ports,
+        routes: template.map(|template| template.routes(&server_name)).unwrap_or_default(),
+        other: template.map(SiteTemplate::settings).unwrap_or_default(),
     };
 
+    // ⚙️ Les réglages de l'hôte, par défaut avec --yes
+    let settings = if args.yes { HostSettings::default() } else { prompt_host_settings() };
+    settings.apply(&mut server, &site_path);
+    if server.routes.is_empty() && !args.yes && confirm("🛣️ Add routes now?", true) {
+        loop {
+            if let Some(route) = prompt_valid_route(&server, None) {
+                server.routes.push(route);
+            }
+            if !confirm("➕ Add another route?", false) {
+                break;
+            }
+        }
+    }
+    // 🏠 Sans route, le site n'est pas servi : la page d'accueil par défaut l'est
+    if server.routes.is_empty() {
+        server.routes.push(default_route(&server_name));
+    }
+
     // 🔧 Ajouter à la configuration
+    let route_count = server.routes.len();
     config.servers.push(server);
     save_config(&config);
 
@@ -199,10 +437,6 @@ fn create_server(args: CreateArgs) {
     thread::sleep(Duration::from_secs(1));
 
     // 📁 Créer le dossier du site
-    let mut site_path = get_config_dir();
-    site_path.push("sites");
-    site_path.push(&server_name);
-    
     if !site_path.exists() {
         fs::create_dir_all(&site_path).expect("❌ Failed to create site directory");
     }
@@ -214,6 +448,10 @@ fn create_server(args: CreateArgs) {
         fs::create_dir_all(&cgi_path).expect("❌ Failed to create cgi-bin directory");
     }
 
+    // 🧱 Remplir le dossier avec le squelette du modèle
+    let scaffolded = template.map(|template| scaffold_site(template, &site_path, &server_name));
+    let error_page = settings.error_page.then(|| write_error_page(&site_path));
+
     pb.finish_with_message("✅ Setup complete!");
 
     println!("\n🎉 Server `{}` is ready!", server_name);
@@ -221,6 +459,218 @@ fn create_server(args: CreateArgs) {
     if has_cgi_bin {
         println!("📁 `cgi-bin` folder created!");
     }
+    match scaffolded {
+        Some(Ok(written)) => {
+            for file in written {
+                println!("🧱 {}", file.display());
+            }
+        }
+        Some(Err(e)) => println!("{}", format!("❌ Failed to write the site skeleton: {}", e).red()),
+        None => {}
+    }
+    match error_page {
+        Some(Ok(path)) => println!("🚧 404 page: {}", path.display()),
+        Some(Err(e)) => println!("{}", format!("❌ Failed to write the 404 page: {}", e).red()),
+        None => {}
+    }
+    println!("🛣️ {} route(s), see `localhost-cli config`.", route_count);
+}
+
+/// ⚙️ The host settings the wizard asks about besides the routes
+#[derive(Debug, PartialEq)]
+struct HostSettings {
+    /// Largest request body, like "10M" or "512K"
+    client_max_body_size: String,
+    /// Seconds a session lasts, no sessions when `None`
+    session_max_age: Option<u64>,
+    /// Whether the site gets its own 404 page
+    error_page: bool,
+}
+
+impl Default for HostSettings {
+    fn default() -> Self {
+        HostSettings { client_max_body_size: DEFAULT_BODY_SIZE.to_string(), session_max_age: None, error_page: false }
+    }
+}
+
+impl HostSettings {
+    /// Write the settings into `server`, whose site is at `site_path`
+    fn apply(&self, server: &mut ServerConfig, site_path: &Path) {
+        server.other.insert("client_max_body_size".to_string(), Value::from(self.client_max_body_size.clone()));
+        if let Some(max_age) = self.session_max_age {
+            server.other.insert("session".to_string(), json!({
+                "enabled": true,
+                "name": "session_id",
+                "options": { "max_age": max_age, "path": "/", "http_only": true, "same_site": "Lax" },
+            }));
+        }
+        if self.error_page {
+            // The server reads the page from where it runs, the path must be absolute
+            let page = site_path.join(ERROR_PAGE).display().to_string();
+            server.other.insert("error_pages".to_string(), json!({ "custom_pages": { "404": page } }));
+        }
+    }
+}
+
+/// ⚙️ Ask for the host settings, each answer defaulting to the settings of
+/// `HostSettings::default`
+fn prompt_host_settings() -> HostSettings {
+    let defaults = HostSettings::default();
+    let client_max_body_size = loop {
+        let size = prompt_user("📦 Largest request body (like 10M or 512K):", Some(&defaults.client_max_body_size));
+        if !is_size(&size) {
+            println!("❌ `{}` is not a size, use a number of bytes followed by K or M!", size);
+            continue;
+        }
+        break size;
+    };
+    let session_max_age = confirm("🍪 Enable sessions?", false).then(|| loop {
+        match prompt_user("⏳ Seconds a session lasts:", Some("86400")).parse::<u64>() {
+            Ok(max_age) => break max_age,
+            Err(_) => println!("❌ Enter a number of seconds!"),
+        }
+    });
+    let error_page = confirm("🚧 Give the site its own 404 page?", defaults.error_page);
+    HostSettings { client_max_body_size, session_max_age, error_page }
+}
+
+/// Whether `value` is a size the server reads, bytes with an optional K or M
+fn is_size(value: &str) -> bool {
+    let digits = value.strip_suffix(['k', 'K', 'm', 'M']).unwrap_or(value);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// 🏠 The route serving the site of `name`, the default home page until it
+/// has an `index.html`
+fn default_route(name: &str) -> Map<String, Value> {
+    let route = json!({ "path": "/", "methods": ["GET"], "root": name });
+    route.as_object().cloned().unwrap_or_default()
+}
+
+/// 🚧 Write the 404 page of the site at `site_path`, keeping the one there.
+/// Returns its path.
+fn write_error_page(site_path: &Path) -> io::Result<PathBuf> {
+    let path = site_path.join(ERROR_PAGE);
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, include_str!("scaffold/404.html"))?;
+    }
+    Ok(path)
+}
+
+/// Why `port` on `address` can't be used by a new server
+#[derive(Debug, PartialEq)]
+enum PortConflict {
+    /// Another server of the configuration listens there
+    Configured(String),
+    /// Binding it failed, another program probably listens there
+    Unavailable(io::ErrorKind),
+}
+
+impl std::fmt::Display for PortConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortConflict::Configured(name) => write!(f, "server `{}` listens there", name),
+            PortConflict::Unavailable(io::ErrorKind::AddrInUse) => write!(f, "another program listens there"),
+            PortConflict::Unavailable(io::ErrorKind::PermissionDenied) => write!(f, "binding it needs root"),
+            PortConflict::Unavailable(kind) => write!(f, "it can't be bound ({})", kind),
+        }
+    }
+}
+
+/// 🔌 The TCP addresses the servers of `config` listen on, with their names
+fn configured_addrs(config: &Config) -> Vec<(SocketAddr, &str)> {
+    let mut addrs = Vec::new();
+    for server in &config.servers {
+        let listen = server.other.get("listen").and_then(Value::as_array).into_iter().flatten()
+            .filter_map(Value::as_str)
+            .filter_map(|entry| ListenAddr::parse(entry).ok());
+        let ports = server.ports.iter().filter_map(|port| ListenAddr::from_parts(&server.server_address, port).ok());
+        for addr in listen.chain(ports) {
+            if let ListenAddr::Tcp(addr) = addr {
+                addrs.push((addr, server.server_name.as_str()));
+            }
+        }
+    }
+    addrs
+}
+
+/// 🔌 Why `addr` can't be listened on by a new server, `None` when it's free.
+/// Addresses overlap when their ports match and either one is a wildcard.
+fn port_conflict(config: &Config, addr: SocketAddr) -> Option<PortConflict> {
+    let overlapping = configured_addrs(config).into_iter().find(|(other, _)| {
+        other.port() == addr.port() && (other.ip() == addr.ip() || other.ip().is_unspecified() || addr.ip().is_unspecified())
+    });
+    if let Some((_, name)) = overlapping {
+        return Some(PortConflict::Configured(name.to_string()));
+    }
+    TcpListener::bind(addr).err().map(|e| PortConflict::Unavailable(e.kind()))
+}
+
+/// 🔌 The first port after the one of `addr` free on its address, and not
+/// among `taken`
+fn next_free_port(config: &Config, addr: SocketAddr, taken: &[u16]) -> Option<u16> {
+    (addr.port().saturating_add(1)..=u16::MAX)
+        .filter(|port| !taken.contains(port))
+        .find(|port| port_conflict(config, SocketAddr::new(addr.ip(), *port)).is_none())
+}
+
+/// 🔌 Check the `ports` a new server would listen on at `address`, offering
+/// the next free port for the ones taken. `None` when they must be entered
+/// again.
+fn check_ports(config: &Config, address: &str, mut ports: Vec<String>) -> Option<Vec<String>> {
+    for i in 0..ports.len() {
+        let addr = match ListenAddr::from_parts(address, &ports[i]) {
+            Ok(ListenAddr::Tcp(addr)) => addr,
+            Ok(ListenAddr::Unix(_)) => continue,
+            // The address isn't an IP, the port can't be probed
+            Err(e) if ports[i].parse::<u16>().is_ok() => {
+                println!("⚠️ Cannot check port {}: {}", ports[i], e);
+                continue;
+            }
+            Err(_) => {
+                println!("❌ `{}` is not a port!", ports[i]);
+                return None;
+            }
+        };
+        if ports[..i].contains(&ports[i]) {
+            println!("❌ Port {} is listed twice!", ports[i]);
+            return None;
+        }
+        let Some(conflict) = port_conflict(config, addr) else { continue };
+
+        println!("{}", format!("⚠️ {} is taken: {}.", addr, conflict).yellow());
+        let taken: Vec<u16> = ports.iter().filter_map(|port| port.parse().ok()).collect();
+        match next_free_port(config, addr, &taken) {
+            Some(free) if confirm(&format!("🔌 Use port {} instead?", free), true) => ports[i] = free.to_string(),
+            Some(_) => return None,
+            None => {
+                println!("❌ No port after {} is free on {}.", addr.port(), addr.ip());
+                return None;
+            }
+        }
+    }
+    Some(ports)
+}
+
+/// 🧱 Write the files of `template` for the server `name` into `site_path`,
+/// keeping the ones already there. Returns the files written.
+fn scaffold_site(template: SiteTemplate, site_path: &Path, name: &str) -> io::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (file, content) in template.files() {
+        let path = site_path.join(file);
+        if path.exists() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content.replace("{{server_name}}", name))?;
+        written.push(path);
+    }
+    Ok(written)
 }
 
 
@@ -232,14 +682,719 @@ fn clean_json() {
     println!("✅ config.json successfully removed!");
 }
 
-/// 📜 Load config.json
+/// 📜 Load config.json, exiting when it can't be read rather than have it
+/// overwritten by an empty one
 fn load_config() -> Config {
     let config_path = get_config_path();
     if config_path.exists() {
-        let content = fs::read_to_string(&config_path).expect("❌ Failed to read config.json");
-        serde_json::from_str(&content).unwrap_or_else(|_| Config { servers: vec![] })
+        read_config(&config_path).unwrap_or_else(|e| {
+            eprintln!("{}", format!("❌ {} is not a valid configuration: {}", config_path.display(), e).red());
+            std::process::exit(1);
+        })
     } else {
-        Config { servers: vec![] }
+        Config { servers: vec![], other: Map::new() }
+    }
+}
+
+/// 📜 The configuration written at `path`
+fn read_config(path: &Path) -> io::Result<Config> {
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content).map_err(io::Error::from)
+}
+
+/// 🔎 The index of the server called `name`, exiting when there's none
+fn find_server(config: &Config, name: &str) -> usize {
+    config.servers.iter().position(|s| s.server_name == name).unwrap_or_else(|| {
+        eprintln!("{}", format!("❌ No server is called `{}`. See `localhost-cli list`.", name).red());
+        std::process::exit(1);
+    })
+}
+
+/// 🔎 The index of the route of `server` on `path`, picked from a list when
+/// no path is given
+fn find_route(server: &ServerConfig, path: Option<String>) -> Option<usize> {
+    let paths: Vec<&str> = server.routes.iter()
+        .map(|route| route.get("path").and_then(Value::as_str).unwrap_or(""))
+        .collect();
+    if paths.is_empty() {
+        println!("⚠️ Server `{}` has no routes. Use `localhost-cli route add {}` to add one.", server.server_name, server.server_name);
+        return None;
+    }
+    match path {
+        Some(path) => {
+            let index = paths.iter().position(|p| *p == path);
+            if index.is_none() {
+                println!("{}", format!("❌ Server `{}` has no route on `{}`.", server.server_name, path).red());
+            }
+            index
+        }
+        None => Some(Select::new()
+            .with_prompt("🛣️ Which route?")
+            .items(&paths)
+            .default(0)
+            .interact()
+            .unwrap()),
+    }
+}
+
+/// 🎤 Prompt for an optional value, an empty answer meaning none
+fn prompt_optional(prompt: &str, current: Option<&str>) -> Option<String> {
+    let mut input = Input::<String>::new().with_prompt(prompt).allow_empty(true);
+    if let Some(current) = current {
+        input = input.default(current.to_string());
+    }
+    let value = input.interact_text().unwrap();
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn confirm(prompt: &str, default: bool) -> bool {
+    Confirm::new().with_prompt(prompt).default(default).interact().unwrap()
+}
+
+/// 🛣️ Ask for the settings of a route of `server`, the ones of `current`
+/// being the defaults. Settings the CLI doesn't ask about are kept.
+fn prompt_route(server: &ServerConfig, current: Option<&Map<String, Value>>) -> Map<String, Value> {
+    let mut route = current.cloned().unwrap_or_default();
+    let text = |route: &Map<String, Value>, key: &str| route.get(key).and_then(Value::as_str).map(str::to_string);
+    let current_path = text(&route, "path");
+
+    let path = loop {
+        let path = prompt_user("🛣️ Route path:", Some(current_path.as_deref().unwrap_or("/")));
+        if !path.starts_with('/') {
+            println!("❌ The path must start with `/`!");
+            continue;
+        }
+        let taken = server.routes.iter()
+            .filter_map(|other| other.get("path").and_then(Value::as_str))
+            .any(|other| other == path && Some(other) != current_path.as_deref());
+        if taken {
+            println!("⚠️ Server `{}` has a route on `{}` already.", server.server_name, path);
+            continue;
+        }
+        break path;
+    };
+    route.insert("path".to_string(), Value::from(path));
+
+    let current_methods: Vec<String> = route.get("methods")
+        .and_then(Value::as_array)
+        .map(|methods| methods.iter().filter_map(Value::as_str).map(str::to_uppercase).collect())
+        .unwrap_or_else(|| vec!["GET".to_string()]);
+    let checked: Vec<bool> = ALLOWED_HTTP_METHODS.iter().map(|m| current_methods.iter().any(|c| c == m)).collect();
+    let methods = loop {
+        let selected = MultiSelect::new()
+            .with_prompt("📬 Methods (space to toggle, enter to confirm)")
+            .items(&ALLOWED_HTTP_METHODS)
+            .defaults(&checked)
+            .interact()
+            .unwrap();
+        if selected.is_empty() {
+            println!("❌ Pick at least one method, a route without any rejects every request!");
+            continue;
+        }
+        break selected.into_iter().map(|i| Value::from(ALLOWED_HTTP_METHODS[i])).collect::<Vec<_>>();
+    };
+    route.insert("methods".to_string(), Value::Array(methods));
+
+    let root = prompt_user("📂 Root directory (under sites/):", Some(text(&route, "root").as_deref().unwrap_or(&server.server_name)));
+    route.insert("root".to_string(), Value::from(root));
+    match prompt_optional("📄 Default page (empty for none):", Some(text(&route, "default_page").as_deref().unwrap_or("index.html"))) {
+        Some(page) => route.insert("default_page".to_string(), Value::from(page)),
+        None => route.remove("default_page"),
+    };
+    let listing = confirm("🗂️ List directories without a default page?", route.get("directory_listing").and_then(Value::as_bool).unwrap_or(false));
+    route.insert("directory_listing".to_string(), Value::from(listing));
+
+    let cgi = route.get("cgi").and_then(Value::as_object).cloned();
+    if confirm("⚙️ Run a CGI script?", cgi.is_some()) {
+        let mut cgi = cgi.unwrap_or_default();
+        let script = prompt_user("📜 Script file name (in cgi-bin/):", text(&cgi, "script_file_name").as_deref());
+        cgi.insert("script_file_name".to_string(), Value::from(script));
+        match prompt_optional("🐍 Interpreter (empty for /usr/bin/python3):", text(&cgi, "interpreter").as_deref()) {
+            Some(interpreter) => cgi.insert("interpreter".to_string(), Value::from(interpreter)),
+            None => cgi.remove("interpreter"),
+        };
+        let current_extensions = cgi.get("extensions")
+            .and_then(Value::as_array)
+            .map(|extensions| extensions.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(","))
+            .unwrap_or_else(|| "py".to_string());
+        let extensions = prompt_user("🧩 Allowed extensions (comma-separated):", Some(&current_extensions));
+        let extensions: Vec<Value> = extensions.split(',')
+            .map(|ext| ext.trim().trim_start_matches('.'))
+            .filter(|ext| !ext.is_empty())
+            .map(Value::from)
+            .collect();
+        cgi.insert("extensions".to_string(), Value::Array(extensions));
+        let timeout = loop {
+            let current = cgi.get("timeout_secs").and_then(Value::as_u64).unwrap_or(30).to_string();
+            match prompt_user("⏱️ Seconds the script may run:", Some(&current)).parse::<u64>() {
+                Ok(timeout) if timeout > 0 => break timeout,
+                _ => println!("❌ Enter a number of seconds greater than 0!"),
+            }
+        };
+        cgi.insert("timeout_secs".to_string(), Value::from(timeout));
+        route.insert("cgi".to_string(), Value::Object(cgi));
+    } else {
+        route.remove("cgi");
+    }
+
+    if confirm("🔐 Require a session?", route.get("session_required").and_then(Value::as_bool).unwrap_or(false)) {
+        route.insert("session_required".to_string(), Value::from(true));
+        let redirect = prompt_user("↪️ Page clients without a session are sent to:", Some(text(&route, "session_redirect").as_deref().unwrap_or("/login")));
+        route.insert("session_redirect".to_string(), Value::from(redirect));
+    } else {
+        route.insert("session_required".to_string(), Value::from(false));
+        route.remove("session_redirect");
+    }
+    route
+}
+
+/// 🩺 The critical errors the server would refuse `route` for. Warnings like
+/// a missing root are left out, the root being resolved under sites/.
+fn route_errors(route: &Map<String, Value>) -> Vec<String> {
+    match serde_json::from_value::<localhost::config::config::Route>(Value::Object(route.clone())) {
+        Ok(route) => route.validate().into_iter()
+            .filter_map(|e| match e {
+                localhost::config::config::ConfigError::Critical(message) => Some(message),
+                _ => None,
+            })
+            .collect(),
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// 🛣️ Ask for a route until the server would accept it, `None` when the user
+/// gives up
+fn prompt_valid_route(server: &ServerConfig, current: Option<&Map<String, Value>>) -> Option<Map<String, Value>> {
+    let mut current = current.cloned();
+    loop {
+        let route = prompt_route(server, current.as_ref());
+        let errors = route_errors(&route);
+        if errors.is_empty() {
+            return Some(route);
+        }
+        for error in &errors {
+            println!("{}", format!("❌ {}", error).red());
+        }
+        if !confirm("🔁 Fix the route?", true) {
+            return None;
+        }
+        current = Some(route);
+    }
+}
+
+/// The path a route answers on
+fn route_path(route: &Map<String, Value>) -> &str {
+    route.get("path").and_then(Value::as_str).unwrap_or_default()
+}
+
+/// 🔎 The server called `name`, or why the CLI can't edit it
+fn server_mut<'a>(config: &'a mut Config, name: &str) -> Result<&'a mut ServerConfig, String> {
+    config.servers.iter_mut()
+        .find(|server| server.server_name == name)
+        .ok_or_else(|| format!("No server is called `{}`. See `localhost-cli list`.", name))
+}
+
+/// 🩺 Why `server` can't have `route`, in place of its route on `replacing`
+/// when given. Its methods are checked against [`ALLOWED_HTTP_METHODS`] with
+/// the rest of what the server would refuse.
+fn check_route(server: &ServerConfig, route: &Map<String, Value>, replacing: Option<&str>) -> Result<(), String> {
+    let path = route_path(route);
+    if !path.starts_with('/') {
+        return Err(format!("The path `{}` must start with `/`", path));
+    }
+    let taken = server.routes.iter().any(|other| route_path(other) == path && Some(path) != replacing);
+    if taken {
+        return Err(format!("Server `{}` has a route on `{}` already", server.server_name, path));
+    }
+    match route_errors(route).as_slice() {
+        [] => Ok(()),
+        errors => Err(errors.join(", ")),
+    }
+}
+
+/// ➕ Add `route` to the server called `server_name`
+fn insert_route(config: &mut Config, server_name: &str, route: Map<String, Value>) -> Result<(), String> {
+    let server = server_mut(config, server_name)?;
+    check_route(server, &route, None)?;
+    server.routes.push(route);
+    Ok(())
+}
+
+/// ✏️ Put `route` in place of the route on `path` of `server_name`
+fn replace_route(config: &mut Config, server_name: &str, path: &str, route: Map<String, Value>) -> Result<(), String> {
+    let server = server_mut(config, server_name)?;
+    let Some(index) = server.routes.iter().position(|route| route_path(route) == path) else {
+        return Err(format!("Server `{}` has no route on `{}`", server_name, path));
+    };
+    check_route(server, &route, Some(path))?;
+    server.routes[index] = route;
+    Ok(())
+}
+
+/// ➖ Take the route on `path` out of `server_name`
+fn delete_route(config: &mut Config, server_name: &str, path: &str) -> Result<Map<String, Value>, String> {
+    let server = server_mut(config, server_name)?;
+    match server.routes.iter().position(|route| route_path(route) == path) {
+        Some(index) => Ok(server.routes.remove(index)),
+        None => Err(format!("Server `{}` has no route on `{}`", server_name, path)),
+    }
+}
+
+/// Print why the configuration couldn't be edited, and exit
+fn edit_failed(error: String) -> ! {
+    eprintln!("{}", format!("❌ {}.", error).red());
+    std::process::exit(1);
+}
+
+/// ➕ Add a route to `server_name`
+fn add_route(server_name: &str) {
+    let mut config = load_config();
+    let index = find_server(&config, server_name);
+    println!("\n🛣️ New route for `{}`\n", server_name);
+    let Some(route) = prompt_valid_route(&config.servers[index], None) else {
+        println!("⚠️ No route was added.");
+        return;
+    };
+    let path = route_path(&route).to_string();
+    if let Err(e) = insert_route(&mut config, server_name, route) {
+        edit_failed(e);
+    }
+    save_config(&config);
+    println!("\n🎉 Route `{}` added to `{}`!", path, server_name);
+}
+
+/// ✏️ Change a route of `server_name`
+fn edit_route(server_name: &str, path: Option<String>) {
+    let mut config = load_config();
+    let index = find_server(&config, server_name);
+    let Some(route_index) = find_route(&config.servers[index], path) else { return };
+    let current = config.servers[index].routes[route_index].clone();
+    let Some(route) = prompt_valid_route(&config.servers[index], Some(&current)) else {
+        println!("⚠️ The route was left unchanged.");
+        return;
+    };
+    let path = route_path(&route).to_string();
+    if let Err(e) = replace_route(&mut config, server_name, route_path(&current), route) {
+        edit_failed(e);
+    }
+    save_config(&config);
+    println!("\n✅ Route `{}` of `{}` updated!", path, server_name);
+}
+
+/// ➖ Remove a route of `server_name`
+fn remove_route(server_name: &str, path: Option<String>) {
+    let mut config = load_config();
+    let index = find_server(&config, server_name);
+    let Some(route_index) = find_route(&config.servers[index], path) else { return };
+    let path = route_path(&config.servers[index].routes[route_index]).to_string();
+    if !confirm(&format!("🗑️ Remove route `{}` of `{}`?", path, server_name), false) {
+        return;
+    }
+    if let Err(e) = delete_route(&mut config, server_name, &path) {
+        edit_failed(e);
+    }
+    save_config(&config);
+    println!("✅ Route `{}` removed from `{}`.", path, server_name);
+}
+
+/// What deleting a server changed besides its configuration
+struct Removal {
+    /// The deletion of its site directory, when asked for
+    site: Option<io::Result<()>>,
+    /// The hosts file, and whether the server had an entry there
+    hosts: (PathBuf, io::Result<bool>),
+}
+
+/// 🗂️ The hosts file the server names its hosts in
+fn hosts_file(config: &Config) -> HostsFile {
+    HostsFile::new(config.other.get("hosts_file").and_then(Value::as_str).unwrap_or(DEFAULT_HOSTS_FILE))
+}
+
+/// 🗑️ Take the server called `name` out of `config` and out of its hosts
+/// file, deleting `site_path` too when given. Saving `config` is left to the
+/// caller.
+fn remove_server(config: &mut Config, name: &str, site_path: Option<&Path>) -> Result<Removal, String> {
+    let index = config.servers.iter().position(|server| server.server_name == name)
+        .ok_or_else(|| format!("No server is called `{}`. See `localhost-cli list`.", name))?;
+    config.servers.remove(index);
+    let site = site_path.map(fs::remove_dir_all);
+    // Only the entries the server added, the ones written by hand are kept
+    let hosts_file = hosts_file(config);
+    let hosts = (hosts_file.path().to_path_buf(), hosts_file.remove(name));
+    Ok(Removal { site, hosts })
+}
+
+/// 🗑️ Delete the server called `name`
+fn delete_server(name: &str) {
+    let mut config = load_config();
+    find_server(&config, name);
+    if !confirm(&format!("🗑️ Delete server `{}` from the configuration?", name), false) {
+        return;
+    }
+    let mut site_path = get_config_dir();
+    site_path.push("sites");
+    site_path.push(name);
+    let delete_site = site_path.is_dir() && confirm(&format!("📂 Also delete {} and everything in it?", site_path.display()), false);
+
+    let removal = match remove_server(&mut config, name, delete_site.then_some(site_path.as_path())) {
+        Ok(removal) => removal,
+        Err(e) => edit_failed(e),
+    };
+    save_config(&config);
+    println!("✅ Server `{}` removed from the configuration.", name);
+    match removal.site {
+        Some(Ok(())) => println!("✅ Site directory deleted."),
+        Some(Err(e)) => println!("{}", format!("❌ Failed to delete {}: {}", site_path.display(), e).red()),
+        None => {}
+    }
+
+    let (hosts_path, hosts) = removal.hosts;
+    match hosts {
+        Ok(true) => println!("✅ `{}` removed from {}.", name, hosts_path.display()),
+        Ok(false) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => println!(
+            "{}",
+            format!("⚠️ Cannot edit {}, remove `{}` from it as root (sudo).", hosts_path.display(), name).yellow()
+        ),
+        Err(e) => println!("{}", format!("❌ Failed to edit {}: {}", hosts_path.display(), e).red()),
+    }
+}
+
+/// 📌 The file keeping the pid of the server started for the configuration
+fn pid_file() -> PathBuf {
+    get_config_path().with_extension("pid")
+}
+
+/// 📜 The file the server started in the background writes its output to
+fn log_file() -> PathBuf {
+    get_config_path().with_extension("log")
+}
+
+/// 🔎 Whether a process with `pid` exists
+fn is_alive(pid: libc::pid_t) -> bool {
+    // Signal 0 only checks the process can be signaled
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// 🔎 The pid of the server written at `path`, when it still runs. A pid
+/// file left by a server that is gone is removed.
+fn running_pid(path: &Path) -> Option<libc::pid_t> {
+    let pid = fs::read_to_string(path).ok()?.trim().parse::<libc::pid_t>().ok().filter(|pid| *pid > 0);
+    match pid {
+        Some(pid) if is_alive(pid) => Some(pid),
+        _ => {
+            let _ = fs::remove_file(path);
+            None
+        }
+    }
+}
+
+/// 🔎 The server binary: the one next to the CLI, else the one on the PATH
+fn server_binary() -> PathBuf {
+    env::current_exe().ok()
+        .map(|exe| exe.with_file_name(SERVER_BINARY))
+        .filter(|binary| binary.is_file())
+        .unwrap_or_else(|| PathBuf::from(SERVER_BINARY))
+}
+
+/// ▶️ Run the server in the background on the configuration, its pid kept
+/// in the pid file
+fn start_server() {
+    let pid_path = pid_file();
+    if let Some(pid) = running_pid(&pid_path) {
+        println!("⚠️ The server already runs (pid {}). Use `localhost-cli restart` to restart it.", pid);
+        return;
+    }
+
+    let log_path = log_file();
+    let log = fs::OpenOptions::new().create(true).append(true).open(&log_path).unwrap_or_else(|e| {
+        eprintln!("{}", format!("❌ Cannot open {}: {}", log_path.display(), e).red());
+        std::process::exit(1);
+    });
+    let errors = log.try_clone().expect("❌ Failed to duplicate the log file");
+
+    // Its own process group keeps the server from the terminal's Ctrl-C
+    let mut child = Command::new(server_binary())
+        .arg("--config")
+        .arg(get_config_path())
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(errors)
+        .process_group(0)
+        .spawn()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", format!("❌ Cannot start {}: {}", SERVER_BINARY, e).red());
+            std::process::exit(1);
+        });
+
+    // A server refusing its configuration stops right away
+    thread::sleep(Duration::from_millis(500));
+    if let Ok(Some(status)) = child.try_wait() {
+        eprintln!("{}", format!("❌ The server stopped ({}), see {}", status, log_path.display()).red());
+        std::process::exit(1);
+    }
+
+    fs::write(&pid_path, format!("{}\n", child.id())).expect("❌ Failed to write the pid file");
+    println!("✅ Server started (pid {}).", child.id());
+    println!("📜 Output: {}", log_path.display());
+}
+
+/// ⏹️ Stop the server with SIGTERM, waiting for it to finish the requests in
+/// progress. Returns whether it was running.
+fn stop_server() -> bool {
+    let pid_path = pid_file();
+    let Some(pid) = running_pid(&pid_path) else {
+        println!("⚠️ The server isn't running.");
+        return false;
+    };
+
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        eprintln!("{}", format!("❌ Cannot stop the server (pid {}): {}", pid, io::Error::last_os_error()).red());
+        std::process::exit(1);
+    }
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::default_spinner().template("{spinner} Stopping...").unwrap());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while is_alive(pid) {
+        if Instant::now() >= deadline {
+            pb.finish_and_clear();
+            eprintln!("{}", format!("❌ The server (pid {}) is still running after {}s.", pid, STOP_TIMEOUT.as_secs()).red());
+            std::process::exit(1);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    pb.finish_and_clear();
+
+    let _ = fs::remove_file(&pid_path);
+    println!("✅ Server stopped (pid {}).", pid);
+    true
+}
+
+/// 🔁 Stop the server when it runs, then start it on the configuration
+fn restart_server() {
+    stop_server();
+    start_server();
+}
+
+/// 🔎 Whether something accepts connections on `address`:`port`
+fn is_listening(address: &str, port: &str) -> bool {
+    let address = if address.is_empty() { "127.0.0.1" } else { address };
+    let Ok(addrs) = (address, port.parse::<u16>().unwrap_or(0)).to_socket_addrs() else {
+        return false;
+    };
+    addrs.into_iter().any(|addr| TcpStream::connect_timeout(&addr, STATUS_TIMEOUT).is_ok())
+}
+
+/// 🔎 Show whether the server runs, and the ports each host answers on
+fn show_status() {
+    let config = load_config();
+    match running_pid(&pid_file()) {
+        Some(pid) => println!("{}", format!("🟢 Server running (pid {})", pid).green()),
+        None => println!("{}", "🔴 Server not running".red()),
+    }
+
+    if config.servers.is_empty() {
+        return;
+    }
+
+    println!();
+    let mut tw = TabWriter::new(io::stdout()).padding(2);
+    writeln!(tw, "{}\t{}\t{}",
+        "Server Name".bold().underline(),
+        "IP Address".bold().underline(),
+        "Ports".bold().underline()
+    ).unwrap();
+    for server in &config.servers {
+        let ports: Vec<String> = server.ports.iter()
+            .map(|port| if is_listening(&server.server_address, port) {
+                format!("{} ✅", port)
+            } else {
+                format!("{} ❌", port)
+            })
+            .collect();
+        writeln!(tw, "{}\t{}\t{}", server.server_name, server.server_address, ports.join(", ")).unwrap();
+    }
+    tw.flush().unwrap();
+}
+
+/// Which log lines to show
+struct LogFilter {
+    host: Option<String>,
+    level: Option<LogLevel>,
+}
+
+impl LogFilter {
+    /// 🔎 Whether `line` is kept. Lines whose level can't be told, like the
+    /// rest of a multiline message, are kept.
+    fn matches(&self, line: &str) -> bool {
+        if line.trim().is_empty() {
+            return self.host.is_none() && self.level.is_none();
+        }
+        if let Some(host) = &self.host {
+            let same_host = match serde_json::from_str::<Value>(line) {
+                Ok(entry) => entry["host"].as_str() == Some(host.as_str()),
+                Err(_) => line.contains(host.as_str()),
+            };
+            if !same_host {
+                return false;
+            }
+        }
+        match (self.level, log_level(line)) {
+            (Some(max), Some(level)) => level <= max,
+            _ => true,
+        }
+    }
+}
+
+/// 🔎 The level of a log line, written as text or JSON. Requests are errors
+/// when answered with a 5xx, warnings with a 4xx.
+fn log_level(line: &str) -> Option<LogLevel> {
+    let status_level = |status: u64| match status {
+        500.. => LogLevel::ERROR,
+        400.. => LogLevel::WARN,
+        _ => LogLevel::INFO,
+    };
+    if let Ok(entry) = serde_json::from_str::<Value>(line) {
+        return match (entry["level"].as_str(), entry["status"].as_u64()) {
+            (Some(level), _) => LogLevel::parse(level),
+            (None, Some(status)) => Some(status_level(status)),
+            _ => None,
+        };
+    }
+    // `<client> - - [<time>] "<method> <uri>" <status> <bytes> "<host>"`
+    if let Some(status) = line.split('"').nth(2).and_then(|rest| rest.split_whitespace().next()) {
+        if let Ok(status) = status.parse::<u64>() {
+            return Some(status_level(status));
+        }
+    }
+    // `<date> <time> <level> <module> │ <message>`
+    line.split_whitespace().nth(2).and_then(LogLevel::parse)
+}
+
+/// 🎨 `line` colored after its level, as the server prints it
+fn colorize(line: &str) -> String {
+    match log_level(line) {
+        Some(LogLevel::ERROR) => line.red().to_string(),
+        Some(LogLevel::WARN) => line.yellow().to_string(),
+        Some(LogLevel::DEBUG) => line.blue().to_string(),
+        Some(LogLevel::TRACE) => line.magenta().to_string(),
+        _ => line.to_string(),
+    }
+}
+
+/// 📜 The log files of the configuration: the ones of `host` when it has its
+/// own, else the server's, or the output of `localhost-cli start`
+fn log_files(config: &Config, host: Option<&str>) -> Vec<PathBuf> {
+    let setting = |settings: &Map<String, Value>, key: &str| settings.get(key).and_then(Value::as_str).map(str::to_string);
+    let section = |key: &str| config.other.get(key).and_then(Value::as_object).cloned().unwrap_or_default();
+    // Hosts take the settings of `defaults` they don't set
+    let defaults = section("defaults");
+    let host_files = |server: &ServerConfig| -> Vec<PathBuf> {
+        ["error_log", "access_log"].iter()
+            .filter_map(|key| setting(&server.other, key).or_else(|| setting(&defaults, key)))
+            .map(|template| PathBuf::from(template.replace("{server_name}", &server.server_name)))
+            .collect()
+    };
+
+    let mut files = Vec::new();
+    match host {
+        Some(host) => {
+            let index = find_server(config, host);
+            files.extend(host_files(&config.servers[index]));
+        }
+        None => files.extend(config.servers.iter().flat_map(host_files)),
+    }
+    if host.is_none() || files.is_empty() {
+        let logging = section("logging");
+        match setting(&logging, "file") {
+            Some(file) => files.push(PathBuf::from(file)),
+            None => files.push(log_file()),
+        }
+        files.extend(setting(&logging, "access_log").map(PathBuf::from));
+    }
+
+    let mut unique = Vec::new();
+    for file in files {
+        if !unique.contains(&file) {
+            unique.push(file);
+        }
+    }
+    unique
+}
+
+/// 📜 Print the last lines of the server's logs kept by `args`, then the new
+/// ones as they come when following
+fn show_logs(args: LogsArgs) {
+    let config = load_config();
+    let level = args.level.as_deref().map(|level| LogLevel::parse(level).unwrap_or_else(|| {
+        eprintln!("{}", format!("❌ Unknown level `{}`, use error, warn, info, debug or trace.", level).red());
+        std::process::exit(1);
+    }));
+    let filter = LogFilter { host: args.host.clone(), level };
+    let files: Vec<PathBuf> = log_files(&config, args.host.as_deref()).into_iter().filter(|file| file.is_file()).collect();
+    if files.is_empty() {
+        println!("⚠️ No log file found. Set `logging.file` in the configuration, or start the server with `localhost-cli start`.");
+        return;
+    }
+
+    let many = files.len() > 1;
+    let mut offsets = Vec::new();
+    for file in &files {
+        if many {
+            println!("{}", format!("==> {} <==", file.display()).bold());
+        }
+        // Only the lines kept count, so `-n` shows as many of them as it can
+        let content = String::from_utf8_lossy(&fs::read(file).unwrap_or_default()).into_owned();
+        let kept: Vec<&str> = content.lines().filter(|line| filter.matches(line)).collect();
+        for line in &kept[kept.len().saturating_sub(args.lines)..] {
+            println!("{}", colorize(line));
+        }
+        offsets.push(fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0));
+    }
+
+    if args.follow {
+        follow_logs(&files, offsets, &filter);
+    }
+}
+
+/// 👀 Print the lines appended to `files` after `offsets`, until interrupted.
+/// A file that shrank was rotated, and is read again from its start.
+fn follow_logs(files: &[PathBuf], mut offsets: Vec<u64>, filter: &LogFilter) -> ! {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut partial = vec![String::new(); files.len()];
+    loop {
+        for (i, file) in files.iter().enumerate() {
+            let Ok(mut handle) = fs::File::open(file) else { continue };
+            let len = handle.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            if len < offsets[i] {
+                offsets[i] = 0;
+                partial[i].clear();
+            }
+            if len == offsets[i] || handle.seek(SeekFrom::Start(offsets[i])).is_err() {
+                continue;
+            }
+            let mut added = Vec::new();
+            if handle.read_to_end(&mut added).is_err() {
+                continue;
+            }
+            offsets[i] += added.len() as u64;
+            partial[i].push_str(&String::from_utf8_lossy(&added));
+
+            // The last line is printed once complete
+            let complete = partial[i].rfind('\n').map(|end| partial[i].drain(..=end).collect::<String>());
+            for line in complete.iter().flat_map(|lines| lines.lines()) {
+                if filter.matches(line) {
+                    println!("{}", colorize(line));
+                }
+            }
+        }
+        thread::sleep(FOLLOW_INTERVAL);
     }
 }
 
@@ -317,6 +1472,14 @@ fn show_config() {
 fn main() {
     let cli = Cli::parse();
 
+    if let Some(config_path) = cli.config {
+        if config_path.extension().and_then(|e| e.to_str()) != Some("json") {
+            eprintln!("{}", "❌ localhost-cli only manages JSON configuration files".red());
+            std::process::exit(1);
+        }
+        let _ = CONFIG_PATH.set(config_path);
+    }
+
     match cli.command {
         Commands::Create(args) => {
             create_server(args);
@@ -330,5 +1493,226 @@ fn main() {
         Commands::Clean => {
             clean_json();
         }
+        Commands::Delete { name } => {
+            delete_server(&name);
+        }
+        Commands::Route { command } => match command {
+            RouteCommands::Add { server } => add_route(&server),
+            RouteCommands::Edit { server, path } => edit_route(&server, path),
+            RouteCommands::Remove { server, path } => remove_route(&server, path),
+        },
+        Commands::Start => start_server(),
+        Commands::Stop => {
+            stop_server();
+        }
+        Commands::Status => show_status(),
+        Commands::Restart => restart_server(),
+        Commands::Logs(args) => show_logs(args),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(servers: Value) -> Config {
+        serde_json::from_value(json!({ "servers": servers })).unwrap()
+    }
+
+    fn route(route: Value) -> Map<String, Value> {
+        serde_json::from_value(route).unwrap()
+    }
+
+    #[test]
+    fn test_routes_are_edited_only_on_servers_and_paths_that_exist() {
+        let mut config = config(json!([{ "server_name": "a.home", "routes": [{ "path": "/", "methods": ["GET"], "root": "a.home" }] }]));
+        let docs = route(json!({ "path": "/docs", "methods": ["GET", "HEAD"], "root": "a.home" }));
+
+        let error = insert_route(&mut config, "b.home", docs.clone()).unwrap_err();
+        assert!(error.contains("`b.home`"), "{}", error);
+        insert_route(&mut config, "a.home", docs.clone()).unwrap();
+        assert_eq!(config.servers[0].routes.len(), 2);
+        // Each path has one route
+        let error = insert_route(&mut config, "a.home", docs.clone()).unwrap_err();
+        assert!(error.contains("`/docs` already"), "{}", error);
+
+        // An edit can keep the path of the route it replaces, not take another's
+        let mut edited = docs.clone();
+        edited.insert("directory_listing".to_string(), Value::from(true));
+        replace_route(&mut config, "a.home", "/docs", edited).unwrap();
+        assert_eq!(config.servers[0].routes[1]["directory_listing"], json!(true));
+        assert!(replace_route(&mut config, "a.home", "/", docs.clone()).is_err());
+        assert!(replace_route(&mut config, "a.home", "/missing", docs).is_err());
+
+        let error = delete_route(&mut config, "a.home", "/missing").unwrap_err();
+        assert!(error.contains("no route on `/missing`"), "{}", error);
+        assert!(delete_route(&mut config, "b.home", "/docs").is_err());
+        assert_eq!(config.servers[0].routes.len(), 2);
+        assert_eq!(route_path(&delete_route(&mut config, "a.home", "/docs").unwrap()), "/docs");
+        assert_eq!(config.servers[0].routes.len(), 1);
+    }
+
+    #[test]
+    fn test_routes_are_refused_without_supported_methods() {
+        let mut config = config(json!([{ "server_name": "a.home" }]));
+        let error = insert_route(&mut config, "a.home", route(json!({ "path": "/", "methods": ["GET", "FETCH"], "root": "a.home" }))).unwrap_err();
+        assert!(error.contains("'FETCH'"), "{}", error);
+        assert!(insert_route(&mut config, "a.home", route(json!({ "path": "/", "methods": [], "root": "a.home" }))).is_err());
+        assert!(insert_route(&mut config, "a.home", route(json!({ "path": "docs", "methods": ["GET"], "root": "a.home" }))).is_err());
+        assert!(config.servers[0].routes.is_empty());
+        for method in ALLOWED_HTTP_METHODS {
+            let route = route(json!({ "path": format!("/{}", method.to_lowercase()), "methods": [method], "root": "a.home" }));
+            insert_route(&mut config, "a.home", route).unwrap();
+        }
+        assert_eq!(config.servers[0].routes.len(), ALLOWED_HTTP_METHODS.len());
+    }
+
+    #[test]
+    fn test_deleting_a_server_cleans_up_after_it_only() {
+        let dir = std::env::temp_dir().join(format!("localhost-cli-delete-{}", uuid::Uuid::new_v4()));
+        for site in ["a.home", "b.home"] {
+            fs::create_dir_all(dir.join("sites").join(site)).unwrap();
+            fs::write(dir.join("sites").join(site).join("index.html"), site).unwrap();
+        }
+        let config_path = dir.join("config.json");
+        let servers = json!([
+            { "server_address": "127.0.0.1", "server_name": "a.home", "ports": ["8080"] },
+            { "server_address": "127.0.0.1", "server_name": "b.home", "ports": ["8081"] },
+        ]);
+        fs::write(&config_path, json!({ "servers": servers }).to_string()).unwrap();
+
+        let mut config = read_config(&config_path).unwrap();
+        let site = dir.join("sites").join("a.home");
+        let removal = remove_server(&mut config, "a.home", Some(&site)).unwrap();
+        write_config(&config, &config_path).unwrap();
+        assert!(matches!(removal.site, Some(Ok(()))));
+
+        let config = read_config(&config_path).unwrap();
+        let names: Vec<&str> = config.servers.iter().map(|server| server.server_name.as_str()).collect();
+        assert_eq!(names, ["b.home"]);
+        assert!(!site.exists());
+        assert!(dir.join("sites").join("b.home").join("index.html").is_file());
+
+        // Nothing changes for a server that isn't there
+        let mut config = read_config(&config_path).unwrap();
+        assert!(remove_server(&mut config, "a.home", None).is_err());
+        assert_eq!(config.servers.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_site_templates_have_valid_routes_and_their_scripts() {
+        for template in SiteTemplate::value_variants() {
+            let routes = template.routes("a.home");
+            assert!(routes.iter().any(|route| route_path(route) == "/"), "{:?}", template);
+            for route in &routes {
+                assert_eq!(route_errors(route), Vec::<String>::new(), "{:?} {}", template, route_path(route));
+                // The scripts the routes run are in the skeleton
+                if let Some(script) = route.get("cgi").and_then(|cgi| cgi["script_file_name"].as_str()) {
+                    let file = format!("cgi-bin/{}", script);
+                    assert!(template.files().iter().any(|(path, _)| *path == file), "{:?} {}", template, file);
+                }
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("localhost-cli-scaffold-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "mine").unwrap();
+        let written = scaffold_site(SiteTemplate::Api, &dir, "a.home").unwrap();
+        assert_eq!(written, [dir.join("cgi-bin/api.py")]);
+        // Files already there are kept
+        assert_eq!(fs::read_to_string(dir.join("index.html")).unwrap(), "mine");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::create_dir_all(&dir);
+        scaffold_site(SiteTemplate::Static, &dir, "a.home").unwrap();
+        let index = fs::read_to_string(dir.join("index.html")).unwrap();
+        assert!(index.contains("<title>a.home</title>") && !index.contains("{{"), "{}", index);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ports_taken_by_servers_or_programs_are_skipped() {
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+        let config = config(json!([
+            { "server_address": "127.0.0.1", "server_name": "a.home", "ports": [(busy_port + 1).to_string()] },
+            { "server_name": "b.home", "listen": [format!("0.0.0.0:{}", busy_port + 2), "unix:/tmp/b.sock"] },
+        ]));
+        let addr = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+
+        assert_eq!(port_conflict(&config, addr(busy_port)), Some(PortConflict::Unavailable(io::ErrorKind::AddrInUse)));
+        assert_eq!(port_conflict(&config, addr(busy_port + 1)), Some(PortConflict::Configured("a.home".to_string())));
+        // Wildcard addresses take the port on every address
+        assert_eq!(port_conflict(&config, addr(busy_port + 2)), Some(PortConflict::Configured("b.home".to_string())));
+        assert_eq!(port_conflict(&config, SocketAddr::from(([127, 0, 0, 2], busy_port + 1))), None);
+
+        // Ports the new server already lists aren't suggested either
+        assert_eq!(next_free_port(&config, addr(busy_port), &[busy_port + 3]), Some(busy_port + 4));
+    }
+
+    #[test]
+    fn test_servers_set_up_by_the_wizard_are_accepted_by_the_server() {
+        let dir = std::env::temp_dir().join(format!("localhost-cli-wizard-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = config(json!([]));
+        let settings = HostSettings { client_max_body_size: "512K".to_string(), session_max_age: Some(3600), error_page: true };
+        for (name, routes) in [("a.home", vec![default_route("a.home")]), ("b.home", SiteTemplate::Spa.routes("b.home"))] {
+            let mut server: ServerConfig = serde_json::from_value(json!({ "server_address": "127.0.0.1", "server_name": name, "ports": ["8080"] })).unwrap();
+            server.routes = routes;
+            settings.apply(&mut server, &dir.join(name));
+            config.servers.push(server);
+        }
+        HostSettings::default().apply(&mut config.servers[1], &dir.join("b.home"));
+        let path = dir.join("config.json");
+        write_config(&config, &path).unwrap();
+
+        let issues = localhost::config::config::ServerConfig::check(&path);
+        assert!(issues.iter().all(|issue| !issue.is_critical()), "{:?}", issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>());
+        assert_eq!(config.servers[0].other["session"]["options"]["max_age"], json!(3600));
+        assert_eq!(config.servers[0].other["error_pages"]["custom_pages"]["404"], json!(dir.join("a.home/error/404.html").display().to_string()));
+        assert_eq!(config.servers[1].other["client_max_body_size"], json!("10M"));
+
+        assert!(is_size("10M") && is_size("512k") && is_size("2048"));
+        assert!(!is_size("M") && !is_size("10 MB") && !is_size(""));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_log_lines_are_filtered_by_host_and_level() {
+        let warnings = LogFilter { host: None, level: LogLevel::parse("warn") };
+        assert!(warnings.matches("2026-01-02 10:00:00.000 ERROR          Server │ Failed to bind"));
+        assert!(warnings.matches("2026-01-02 10:00:00.000 WARN           Config │ Unused setting"));
+        assert!(!warnings.matches("2026-01-02 10:00:00.000 INFO           Server │ Starting server..."));
+        assert!(warnings.matches(r#"{"time":"2026-01-02T10:00:00+00:00","level":"ERROR","module":"Server","host":null,"message":"down"}"#));
+        assert!(!warnings.matches(r#"{"time":"2026-01-02T10:00:00+00:00","level":"DEBUG","module":"Server","host":null,"message":"tick"}"#));
+        // Requests take the level of their status
+        assert!(warnings.matches(r#"127.0.0.1 - - [02/Jan/2026:10:00:00 +0000] "GET /missing" 404 120 "a.home""#));
+        assert!(!warnings.matches(r#"127.0.0.1 - - [02/Jan/2026:10:00:00 +0000] "GET /" 200 512 "a.home""#));
+        assert!(warnings.matches(r#"{"time":"2026-01-02T10:00:00+00:00","client":null,"host":"a.home","method":"GET","uri":"/","status":502,"bytes":0}"#));
+        // Lines without a level, like the banner, are kept
+        assert!(warnings.matches("║   🚀 Server Initialization Details"));
+
+        let host = LogFilter { host: Some("a.home".to_string()), level: None };
+        assert!(host.matches(r#"127.0.0.1 - - [02/Jan/2026:10:00:00 +0000] "GET /" 200 512 "a.home""#));
+        assert!(!host.matches(r#"127.0.0.1 - - [02/Jan/2026:10:00:00 +0000] "GET /" 200 512 "b.home""#));
+        assert!(host.matches(r#"{"level":"INFO","module":"Server","host":"a.home","message":"up"}"#));
+        assert!(!host.matches(r#"{"level":"INFO","module":"Server","host":null,"message":"a.home is up"}"#));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_pid_files_of_servers_gone_are_removed() {
+        let path = std::env::temp_dir().join(format!("localhost-cli-{}.pid", uuid::Uuid::new_v4()));
+        fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(running_pid(&path), Some(std::process::id() as libc::pid_t));
+        assert!(path.exists());
+
+        let mut exited = Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        fs::write(&path, exited.id().to_string()).unwrap();
+        assert_eq!(running_pid(&path), None);
+        assert!(!path.exists());
+
+        fs::write(&path, "not a pid").unwrap();
+        assert_eq!(running_pid(&path), None);
+        assert_eq!(running_pid(&path), None);
+    }
+}
@@ -0,0 +1,28 @@
+#!/usr/bin/env python3
+"""Answers GET /api/hello?name=you with JSON"""
+import json
+import os
+import sys
+from urllib.parse import parse_qs, urlsplit
+
+
+def respond(status, data):
+    body = json.dumps(data)
+    headers = [f"Status: {status}", "Content-Type: application/json", f"Content-Length: {len(body.encode())}"]
+    # The server expects a blank line made of CRLFs after the headers
+    sys.stdout.write("\r\n".join(headers) + "\r\n\r\n" + body)
+
+
+def main():
+    method = os.environ.get("REQUEST_METHOD", "GET")
+    if method != "GET":
+        respond(405, {"error": f"{method} is not supported"})
+        return
+    # The query string comes with the request URI
+    query = urlsplit(os.environ.get("SCRIPT_NAME", "")).query or os.environ.get("QUERY_STRING", "")
+    name = parse_qs(query).get("name", ["world"])[0]
+    respond(200, {"message": f"Hello, {name}!"})
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,21 @@
+#!/usr/bin/env python3
+"""Greets the name sent by the form of index.html"""
+import html
+import os
+import sys
+from urllib.parse import parse_qs, urlsplit
+
+
+def main():
+    # The query string comes with the request URI
+    query = urlsplit(os.environ.get("SCRIPT_NAME", "")).query or os.environ.get("QUERY_STRING", "")
+    name = html.escape(parse_qs(query).get("name", ["stranger"])[0])
+
+    body = f"<!DOCTYPE html><html><body><h1>Hello, {name}!</h1><p><a href=\"/\">Back</a></p></body></html>"
+    # The server expects a blank line made of CRLFs after the headers
+    headers = ["Content-Type: text/html; charset=utf-8", f"Content-Length: {len(body.encode())}"]
+    sys.stdout.write("\r\n".join(headers) + "\r\n\r\n" + body)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,23 @@
+// Pages rendered in the browser. The server answers index.html for any path
+// without an extension, so reloading /about works too.
+const pages = {
+    "/": () => "<h1>{{server_name}}</h1><p>This page was rendered by <code>app.js</code>.</p>",
+    "/about": () => "<h1>About</h1><p>Add pages to <code>pages</code> in <code>app.js</code>.</p>",
+};
+
+function render() {
+    const page = pages[location.pathname] || (() => "<h1>Not found</h1>");
+    document.getElementById("app").innerHTML = page();
+}
+
+document.addEventListener("click", (event) => {
+    const link = event.target.closest("a[data-link]");
+    if (link) {
+        event.preventDefault();
+        history.pushState(null, "", link.getAttribute("href"));
+        render();
+    }
+});
+
+window.addEventListener("popstate", render);
+render();
@@ -6,23 +6,51 @@ use localhost::http;
 use localhost::config;
 
 use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::Local;
+use serde_json::Value;
 
 use server::errors::ServerError;
 use server::server::Server;
 use crate::server::host::Host;
-use crate::server::static_files::{ErrorPages, ServerStaticFiles};
-use crate::server::uploader::Uploader;
+use crate::server::static_files::{sites_dir, ErrorPages, ServerStaticFiles};
+use crate::server::uploader::{Uploader, UploadQuota};
+use crate::server::file_types::{set_type_overrides, UploadTypePolicy};
+use crate::server::upload_validator::{ClamAvAddress, ClamAvValidator, MaxSizeValidator, UploadValidator};
 use crate::server::route::{Route, RouteMatcher};
+use crate::server::middleware::Middlewares;
+use crate::server::auth::BasicAuth;
+use crate::server::digest::{DigestAlgorithm, DigestAuth, DEFAULT_NONCE_TTL};
+use crate::server::jwt::JwtAuth;
+use crate::server::access::AccessList;
+use crate::server::rate_limit::{parse_rate, LimitKey, RateLimiter};
+use crate::server::ban;
+use crate::server::forwarded;
+use crate::server::standard_headers;
 use crate::server::cgi::CGIConfig;
-use crate::server::logger::{Logger, LogLevel};
-use crate::config::config::ServerConfig;
+use crate::server::handlers::handlers::RouteHandler;
+use crate::server::templates::{TemplateData, TemplateHandler};
+use crate::server::tls;
+use crate::server::hosts_file::HostsFile;
+use crate::server::plugin;
+use crate::server::rewrite::RewriteRule;
+use crate::server::logger::{self, configure, logger, HostLogs, LogFormat, LogLevel, LogSettings, LogTarget, Rotation};
+use crate::server::notifier::{Notifier, Thresholds, Webhook, WebhookFormat};
+use crate::server::syslog::{parse_facility, SyslogAddress, DEFAULT_FACILITY, DEFAULT_SYSLOG_SOCKET};
+use crate::config::config::{parse_duration, AuthBasicConfig, AuthDigestConfig, BanConfig, CgiConfig, ConfigError, LimitConfig, LoggingConfig, NotifyConfig, ProxyConfig, ServerConfig, TemplateConfig, UploadQuotaConfig, UploadValidatorConfig};
 use crate::server::session::session::{MemorySessionStore, SessionManager};
 use crate::http::request::HttpMethod;
+use crate::http::header::Header;
+use crate::http::body::Body;
+use crate::http::response::Response;
+use crate::http::status::HttpStatusCode;
+use crate::server::listen::ListenAddr;
+use localhost::testing::{LoadTest, TempDir};
+use localhost::{HostBuilder, ServerBuilder};
 
 
 
@@ -41,10 +69,11 @@ const BANNER: &str = r#"
 ║   • Environment:   {environment}                                            ║
 ║   • Hosts:         {host_count}                                                      ║
 ║   • Upload Dir:    {upload_dir}                                         ║
+║   • Config:        {config_path}                                         ║
 ╚═══════════════════════════════════════════════════════════════════════════╝
 "#;
 
-fn display_banner(host_count: usize, upload_dir: &str, warn: bool) {
+fn display_banner(host_count: usize, upload_dir: &str, config_path: &Path, warn: bool) {
     let current_time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
     let environment = option_env!("ENV").unwrap_or("Development");
     let mode = if warn { "Debug" } else { "Release" };
@@ -53,35 +82,250 @@ fn display_banner(host_count: usize, upload_dir: &str, warn: bool) {
         .replace("{current_time}", &current_time)
         .replace("{environment}", environment)
         .replace("{host_count}", &host_count.to_string())
-        .replace("{upload_dir}", upload_dir);
+        .replace("{upload_dir}", upload_dir)
+        .replace("{config_path}", &config_path.display().to_string());
 
     println!("{}", banner);
 }
 
-fn update_hosts_file(server_name: &str, ip_address: &str) -> Result<(), std::io::Error> {
-    let hosts_path = "/etc/hosts";
-    let hosts_file = OpenOptions::new().read(true).write(true).open(hosts_path)?;
-    let logger = Logger::new(LogLevel::INFO);
+/// Names `server_name` in the hosts file, the host being served anyway when
+/// the file can't be edited
+fn add_hosts_entry(hosts_file: &HostsFile, server_name: &str, ip_address: &str) {
+    let logger = logger();
+    let path = hosts_file.path().display();
+    match hosts_file.add(server_name, ip_address) {
+        Ok(true) => logger.info(&format!("Added '{}' to {} with IP address '{}'", server_name, path, ip_address), "INIT"),
+        Ok(false) => logger.warn(&format!("The entry '{}' already exists in {}", server_name, path), "INIT"),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => logger.warn(
+            &format!("Cannot edit {} without root, add '{} {}' to it yourself", path, ip_address, server_name),
+            "INIT",
+        ),
+        Err(e) => logger.error(&format!("Cannot add '{}' to {}: {}", server_name, path, e), "INIT"),
+    }
+}
 
-    let reader = BufReader::new(&hosts_file);
-    let entry_exists = reader
-        .lines()
-        .filter_map(Result::ok)
-        .any(|line| line.contains(server_name));
+/// Takes the entries the server added out of the hosts file
+fn remove_hosts_entries(hosts_file: &HostsFile) {
+    let logger = logger();
+    match hosts_file.remove_added() {
+        Ok(names) if !names.is_empty() => logger.info(&format!("Removed {} from {}", names.join(", "), hosts_file.path().display()), "Server"),
+        Ok(_) => {}
+        Err(e) => logger.error(&format!("Cannot remove the entries added to {}: {}", hosts_file.path().display(), e), "Server"),
+    }
+}
 
-    if !entry_exists {
-        let mut file = OpenOptions::new().append(true).open(hosts_path)?;
-        writeln!(file, "{}      {}", ip_address, server_name)?;
-        logger.info(&format!("Added '{}' to /etc/hosts with IP address '{}'", server_name, ip_address), "INIT");
+/// Resolves a path of the config, relative ones being under the sites directory
+fn resolve_site_path(dir: &str) -> PathBuf {
+    let path = Path::new(dir);
+    if path.is_absolute() {
+        path.to_path_buf()
     } else {
-        logger.warn(&format!("The entry '{}' already exists in /etc/hosts", server_name), "INIT");
+        Path::new(&sites_dir()).join(path)
+    }
+}
+
+/// Checks credentials against the users file of `config`, or the users it lists
+fn basic_auth(config: &AuthBasicConfig, default_realm: &str) -> Arc<BasicAuth> {
+    let realm = config.realm.as_deref().unwrap_or(default_realm);
+    match &config.users_file {
+        Some(users_file) => Arc::new(BasicAuth::new(realm, resolve_site_path(users_file))),
+        None => Arc::new(BasicAuth::with_users(realm, config.users.clone().unwrap_or_default())),
+    }
+}
+
+fn digest_auth(config: &AuthDigestConfig) -> Arc<DigestAuth> {
+    Arc::new(DigestAuth::new(
+        config.realm.as_deref().unwrap_or("Restricted"),
+        resolve_site_path(&config.users_file),
+        config.algorithm.as_deref().and_then(DigestAlgorithm::parse).unwrap_or_default(),
+        config.nonce_ttl.as_deref().and_then(parse_duration).unwrap_or(DEFAULT_NONCE_TTL),
+    ))
+}
+
+/// Runs the script of `config`, found in the `cgi-bin` directory of the site
+fn cgi_config(config: CgiConfig, root_dir: &str) -> CGIConfig {
+    let script_path = format!("{}/{}/cgi-bin/{}", sites_dir(), root_dir, config.script_file_name);
+    let mut cgi_config = CGIConfig::new(script_path)
+        .with_extensions(config.allowed_extensions())
+        .with_env(config.env.unwrap_or_default());
+    if let Some(interpreter) = config.interpreter {
+        cgi_config = cgi_config.with_interpreter(interpreter);
+    }
+    if let Some(timeout) = config.timeout_secs {
+        cgi_config = cgi_config.with_timeout(Duration::from_secs(timeout));
     }
+    cgi_config
+}
 
-    Ok(())
+/// Renders the template of `config`, its files being in the site
+fn template_handler(config: TemplateConfig, root_dir: &str) -> Result<TemplateHandler, ServerError> {
+    let site = Path::new(&sites_dir()).join(root_dir);
+    let data = match (config.data, config.data_cgi) {
+        (Some(file), _) => TemplateData::File(site.join(file)),
+        (None, Some(cgi)) => TemplateData::Cgi(cgi_config(cgi, root_dir)),
+        (None, None) => TemplateData::None,
+    };
+    let handler = TemplateHandler::new(site.join(&config.file), data)?;
+    match config.partials {
+        Some(partials) => handler.with_partials(site.join(partials)),
+        None => Ok(handler),
+    }
 }
 
-fn sites_dir() -> String {
-    format!("{}/.cargo/localhost-cli/sites", env!("HOME"))
+fn upload_quota(config: UploadQuotaConfig) -> UploadQuota {
+    UploadQuota {
+        max_bytes: config.max_total_size.map(|size| convert_m_or_k(Some(size)) as u64),
+        max_files: config.max_files,
+        session_max_bytes: config.session_max_total_size.map(|size| convert_m_or_k(Some(size)) as u64),
+        session_max_files: config.session_max_files,
+    }
+}
+
+/// Builds a type policy when any list is set, taking the others from `parent`
+fn upload_type_policy(
+    parent: &UploadTypePolicy,
+    allowed_types: Option<Vec<String>>,
+    denied_types: Option<Vec<String>>,
+    allowed_extensions: Option<Vec<String>>,
+    denied_extensions: Option<Vec<String>>,
+) -> Option<UploadTypePolicy> {
+    if allowed_types.is_none() && denied_types.is_none() && allowed_extensions.is_none() && denied_extensions.is_none() {
+        return None;
+    }
+
+    Some(UploadTypePolicy {
+        allowed_types: allowed_types.unwrap_or_else(|| parent.allowed_types.clone()),
+        denied_types: denied_types.unwrap_or_else(|| parent.denied_types.clone()),
+        allowed_extensions: allowed_extensions.or_else(|| parent.allowed_extensions.clone()),
+        denied_extensions: denied_extensions.unwrap_or_else(|| parent.denied_extensions.clone()),
+    })
+}
+
+/// Builds a dedicated uploader when any upload setting is set, taking the rest from `parent`
+fn configure_uploader(
+    parent: &Uploader,
+    dir: Option<String>,
+    type_policy: Option<UploadTypePolicy>,
+    deduplicate: Option<bool>,
+    quota: Option<UploadQuota>,
+    ttl: Option<String>,
+    validators: Option<Vec<UploadValidatorConfig>>,
+    max_file_size: Option<String>,
+) -> Option<Uploader> {
+    let ttl = ttl.and_then(|ttl| parse_duration(&ttl));
+    if dir.is_none() && type_policy.is_none() && deduplicate.is_none() && quota.is_none() && ttl.is_none() && validators.is_none()
+        && max_file_size.is_none() {
+        return None;
+    }
+
+    let upload_dir = dir.map(|d| resolve_site_path(&d)).unwrap_or_else(|| parent.upload_dir.clone());
+    let type_policy = type_policy.unwrap_or_else(|| parent.type_policy.clone());
+    let mut uploader = Uploader::new(upload_dir)
+        .with_type_policy(type_policy)
+        .with_deduplication(deduplicate.unwrap_or(parent.deduplicate))
+        .with_quota(quota.or_else(|| parent.quota.clone()))
+        .with_ttl(ttl.or(parent.ttl))
+        .with_max_file_size(max_file_size.map(|size| convert_m_or_k(Some(size))).or(parent.max_file_size));
+    uploader.validators = validators
+        .map(|configs| configs.into_iter().map(upload_validator).collect())
+        .unwrap_or_else(|| parent.validators.clone());
+    Some(uploader)
+}
+
+fn upload_validator(config: UploadValidatorConfig) -> Arc<dyn UploadValidator> {
+    match config {
+        UploadValidatorConfig::MaxSize { size } => Arc::new(MaxSizeValidator {
+            max_size: convert_m_or_k(Some(size)) as u64,
+        }),
+        UploadValidatorConfig::Clamav { socket, address, timeout } => {
+            let address = match (socket, address) {
+                (Some(socket), _) => ClamAvAddress::Unix(PathBuf::from(socket)),
+                (None, address) => ClamAvAddress::Tcp(address.unwrap_or_else(|| "127.0.0.1:3310".to_string())),
+            };
+            let validator = ClamAvValidator::new(address);
+            Arc::new(match timeout.and_then(|t| parse_duration(&t)) {
+                Some(timeout) => validator.with_timeout(timeout),
+                None => validator,
+            })
+        }
+    }
+}
+
+/// Turns an `add_headers` block into headers, sorted so responses are stable
+fn added_headers(config: Option<HashMap<String, String>>) -> Vec<Header> {
+    let mut headers: Vec<_> = config.unwrap_or_default().into_iter().collect();
+    headers.sort();
+    headers.iter().map(|(name, value)| Header::from_str(name, value)).collect()
+}
+
+fn rate_limiter(name: &str, config: &LimitConfig) -> Option<Arc<RateLimiter>> {
+    let key = match config.key.as_deref() {
+        Some(key) => LimitKey::parse(key).ok()?,
+        None => LimitKey::Ip,
+    };
+    let rate = parse_rate(&config.rate)?;
+    Some(Arc::new(RateLimiter::new(name, rate, config.burst.unwrap_or(0), key)))
+}
+
+fn log_settings(config: &ServerConfig) -> LogSettings {
+    let defaults = LogSettings::default();
+    let hosts = config.servers.iter()
+        .filter(|host| host.access_log.is_some() || host.error_log.is_some())
+        .map(|host| {
+            let (access_log, error_log) = host.log_paths();
+            (host.server_name.clone().unwrap_or_default(), HostLogs { access_log, error_log })
+        })
+        .collect();
+    // RUST_LOG takes precedence over the config file
+    let env_level = std::env::var("RUST_LOG").ok().and_then(|filter| LogLevel::from_filter(&filter));
+    let Some(logging) = &config.logging else {
+        return LogSettings { level: env_level.unwrap_or(defaults.level), hosts, ..defaults };
+    };
+    LogSettings {
+        level: env_level
+            .or_else(|| logging.level.as_deref().and_then(LogLevel::parse))
+            .unwrap_or(defaults.level),
+        format: logging.format.as_deref().and_then(LogFormat::parse).unwrap_or(defaults.format),
+        file: logging.file.as_ref().map(PathBuf::from),
+        access_log: logging.access_log.as_ref().map(PathBuf::from),
+        audit_log: logging.audit_log.as_ref().map(PathBuf::from),
+        rotate: logging.rotate.as_ref().map(|rotate| Rotation {
+            max_size: convert_m_or_k(Some(rotate.max_size.clone())) as u64,
+            keep: rotate.keep.unwrap_or(5),
+        }),
+        hosts,
+        target: log_target(logging),
+    }
+}
+
+fn log_target(logging: &LoggingConfig) -> LogTarget {
+    let syslog = logging.syslog.as_ref();
+    let app_name = syslog.and_then(|syslog| syslog.app_name.clone()).unwrap_or_else(|| "localhost".to_string());
+    match logging.target.as_deref().map(str::trim) {
+        Some("syslog") => LogTarget::Syslog {
+            address: syslog.and_then(|syslog| syslog.address.as_deref())
+                .and_then(|address| SyslogAddress::parse(address).ok())
+                .unwrap_or_else(|| SyslogAddress::Unix(PathBuf::from(DEFAULT_SYSLOG_SOCKET))),
+            facility: syslog.and_then(|syslog| syslog.facility.as_deref())
+                .and_then(parse_facility)
+                .unwrap_or(DEFAULT_FACILITY),
+            app_name,
+        },
+        Some("journald") => LogTarget::Journald { identifier: app_name },
+        _ => LogTarget::Standard,
+    }
+}
+
+/// The notifier posting to the webhook of `config`
+fn notifier(config: &NotifyConfig) -> Result<Notifier, String> {
+    let webhook = Webhook::parse(&config.webhook)?;
+    let format = config.format.as_deref().and_then(WebhookFormat::parse).unwrap_or_default();
+    let thresholds = Thresholds {
+        error_percent: config.error_percent.unwrap_or(5),
+        min_requests: config.min_requests.unwrap_or(20),
+    };
+    let interval = config.interval.as_deref().and_then(parse_duration).unwrap_or(Duration::from_secs(300));
+    Notifier::new(webhook, format, thresholds, interval).map_err(|e| e.to_string())
 }
 
 fn convert_m_or_k(max_body_size: Option<String>) -> usize {
@@ -98,12 +342,447 @@ fn convert_m_or_k(max_body_size: Option<String>) -> usize {
     }
 }
 
+/// Value of `<flag> <value>` or `<flag>=<value>`
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == flag {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')).map(str::to_string)
+        }
+    })
+}
+
+/// Value of `--config <path>` or `--config=<path>`
+fn config_arg(args: &[String]) -> Option<String> {
+    flag_value(args, "--config")
+}
+
+/// The configuration to load: `--config`, then `LOCALHOST_CONFIG`, then the
+/// one of the CLI
+fn config_path(args: &[String]) -> PathBuf {
+    ServerConfig::resolve_path(config_arg(args).as_deref())
+}
+
+/// Validates the config without binding any port, nginx `-t` style
+fn check_config(config_path: &Path) -> ExitCode {
+    let issues = ServerConfig::check(config_path);
+    for issue in &issues {
+        eprintln!("{}", issue);
+    }
+
+    let critical = issues.iter().filter(|issue| issue.is_critical()).count();
+    let warnings = issues.len() - critical;
+    if critical > 0 {
+        eprintln!("Configuration {} is invalid: {} error(s), {} warning(s)", config_path.display(), critical, warnings);
+        ExitCode::FAILURE
+    } else {
+        println!("Configuration {} is valid: {} warning(s)", config_path.display(), warnings);
+        ExitCode::SUCCESS
+    }
+}
+
+/// Paths of the synthetic site `--selfcheck-bench` loads: a page, a larger
+/// file and a handler
+const BENCH_PATHS: [&str; 3] = ["/", "/large.txt", "/hello"];
+/// Size of the larger file of the synthetic site
+const BENCH_LARGE_FILE: usize = 64 * 1024;
+
+/// Options of `--selfcheck-bench`
+#[derive(Debug, PartialEq)]
+struct BenchOptions {
+    connections: usize,
+    duration: Duration,
+    paths: Vec<String>,
+    keep_alive: bool,
+}
+
+fn bench_options(args: &[String]) -> Result<BenchOptions, String> {
+    let connections = match flag_value(args, "--connections") {
+        Some(value) => value.parse::<usize>().ok().filter(|n| *n > 0)
+            .ok_or_else(|| format!("Invalid --connections '{}', expected a number of connections", value))?,
+        None => 16,
+    };
+    let duration = match flag_value(args, "--duration") {
+        Some(value) => parse_duration(&value).filter(|duration| !duration.is_zero())
+            .ok_or_else(|| format!("Invalid --duration '{}', expected a duration like 10s", value))?,
+        None => Duration::from_secs(10),
+    };
+    let paths = match flag_value(args, "--path") {
+        Some(path) => vec![path],
+        None => BENCH_PATHS.iter().map(|path| path.to_string()).collect(),
+    };
+    let keep_alive = !args.iter().any(|arg| arg == "--close");
+    Ok(BenchOptions { connections, duration, paths, keep_alive })
+}
+
+/// Serves a synthetic site on a free port and loads it, without the config,
+/// so that measures compare from one change to the next
+fn self_bench(args: &[String]) -> ExitCode {
+    let options = match bench_options(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run_bench(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_bench(options: &BenchOptions) -> Result<(), ServerError> {
+    let site = TempDir::new().map_err(ServerError::IoError)?;
+    site.write("index.html", "<!DOCTYPE html><html><head><title>Bench</title></head><body><h1>Hello</h1></body></html>")
+        .map_err(ServerError::IoError)?;
+    site.write("large.txt", "x".repeat(BENCH_LARGE_FILE)).map_err(ServerError::IoError)?;
+
+    let host = HostBuilder::new("bench.local")
+        .listen("127.0.0.1:0")
+        .static_files("/", site.path(), Some("index.html"))
+        .handler("/hello", vec![HttpMethod::GET], RouteHandler::from_fn(|_, _| {
+            let body = Body::text("Hello, world!");
+            let length = body.body_len().to_string();
+            Ok(Response::new(HttpStatusCode::Ok, vec![
+                Header::from_str("content-type", "text/plain"),
+                Header::from_str("content-length", &length),
+            ], Some(body)))
+        }))
+        .build()?;
+    // Requests aren't logged, writing them would be most of what's measured
+    let server = ServerBuilder::new()
+        .host(host)
+        .logging(LogSettings { level: LogLevel::WARN, ..LogSettings::default() })
+        .spawn()?;
+    let addr = server.local_addrs().iter()
+        .find_map(|addr| match addr {
+            ListenAddr::Tcp(addr) => Some(*addr),
+            ListenAddr::Unix(_) => None,
+        })
+        .ok_or_else(|| ServerError::ConnectionError("The bench server listens on no TCP port".to_string()))?;
+
+    let connections = if options.keep_alive { "keep-alive" } else { "a connection per request" };
+    for path in &options.paths {
+        println!("GET {}: {} connections for {:?}, {}", path, options.connections, options.duration, connections);
+        let report = LoadTest::new(path)
+            .connections(options.connections)
+            .duration(options.duration)
+            .keep_alive(options.keep_alive)
+            .run(addr);
+        println!("{}\n", report);
+    }
+
+    server.shutdown();
+    server.wait()
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let config_path = config_path(&args);
+
+    if args.iter().any(|arg| arg == "--check" || arg == "-t") {
+        return check_config(&config_path);
+    }
+
+    if args.iter().any(|arg| arg == "--selfcheck-bench") {
+        return self_bench(&args);
+    }
+
+    let result = run(&args, config_path);
+    logger::flush();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Builds the hosts described by `server_config`, their listeners not bound
+/// yet, naming them in `hosts_file` when given
+fn build_hosts(server_config: ServerConfig, uploader: &Uploader, hosts_file: Option<&HostsFile>) -> Vec<Host> {
+    let limiters: HashMap<String, Arc<RateLimiter>> = server_config.limits.iter()
+        .filter_map(|(name, config)| Some((name.clone(), rate_limiter(name, config)?)))
+        .collect();
+
+    let mut hosts = Vec::new();
+    for host_config in server_config.servers {
+        let listen = host_config.listen_addrs();
+        let mut routes: Vec<Route> = Vec::new();
+        let mut error_pages: Option<ErrorPages> = None;
+        let max_request_size= convert_m_or_k(host_config.client_max_body_size); 
+        // The uploads block takes precedence over the flat upload keys
+        let uploads = host_config.uploads.unwrap_or_default();
+        let host_uploader = configure_uploader(
+            uploader,
+            uploads.dir.or(host_config.upload_dir),
+            upload_type_policy(
+                &uploader.type_policy,
+                uploads.allowed_types.or(host_config.allowed_upload_types),
+                host_config.denied_upload_types,
+                host_config.allowed_upload_extensions,
+                host_config.denied_upload_extensions,
+            ),
+            host_config.deduplicate_uploads,
+            uploads.quota.or(host_config.upload_quota).map(upload_quota),
+            host_config.upload_ttl,
+            host_config.upload_validators,
+            uploads.max_file_size,
+        );
+        let parent_uploader = host_uploader.as_ref().unwrap_or(uploader);
+        // Asked by the routes without credentials of their own, sharing the
+        // verified passwords and nonces
+        let host_basic = host_config.auth_basic.as_ref().map(|auth| basic_auth(auth, "Restricted"));
+        let host_digest = host_config.auth_digest.as_ref().map(digest_auth);
+
+        if let Some(tab_routes) = host_config.routes {
+            for r in tab_routes {
+                let methods = r.methods.iter()
+                    .flat_map(|v| v.iter().map(|m| HttpMethod::from_str(m)))
+                    .collect::<Vec<HttpMethod>>();
+
+                error_pages = if let Some(ref pages) = host_config.error_pages {
+                    Some(ErrorPages {
+                        custom_pages: pages.custom_pages.clone(),
+                    })
+                } else {
+                    None
+                };
+
+                let root_dir = r.root.clone().unwrap_or("".to_string());
+
+                let results = ServerStaticFiles::new(
+                    PathBuf::from(r.root.unwrap_or("".to_string())), r.default_page, r.directory_listing.unwrap_or(false), error_pages.clone());
+
+                let static_files = match results {
+                    Ok(files) => Some(files),
+                    Err(e) => {
+                        None
+                    }
+                    
+                };
+
+                let cgi_config = r.cgi.map(|cgi| cgi_config(cgi, &root_dir));
 
-fn main() -> Result<(), ServerError> {    
+                // Invalid templates are skipped, the route then serving its files
+                let handler = r.template.and_then(|template| match template_handler(template, &root_dir) {
+                    Ok(handler) => Some(RouteHandler::new(handler)),
+                    Err(e) => {
+                        logger().error(&format!("Skipping template of route {}: {}", r.path.as_deref().unwrap_or(""), e), "Template");
+                        None
+                    }
+                });
+
+                // Credentials are checked first, ahead of the middlewares
+                // registered in code. A route setting either scheme doesn't
+                // inherit the host's
+                let (auth_basic, auth_digest) = if r.auth_basic.is_some() || r.auth_digest.is_some() {
+                    (r.auth_basic.as_ref().map(|auth| basic_auth(auth, "Restricted")), r.auth_digest.as_ref().map(digest_auth))
+                } else {
+                    (host_basic.clone(), host_digest.clone())
+                };
+                let jwt = r.jwt.as_ref().and_then(|config| {
+                    // Invalid settings are reported by the config validation
+                    let auth = JwtAuth::new(config.key().ok()?, config.algorithms().ok()?).ok()?;
+                    Some(auth
+                        .with_issuer(config.issuer.clone())
+                        .with_audience(config.audience.clone())
+                        .with_scopes(config.scopes.clone().unwrap_or_default()))
+                });
+                let mut middlewares = Middlewares::default();
+                if let Some(auth) = auth_basic {
+                    middlewares.push(auth);
+                }
+                if let Some(auth) = auth_digest {
+                    middlewares.push(auth);
+                }
+                if let Some(auth) = jwt {
+                    middlewares.push(auth);
+                }
+
+                routes.push(Route { 
+                    path: r.path.clone().unwrap(), 
+                    methods , 
+                    static_files, 
+                    cgi_config,
+                    redirect: r.redirect.clone(), 
+                    session_required: r.session_required, 
+                    session_redirect: r.session_redirect.clone(),
+                    matcher: Some(RouteMatcher::from_path(r.path.unwrap().as_str())),
+                    params: HashMap::new(),
+                    max_body_size: r.client_max_body_size.map(|size| convert_m_or_k(Some(size))),
+                    uploader: configure_uploader(
+                        parent_uploader,
+                        r.upload_dir,
+                        upload_type_policy(
+                            &parent_uploader.type_policy,
+                            r.allowed_upload_types,
+                            r.denied_upload_types,
+                            r.allowed_upload_extensions,
+                            r.denied_upload_extensions,
+                        ),
+                        r.deduplicate_uploads,
+                        None,
+                        r.upload_ttl,
+                        r.upload_validators,
+                        None,
+                    ),
+                    headers: added_headers(r.add_headers),
+                    access: (r.allow.is_some() || r.deny.is_some()).then(|| {
+                        // Invalid entries are reported by the config validation, refuse everyone then
+                        AccessList::new(&r.allow.unwrap_or_default(), &r.deny.unwrap_or_default())
+                            .unwrap_or_else(|_| AccessList::deny_all())
+                    }),
+                    rate_limit: r.rate_limit.as_ref().and_then(|name| limiters.get(name).cloned()),
+                    admin: false,
+                    content_types: r.content_types.unwrap_or_default(),
+                    cors_allowed_headers: r.cors_allowed_headers.unwrap_or_default(),
+                    proxy: r.proxy.as_ref().and_then(ProxyConfig::proxy).map(Arc::new),
+                    // Other handlers, channels and middlewares are registered
+                    // in code, see Host::add_handler, Host::add_websocket,
+                    // Host::add_event_stream, Host::add_upgrade and
+                    // Host::add_middleware
+                    websocket: None,
+                    events: None,
+                    upgrade: None,
+                    handler,
+                    middlewares,
+                });
+            }
+        }
+
+        let session_manager = match host_config.session {
+            Some(config) => match &config.store {
+                Some(store) => match plugin::session_store(&store.name, store.settings.as_ref().unwrap_or(&Value::Null)) {
+                    Ok(store) => Some(SessionManager::with_shared_store(config, store)),
+                    Err(e) => {
+                        // Sessions kept in memory instead would be lost on restart
+                        logger().error(&format!("Host {} is not served: {}", host_config.server_name.as_deref().unwrap_or(""), e), "Plugin");
+                        continue;
+                    }
+                },
+                None => Some(SessionManager::new(config, MemorySessionStore::new())),
+            },
+            None => None,
+        };
+
+        let mut host = Host::new(
+            host_config.server_address.as_deref().unwrap_or(""),
+            host_config.server_name.as_deref().unwrap_or(""),
+            host_config.ports.unwrap_or_default(),
+            routes.clone(),
+            session_manager.clone(),
+            error_pages,
+            Some(max_request_size),
+        ).unwrap();
+
+        for addr in listen {
+            host.add_listen(addr);
+        }
+
+        if session_manager.is_some() {
+            host.add_session_api();
+        }
+
+        if let Some(admin) = &host_config.admin {
+            // Only the machine itself may reach the admin API unless told otherwise
+            let allow = admin.allow.clone().unwrap_or_else(|| vec!["127.0.0.1".to_string(), "::1".to_string()]);
+            let access = AccessList::new(&allow, &admin.deny.clone().unwrap_or_default())
+                .unwrap_or_else(|_| AccessList::deny_all());
+            let auth = admin.auth_basic.as_ref().map(|auth| basic_auth(auth, "Admin"));
+            host.add_admin_api(access, auth);
+        }
+
+        if host_config.health_check.unwrap_or(true) {
+            host.add_health_checks();
+        }
+
+        if let Some(host_uploader) = host_uploader {
+            host.set_uploader(host_uploader);
+        }
+
+        host.set_headers(added_headers(host_config.add_headers));
+
+        let rewrites = host_config.rewrite.iter().flatten()
+            .filter_map(|rule| RewriteRule::new(&rule.pattern, &rule.replacement, rule.flag.as_deref())
+                .map_err(|e| logger().error(&format!("Skipping rewrite of host {}: {}", host.server_name, e), "Config"))
+                .ok())
+            .collect();
+        host.set_rewrites(rewrites);
+
+        if let Some(limiter) = server_config.rate_limit.as_ref().and_then(|name| limiters.get(name)) {
+            host.set_rate_limit(limiter.clone());
+        }
+
+        if let Some(timeout) = host_config.request_timeout.as_deref().and_then(parse_duration) {
+            host.set_request_timeout(timeout);
+        }
+
+        if let Some(max) = host_config.keep_alive_requests {
+            host.set_keep_alive_requests(max);
+        }
+
+        if let Some(compression) = &host_config.compression {
+            host.set_compression(compression.settings());
+        }
+
+        if let Some(limits) = &host_config.header_limits {
+            host.set_header_limits(limits.settings());
+        }
+
+        for config in host_config.plugins.iter().flatten() {
+            if let Err(e) = plugin::install(&mut host, &config.name, config.settings.as_ref().unwrap_or(&Value::Null)) {
+                logger().error(&format!("Skipping plugin {} of host {}: {}", config.name, host.server_name, e), "Plugin");
+            }
+        }
+
+        if let Some(tls) = &host_config.tls {
+            match tls::server_config(&tls.cert_path(), &tls.key_path(), tls.min_version.as_deref(), tls.ciphers.as_deref()) {
+                Ok(config) => host.set_tls(config, tls.redirect_http.unwrap_or(false)),
+                Err(e) => {
+                    // Serving plain HTTP instead would expose what should be encrypted
+                    logger().error(&format!("Host {} is not served: {}", host.server_name, e), "TLS");
+                    continue;
+                }
+            }
+        }
+
+        let address = host_config.server_address.clone()
+            .or_else(|| host.listen.iter().find_map(|addr| addr.ip().filter(|ip| !ip.is_unspecified())).map(|ip| ip.to_string()));
+        if let (Some(hosts_file), Some(ip)) = (hosts_file, address) {
+            add_hosts_entry(hosts_file, host_config.server_name.as_deref().unwrap_or(""), &ip);
+        }
+
+        hosts.push(host);
+    }
+
+    hosts
+}
+
+/// Registers the plugins of the `plugin_libraries`, the ones already loaded
+/// being skipped
+fn load_plugin_libraries(config: &ServerConfig) {
+    for path in config.plugin_library_paths() {
+        #[cfg(feature = "dynamic-plugins")]
+        match plugin::load_library(&path) {
+            Ok(names) if names.is_empty() => {}
+            Ok(names) => logger().info(&format!("Loaded plugins {} from {}", names.join(", "), path.display()), "Plugin"),
+            Err(e) => logger().error(&format!("Cannot load plugins from {}: {}", path.display(), e), "Plugin"),
+        }
+        #[cfg(not(feature = "dynamic-plugins"))]
+        logger().error(&format!("Cannot load plugins from {}: built without the dynamic-plugins feature", path.display()), "Plugin");
+    }
+}
+
+fn run(args: &[String], config_path: PathBuf) -> Result<(), ServerError> {
     print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
     
     let mut active_warn_opt = false;
-    let args: Vec<String> = std::env::args().collect(); 
 
     if args.contains(&String::from("--warn")) {
         active_warn_opt = true;
@@ -112,105 +791,128 @@ fn main() -> Result<(), ServerError> {
     let uploader = Uploader::new(Path::new(&format!("{}/example/upload", sites_dir())).to_path_buf());
 
     let mut servers = Server::new(Some(uploader.clone())).unwrap();
-    let load_config = ServerConfig::load_and_validate(active_warn_opt);
+    let load_config = ServerConfig::load_and_validate_from(&config_path, active_warn_opt);
 
 
     let mut host_count = 0;
+    let mut hosts_file = None;
 
     match load_config {
         Ok(server_config) => {
-            for host_config in server_config.servers {
-                let mut routes: Vec<Route> = Vec::new();
-                let mut error_pages: Option<ErrorPages> = None;
-                let max_request_size= convert_m_or_k(host_config.client_max_body_size); 
-
-                if let Some(tab_routes) = host_config.routes {
-                    for r in tab_routes {
-                        let methods = r.methods.iter()
-                            .flat_map(|v| v.iter().map(|m| HttpMethod::from_str(m)))
-                            .collect::<Vec<HttpMethod>>();
-    
-                        error_pages = if let Some(ref pages) = host_config.error_pages {
-                            Some(ErrorPages {
-                                custom_pages: pages.custom_pages.clone(),
-                            })
-                        } else {
-                            None
-                        };
-
-                        let root_dir = r.root.clone().unwrap_or("".to_string());
-    
-                        let results = ServerStaticFiles::new(
-                            PathBuf::from(r.root.unwrap_or("".to_string())), r.default_page, r.directory_listing.unwrap_or(false), error_pages.clone());
-    
-                        let static_files = match results {
-                            Ok(files) => Some(files),
-                            Err(e) => {
-                                None
-                            }
-                            
-                        };
-    
-                        let cgi_config = 
-                        if let Some(cgi) = r.cgi {
-                                let script_path = format!("{}/{}/cgi-bin/{}", sites_dir(), root_dir, cgi.script_file_name);
-                                Some(CGIConfig::new(script_path))
-                            } else {
-                                None
-                            };
-    
-                        routes.push(Route { 
-                            path: r.path.clone().unwrap(), 
-                            methods , 
-                            static_files, 
-                            cgi_config,
-                            redirect: r.redirect.clone(), 
-                            session_required: r.session_required, 
-                            session_redirect: r.session_redirect.clone(),
-                            matcher: Some(RouteMatcher::from_path(r.path.unwrap().as_str())),
-                            params: HashMap::new(),
-                        });
+            configure(log_settings(&server_config))
+                .map_err(|e| ServerError::ConfigError(ConfigError::Critical(format!("Cannot set up logging: {}", e))))?;
+            set_type_overrides(&server_config.types);
+            ban::configure(server_config.ban.as_ref().map(BanConfig::policy));
+            forwarded::configure(server_config.trusted_networks());
+            standard_headers::configure(server_config.server_header.clone());
+            if let Some(notify) = &server_config.notify {
+                match notifier(notify) {
+                    Ok(notifier) => servers.set_notifier(notifier),
+                    Err(e) => logger().error(&format!("Webhook notifications disabled: {}", e), "Notifier"),
+                }
+            }
+            load_plugin_libraries(&server_config);
+            // Applied on restart, like the webhook
+            hosts_file = server_config.managed_hosts_file().map(|path| Arc::new(HostsFile::new(path)));
+            let mut current = server_config.clone();
+            for host in build_hosts(server_config, &uploader, hosts_file.as_deref()) {
+                if let Err(e) = servers.add_host(host) {
+                    if let Some(hosts_file) = &hosts_file {
+                        remove_hosts_entries(hosts_file);
                     }
+                    return Err(e);
                 }
-
-                let session_manager = if let Some(config) = host_config.session {
-                        Some(SessionManager::new(config, Box::new(MemorySessionStore::new())))
-                } else {
-                    None
-                };
-                
-
-                let mut host = Host::new(
-                    host_config.server_address.as_deref().unwrap_or(""),
-                    host_config.server_name.as_deref().unwrap_or(""),
-                    host_config.ports.unwrap_or_default(),
-                    routes.clone(),
-                    session_manager.clone(),
-                    error_pages,
-                    Some(max_request_size),
-                ).unwrap();
-
-                if session_manager.is_some() {
-                    host.add_session_api();
+                host_count += 1;
+            }
+
+            // SIGHUP applies the changes of the config file to the running server
+            let reload_uploader = uploader.clone();
+            let reload_path = config_path.clone();
+            let reload_hosts_file = hosts_file.clone();
+            servers.set_reloader(Box::new(move || {
+                let config = ServerConfig::load_and_validate_from(&reload_path, active_warn_opt)
+                    .map_err(ServerError::ConfigError)?;
+                if let Err(e) = configure(log_settings(&config)) {
+                    logger().error(&format!("Cannot set up logging, keeping the current one: {}", e), "Reload");
                 }
-
-                if let Some(ip) = host_config.server_address {
-                    update_hosts_file(host_config.server_name.as_deref().unwrap_or(""), &ip).unwrap();
+                set_type_overrides(&config.types);
+                ban::configure(config.ban.as_ref().map(BanConfig::policy));
+                forwarded::configure(config.trusted_networks());
+                standard_headers::configure(config.server_header.clone());
+                load_plugin_libraries(&config);
+                let logger = logger();
+                let changes = current.diff(&config);
+                if changes.is_empty() {
+                    logger.info("Configuration unchanged", "Reload");
                 }
-
-                let _ = servers.add_host(host);
-                host_count += 1;
-
-            }   
+                for change in changes {
+                    logger.info(&change, "Reload");
+                }
+                current = config.clone();
+                Ok(build_hosts(config, &reload_uploader, reload_hosts_file.as_deref()))
+            }));
 
 
  
-            display_banner(host_count, &uploader.get_upload_dir(), active_warn_opt);
+            display_banner(host_count, &uploader.get_upload_dir(), &config_path, active_warn_opt);
         }
         Err(e) => {
             return Err(ServerError::ConfigError(e));
         }  
     }
 
-    servers.run()
-}
\ No newline at end of file
+    servers.stop_on_signals();
+    let result = servers.run();
+    if let Some(hosts_file) = &hosts_file {
+        remove_hosts_entries(hosts_file);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use localhost::config::config::CONFIG_ENV_VAR;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // The one test of this binary setting the variable, so others don't see it
+    #[test]
+    fn test_config_flag_comes_before_the_environment_and_the_default() {
+        std::env::set_var(CONFIG_ENV_VAR, "/srv/env.yaml");
+        assert_eq!(config_path(&args(&["localhost-server", "--config", "/srv/flag.json"])), PathBuf::from("/srv/flag.json"));
+        assert_eq!(config_path(&args(&["localhost-server", "-t", "--config=/srv/flag.toml"])), PathBuf::from("/srv/flag.toml"));
+        assert_eq!(config_path(&args(&["localhost-server", "-t"])), PathBuf::from("/srv/env.yaml"));
+
+        // An empty variable counts as unset
+        std::env::set_var(CONFIG_ENV_VAR, "");
+        assert_eq!(config_path(&args(&["localhost-server"])), ServerConfig::default_path());
+        std::env::remove_var(CONFIG_ENV_VAR);
+        let default = config_path(&args(&["localhost-server"]));
+        assert_eq!(default, ServerConfig::default_path());
+        assert!(default.parent().unwrap().ends_with(".cargo/localhost-cli"), "{}", default.display());
+        assert_eq!(config_path(&args(&["localhost-server", "--config", "site.yml"])), PathBuf::from("site.yml"));
+    }
+
+    #[test]
+    fn test_bench_options_default_to_every_path_kept_alive() {
+        let options = bench_options(&args(&["localhost-server", "--selfcheck-bench"])).unwrap();
+        assert_eq!(options, BenchOptions {
+            connections: 16,
+            duration: Duration::from_secs(10),
+            paths: BENCH_PATHS.iter().map(|path| path.to_string()).collect(),
+            keep_alive: true,
+        });
+
+        let options = bench_options(&args(&["localhost-server", "--selfcheck-bench", "--connections", "64", "--duration=2m", "--path", "/hello", "--close"])).unwrap();
+        assert_eq!((options.connections, options.duration), (64, Duration::from_secs(120)));
+        assert_eq!((options.paths, options.keep_alive), (vec!["/hello".to_string()], false));
+
+        assert!(bench_options(&args(&["localhost-server", "--connections", "0"])).is_err());
+        assert!(bench_options(&args(&["localhost-server", "--duration", "soon"])).is_err());
+        // Flags are told apart from those they start like
+        assert_eq!(flag_value(&args(&["localhost-server", "--configs=a"]), "--config"), None);
+    }
+}
@@ -3,27 +3,195 @@ use std::collections::HashSet;
 use std::fmt;
 use std::fs;
 use std::env;
+use std::os::unix::fs::PermissionsExt;
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::collections::HashMap;
-use crate::server::logger::{Logger, LogLevel};
+use crate::server::logger::{logger, LogFormat, LogLevel};
+use crate::config::schema;
+use crate::http::header::is_valid_name;
+use crate::server::access::parse_network;
+use crate::server::ban::BanPolicy;
+use ipnet::IpNet;
+use crate::server::rate_limit::{parse_rate, LimitKey};
+use crate::server::cgi::DEFAULT_EXTENSIONS;
+use crate::server::static_files::sites_dir;
+use crate::server::tls;
+use crate::server::hosts_file::DEFAULT_HOSTS_FILE;
+use crate::server::auth;
+use crate::server::digest::DigestAlgorithm;
+use crate::server::jwt::{self, JwtAuth, JwtKey};
+use crate::server::http_client::HttpUrl;
+use jsonwebtoken::Algorithm;
+use std::str::FromStr;
+use crate::server::rewrite::RewriteRule;
+use crate::server::listen::ListenAddr;
+use crate::server::syslog::{parse_facility, SyslogAddress};
+use crate::server::notifier::{Webhook, WebhookFormat};
+use crate::server::proxy::{Balance, Buffering, ConnectionPool, HealthCheck, Proxy, RetryOn, RetryPolicy, Upstream};
+use crate::server::proxy_cache::{CacheSettings, ProxyCache};
+use crate::server::compression::Compression;
+use crate::server::stream::request_stream::HeaderLimits;
+use crate::http::request::MAX_HEADERS;
+use crate::http::header::sanitize_value;
 
-const ALLOWED_EXTENSIONS: [&str; 1] = ["py"];
 const ALLOWED_STATUS: [&str; 8] = ["400", "403", "404", "405", "413", "500", "502", "503"];
-const ALLOWED_HTTP_METHODS: [&str; 3] = ["GET", "POST", "DELETE"];
+/// Methods a route may list, which the CLI offers too
+pub const ALLOWED_HTTP_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+/// Headers computed for each response, which `add_headers` can't override
+const RESERVED_HEADERS: [&str; 3] = ["Connection", "Content-Length", "Transfer-Encoding"];
+/// Methods of the routes that list none, when their host sets no `default_methods`
+const DEFAULT_ROUTE_METHODS: [&str; 1] = ["GET"];
 const MODULE : &str = "CONFIG";
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct CgiConfig {
     pub script_file_name: String,
+    /// Program running the script, python3 by default
+    pub interpreter: Option<String>,
+    /// Extensions of the scripts allowed to run, without the dot
+    pub extensions: Option<Vec<String>>,
+    /// Seconds the script may run before it is killed, 30 by default
+    pub timeout_secs: Option<u64>,
+    /// Variables added to the environment of the script
+    pub env: Option<HashMap<String, String>>,
 }
 
-#[derive(Deserialize, Debug)]
+/// A page rendered from a Handlebars template and the JSON of its data
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TemplateConfig {
+    /// Template file, relative to the root of the site
+    pub file: String,
+    /// JSON file of the data, relative to the root of the site
+    pub data: Option<String>,
+    /// Script printing the JSON of the data, instead of a file
+    pub data_cgi: Option<CgiConfig>,
+    /// Directory whose `.hbs` files are partials, relative to the root of the site
+    pub partials: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthBasicConfig {
+    pub realm: Option<String>,
+    /// htpasswd style file of `user:bcrypt-hash` lines
+    pub users_file: Option<String>,
+    /// Bcrypt hashes per user name, instead of a users file
+    pub users: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthDigestConfig {
+    pub realm: Option<String>,
+    /// htdigest style file of `user:realm:hash` lines
+    pub users_file: String,
+    /// "MD5" (default) or "SHA-256", the hash of the users file
+    pub algorithm: Option<String>,
+    /// Time a nonce is accepted before clients are asked to renew it, like "5m"
+    pub nonce_ttl: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct JwtConfig {
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+    /// Shared secret of the HS algorithms, or the PEM file of the public key of the others
+    pub key: Option<String>,
+    /// JSON Web Key Set of the issuer, instead of a `key`
+    pub jwks_url: Option<String>,
+    /// Accepted signature algorithms, like "RS256" or "HS256"
+    pub algorithms: Vec<String>,
+    /// Scopes tokens must grant, requests being forbidden otherwise
+    pub scopes: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ProxyConfig {
+    /// Server the requests are forwarded to, like "http://127.0.0.1:3000"
+    pub upstream: Option<String>,
+    /// Servers the requests are spread over, instead of a single `upstream`
+    pub upstreams: Option<Vec<UpstreamConfig>>,
+    /// "round-robin" (default), "least-connections" or "ip-hash"
+    pub strategy: Option<String>,
+    /// Time allowed to connect to the upstream, 5s by default
+    pub connect_timeout: Option<String>,
+    /// Longest wait for the upstream's response and between its parts, 60s by default
+    pub timeout: Option<String>,
+    /// Probes taking failing upstreams out of rotation
+    pub health_check: Option<HealthCheckConfig>,
+    /// Sends failed idempotent requests again
+    pub retry: Option<RetryConfig>,
+    /// Keeps upstream connections open between requests
+    pub pool: Option<PoolConfig>,
+    /// "streaming" (default) passes responses on as they come, "buffered"
+    /// holds them until complete
+    pub buffering: Option<String>,
+    /// Response bytes waiting for a slow client before the upstream is no
+    /// longer read, 64k by default
+    pub buffer_size: Option<String>,
+    /// Largest response held whole when buffered, 1m by default
+    pub max_buffered: Option<String>,
+    /// Keeps the responses to GET requests
+    pub cache: Option<ProxyCacheConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Tries in all, the first one included, 2 by default
+    pub attempts: Option<usize>,
+    /// Wait before the first retry, doubled for each next one, 100ms by default
+    pub backoff: Option<String>,
+    /// "error", "timeout" or statuses like "http_503", ["error", "timeout"] by default
+    pub next_upstream: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PoolConfig {
+    /// Idle connections kept for each upstream, 8 by default
+    pub max_per_upstream: Option<usize>,
+    /// Idle connections are closed after this long, 60s by default
+    pub idle_timeout: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ProxyCacheConfig {
+    /// Lifetime of the responses without a Cache-Control max-age, 60s by default
+    pub ttl: Option<String>,
+    /// Memory the responses may take together, like "64m", 64m by default
+    pub max_memory: Option<String>,
+    /// Largest response body kept, 1m by default
+    pub max_entry_size: Option<String>,
+    /// Directory the responses are also written to, relative to the sites directory
+    pub dir: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HealthCheckConfig {
+    /// Path requested from each upstream, "/" by default
+    pub path: Option<String>,
+    /// Time between probes, 10s by default
+    pub interval: Option<String>,
+    /// Probes in a row to pass to get requests again, 2 by default
+    pub healthy_threshold: Option<usize>,
+    /// Probes in a row to fail to be taken out, 3 by default
+    pub unhealthy_threshold: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UpstreamConfig {
+    pub url: String,
+    /// Share of the requests, relative to the other upstreams' weights, 1 by default
+    pub weight: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct ErrorPages {
     pub custom_pages: HashMap<String, String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Route {
     pub path: Option<String>,
     pub methods: Option<Vec<String>>,
@@ -32,11 +200,39 @@ pub struct Route {
     pub directory_listing: Option<bool>,
     pub redirect: Option<String>,
     pub cgi: Option<CgiConfig>,
+    pub template: Option<TemplateConfig>,
     pub session_required: Option<bool>,
     pub session_redirect: Option<String>,
+    pub client_max_body_size: Option<String>,
+    pub upload_dir: Option<String>,
+    pub allowed_upload_types: Option<Vec<String>>,
+    pub denied_upload_types: Option<Vec<String>>,
+    pub allowed_upload_extensions: Option<Vec<String>>,
+    pub denied_upload_extensions: Option<Vec<String>>,
+    pub deduplicate_uploads: Option<bool>,
+    /// Age after which uploaded files are purged, like "30m", "12h" or "7d"
+    pub upload_ttl: Option<String>,
+    pub upload_validators: Option<Vec<UploadValidatorConfig>>,
+    /// Headers added to the route's responses, replacing the host's ones of the same name
+    pub add_headers: Option<HashMap<String, String>>,
+    pub auth_basic: Option<AuthBasicConfig>,
+    pub auth_digest: Option<AuthDigestConfig>,
+    pub jwt: Option<JwtConfig>,
+    /// Addresses or CIDR networks allowed to reach the route, everyone when unset
+    pub allow: Option<Vec<String>>,
+    /// Addresses or CIDR networks refused, even when allowed
+    pub deny: Option<Vec<String>>,
+    /// Name of the `limits` entry throttling the route
+    pub rate_limit: Option<String>,
+    /// Media types request bodies may have, like "application/json" or "image/*"
+    pub content_types: Option<Vec<String>>,
+    /// Request headers CORS preflights may ask for, like "Content-Type"
+    pub cors_allowed_headers: Option<Vec<String>>,
+    /// Upstream server the route forwards its requests to
+    pub proxy: Option<ProxyConfig>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct SessionOptionsConfig {
     pub http_only: Option<bool>,
     pub secure: Option<bool>,
@@ -47,28 +243,286 @@ pub struct SessionOptionsConfig {
     pub same_site: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RememberMeConfig {
+    pub enabled: Option<bool>,
+    pub name: Option<String>,
+    pub max_age: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UploadQuotaConfig {
+    pub max_total_size: Option<String>,
+    pub max_files: Option<usize>,
+    pub session_max_total_size: Option<String>,
+    pub session_max_files: Option<usize>,
+}
+
+/// A named rate limiter that routes reference with `rate_limit`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LimitConfig {
+    /// Requests allowed, like "10r/s", "30r/m" or "500r/h"
+    pub rate: String,
+    /// Requests allowed above the rate in a burst, 0 by default
+    pub burst: Option<u32>,
+    /// What requests are counted by: "ip" (default), "global" or "header:<name>"
+    pub key: Option<String>,
+}
+
+/// Upload settings of a host, taking precedence over the flat `upload_*` keys
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct UploadsConfig {
+    pub dir: Option<String>,
+    /// Largest file accepted, like "512k" or "20m"
+    pub max_file_size: Option<String>,
+    /// MIME types or prefixes like "image/" accepted
+    pub allowed_types: Option<Vec<String>>,
+    pub quota: Option<UploadQuotaConfig>,
+}
+
+/// HTTPS settings of a host
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// PEM certificate chain, relative to the sites directory unless absolute
+    pub cert: String,
+    /// PEM private key of the certificate
+    pub key: String,
+    /// "1.2" (default) or "1.3"
+    pub min_version: Option<String>,
+    /// Cipher suites allowed, like "TLS13_AES_128_GCM_SHA256", all by default
+    pub ciphers: Option<Vec<String>>,
+    /// Answer plain HTTP requests with a redirect to HTTPS instead of 400
+    pub redirect_http: Option<bool>,
+}
+
+/// A plugin a host uses, see `server::plugin`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PluginConfig {
+    /// Name the plugin was registered under
+    pub name: String,
+    /// Handed to the plugin as they are, `null` when missing
+    pub settings: Option<serde_json::Value>,
+}
+
+/// A URL rewrite applied before routing
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RewriteConfig {
+    /// Regex matched against the request path
+    pub pattern: String,
+    /// New path, `$1` standing for the first captured group and `$${name}`,
+    /// escaped from the environment, for the group named `name`
+    pub replacement: String,
+    /// "last" (default), "redirect" or "permanent"
+    pub flag: Option<String>,
+}
+
+/// Admin API of a host, served under `/api/admin`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+    /// Addresses or CIDR networks allowed, only the loopback addresses when unset
+    pub allow: Option<Vec<String>>,
+    /// Addresses or CIDR networks refused, even when allowed
+    pub deny: Option<Vec<String>>,
+    pub auth_basic: Option<AuthBasicConfig>,
+}
+
+/// Where and how the server logs
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LoggingConfig {
+    /// "error", "warn", "info", "debug" (default) or "trace"
+    pub level: Option<String>,
+    /// "text" (default) or "json"
+    pub format: Option<String>,
+    /// File written instead of the standard output
+    pub file: Option<String>,
+    /// File requests are written to, with the other messages when unset
+    pub access_log: Option<String>,
+    /// Append-only file recording sessions, file changes, reloads and admin calls
+    pub audit_log: Option<String>,
+    pub rotate: Option<LogRotateConfig>,
+    /// "stdout" (default, or `file` when set), "syslog" or "journald"
+    pub target: Option<String>,
+    pub syslog: Option<SyslogConfig>,
+}
+
+/// Temporary bans of the clients whose requests keep failing
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BanConfig {
+    /// Failures (4xx answers and unparsable requests) within `window` banning a client, 20 by default
+    pub max_failures: Option<u32>,
+    /// Time over which failures are counted, "1m" by default
+    pub window: Option<String>,
+    /// How long a ban lasts, "10m" by default
+    pub duration: Option<String>,
+    /// Addresses or CIDR networks never banned
+    pub ignore: Option<Vec<String>>,
+}
+
+/// Webhook told about hosts in trouble
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NotifyConfig {
+    /// http:// or https:// URL the alerts are POSTed to
+    pub webhook: String,
+    /// "generic" (default), "slack" or "discord"
+    pub format: Option<String>,
+    /// Percentage of 5xx answers over the last minute raising an alert, 5 by default
+    pub error_percent: Option<u64>,
+    /// Requests over the last minute below which the error rate isn't checked, 20 by default
+    pub min_requests: Option<u64>,
+    /// Least time between two alerts of the same kind for a host, "5m" by default
+    pub interval: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SyslogConfig {
+    /// "unix:/dev/log" (default) or "udp:<host>:<port>"
+    pub address: Option<String>,
+    /// "daemon" (default), "user", "local0" to "local7"...
+    pub facility: Option<String>,
+    /// Name the messages are tagged with, "localhost" by default
+    pub app_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LogRotateConfig {
+    /// Size a log file may reach before it is rotated, like "10m"
+    pub max_size: String,
+    /// Rotated files kept, 5 by default
+    pub keep: Option<usize>,
+}
+
+/// A check run on uploaded files before they are stored
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UploadValidatorConfig {
+    MaxSize {
+        size: String,
+    },
+    /// ClamAV daemon, reached through its Unix `socket` or a TCP `address`
+    Clamav {
+        socket: Option<String>,
+        address: Option<String>,
+        timeout: Option<String>,
+    },
+}
+
+/// gzip compression of the responses of a host
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    /// Smallest body compressed, like "512" or "1k", 1k by default
+    pub min_size: Option<String>,
+    /// MIME types compressed, like "text/html" or "text/*", the common text types by default
+    pub types: Option<Vec<String>>,
+}
+
+/// Size and number of the headers of the requests to a host
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HeaderLimitsConfig {
+    /// Largest head of a request, its request line included, like "8k", 16k by default
+    pub max_size: Option<String>,
+    /// Most headers of a request, 100 by default and at most
+    pub max_count: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct SessionConfig {
     pub enabled: Option<bool>,
     pub name: Option<String>,
     pub options: Option<SessionOptionsConfig>,
+    pub max_sessions_per_user: Option<usize>,
+    pub remember_me: Option<RememberMeConfig>,
+    /// Plugin keeping the sessions, in memory when none
+    pub store: Option<PluginConfig>,
 }
 
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Host {
     pub server_address: Option<String>,
     pub ports: Option<Vec<String>>,
+    /// Addresses listened on besides the `ports` of `server_address`, like
+    /// "127.0.0.1:8080", "[::1]:8080" or "unix:/run/app.sock"
+    pub listen: Option<Vec<String>>,
     pub server_name: Option<String>,
     pub routes: Option<Vec<Route>>,
     pub error_pages: Option<ErrorPages>,
     pub client_max_body_size: Option<String>,
     pub session: Option<SessionConfig>,
+    pub upload_quota: Option<UploadQuotaConfig>,
+    pub upload_dir: Option<String>,
+    pub allowed_upload_types: Option<Vec<String>>,
+    pub denied_upload_types: Option<Vec<String>>,
+    pub allowed_upload_extensions: Option<Vec<String>>,
+    pub denied_upload_extensions: Option<Vec<String>>,
+    pub deduplicate_uploads: Option<bool>,
+    /// Age after which uploaded files are purged, like "30m", "12h" or "7d"
+    pub upload_ttl: Option<String>,
+    pub upload_validators: Option<Vec<UploadValidatorConfig>>,
+    /// Time a client may take to send its next request before its connection
+    /// is closed, like "30s"
+    pub request_timeout: Option<String>,
+    /// Requests a client may send on one connection before it's closed,
+    /// unbounded when unset
+    pub keep_alive_requests: Option<usize>,
+    /// Methods of the routes that don't list their own
+    pub default_methods: Option<Vec<String>>,
+    /// Headers added to every response of the host
+    pub add_headers: Option<HashMap<String, String>>,
+    /// Gzips the responses of the clients accepting it when set
+    pub compression: Option<CompressionConfig>,
+    /// Requests with larger or more headers are answered with 431
+    pub header_limits: Option<HeaderLimitsConfig>,
+    pub uploads: Option<UploadsConfig>,
+    pub tls: Option<TlsConfig>,
+    pub rewrite: Option<Vec<RewriteConfig>>,
+    /// Requests of the host, logged here instead of the server's access log.
+    /// `{server_name}` is replaced by the host's name.
+    pub access_log: Option<String>,
+    /// Messages about the host, written here instead of the server's log
+    pub error_log: Option<String>,
+    pub admin: Option<AdminConfig>,
+    /// Serves `/healthz` and `/readyz`, true by default
+    pub health_check: Option<bool>,
+    /// Credentials asked by the routes that don't set their own `auth_basic`
+    /// or `auth_digest`
+    pub auth_basic: Option<AuthBasicConfig>,
+    pub auth_digest: Option<AuthDigestConfig>,
+    /// Plugins adding handlers, middlewares or hooks to the host, in order
+    pub plugins: Option<Vec<PluginConfig>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct ServerConfig {
+    #[serde(default)]
     pub servers: Vec<Host>,
+    /// Other config files whose hosts are added to `servers`, as paths or glob
+    /// patterns relative to this file
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Rate limiters shared by the routes referencing them
+    #[serde(default)]
+    pub limits: HashMap<String, LimitConfig>,
+    /// Name of the `limits` entry throttling every request, before routing
+    pub rate_limit: Option<String>,
+    pub ban: Option<BanConfig>,
+    /// Addresses or CIDR networks of the proxies whose `X-Forwarded-For` names the client
+    pub trusted_proxies: Option<Vec<String>>,
+    pub logging: Option<LoggingConfig>,
+    pub notify: Option<NotifyConfig>,
+    /// Extensions per MIME type, overriding the built-in table
+    #[serde(default)]
+    pub types: HashMap<String, Vec<String>>,
+    /// Shared libraries whose plugins are registered before the hosts are
+    /// built, with the `dynamic-plugins` feature
+    #[serde(default)]
+    pub plugin_libraries: Vec<String>,
+    /// Names the hosts in the hosts file while the server runs, false by default
+    pub manage_hosts_file: Option<bool>,
+    /// Hosts file edited with `manage_hosts_file`, /etc/hosts by default
+    pub hosts_file: Option<String>,
+    /// Name sent in the `Server` header of every response, like "localhost/0.1.0"
+    /// by default. Empty leaves the header out.
+    pub server_header: Option<String>,
     #[serde(skip)]
     pub validation_errors: Vec<String>,
 }
@@ -103,6 +557,7 @@ impl CgiConfig {
             .extension()
             .and_then(|ext| ext.to_str())
             .unwrap_or("");
+        let allowed = self.allowed_extensions();
             
         // Vérifier si l'extension est autorisée
         if extension.is_empty() {
@@ -110,15 +565,45 @@ impl CgiConfig {
                 "CgiConfig script '{}' has no extension",
                 self.script_file_name
             )));
-        } else if !ALLOWED_EXTENSIONS.contains(&extension) {
+        } else if !allowed.iter().any(|allowed| allowed == extension) {
             errors.push(ConfigError::Warning(format!(
                 "CgiConfig script extension '{}' is not allowed. Allowed extensions: {:?}",
-                extension, ALLOWED_EXTENSIONS
+                extension, allowed
             )));
         }
 
+        if let Some(interpreter) = &self.interpreter {
+            if interpreter.trim().is_empty() {
+                errors.push(ConfigError::Critical("CgiConfig interpreter is empty".to_string()));
+            } else if Path::new(interpreter).is_absolute() && !Path::new(interpreter).is_file() {
+                errors.push(ConfigError::Warning(format!("CgiConfig interpreter '{}' does not exist", interpreter)));
+            }
+        }
+
+        if self.extensions.as_ref().is_some_and(|extensions| extensions.is_empty()) {
+            errors.push(ConfigError::Critical("CgiConfig extensions is empty, no script could run".to_string()));
+        }
+
+        if self.timeout_secs == Some(0) {
+            errors.push(ConfigError::Critical("CgiConfig timeout_secs must be greater than 0".to_string()));
+        }
+
+        for name in self.env.iter().flatten().map(|(name, _)| name) {
+            if name.is_empty() || name.contains(['=', '\0']) {
+                errors.push(ConfigError::Critical(format!("CgiConfig env name '{}' is invalid", name)));
+            }
+        }
+
         errors
     }
+
+    /// The extensions allowed to run, without their leading dot
+    pub fn allowed_extensions(&self) -> Vec<String> {
+        match &self.extensions {
+            Some(extensions) => extensions.iter().map(|ext| ext.trim_start_matches('.').to_string()).collect(),
+            None => DEFAULT_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+        }
+    }
 }
 
 
@@ -147,6 +632,860 @@ impl ErrorPages {
 }
 
 
+/// Checks that `add_headers` holds valid header lines that the server doesn't manage itself
+fn validate_add_headers(scope: &str, headers: &Option<HashMap<String, String>>) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    for (name, value) in headers.iter().flatten() {
+        if !is_valid_name(name) {
+            errors.push(ConfigError::Critical(format!("{} add_headers has an invalid header name '{}'", scope, name)));
+        } else if RESERVED_HEADERS.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+            errors.push(ConfigError::Critical(format!("{} add_headers can't set '{}', the server manages it", scope, name)));
+        }
+        if value.contains(['\r', '\n']) {
+            errors.push(ConfigError::Critical(format!("{} add_headers value of '{}' contains a line break", scope, name)));
+        }
+    }
+    errors
+}
+
+/// Checks that every entry of a `methods` list is a supported HTTP method
+fn validate_methods(scope: &str, methods: &[String]) -> Vec<ConfigError> {
+    methods.iter()
+        .filter(|method| !ALLOWED_HTTP_METHODS.contains(&method.as_str()))
+        .map(|method| ConfigError::Critical(format!(
+            "{} has invalid HTTP method '{}'. Allowed methods: {:?}",
+            scope, method, ALLOWED_HTTP_METHODS
+        )))
+        .collect()
+}
+
+/// Whether `value` is a media type like "text/html", or a range like "image/*" or "*/*"
+fn is_media_range(value: &str) -> bool {
+    let token = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b));
+    match value.trim().split_once('/') {
+        Some(("*", "*")) => true,
+        Some((kind, "*")) => token(kind),
+        Some((kind, subtype)) => token(kind) && token(subtype),
+        None => false,
+    }
+}
+
+/// Parses a duration made of a number and an optional `s`, `m`, `h` or `d` unit
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().to_ascii_lowercase();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => value.split_at(pos),
+        None => (value.as_str(), "s"),
+    };
+    let millis = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(millis).map(Duration::from_millis)
+}
+
+/// Whether `value` is a size in bytes, optionally followed by k or m
+fn is_size(value: &str) -> bool {
+    let value = value.trim().to_ascii_lowercase();
+    let digits = value.strip_suffix(['k', 'm']).unwrap_or(&value);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Parses a size like "512", "64k" or "10m" into bytes
+fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim().to_ascii_lowercase();
+    let (digits, unit) = match value.strip_suffix('k') {
+        Some(digits) => (digits, 1024),
+        None => value.strip_suffix('m').map_or((value.as_str(), 1), |digits| (digits, 1024 * 1024)),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// Shared checks for the upload settings accepted on hosts and routes
+fn validate_upload_settings(
+    scope: &str,
+    upload_dir: &Option<String>,
+    upload_ttl: &Option<String>,
+    upload_validators: &Option<Vec<UploadValidatorConfig>>,
+    allowed_upload_types: &Option<Vec<String>>,
+    denied_upload_types: &Option<Vec<String>>,
+    allowed_upload_extensions: &Option<Vec<String>>,
+) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+
+    if let Some(dir) = upload_dir {
+        if dir.trim().is_empty() {
+            errors.push(ConfigError::Warning(format!("{} upload_dir is empty", scope)));
+        }
+    }
+
+    if let Some(ttl) = upload_ttl {
+        if parse_duration(ttl).is_none_or(|ttl| ttl.is_zero()) {
+            errors.push(ConfigError::Critical(format!(
+                "{} upload_ttl '{}' is not a duration like '30m', '12h' or '7d'",
+                scope, ttl
+            )));
+        }
+    }
+
+    if let Some(types) = allowed_upload_types {
+        if types.is_empty() {
+            errors.push(ConfigError::Warning(format!(
+                "{} allowed_upload_types is empty, every upload will be rejected",
+                scope
+            )));
+        }
+    }
+
+    for (key, types) in [
+        ("allowed_upload_types", allowed_upload_types),
+        ("denied_upload_types", denied_upload_types),
+    ] {
+        for mime in types.iter().flatten().filter(|t| !t.contains('/')) {
+            errors.push(ConfigError::Warning(format!(
+                "{} {} entry '{}' is not a MIME type or prefix like 'image/'",
+                scope, key, mime
+            )));
+        }
+    }
+
+    for validator in upload_validators.iter().flatten() {
+        errors.extend(validator.validate(scope));
+    }
+
+    if allowed_upload_extensions.as_ref().is_some_and(|extensions| extensions.is_empty()) {
+        errors.push(ConfigError::Warning(format!(
+            "{} allowed_upload_extensions is empty, every upload will be rejected",
+            scope
+        )));
+    }
+
+    errors
+}
+
+impl UploadValidatorConfig {
+    pub fn validate(&self, scope: &str) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        match self {
+            UploadValidatorConfig::MaxSize { size } => {
+                if !size.ends_with("k") && !size.ends_with("m") {
+                    errors.push(ConfigError::Warning(format!("{} max_size validator size is not in k or m", scope)));
+                }
+            }
+            UploadValidatorConfig::Clamav { socket, address, timeout } => {
+                if socket.is_some() == address.is_some() {
+                    errors.push(ConfigError::Critical(format!(
+                        "{} clamav validator needs either a socket or an address",
+                        scope
+                    )));
+                }
+                if let Some(timeout) = timeout {
+                    if parse_duration(timeout).is_none() {
+                        errors.push(ConfigError::Critical(format!(
+                            "{} clamav validator timeout '{}' is not a duration",
+                            scope, timeout
+                        )));
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+impl LimitConfig {
+    pub fn validate(&self, name: &str) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if parse_rate(&self.rate).is_none() {
+            errors.push(ConfigError::Critical(format!(
+                "Limit '{}' rate '{}' is not like '10r/s', '30r/m' or '500r/h'", name, self.rate
+            )));
+        }
+        if let Some(key) = &self.key {
+            if let Err(e) = LimitKey::parse(key) {
+                errors.push(ConfigError::Critical(format!("Limit '{}' key {}", name, e)));
+            }
+        }
+        errors
+    }
+}
+
+impl UploadsConfig {
+    /// Checks the block, warning about the flat keys of `host` it overrides
+    pub fn validate(&self, host: &Host) -> Vec<ConfigError> {
+        let mut errors = validate_upload_settings(
+            "Host uploads", &self.dir, &None, &None, &self.allowed_types, &None, &None,
+        );
+
+        if let Some(size) = self.max_file_size.as_ref().filter(|size| !is_size(size)) {
+            errors.push(ConfigError::Critical(format!(
+                "Host uploads max_file_size '{}' is invalid, expected a size like 512k or 20m", size
+            )));
+        }
+        if let Some(quota) = &self.quota {
+            errors.extend(quota.validate());
+        }
+
+        for (key, set, legacy, flat) in [
+            ("dir", self.dir.is_some(), host.upload_dir.is_some(), "upload_dir"),
+            ("allowed_types", self.allowed_types.is_some(), host.allowed_upload_types.is_some(), "allowed_upload_types"),
+            ("quota", self.quota.is_some(), host.upload_quota.is_some(), "upload_quota"),
+        ] {
+            if set && legacy {
+                errors.push(ConfigError::Warning(format!("Host uploads {} overrides {}", key, flat)));
+            }
+        }
+        errors
+    }
+}
+
+impl TlsConfig {
+    pub fn cert_path(&self) -> PathBuf {
+        site_path(&self.cert)
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        site_path(&self.key)
+    }
+
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        for (key, path) in [("cert", &self.cert), ("key", &self.key)] {
+            if path.trim().is_empty() {
+                errors.push(ConfigError::Critical(format!("Host tls {} is empty", key)));
+            } else if let Err(e) = fs::File::open(site_path(path)) {
+                errors.push(ConfigError::Critical(format!(
+                    "Host tls {} '{}' can't be read: {}", key, site_path(path).display(), e
+                )));
+            }
+        }
+        if !errors.is_empty() {
+            return errors;
+        }
+
+        if let Err(e) = check_secret_permissions(&self.key_path()) {
+            errors.push(ConfigError::Warning(format!("Host tls key '{}' {}", self.key_path().display(), e)));
+        }
+
+        // Loads the files like the server does, which checks that the key matches the certificate
+        if let Err(e) = tls::server_config(
+            &self.cert_path(),
+            &self.key_path(),
+            self.min_version.as_deref(),
+            self.ciphers.as_deref(),
+        ) {
+            errors.push(ConfigError::Critical(format!("Host tls {}", e)));
+        }
+        errors
+    }
+}
+
+impl AuthBasicConfig {
+    pub fn validate(&self, scope: &str) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        match (&self.users_file, &self.users) {
+            (None, None) => errors.push(ConfigError::Critical(format!(
+                "{} auth_basic needs a users_file or users", scope
+            ))),
+            (Some(_), Some(_)) => errors.push(ConfigError::Critical(format!(
+                "{} auth_basic sets both users_file and users, keep one", scope
+            ))),
+            (Some(file), None) if file.trim().is_empty() => errors.push(ConfigError::Critical(format!(
+                "{} auth_basic users_file is empty", scope
+            ))),
+            _ => {}
+        }
+        for (user, hash) in self.users.iter().flatten() {
+            if user.is_empty() || user.contains(':') {
+                errors.push(ConfigError::Critical(format!(
+                    "{} auth_basic user '{}' can't be empty or hold a ':'", scope, user
+                )));
+            }
+            if !auth::is_bcrypt(hash) {
+                errors.push(ConfigError::Critical(format!(
+                    "{} auth_basic user '{}' doesn't have a bcrypt hash", scope, user
+                )));
+            }
+        }
+        errors
+    }
+}
+
+impl AuthDigestConfig {
+    pub fn validate(&self, scope: &str) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if self.users_file.trim().is_empty() {
+            errors.push(ConfigError::Critical(format!("{} auth_digest users_file is empty", scope)));
+        }
+        if let Some(algorithm) = &self.algorithm {
+            if DigestAlgorithm::parse(algorithm).is_none() {
+                errors.push(ConfigError::Critical(format!(
+                    "{} auth_digest algorithm '{}' is not MD5 or SHA-256", scope, algorithm
+                )));
+            }
+        }
+        if let Some(ttl) = &self.nonce_ttl {
+            if parse_duration(ttl).is_none_or(|ttl| ttl.is_zero()) {
+                errors.push(ConfigError::Critical(format!(
+                    "{} auth_digest nonce_ttl '{}' is not a duration like '30s' or '5m'", scope, ttl
+                )));
+            }
+        }
+        errors
+    }
+}
+
+impl ProxyConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        match (&self.upstream, &self.upstreams) {
+            (None, None) => errors.push(ConfigError::Critical("Route proxy needs an upstream".to_string())),
+            (Some(_), Some(_)) => errors.push(ConfigError::Critical(
+                "Route proxy sets both upstream and upstreams, keep one".to_string()
+            )),
+            (_, Some(upstreams)) if upstreams.is_empty() => {
+                errors.push(ConfigError::Critical("Route proxy upstreams is empty".to_string()));
+            }
+            _ => {}
+        }
+        for (url, weight) in self.upstream_entries() {
+            match HttpUrl::parse(url) {
+                Ok(parsed) if parsed.https => errors.push(ConfigError::Critical(format!(
+                    "Route proxy upstream '{}' uses https, only http:// upstreams are supported", url
+                ))),
+                Ok(_) => {}
+                Err(e) => errors.push(ConfigError::Critical(format!("Route proxy upstream {}", e))),
+            }
+            if weight == 0 {
+                errors.push(ConfigError::Critical(format!("Route proxy upstream '{}' weight must be greater than 0", url)));
+            }
+        }
+        if let Some(strategy) = self.strategy.as_ref().filter(|strategy| Balance::parse(strategy).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Route proxy strategy '{}' is invalid, expected round-robin, least-connections or ip-hash", strategy
+            )));
+        }
+        if let Some(check) = &self.health_check {
+            if let Some(path) = check.path.as_ref().filter(|path| !path.starts_with('/')) {
+                errors.push(ConfigError::Critical(format!("Route proxy health_check path '{}' must start with '/'", path)));
+            }
+            if let Some(interval) = check.interval.as_ref().filter(|value| parse_duration(value).is_none_or(|d| d.is_zero())) {
+                errors.push(ConfigError::Critical(format!(
+                    "Route proxy health_check interval '{}' is invalid, expected a duration like 10s", interval
+                )));
+            }
+            for (key, value) in [("healthy_threshold", check.healthy_threshold), ("unhealthy_threshold", check.unhealthy_threshold)] {
+                if value == Some(0) {
+                    errors.push(ConfigError::Critical(format!("Route proxy health_check {} must be greater than 0", key)));
+                }
+            }
+        }
+        for (key, value) in [("connect_timeout", &self.connect_timeout), ("timeout", &self.timeout)] {
+            if let Some(value) = value.as_ref().filter(|value| parse_duration(value).is_none_or(|d| d.is_zero())) {
+                errors.push(ConfigError::Critical(format!(
+                    "Route proxy {} '{}' is invalid, expected a duration like 5s or 1m", key, value
+                )));
+            }
+        }
+        if let Some(retry) = &self.retry {
+            errors.extend(retry.validate());
+        }
+        if let Some(pool) = &self.pool {
+            if pool.max_per_upstream == Some(0) {
+                errors.push(ConfigError::Warning("Route proxy pool max_per_upstream is 0, no connection is kept open".to_string()));
+            }
+            if let Some(timeout) = pool.idle_timeout.as_ref().filter(|value| parse_duration(value).is_none_or(|d| d.is_zero())) {
+                errors.push(ConfigError::Critical(format!(
+                    "Route proxy pool idle_timeout '{}' is invalid, expected a duration like 60s", timeout
+                )));
+            }
+        }
+        if let Some(buffering) = self.buffering.as_ref().filter(|buffering| Buffering::parse(buffering).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Route proxy buffering '{}' is invalid, expected streaming or buffered", buffering
+            )));
+        }
+        for (key, value) in [("buffer_size", &self.buffer_size), ("max_buffered", &self.max_buffered)] {
+            if let Some(value) = value.as_ref().filter(|value| parse_size(value).is_none_or(|size| size == 0)) {
+                errors.push(ConfigError::Critical(format!(
+                    "Route proxy {} '{}' is invalid, expected a size like 64k or 1m", key, value
+                )));
+            }
+        }
+        if self.max_buffered.is_some() && self.buffering.as_deref().and_then(Buffering::parse) != Some(Buffering::Buffered) {
+            errors.push(ConfigError::Warning(
+                "Route proxy max_buffered is set but responses are streamed, set buffering to buffered".to_string()
+            ));
+        }
+        if let Some(cache) = &self.cache {
+            errors.extend(cache.validate());
+        }
+        errors
+    }
+
+    /// The upstream URLs with their weights
+    fn upstream_entries(&self) -> Vec<(&str, usize)> {
+        let single = self.upstream.as_deref().map(|url| (url, 1));
+        let listed = self.upstreams.iter().flatten().map(|upstream| (upstream.url.as_str(), upstream.weight.unwrap_or(1)));
+        single.into_iter().chain(listed).collect()
+    }
+
+    /// The proxy, None when an upstream is invalid
+    pub fn proxy(&self) -> Option<Proxy> {
+        let upstreams = self.upstream_entries().into_iter()
+            .map(|(url, weight)| {
+                let url = HttpUrl::parse(url).ok().filter(|url| !url.https)?;
+                Some(Upstream::new(url, weight))
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|upstreams| !upstreams.is_empty())?;
+        let balance = self.strategy.as_deref().and_then(Balance::parse).unwrap_or_default();
+        let mut proxy = Proxy::balanced(upstreams, balance);
+        if let Some(timeout) = self.connect_timeout.as_deref().and_then(parse_duration) {
+            proxy = proxy.with_connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout.as_deref().and_then(parse_duration) {
+            proxy = proxy.with_timeout(timeout);
+        }
+        if let Some(check) = &self.health_check {
+            proxy = proxy.with_health_check(check.check());
+        }
+        if let Some(retry) = &self.retry {
+            proxy = proxy.with_retry(retry.policy());
+        }
+        if let Some(pool) = &self.pool {
+            proxy = proxy.with_pool(pool.pool());
+        }
+        if let Some(buffering) = self.buffering.as_deref().and_then(Buffering::parse) {
+            proxy = proxy.with_buffering(buffering);
+        }
+        if let Some(size) = self.buffer_size.as_deref().and_then(parse_size) {
+            proxy = proxy.with_buffer_size(size);
+        }
+        if let Some(size) = self.max_buffered.as_deref().and_then(parse_size) {
+            proxy = proxy.with_max_buffered(size);
+        }
+        if let Some(cache) = &self.cache {
+            proxy = proxy.with_cache(ProxyCache::new(cache.settings()));
+        }
+        Some(proxy)
+    }
+}
+
+impl PoolConfig {
+    /// The pool, defaults filled in
+    pub fn pool(&self) -> ConnectionPool {
+        let defaults = ConnectionPool::default();
+        ConnectionPool {
+            max_per_upstream: self.max_per_upstream.unwrap_or(defaults.max_per_upstream),
+            idle_timeout: self.idle_timeout.as_deref().and_then(parse_duration).unwrap_or(defaults.idle_timeout),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if self.attempts == Some(0) {
+            errors.push(ConfigError::Critical("Route proxy retry attempts must be greater than 0".to_string()));
+        }
+        if let Some(backoff) = self.backoff.as_ref().filter(|backoff| parse_duration(backoff).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Route proxy retry backoff '{}' is invalid, expected a duration like 100ms or 1s", backoff
+            )));
+        }
+        for condition in self.next_upstream.iter().flatten().filter(|condition| RetryOn::parse(condition).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Route proxy retry next_upstream '{}' is invalid, expected error, timeout or a 5xx status like http_503", condition
+            )));
+        }
+        if self.next_upstream.as_ref().is_some_and(|conditions| conditions.is_empty()) {
+            errors.push(ConfigError::Warning("Route proxy retry next_upstream is empty, requests are never retried".to_string()));
+        }
+        errors
+    }
+
+    /// The policy, defaults filled in
+    pub fn policy(&self) -> RetryPolicy {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            attempts: self.attempts.unwrap_or(defaults.attempts).max(1),
+            backoff: self.backoff.as_deref().and_then(parse_duration).unwrap_or(defaults.backoff),
+            next_upstream: match &self.next_upstream {
+                Some(conditions) => conditions.iter().filter_map(|condition| RetryOn::parse(condition)).collect(),
+                None => defaults.next_upstream,
+            },
+        }
+    }
+}
+
+impl ProxyCacheConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if let Some(ttl) = self.ttl.as_ref().filter(|ttl| parse_duration(ttl).is_none_or(|d| d.is_zero())) {
+            errors.push(ConfigError::Critical(format!(
+                "Route proxy cache ttl '{}' is invalid, expected a duration like 60s or 5m", ttl
+            )));
+        }
+        for (key, value) in [("max_memory", &self.max_memory), ("max_entry_size", &self.max_entry_size)] {
+            if let Some(value) = value.as_ref().filter(|value| parse_size(value).is_none_or(|size| size == 0)) {
+                errors.push(ConfigError::Critical(format!(
+                    "Route proxy cache {} '{}' is invalid, expected a size like 512k or 64m", key, value
+                )));
+            }
+        }
+        let settings = self.settings();
+        if settings.max_entry_size > settings.max_memory {
+            errors.push(ConfigError::Warning(
+                "Route proxy cache max_entry_size is over max_memory, the largest responses are kept on disk only".to_string()
+            ));
+        }
+        if self.dir.as_ref().is_some_and(|dir| dir.trim().is_empty()) {
+            errors.push(ConfigError::Warning("Route proxy cache dir is empty, responses are kept in memory only".to_string()));
+        }
+        errors
+    }
+
+    /// The settings, defaults filled in
+    pub fn settings(&self) -> CacheSettings {
+        let defaults = CacheSettings::default();
+        CacheSettings {
+            ttl: self.ttl.as_deref().and_then(parse_duration).unwrap_or(defaults.ttl),
+            max_memory: self.max_memory.as_deref().and_then(parse_size).unwrap_or(defaults.max_memory),
+            max_entry_size: self.max_entry_size.as_deref().and_then(parse_size).unwrap_or(defaults.max_entry_size),
+            dir: self.dir.as_deref().filter(|dir| !dir.trim().is_empty()).map(site_path),
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if let Some(size) = self.min_size.as_ref().filter(|size| parse_size(size).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Host compression min_size '{}' is invalid, expected a size like 512 or 1k", size
+            )));
+        }
+        for mime in self.types.iter().flatten().filter(|mime| !mime.contains('/')) {
+            errors.push(ConfigError::Warning(format!(
+                "Host compression type '{}' is not a MIME type like text/html or text/*", mime
+            )));
+        }
+        if self.types.as_ref().is_some_and(Vec::is_empty) {
+            errors.push(ConfigError::Warning("Host compression types is empty, nothing is compressed".to_string()));
+        }
+        errors
+    }
+
+    /// The settings, defaults filled in
+    pub fn settings(&self) -> Compression {
+        let defaults = Compression::default();
+        Compression {
+            min_size: self.min_size.as_deref().and_then(parse_size).unwrap_or(defaults.min_size),
+            types: self.types.clone().unwrap_or(defaults.types),
+        }
+    }
+}
+
+impl HeaderLimitsConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if let Some(size) = self.max_size.as_ref().filter(|size| parse_size(size).is_none_or(|size| size == 0)) {
+            errors.push(ConfigError::Critical(format!(
+                "Host header_limits max_size '{}' is invalid, expected a size like 8k", size
+            )));
+        }
+        if let Some(count) = self.max_count.filter(|count| *count == 0 || *count > MAX_HEADERS) {
+            errors.push(ConfigError::Critical(format!(
+                "Host header_limits max_count {} is invalid, expected 1 to {}", count, MAX_HEADERS
+            )));
+        }
+        errors
+    }
+
+    /// The limits, defaults filled in
+    pub fn settings(&self) -> HeaderLimits {
+        let defaults = HeaderLimits::default();
+        HeaderLimits {
+            max_size: self.max_size.as_deref().and_then(parse_size).unwrap_or(defaults.max_size),
+            max_count: self.max_count.unwrap_or(defaults.max_count).min(MAX_HEADERS),
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    /// The check, defaults filled in
+    pub fn check(&self) -> HealthCheck {
+        let defaults = HealthCheck::default();
+        HealthCheck {
+            path: self.path.clone().unwrap_or(defaults.path),
+            interval: self.interval.as_deref().and_then(parse_duration).unwrap_or(defaults.interval),
+            healthy_threshold: self.healthy_threshold.unwrap_or(defaults.healthy_threshold).max(1),
+            unhealthy_threshold: self.unhealthy_threshold.unwrap_or(defaults.unhealthy_threshold).max(1),
+        }
+    }
+}
+
+impl JwtConfig {
+    pub fn algorithms(&self) -> Result<Vec<Algorithm>, String> {
+        self.algorithms.iter()
+            .map(|name| Algorithm::from_str(name.trim()).map_err(|_| format!("algorithm '{}' is unknown", name)))
+            .collect()
+    }
+
+    /// The key tokens are verified with, a `key` being a secret for the HS
+    /// algorithms and a file path for the others
+    pub fn key(&self) -> Result<JwtKey, String> {
+        match (&self.key, &self.jwks_url) {
+            (Some(key), None) if self.algorithms()?.first().copied().is_some_and(jwt::is_hmac) => {
+                Ok(JwtKey::Secret(key.clone()))
+            }
+            (Some(key), None) => Ok(JwtKey::PemFile(site_path(key))),
+            (None, Some(url)) => Ok(JwtKey::Jwks(HttpUrl::parse(url)?)),
+            (None, None) => Err("needs a key or a jwks_url".to_string()),
+            (Some(_), Some(_)) => Err("sets both key and jwks_url, keep one".to_string()),
+        }
+    }
+
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        let checked = self.algorithms().and_then(|algorithms| {
+            if algorithms.is_empty() {
+                return Err("algorithms is empty, every token would be refused".to_string());
+            }
+            match self.key()? {
+                // Downloaded by the server only
+                JwtKey::Jwks(url) => {
+                    if !url.https {
+                        errors.push(ConfigError::Warning(format!(
+                            "Route jwt jwks_url '{}' is fetched over plain HTTP, its keys could be tampered with",
+                            self.jwks_url.as_deref().unwrap_or_default()
+                        )));
+                    }
+                    Ok(())
+                }
+                key => JwtAuth::new(key, algorithms).map(|_| ()),
+            }
+        });
+        if let Err(e) = checked {
+            errors.push(ConfigError::Critical(format!("Route jwt {}", e)));
+        }
+        if matches!(&self.key, Some(key) if key.trim().is_empty()) {
+            errors.push(ConfigError::Critical("Route jwt key is empty".to_string()));
+        }
+        errors
+    }
+}
+
+impl AdminConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        for (list, entries) in [("allow", &self.allow), ("deny", &self.deny)] {
+            for entry in entries.iter().flatten() {
+                if let Err(e) = parse_network(entry) {
+                    errors.push(ConfigError::Critical(format!("Host admin {}: {}", list, e)));
+                }
+            }
+        }
+        if let Some(auth) = &self.auth_basic {
+            errors.extend(auth.validate("Host admin"));
+        }
+        if self.auth_basic.is_none() && self.allow.is_some() {
+            errors.push(ConfigError::Warning(
+                "Host admin is reachable without credentials by every allowed address".to_string(),
+            ));
+        }
+        errors
+    }
+}
+
+impl RewriteConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        match RewriteRule::new(&self.pattern, &self.replacement, self.flag.as_deref()) {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![ConfigError::Critical(format!("Host rewrite {}", e))],
+        }
+    }
+}
+
+/// Checks the `types` section, where an extension may only map to one MIME type
+fn validate_types(types: &HashMap<String, Vec<String>>) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    let mut owners: HashMap<String, &str> = HashMap::new();
+
+    let mut mimes: Vec<_> = types.keys().collect();
+    mimes.sort();
+    for mime in mimes {
+        let valid = mime.split_once('/')
+            .is_some_and(|(kind, subtype)| !kind.trim().is_empty() && !subtype.trim().is_empty());
+        if !valid {
+            errors.push(ConfigError::Critical(format!("types entry '{}' is not a MIME type like 'application/wasm'", mime)));
+            continue;
+        }
+        for extension in &types[mime] {
+            let normalized = extension.trim().trim_start_matches('.').to_ascii_lowercase();
+            if normalized.is_empty() {
+                errors.push(ConfigError::Critical(format!("types {} has an empty extension", mime)));
+            } else if let Some(owner) = owners.insert(normalized.clone(), mime) {
+                errors.push(ConfigError::Warning(format!(
+                    "types extension '{}' is listed under both {} and {}", normalized, owner, mime
+                )));
+            }
+        }
+    }
+    errors
+}
+
+/// Resolves a path of the config, relative ones being under the sites directory
+fn site_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        Path::new(&sites_dir()).join(path)
+    }
+}
+
+impl LoggingConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        if let Some(level) = self.level.as_ref().filter(|level| LogLevel::parse(level).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Logging level '{}' is invalid, expected error, warn, info, debug or trace", level
+            )));
+        }
+        if let Some(format) = self.format.as_ref().filter(|format| LogFormat::parse(format).is_none()) {
+            errors.push(ConfigError::Critical(format!("Logging format '{}' is invalid, expected text or json", format)));
+        }
+        for (key, path) in [("file", &self.file), ("access_log", &self.access_log), ("audit_log", &self.audit_log)] {
+            if path.as_ref().is_some_and(|path| path.trim().is_empty()) {
+                errors.push(ConfigError::Critical(format!("Logging {} is empty", key)));
+            }
+        }
+        match self.target.as_deref().map(str::trim) {
+            None | Some("stdout") => {
+                if self.syslog.is_some() {
+                    errors.push(ConfigError::Warning("Logging syslog has no effect unless target is syslog".to_string()));
+                }
+            }
+            Some(target @ ("syslog" | "journald")) => {
+                if self.file.is_some() {
+                    errors.push(ConfigError::Warning(format!("Logging file is unused when target is {}", target)));
+                }
+            }
+            Some(target) => errors.push(ConfigError::Critical(format!(
+                "Logging target '{}' is invalid, expected stdout, syslog or journald", target
+            ))),
+        }
+        if let Some(syslog) = &self.syslog {
+            if let Some(Err(e)) = syslog.address.as_deref().map(SyslogAddress::parse) {
+                errors.push(ConfigError::Critical(format!("Logging syslog {}", e)));
+            }
+            if let Some(facility) = syslog.facility.as_ref().filter(|facility| parse_facility(facility).is_none()) {
+                errors.push(ConfigError::Critical(format!("Logging syslog facility '{}' is unknown", facility)));
+            }
+        }
+        if let Some(rotate) = &self.rotate {
+            if !is_size(&rotate.max_size) {
+                errors.push(ConfigError::Critical(format!(
+                    "Logging rotate max_size '{}' is invalid, expected a size like 512k or 10m", rotate.max_size
+                )));
+            }
+            if self.file.is_none() && self.access_log.is_none() {
+                errors.push(ConfigError::Warning("Logging rotate has no effect without file or access_log".to_string()));
+            }
+        }
+        errors
+    }
+}
+
+impl BanConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        if self.max_failures == Some(0) {
+            errors.push(ConfigError::Critical("Ban max_failures must be greater than 0".to_string()));
+        }
+        for (key, value) in [("window", &self.window), ("duration", &self.duration)] {
+            if let Some(value) = value.as_ref().filter(|value| parse_duration(value).is_none_or(|d| d.is_zero())) {
+                errors.push(ConfigError::Critical(format!(
+                    "Ban {} '{}' is invalid, expected a duration like 30s or 10m", key, value
+                )));
+            }
+        }
+        for entry in self.ignore.iter().flatten() {
+            if let Err(e) = parse_network(entry) {
+                errors.push(ConfigError::Critical(format!("Ban ignore {}", e)));
+            }
+        }
+        errors
+    }
+
+    /// The policy, defaults filled in
+    pub fn policy(&self) -> BanPolicy {
+        let duration = |value: &Option<String>, default| value.as_deref().and_then(parse_duration).unwrap_or(default);
+        BanPolicy {
+            max_failures: self.max_failures.unwrap_or(20) as usize,
+            window: duration(&self.window, Duration::from_secs(60)),
+            duration: duration(&self.duration, Duration::from_secs(600)),
+            ignore: self.ignore.iter().flatten().filter_map(|entry| parse_network(entry).ok()).collect(),
+        }
+    }
+}
+
+impl NotifyConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        if let Err(e) = Webhook::parse(&self.webhook) {
+            errors.push(ConfigError::Critical(format!("Notify webhook {}", e)));
+        }
+        if let Some(format) = self.format.as_ref().filter(|format| WebhookFormat::parse(format).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Notify format '{}' is invalid, expected generic, slack or discord", format
+            )));
+        }
+        if let Some(percent) = self.error_percent.filter(|percent| !(1..=100).contains(percent)) {
+            errors.push(ConfigError::Critical(format!("Notify error_percent {} is not between 1 and 100", percent)));
+        }
+        if let Some(interval) = self.interval.as_ref().filter(|interval| parse_duration(interval).is_none()) {
+            errors.push(ConfigError::Critical(format!(
+                "Notify interval '{}' is invalid, expected a duration like 30s or 5m", interval
+            )));
+        }
+        errors
+    }
+}
+
+impl UploadQuotaConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        for (key, size) in [
+            ("max_total_size", &self.max_total_size),
+            ("session_max_total_size", &self.session_max_total_size),
+        ] {
+            if let Some(size) = size {
+                if !size.ends_with("k") && !size.ends_with("m") {
+                    errors.push(ConfigError::Warning(format!("Upload quota {} is not in k or m", key)));
+                }
+            }
+        }
+
+        errors
+    }
+}
+
 impl Route {
     pub fn validate(&self) -> Vec<ConfigError> {
         let mut errors = Vec::new();
@@ -166,26 +1505,61 @@ impl Route {
             _ => {}
         }
 
-        // Validate methods
+        // Validate methods, once expanded with the host's defaults
         match &self.methods {
-            None => errors.push(ConfigError::Warning("Route methods is undefined".to_string())),
-            Some(methods) if methods.is_empty() => {
-                errors.push(ConfigError::Warning("Route methods is empty".to_string()))
+            None => errors.push(ConfigError::Critical("Route methods is undefined".to_string())),
+            Some(methods) if methods.is_empty() => errors.push(ConfigError::Critical(format!(
+                "Route '{}' methods is empty, every request would be rejected",
+                self.path.as_deref().unwrap_or_default()
+            ))),
+            Some(methods) => errors.extend(validate_methods("Route", methods)),
+        }
+
+        errors.extend(validate_add_headers("Route", &self.add_headers));
+
+        if let Some(auth) = &self.auth_basic {
+            errors.extend(auth.validate("Route"));
+        }
+        if let Some(auth) = &self.auth_digest {
+            errors.extend(auth.validate("Route"));
+        }
+        if let Some(jwt) = &self.jwt {
+            errors.extend(jwt.validate());
+        }
+
+        for (list, entries) in [("allow", &self.allow), ("deny", &self.deny)] {
+            for entry in entries.iter().flatten() {
+                if let Err(e) = parse_network(entry) {
+                    errors.push(ConfigError::Critical(format!("Route {}: {}", list, e)));
+                }
             }
-            Some(methods) => {
-                for method in methods {
-                    if !ALLOWED_HTTP_METHODS.contains(&method.as_str()) {
-                        errors.push(ConfigError::Warning(format!(
-                            "Invalid HTTP method '{}'. Allowed methods: {:?}",
-                            method, ALLOWED_HTTP_METHODS
-                        )));
-                    }
+        }
+
+        match &self.content_types {
+            Some(types) if types.is_empty() => {
+                errors.push(ConfigError::Critical("Route content_types is empty, no body could be sent".to_string()));
+            }
+            Some(types) => {
+                for media_type in types.iter().filter(|media_type| !is_media_range(media_type)) {
+                    errors.push(ConfigError::Critical(format!(
+                        "Route content_types '{}' is not a media type like application/json or image/*", media_type
+                    )));
                 }
             }
+            None => {}
+        }
+
+        for name in self.cors_allowed_headers.iter().flatten().filter(|name| !is_valid_name(name)) {
+            errors.push(ConfigError::Critical(format!("Route cors_allowed_headers has an invalid header name '{}'", name)));
         }
 
-        // Validate root directory
+        if let Some(proxy) = &self.proxy {
+            errors.extend(proxy.validate());
+        }
+
+        // Validate root directory, proxy routes serving no files
         match &self.root {
+            None if self.proxy.is_some() => {}
             None => errors.push(ConfigError::Warning("Route root is undefined".to_string())),
             Some(root) if root.is_empty() => {
                 errors.push(ConfigError::Warning("Route root is empty".to_string()))
@@ -236,11 +1610,52 @@ impl Route {
             }
         }
 
+        if let Some(size) = &self.client_max_body_size {
+            if !size.ends_with("k") && !size.ends_with("m") {
+                errors.push(ConfigError::Warning("Route client_max_body_size is not in k or m".to_string()));
+            }
+        }
+
+        errors.extend(validate_upload_settings(
+            "Route",
+            &self.upload_dir,
+            &self.upload_ttl,
+            &self.upload_validators,
+            &self.allowed_upload_types,
+            &self.denied_upload_types,
+            &self.allowed_upload_extensions,
+        ));
+
         // Validate CGI configuration if present
         if let Some(ref cgi) = self.cgi {
             errors.extend(cgi.validate());
         }
 
+        if let Some(ref template) = self.template {
+            errors.extend(template.validate());
+            if self.cgi.is_some() || self.redirect.is_some() || self.proxy.is_some() {
+                errors.push(ConfigError::Critical(
+                    "Route template can't be combined with cgi, redirect or proxy".to_string(),
+                ));
+            }
+        }
+
+        errors
+    }
+}
+
+impl TemplateConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        if self.file.is_empty() {
+            errors.push(ConfigError::Critical("Template file is empty".to_string()));
+        }
+        if self.data.is_some() && self.data_cgi.is_some() {
+            errors.push(ConfigError::Critical("Template data and data_cgi are exclusive".to_string()));
+        }
+        if let Some(ref cgi) = self.data_cgi {
+            errors.extend(cgi.validate());
+        }
         errors
     }
 }
@@ -312,6 +1727,19 @@ impl SessionConfig {
             if let Some(options) = &self.options {
                 errors.extend(options.validate());
             }
+
+            if self.max_sessions_per_user == Some(0) {
+                errors.push(ConfigError::Critical("Session max_sessions_per_user must be greater than 0".to_string()));
+            }
+
+            if let Some(remember) = &self.remember_me {
+                if remember.name.as_deref() == self.name.as_deref() && remember.name.is_some() {
+                    errors.push(ConfigError::Critical("Remember-me cookie name must differ from the session cookie name".to_string()));
+                }
+                if remember.max_age == Some(0) {
+                    errors.push(ConfigError::Warning("Remember-me max_age must be greater than 0".to_string()));
+                }
+            }
         }
 
         errors
@@ -330,6 +1758,22 @@ impl Host {
             return Err(ConfigError::Critical("Host server_name is empty".to_string()));
         }
         
+        if let Some(listen) = &self.listen {
+            if listen.is_empty() {
+                return Err(ConfigError::Critical("Host listen is empty".to_string()));
+            }
+            for entry in listen {
+                ListenAddr::parse(entry).map_err(|e| ConfigError::Critical(format!("Host listen {}", e)))?;
+            }
+            // `server_address` then only names the address of the hosts file entry
+            if self.ports.is_none() {
+                if let Some(address) = self.server_address.as_ref().filter(|a| a.parse::<IpAddr>().is_err()) {
+                    return Err(ConfigError::Critical(format!("Host server_address is invalid: {}", address)));
+                }
+                return Ok(());
+            }
+        }
+
         // Validation du server_address
         let server_address = self.server_address
             .as_ref()
@@ -362,7 +1806,7 @@ impl Host {
             return Err(ConfigError::Critical("Host ports contains an invalid port".to_string()));
         }
         
-        let logger = Logger::new(LogLevel::DEBUG);
+        let logger = logger();
 
         if valid_ports.len() < ports.len() {
             logger.warn(&format!("Host ports contain duplicate values on {:?}", self.server_name.as_ref().unwrap()), MODULE);
@@ -374,6 +1818,88 @@ impl Host {
     }
 
 
+    /// Gives the routes that list no methods the host's `default_methods`
+    /// Describes how `new` differs from this host
+    pub fn diff(&self, new: &Host) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        // The listeners only change with the set of addresses, whatever their order
+        let listen = |host: &Host| {
+            let mut addresses: Vec<_> = host.listen_addrs().iter().map(ToString::to_string).collect();
+            addresses.sort();
+            addresses.dedup();
+            addresses
+        };
+        if listen(self) != listen(new) {
+            changes.push(format!("listening on {}", listen(new).join(", ")));
+        }
+
+        let routes = |host: &Host| host.routes.clone().unwrap_or_default();
+        let (old_routes, new_routes) = (routes(self), routes(new));
+        for route in &old_routes {
+            match new_routes.iter().find(|r| r.path == route.path) {
+                Some(updated) if updated != route => changes.push(format!("route {} changed", route.path.clone().unwrap_or_default())),
+                Some(_) => {}
+                None => changes.push(format!("route {} removed", route.path.clone().unwrap_or_default())),
+            }
+        }
+        for route in new_routes.iter().filter(|r| !old_routes.iter().any(|old| old.path == r.path)) {
+            changes.push(format!("route {} added", route.path.clone().unwrap_or_default()));
+        }
+
+        if self.error_pages != new.error_pages {
+            changes.push("error pages changed".to_string());
+        }
+        if self.session != new.session {
+            changes.push("session settings changed".to_string());
+        }
+
+        let others = |host: &Host| Host {
+            server_address: None,
+            ports: None,
+            listen: None,
+            routes: None,
+            error_pages: None,
+            session: None,
+            ..host.clone()
+        };
+        if others(self) != others(new) {
+            changes.push("settings changed".to_string());
+        }
+        changes
+    }
+
+    /// The access and error log paths, `{server_name}` replaced in their templates
+    pub fn log_paths(&self) -> (Option<PathBuf>, Option<PathBuf>) {
+        let name = self.server_name.as_deref().unwrap_or_default();
+        let expand = |template: &Option<String>| template.as_ref()
+            .map(|template| PathBuf::from(template.replace("{server_name}", name)));
+        (expand(&self.access_log), expand(&self.error_log))
+    }
+
+    /// The addresses of `listen` followed by the `ports` of `server_address`,
+    /// skipping the invalid ones
+    pub fn listen_addrs(&self) -> Vec<ListenAddr> {
+        let listen = self.listen.iter().flatten().filter_map(|entry| ListenAddr::parse(entry).ok());
+        let address = self.server_address.as_deref().unwrap_or_default();
+        let ports = self.ports.iter().flatten().filter_map(|port| ListenAddr::from_parts(address, port).ok());
+        let mut addrs: Vec<ListenAddr> = Vec::new();
+        for addr in listen.chain(ports) {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        addrs
+    }
+
+    pub fn expand_route_methods(&mut self) {
+        let defaults = self.default_methods.clone()
+            .unwrap_or_else(|| DEFAULT_ROUTE_METHODS.iter().map(|m| m.to_string()).collect());
+        for route in self.routes.iter_mut().flatten() {
+            route.methods.get_or_insert_with(|| defaults.clone());
+        }
+    }
+
     pub fn collect_warnings(&self) -> Vec<ConfigError> {
         let mut warnings = Vec::new();
 
@@ -388,6 +1914,16 @@ impl Host {
             }
         }
 
+        let listen: Vec<_> = self.listen.iter().flatten().filter_map(|entry| ListenAddr::parse(entry).ok()).collect();
+        if self.listen_addrs().len() < listen.len() + self.ports.as_ref().map_or(0, Vec::len) {
+            warnings.push(ConfigError::Warning("Host listen repeats an address".to_string()));
+        }
+        if self.tls.is_some() && listen.iter().any(|addr| matches!(addr, ListenAddr::Unix(_))) {
+            warnings.push(ConfigError::Warning(
+                "Host tls doesn't apply to unix sockets, which are served plain HTTP".to_string(),
+            ));
+        }
+
         if let Some(size) = &self.client_max_body_size {
             if !size.ends_with("k") && !size.ends_with("m") {
                 warnings.push(ConfigError::Warning("Host client_max_body_size is not in k or m".to_string()));
@@ -398,6 +1934,86 @@ impl Host {
             warnings.extend(session_config.validate());
         }
 
+        if let Some(methods) = &self.default_methods {
+            warnings.extend(validate_methods("Host default_methods", methods));
+        }
+
+        warnings.extend(validate_add_headers("Host", &self.add_headers));
+
+        if let Some(compression) = &self.compression {
+            warnings.extend(compression.validate());
+        }
+
+        if let Some(limits) = &self.header_limits {
+            warnings.extend(limits.validate());
+        }
+
+        if let Some(timeout) = &self.request_timeout {
+            if parse_duration(timeout).is_none_or(|timeout| timeout.is_zero()) {
+                warnings.push(ConfigError::Critical(format!(
+                    "Host request_timeout '{}' is not a duration like '30s' or '2m'",
+                    timeout
+                )));
+            }
+        }
+
+        if self.keep_alive_requests == Some(0) {
+            warnings.push(ConfigError::Critical(
+                "Host keep_alive_requests must allow at least one request".to_string()
+            ));
+        }
+
+        warnings.extend(validate_upload_settings(
+            "Host",
+            &self.upload_dir,
+            &self.upload_ttl,
+            &self.upload_validators,
+            &self.allowed_upload_types,
+            &self.denied_upload_types,
+            &self.allowed_upload_extensions,
+        ));
+
+        if let Some(quota) = &self.upload_quota {
+            warnings.extend(quota.validate());
+        }
+        if let Some(uploads) = &self.uploads {
+            warnings.extend(uploads.validate(self));
+        }
+
+        if let Some(tls) = &self.tls {
+            warnings.extend(tls.validate());
+        }
+
+        for (key, path) in [("access_log", &self.access_log), ("error_log", &self.error_log)] {
+            if path.as_ref().is_some_and(|path| path.trim().is_empty()) {
+                warnings.push(ConfigError::Critical(format!("Host {} is empty", key)));
+            }
+        }
+
+        if let Some(admin) = &self.admin {
+            warnings.extend(admin.validate());
+        }
+        if let Some(auth) = &self.auth_basic {
+            warnings.extend(auth.validate("Host"));
+        }
+        if let Some(auth) = &self.auth_digest {
+            warnings.extend(auth.validate("Host"));
+        }
+
+        for rule in self.rewrite.iter().flatten() {
+            warnings.extend(rule.validate());
+        }
+
+        let quota = self.uploads.as_ref().and_then(|uploads| uploads.quota.as_ref());
+        if let Some(quota) = quota.or(self.upload_quota.as_ref()) {
+            let per_session = quota.session_max_total_size.is_some() || quota.session_max_files.is_some();
+            if per_session && self.session.is_none() {
+                warnings.push(ConfigError::Warning(
+                    "Upload quota per session only applies to hosts with sessions enabled".to_string(),
+                ));
+            }
+        }
+
         if let Some(routes) = &self.routes {
             for route in routes {
                 warnings.extend(route.validate());
@@ -414,79 +2030,531 @@ impl Host {
     }
 }
 
+/// Adds the hosts of an included file to `config`, and the limits and types it doesn't define yet
+fn merge_included(config: &mut serde_json::Value, included: serde_json::Value) {
+    let (Some(fields), serde_json::Value::Object(mut included)) = (config.as_object_mut(), included) else { return };
+
+    if let Some(serde_json::Value::Array(hosts)) = included.remove("servers") {
+        if let serde_json::Value::Array(servers) = fields.entry("servers").or_insert_with(|| serde_json::json!([])) {
+            servers.extend(hosts);
+        }
+    }
+    for key in ["limits", "types"] {
+        if let Some(serde_json::Value::Object(entries)) = included.remove(key) {
+            if let serde_json::Value::Object(own) = fields.entry(key).or_insert_with(|| serde_json::json!({})) {
+                for (name, entry) in entries {
+                    own.entry(name).or_insert(entry);
+                }
+            }
+        }
+    }
+}
+
+/// Gives every host of `config` the settings of its `defaults` block it doesn't set
+fn apply_defaults(config: &mut serde_json::Value) {
+    let Some(defaults) = config.as_object_mut().and_then(|fields| fields.remove("defaults")) else { return };
+    if let Some(serde_json::Value::Array(hosts)) = config.get_mut("servers") {
+        for host in hosts {
+            merge_defaults(host, &defaults);
+        }
+    }
+}
+
+/// Adds the keys of `defaults` missing from `value`, recursing into objects set on both
+/// sides. A key set to null keeps its null, which opts the host out of the default.
+fn merge_defaults(value: &mut serde_json::Value, defaults: &serde_json::Value) {
+    let (serde_json::Value::Object(fields), serde_json::Value::Object(defaults)) = (value, defaults) else { return };
+    for (key, default) in defaults {
+        match fields.get_mut(key) {
+            Some(field) => merge_defaults(field, default),
+            None => { fields.insert(key.clone(), default.clone()); }
+        }
+    }
+}
+
+/// Expands the environment variables and secret files referenced by every
+/// string of `value`, relative secret files being read from `base_dir`
+fn interpolate_env(value: &mut serde_json::Value, lookup: &dyn Fn(&str) -> Option<String>, base_dir: &Path) -> Result<(), ConfigError> {
+    match value {
+        serde_json::Value::String(text) => {
+            *text = expand_references(text, lookup, base_dir).map_err(ConfigError::Critical)?;
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                interpolate_env(item, lookup, base_dir)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for field in fields.values_mut() {
+                interpolate_env(field, lookup, base_dir)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces `${VAR}` and `${VAR:-default}` in `input`, `$${` stands for a literal `${`
+pub fn expand_env_vars(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    expand_references(input, lookup, Path::new("."))
+}
+
+/// Like [`expand_env_vars`], also replacing `${file:<path>}` with the secret
+/// kept in that file, a relative path being read from `base_dir`
+pub fn expand_references(input: &str, lookup: &dyn Fn(&str) -> Option<String>, base_dir: &Path) -> Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("$${") {
+            output.push_str("${");
+            rest = &rest[3..];
+            continue;
+        }
+        if !rest.starts_with("${") {
+            output.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let end = rest.find('}')
+            .ok_or_else(|| format!("Unterminated variable reference in '{}'", input))?;
+        let reference = &rest[2..end];
+        if let Some(path) = reference.strip_prefix("file:") {
+            output.push_str(&read_secret(&base_dir.join(path.trim()))?);
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid variable name '{}' in '{}'", name, input));
+        }
+
+        // Like the shell, an empty variable falls back to the default
+        match (lookup(name).filter(|v| !v.is_empty() || default.is_none()), default) {
+            (Some(value), _) => output.push_str(&value),
+            (None, Some(default)) => output.push_str(default),
+            (None, None) => return Err(format!("Environment variable {} is not set", name)),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// The secret kept in the file at `path`, without the newline ending it. Only
+/// the user running the server may be able to read the file.
+pub fn read_secret(path: &Path) -> Result<String, String> {
+    let secret = check_secret_permissions(path)
+        .and_then(|_| fs::read_to_string(path).map_err(|e| format!("can't be read: {}", e)))
+        .map_err(|e| format!("Secret file '{}' {}", path.display(), e))?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Fails when the file at `path` is open to its group or to other users
+fn check_secret_permissions(path: &Path) -> Result<(), String> {
+    let mode = fs::metadata(path)
+        .map_err(|e| format!("can't be read: {}", e))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(format!("is accessible to other users (mode {:o}), restrict it with chmod 600", mode & 0o777));
+    }
+    Ok(())
+}
+
+/// Lists the files matched by an `include` entry, sorted so hosts load in a stable order
+fn expand_include(base_dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    let full_pattern = base_dir.join(pattern);
+    let full_pattern = full_pattern.to_string_lossy();
+    let mut files: Vec<PathBuf> = glob::glob(&full_pattern)
+        .map_err(|e| ConfigError::Critical(format!("Invalid include pattern '{}': {}", pattern, e)))?
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+
+    // A plain path must exist, a pattern may match nothing
+    let is_pattern = pattern.contains(['*', '?', '[']);
+    if files.is_empty() && !is_pattern {
+        return Err(ConfigError::Critical(format!("Included config file not found: {}", full_pattern)));
+    }
+    Ok(files)
+}
+
+/// A problem found while validating the config, with the host it belongs to
+#[derive(Debug)]
+pub struct ConfigIssue {
+    pub host: Option<String>,
+    pub error: ConfigError,
+}
+
+impl ConfigIssue {
+    pub fn is_critical(&self) -> bool {
+        matches!(self.error, ConfigError::Critical(_))
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.host {
+            Some(host) => write!(f, "[{}] {}", host, self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// Environment variable naming the config file when `--config` isn't given
+pub const CONFIG_ENV_VAR: &str = "LOCALHOST_CONFIG";
+
+/// Formats a configuration file can be written in, told apart by extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
 impl ServerConfig {
-    pub fn load_and_validate(with_warn: bool) -> Result<ServerConfig, ConfigError> {
-        let logger = Logger::new(LogLevel::DEBUG);
-    
+    /// The `plugin_libraries`, relative ones being under the sites directory
+    pub fn plugin_library_paths(&self) -> Vec<PathBuf> {
+        self.plugin_libraries.iter().map(|path| site_path(path)).collect()
+    }
+
+    /// The hosts file to edit, when `manage_hosts_file` is set
+    pub fn managed_hosts_file(&self) -> Option<PathBuf> {
+        self.manage_hosts_file.unwrap_or(false)
+            .then(|| PathBuf::from(self.hosts_file.as_deref().unwrap_or(DEFAULT_HOSTS_FILE)))
+    }
+
+    /// Networks of the trusted proxies, invalid entries being reported by the validation
+    pub fn trusted_networks(&self) -> Vec<IpNet> {
+        self.trusted_proxies.iter().flatten().filter_map(|entry| parse_network(entry).ok()).collect()
+    }
+
+    /// Picks the config file: `explicit` (the `--config` flag), else the
+    /// `LOCALHOST_CONFIG` environment variable, else `default_path()`
+    pub fn resolve_path(explicit: Option<&str>) -> PathBuf {
+        explicit.map(PathBuf::from)
+            .or_else(|| env::var(CONFIG_ENV_VAR).ok().filter(|path| !path.is_empty()).map(PathBuf::from))
+            .unwrap_or_else(Self::default_path)
+    }
+
+    /// Returns the first of `config.json`, `config.yaml`, `config.yml` and `config.toml`
+    /// found in the CLI directory, `config.json` when there is none
+    pub fn default_path() -> PathBuf {
         let home_dir = env::var("HOME").expect("Failed to get home directory");
-        let config_content = fs::read_to_string(&format!("{}/.cargo/localhost-cli/config.json", home_dir))
-            .map_err(|e| {
-                logger.error(&format!("Cannot read config file: {}", e), MODULE);
-                ConfigError::Critical(format!("Cannot read config file: {}", e))
-            })?;
-    
-        let mut config: ServerConfig = serde_json::from_str(&config_content)
-            .map_err(|e| {
-                logger.error(&format!("Cannot parse config file: {}", e), MODULE);
-                ConfigError::Critical(format!("Cannot parse config file: {}", e))
-            })?;
-    
+        let dir = Path::new(&home_dir).join(".cargo/localhost-cli");
+        ["config.json", "config.yaml", "config.yml", "config.toml"].iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| dir.join("config.json"))
+    }
+
+    /// Deserializes a configuration written in `format`, expanding `${VAR}` and
+    /// `${VAR:-default}` in its strings from the environment and `${file:<path>}`
+    /// from secret files. Hosts take the settings they leave unset from the
+    /// `defaults` block.
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<ServerConfig, ConfigError> {
+        let mut value = Self::parse_value(content, format, Path::new("."))?;
+        apply_defaults(&mut value);
+        serde_json::from_value(value)
+            .map_err(|e| ConfigError::Critical(format!("Cannot parse {} config file: {}", format, e)))
+    }
+
+    /// Parses and checks a config file, leaving its `defaults` and `include` unresolved.
+    /// Secret files are read relative to `base_dir`.
+    fn parse_value(content: &str, format: ConfigFormat, base_dir: &Path) -> Result<serde_json::Value, ConfigError> {
+        let parsed: Result<serde_json::Value, String> = match format {
+            ConfigFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+        };
+        let mut value = parsed
+            .map_err(|e| ConfigError::Critical(format!("Cannot parse {} config file: {}", format, e)))?;
+
+        // Included files may hold a single host instead of a `servers` list
+        let single_host = value.as_object().is_some_and(|fields|
+            !["servers", "include", "defaults", "limits", "ban", "trusted_proxies", "logging", "notify", "types"].iter().any(|key| fields.contains_key(*key))
+        );
+
+        let lines = match format {
+            ConfigFormat::Json => schema::json_lines(content),
+            _ => HashMap::new(),
+        };
+        let fields = if single_host { schema::HOST_FIELDS } else { schema::CONFIG_FIELDS };
+        let issues = schema::validate(&value, fields, &lines);
+        if !issues.is_empty() {
+            let issues: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+            return Err(ConfigError::Critical(format!(
+                "Invalid {} config file, {} problem(s) found:\n  {}",
+                format, issues.len(), issues.join("\n  ")
+            )));
+        }
+
+        interpolate_env(&mut value, &|name| env::var(name).ok(), base_dir)?;
+
+        if single_host {
+            value = serde_json::json!({ "servers": [value] });
+        }
+        Ok(value)
+    }
+
+    /// Reads the config file at `path` along with the files it includes
+    pub fn load(path: &Path) -> Result<ServerConfig, ConfigError> {
+        let value = Self::load_file(path, &mut HashSet::new())?;
+        serde_json::from_value(value)
+            .map_err(|e| ConfigError::Critical(format!("{}: Cannot parse config file: {}", path.display(), e)))
+    }
+
+    fn load_file(path: &Path, visited: &mut HashSet<PathBuf>) -> Result<serde_json::Value, ConfigError> {
+        let read_error = |e: std::io::Error| ConfigError::Critical(format!("Cannot read config file {}: {}", path.display(), e));
+        if !visited.insert(fs::canonicalize(path).map_err(read_error)?) {
+            return Err(ConfigError::Critical(format!("Config file {} is included more than once", path.display())));
+        }
+
+        let content = fs::read_to_string(path).map_err(read_error)?;
+        let format = ConfigFormat::from_path(path).ok_or_else(|| ConfigError::Critical(format!(
+            "Unknown config file format: {} (expected .json, .yaml, .yml or .toml)",
+            path.display()
+        )))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        let mut config = Self::parse_value(&content, format, base_dir).map_err(|e| match e {
+            ConfigError::Critical(msg) => ConfigError::Critical(format!("{}: {}", path.display(), msg)),
+            warning => warning,
+        })?;
+
+        let patterns: Vec<String> = config.as_object_mut()
+            .and_then(|fields| fields.remove("include"))
+            .and_then(|include| serde_json::from_value(include).ok())
+            .unwrap_or_default();
+        for pattern in patterns {
+            for included in expand_include(base_dir, &pattern)? {
+                // Included hosts take their own file's defaults first, then this one's
+                merge_included(&mut config, Self::load_file(&included, visited)?);
+            }
+        }
+
+        apply_defaults(&mut config);
+        Ok(config)
+    }
+
+    pub fn load_and_validate(with_warn: bool) -> Result<ServerConfig, ConfigError> {
+        Self::load_and_validate_from(&Self::default_path(), with_warn)
+    }
+
+    pub fn load_and_validate_from(path: &Path, with_warn: bool) -> Result<ServerConfig, ConfigError> {
+        let logger = logger();
+
+        let mut issues = Vec::new();
+        let result = Self::load(path).and_then(|config| config.validate(&mut issues));
+
+        for issue in &issues {
+            let module = format!("{} - {}", MODULE, issue.host.clone().unwrap_or_default());
+            match &issue.error {
+                ConfigError::Critical(msg) => logger.error(msg, &module),
+                ConfigError::Warning(msg) if with_warn => logger.warn(msg, &module),
+                ConfigError::Warning(_) => {}
+            }
+        }
+        if let Err(ConfigError::Critical(msg)) = &result {
+            logger.error(msg, MODULE);
+        }
+
+        result
+    }
+
+    /// Validates the file at `path` and returns every problem found, fatal or not
+    pub fn check(path: &Path) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        if let Err(error) = Self::load(path).and_then(|config| config.validate(&mut issues)) {
+            issues.push(ConfigIssue { host: None, error });
+        }
+        issues
+    }
+
+    /// Describes how `new` differs from this configuration, one line per change
+    pub fn diff(&self, new: &ServerConfig) -> Vec<String> {
+        let find = |config: &ServerConfig, name: &Option<String>| {
+            config.servers.iter().find(|host| &host.server_name == name).cloned()
+        };
+        let mut changes = Vec::new();
+
+        for host in &self.servers {
+            let name = host.server_name.clone().unwrap_or_default();
+            match find(new, &host.server_name) {
+                Some(updated) => changes.extend(host.diff(&updated).into_iter()
+                    .map(|change| format!("Host '{}': {}", name, change))),
+                None => changes.push(format!("Host '{}' removed", name)),
+            }
+        }
+        for host in &new.servers {
+            if find(self, &host.server_name).is_none() {
+                changes.push(format!("Host '{}' added", host.server_name.clone().unwrap_or_default()));
+            }
+        }
+        if self.limits != new.limits {
+            changes.push("limits changed".to_string());
+        }
+        if self.rate_limit != new.rate_limit {
+            changes.push("rate_limit changed".to_string());
+        }
+        if self.ban != new.ban {
+            changes.push("ban changed".to_string());
+        }
+        if self.trusted_proxies != new.trusted_proxies {
+            changes.push("trusted_proxies changed".to_string());
+        }
+        if self.server_header != new.server_header {
+            changes.push("server_header changed".to_string());
+        }
+        if self.logging != new.logging {
+            changes.push("logging changed".to_string());
+        }
+        if self.types != new.types {
+            changes.push("types changed".to_string());
+        }
+        if self.notify != new.notify {
+            changes.push("notify changed, applied on restart".to_string());
+        }
+        if self.plugin_libraries != new.plugin_libraries {
+            changes.push("plugin_libraries changed, libraries already loaded are kept".to_string());
+        }
+        if self.manage_hosts_file != new.manage_hosts_file || self.hosts_file != new.hosts_file {
+            changes.push("hosts file management changed, applied on restart".to_string());
+        }
+        changes
+    }
+
+    /// Drops the hosts missing essential settings, recording every problem in `issues`.
+    /// Fails when no host is left or two hosts share a name.
+    fn validate(mut self, issues: &mut Vec<ConfigIssue>) -> Result<ServerConfig, ConfigError> {
         let mut server_names = std::collections::HashSet::new();
-        let mut validation_errors = Vec::new();
-    
-        config.servers = config.servers.into_iter().filter_map(|mut host| {
+        let mut duplicates = false;
+
+        for error in self.logging.iter().flat_map(LoggingConfig::validate) {
+            issues.push(ConfigIssue { host: None, error });
+        }
+
+        for error in self.notify.iter().flat_map(NotifyConfig::validate) {
+            issues.push(ConfigIssue { host: None, error });
+        }
+
+        for error in self.ban.iter().flat_map(BanConfig::validate) {
+            issues.push(ConfigIssue { host: None, error });
+        }
+
+        for entry in self.trusted_proxies.iter().flatten() {
+            if let Err(e) = parse_network(entry) {
+                issues.push(ConfigIssue { host: None, error: ConfigError::Critical(format!("Trusted proxies {}", e)) });
+            }
+        }
+
+        if let Some(server) = self.server_header.as_ref().filter(|server| sanitize_value(server) != server.as_str()) {
+            issues.push(ConfigIssue {
+                host: None,
+                error: ConfigError::Critical(format!("server_header {:?} has control characters", server)),
+            });
+        }
+
+        for (name, limit) in &self.limits {
+            for error in limit.validate(name) {
+                issues.push(ConfigIssue { host: None, error });
+            }
+        }
+
+        for error in validate_types(&self.types) {
+            issues.push(ConfigIssue { host: None, error });
+        }
+
+        if let Some(name) = self.rate_limit.as_ref().filter(|name| !self.limits.contains_key(*name)) {
+            issues.push(ConfigIssue {
+                host: None,
+                error: ConfigError::Critical(format!("rate_limit '{}' is not defined in limits", name)),
+            });
+        }
+        // Requests are throttled before routing, by client only
+        let keyed = self.rate_limit.as_ref()
+            .and_then(|name| Some((name, self.limits.get(name)?.key.as_deref()?)))
+            .filter(|(_, key)| LimitKey::parse(key).is_ok_and(|key| key != LimitKey::Ip));
+        if let Some((name, key)) = keyed {
+            issues.push(ConfigIssue {
+                host: None,
+                error: ConfigError::Critical(format!("rate_limit '{}' is keyed by '{}', it can only count by 'ip'", name, key)),
+            });
+        }
+
+        let limits = &self.limits;
+        self.servers = self.servers.into_iter().filter_map(|mut host| {
             match host.is_valid_essential_config() {
                 Ok(()) => {
                     if !server_names.insert(host.server_name.clone()) {
-                        validation_errors.push(format!("Duplicate server name: {:?}", host.server_name));
+                        issues.push(ConfigIssue {
+                            host: None,
+                            error: ConfigError::Critical(format!("Duplicate server name: {:?}", host.server_name)),
+                        });
+                        duplicates = true;
                         None
                     } else {
-                        let warnings = host.collect_warnings();
-                        for warn in warnings {
-                            match warn {
-                                ConfigError::Critical(msg) => {
-                                    logger.error(&msg, &format!("{} - {}", MODULE, host.server_name.clone().unwrap_or_default()));
-                                },
-                                ConfigError::Warning(msg) => {
-                                    if with_warn {
-                                        logger.warn(&msg, &format!("{} - {}", MODULE, host.server_name.clone().unwrap_or_default()));
-                                    }
-                                }
+                        host.expand_route_methods();
+                        for error in host.collect_warnings() {
+                            issues.push(ConfigIssue { host: host.server_name.clone(), error });
+                        }
+                        for route in host.routes.iter().flatten() {
+                            if let Some(name) = route.rate_limit.as_ref().filter(|name| !limits.contains_key(*name)) {
+                                issues.push(ConfigIssue {
+                                    host: host.server_name.clone(),
+                                    error: ConfigError::Critical(format!("Route rate_limit '{}' is not defined in limits", name)),
+                                });
                             }
                         }
                         Some(host) // Conserve l'hôte valide
                     }
                 },
-                Err(e) => {
-                    match e {
-                        ConfigError::Critical(msg) => {
-                            logger.error(&msg, &format!("{} - {}", MODULE, host.server_name.clone().unwrap_or_default()));
-                        },
-                        ConfigError::Warning(msg) => {
-                            if with_warn {
-                                logger.warn(&msg, &format!("{} - {}", MODULE, host.server_name.clone().unwrap_or_default()));
-                            }
-                        }
-                    }
+                Err(error) => {
+                    issues.push(ConfigIssue { host: host.server_name.clone(), error });
                     None // Supprime l'hôte invalide
                 }
             }
         }).collect();
-    
-        if config.servers.is_empty() {
-            let msg = "No valid server configuration found";
-            logger.error(&msg, MODULE);
-            return Err(ConfigError::Critical(msg.to_string()));
+
+        if self.servers.is_empty() {
+            return Err(ConfigError::Critical("No valid server configuration found".to_string()));
         }
-    
-        if !validation_errors.is_empty() {
-            for error in &validation_errors {
-                logger.error(error, MODULE);
-            }
+
+        if duplicates {
             return Err(ConfigError::Critical("Invalid configuration".to_string()));
         }
-    
-        Ok(config)
+
+        Ok(self)
     }
 }
 
@@ -496,6 +2564,9 @@ impl Default for SessionConfig {
             enabled: None,
             name: None,
             options: None,
+            max_sessions_per_user: None,
+            remember_me: None,
+            store: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,549 @@
+//! Structural validation of configuration files
+//!
+//! Serde stops at the first problem and reports it without telling where it is.
+//! The config is checked against this schema first, so every unknown key and
+//! wrongly typed value is reported at once with its JSON pointer, and its line
+//! when the file is JSON.
+
+use std::collections::HashMap;
+use std::fmt;
+use serde_json::Value;
+
+/// Expected shape of a value
+pub enum Kind {
+    String,
+    Bool,
+    /// Non negative integer
+    Integer,
+    Array(&'static Kind),
+    Object(&'static [Field]),
+    /// Object with arbitrary keys, all holding the same kind of value
+    Map(&'static Kind),
+    /// Object whose fields depend on the value of its `tag` key
+    Tagged { tag: &'static str, variants: &'static [(&'static str, &'static [Field])] },
+    /// Any value, checked by whoever reads it, like the settings of a plugin
+    Any,
+}
+
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub required: bool,
+}
+
+const fn optional(name: &'static str, kind: Kind) -> Field {
+    Field { name, kind, required: false }
+}
+
+const fn required(name: &'static str, kind: Kind) -> Field {
+    Field { name, kind, required: true }
+}
+
+const STRINGS: Kind = Kind::Array(&Kind::String);
+
+const UPLOAD_VALIDATOR: Kind = Kind::Tagged {
+    tag: "type",
+    variants: &[
+        ("max_size", &[required("size", Kind::String)]),
+        ("clamav", &[
+            optional("socket", Kind::String),
+            optional("address", Kind::String),
+            optional("timeout", Kind::String),
+        ]),
+    ],
+};
+
+const AUTH_BASIC_FIELDS: &[Field] = &[
+    optional("realm", Kind::String),
+    optional("users_file", Kind::String),
+    optional("users", Kind::Map(&Kind::String)),
+];
+
+const AUTH_DIGEST_FIELDS: &[Field] = &[
+    optional("realm", Kind::String),
+    required("users_file", Kind::String),
+    optional("algorithm", Kind::String),
+    optional("nonce_ttl", Kind::String),
+];
+
+const JWT_FIELDS: &[Field] = &[
+    optional("issuer", Kind::String),
+    optional("audience", Kind::String),
+    optional("key", Kind::String),
+    optional("jwks_url", Kind::String),
+    required("algorithms", STRINGS),
+    optional("scopes", STRINGS),
+];
+
+const CGI_FIELDS: &[Field] = &[
+    required("script_file_name", Kind::String),
+    optional("interpreter", Kind::String),
+    optional("extensions", STRINGS),
+    optional("timeout_secs", Kind::Integer),
+    optional("env", Kind::Map(&Kind::String)),
+];
+
+const ROUTE_FIELDS: &[Field] = &[
+    optional("path", Kind::String),
+    optional("methods", STRINGS),
+    optional("root", Kind::String),
+    optional("default_page", Kind::String),
+    optional("directory_listing", Kind::Bool),
+    optional("redirect", Kind::String),
+    optional("cgi", Kind::Object(CGI_FIELDS)),
+    optional("template", Kind::Object(&[
+        required("file", Kind::String),
+        optional("data", Kind::String),
+        optional("data_cgi", Kind::Object(CGI_FIELDS)),
+        optional("partials", Kind::String),
+    ])),
+    optional("session_required", Kind::Bool),
+    optional("session_redirect", Kind::String),
+    optional("client_max_body_size", Kind::String),
+    optional("upload_dir", Kind::String),
+    optional("allowed_upload_types", STRINGS),
+    optional("denied_upload_types", STRINGS),
+    optional("allowed_upload_extensions", STRINGS),
+    optional("denied_upload_extensions", STRINGS),
+    optional("deduplicate_uploads", Kind::Bool),
+    optional("upload_ttl", Kind::String),
+    optional("upload_validators", Kind::Array(&UPLOAD_VALIDATOR)),
+    optional("add_headers", Kind::Map(&Kind::String)),
+    optional("auth_basic", Kind::Object(AUTH_BASIC_FIELDS)),
+    optional("auth_digest", Kind::Object(AUTH_DIGEST_FIELDS)),
+    optional("jwt", Kind::Object(JWT_FIELDS)),
+    optional("allow", STRINGS),
+    optional("deny", STRINGS),
+    optional("rate_limit", Kind::String),
+    optional("content_types", STRINGS),
+    optional("cors_allowed_headers", STRINGS),
+    optional("proxy", Kind::Object(&[
+        optional("upstream", Kind::String),
+        optional("upstreams", Kind::Array(&Kind::Object(&[
+            required("url", Kind::String),
+            optional("weight", Kind::Integer),
+        ]))),
+        optional("strategy", Kind::String),
+        optional("health_check", Kind::Object(&[
+            optional("path", Kind::String),
+            optional("interval", Kind::String),
+            optional("healthy_threshold", Kind::Integer),
+            optional("unhealthy_threshold", Kind::Integer),
+        ])),
+        optional("retry", Kind::Object(&[
+            optional("attempts", Kind::Integer),
+            optional("backoff", Kind::String),
+            optional("next_upstream", Kind::Array(&Kind::String)),
+        ])),
+        optional("pool", Kind::Object(&[
+            optional("max_per_upstream", Kind::Integer),
+            optional("idle_timeout", Kind::String),
+        ])),
+        optional("buffering", Kind::String),
+        optional("buffer_size", Kind::String),
+        optional("max_buffered", Kind::String),
+        optional("cache", Kind::Object(&[
+            optional("ttl", Kind::String),
+            optional("max_memory", Kind::String),
+            optional("max_entry_size", Kind::String),
+            optional("dir", Kind::String),
+        ])),
+        optional("connect_timeout", Kind::String),
+        optional("timeout", Kind::String),
+    ])),
+];
+
+const PLUGIN_FIELDS: &[Field] = &[
+    required("name", Kind::String),
+    optional("settings", Kind::Any),
+];
+
+const SESSION_FIELDS: &[Field] = &[
+    optional("enabled", Kind::Bool),
+    optional("name", Kind::String),
+    optional("options", Kind::Object(&[
+        optional("http_only", Kind::Bool),
+        optional("secure", Kind::Bool),
+        optional("max_age", Kind::Integer),
+        optional("path", Kind::String),
+        optional("expires", Kind::Integer),
+        optional("domain", Kind::String),
+        optional("same_site", Kind::String),
+    ])),
+    optional("max_sessions_per_user", Kind::Integer),
+    optional("remember_me", Kind::Object(&[
+        optional("enabled", Kind::Bool),
+        optional("name", Kind::String),
+        optional("max_age", Kind::Integer),
+    ])),
+    optional("store", Kind::Object(PLUGIN_FIELDS)),
+];
+
+const UPLOAD_QUOTA_FIELDS: &[Field] = &[
+    optional("max_total_size", Kind::String),
+    optional("max_files", Kind::Integer),
+    optional("session_max_total_size", Kind::String),
+    optional("session_max_files", Kind::Integer),
+];
+
+/// Keeps the fields that identify a host first, `DEFAULT_FIELDS` skips them
+pub const HOST_FIELDS: &[Field] = &[
+    optional("server_address", Kind::String),
+    optional("ports", STRINGS),
+    optional("listen", STRINGS),
+    optional("server_name", Kind::String),
+    optional("routes", Kind::Array(&Kind::Object(ROUTE_FIELDS))),
+    optional("error_pages", Kind::Object(&[required("custom_pages", Kind::Map(&Kind::String))])),
+    optional("client_max_body_size", Kind::String),
+    optional("session", Kind::Object(SESSION_FIELDS)),
+    optional("upload_quota", Kind::Object(UPLOAD_QUOTA_FIELDS)),
+    optional("upload_dir", Kind::String),
+    optional("allowed_upload_types", STRINGS),
+    optional("denied_upload_types", STRINGS),
+    optional("allowed_upload_extensions", STRINGS),
+    optional("denied_upload_extensions", STRINGS),
+    optional("deduplicate_uploads", Kind::Bool),
+    optional("upload_ttl", Kind::String),
+    optional("upload_validators", Kind::Array(&UPLOAD_VALIDATOR)),
+    optional("request_timeout", Kind::String),
+    optional("keep_alive_requests", Kind::Integer),
+    optional("default_methods", STRINGS),
+    optional("add_headers", Kind::Map(&Kind::String)),
+    optional("compression", Kind::Object(&[
+        optional("min_size", Kind::String),
+        optional("types", STRINGS),
+    ])),
+    optional("header_limits", Kind::Object(&[
+        optional("max_size", Kind::String),
+        optional("max_count", Kind::Integer),
+    ])),
+    optional("tls", Kind::Object(&[
+        required("cert", Kind::String),
+        required("key", Kind::String),
+        optional("min_version", Kind::String),
+        optional("ciphers", STRINGS),
+        optional("redirect_http", Kind::Bool),
+    ])),
+    optional("uploads", Kind::Object(&[
+        optional("dir", Kind::String),
+        optional("max_file_size", Kind::String),
+        optional("allowed_types", STRINGS),
+        optional("quota", Kind::Object(UPLOAD_QUOTA_FIELDS)),
+    ])),
+    optional("access_log", Kind::String),
+    optional("error_log", Kind::String),
+    optional("admin", Kind::Object(&[
+        optional("allow", STRINGS),
+        optional("deny", STRINGS),
+        optional("auth_basic", Kind::Object(AUTH_BASIC_FIELDS)),
+    ])),
+    optional("health_check", Kind::Bool),
+    optional("auth_basic", Kind::Object(AUTH_BASIC_FIELDS)),
+    optional("auth_digest", Kind::Object(AUTH_DIGEST_FIELDS)),
+    optional("rewrite", Kind::Array(&Kind::Object(&[
+        required("pattern", Kind::String),
+        required("replacement", Kind::String),
+        optional("flag", Kind::String),
+    ]))),
+    optional("plugins", Kind::Array(&Kind::Object(PLUGIN_FIELDS))),
+];
+
+/// Host settings that a `defaults` block may provide
+const DEFAULT_FIELDS: &[Field] = HOST_FIELDS.split_at(5).1;
+
+pub const CONFIG_FIELDS: &[Field] = &[
+    optional("servers", Kind::Array(&Kind::Object(HOST_FIELDS))),
+    optional("include", STRINGS),
+    optional("defaults", Kind::Object(DEFAULT_FIELDS)),
+    optional("limits", Kind::Map(&Kind::Object(&[
+        required("rate", Kind::String),
+        optional("burst", Kind::Integer),
+        optional("key", Kind::String),
+    ]))),
+    optional("rate_limit", Kind::String),
+    optional("ban", Kind::Object(&[
+        optional("max_failures", Kind::Integer),
+        optional("window", Kind::String),
+        optional("duration", Kind::String),
+        optional("ignore", STRINGS),
+    ])),
+    optional("trusted_proxies", STRINGS),
+    optional("logging", Kind::Object(&[
+        optional("level", Kind::String),
+        optional("format", Kind::String),
+        optional("file", Kind::String),
+        optional("access_log", Kind::String),
+        optional("audit_log", Kind::String),
+        optional("rotate", Kind::Object(&[
+            required("max_size", Kind::String),
+            optional("keep", Kind::Integer),
+        ])),
+        optional("target", Kind::String),
+        optional("syslog", Kind::Object(&[
+            optional("address", Kind::String),
+            optional("facility", Kind::String),
+            optional("app_name", Kind::String),
+        ])),
+    ])),
+    optional("notify", Kind::Object(&[
+        required("webhook", Kind::String),
+        optional("format", Kind::String),
+        optional("error_percent", Kind::Integer),
+        optional("min_requests", Kind::Integer),
+        optional("interval", Kind::String),
+    ])),
+    optional("types", Kind::Map(&STRINGS)),
+    optional("plugin_libraries", STRINGS),
+    optional("manage_hosts_file", Kind::Bool),
+    optional("hosts_file", Kind::String),
+    optional("server_header", Kind::String),
+];
+
+/// One problem found in a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaIssue {
+    /// JSON pointer to the offending value
+    pub pointer: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() { "/" } else { &self.pointer };
+        match self.line {
+            Some(line) => write!(f, "{} (line {}): {}", pointer, line, self.message),
+            None => write!(f, "{}: {}", pointer, self.message),
+        }
+    }
+}
+
+/// Checks `value` against `fields`, `lines` maps pointers to the line they start on
+pub fn validate(value: &Value, fields: &'static [Field], lines: &HashMap<String, usize>) -> Vec<SchemaIssue> {
+    let mut checker = Checker { lines, issues: Vec::new() };
+    checker.check(value, &Kind::Object(fields), "");
+    checker.issues
+}
+
+struct Checker<'a> {
+    lines: &'a HashMap<String, usize>,
+    issues: Vec<SchemaIssue>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, pointer: &str, message: String) {
+        self.issues.push(SchemaIssue {
+            pointer: pointer.to_string(),
+            line: self.lines.get(pointer).copied(),
+            message,
+        });
+    }
+
+    fn check(&mut self, value: &Value, kind: &Kind, pointer: &str) {
+        match (kind, value) {
+            (Kind::String, Value::String(_)) | (Kind::Bool, Value::Bool(_)) | (Kind::Any, _) => {}
+            (Kind::Integer, Value::Number(n)) if n.is_u64() => {}
+            (Kind::Array(item), Value::Array(items)) => {
+                for (i, value) in items.iter().enumerate() {
+                    self.check(value, item, &format!("{}/{}", pointer, i));
+                }
+            }
+            (Kind::Map(item), Value::Object(entries)) => {
+                for (key, value) in entries {
+                    self.check(value, item, &child(pointer, key));
+                }
+            }
+            (Kind::Object(fields), Value::Object(_)) => self.check_fields(value, fields, &[], pointer),
+            (Kind::Tagged { tag, variants }, Value::Object(entries)) => {
+                let names = variants.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+                match entries.get(*tag) {
+                    Some(Value::String(variant)) => match variants.iter().find(|(name, _)| name == variant) {
+                        Some((_, fields)) => self.check_fields(value, fields, &[tag], pointer),
+                        None => self.report(&child(pointer, tag), format!(
+                            "unknown {} '{}', expected one of: {}", tag, variant, names
+                        )),
+                    },
+                    _ => self.report(pointer, format!("missing string key '{}' (one of: {})", tag, names)),
+                }
+            }
+            (kind, value) => self.report(pointer, format!(
+                "expected {}, found {}", kind_name(kind), value_name(value)
+            )),
+        }
+    }
+
+    fn check_fields(&mut self, value: &Value, fields: &[Field], extra: &[&str], pointer: &str) {
+        let Value::Object(entries) = value else { return };
+
+        for (key, value) in entries {
+            match fields.iter().find(|field| field.name == key) {
+                // Optional fields may be explicitly null
+                Some(field) if value.is_null() && !field.required => {}
+                Some(field) => self.check(value, &field.kind, &child(pointer, key)),
+                None if extra.contains(&key.as_str()) => {}
+                None => {
+                    let suggestion = closest(key, fields)
+                        .map(|name| format!(", did you mean '{}'?", name))
+                        .unwrap_or_default();
+                    self.report(&child(pointer, key), format!("unknown key '{}'{}", key, suggestion));
+                }
+            }
+        }
+
+        for field in fields.iter().filter(|f| f.required && !entries.contains_key(f.name)) {
+            self.report(pointer, format!("missing required key '{}'", field.name));
+        }
+    }
+}
+
+fn child(pointer: &str, key: &str) -> String {
+    format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn kind_name(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::String => "a string",
+        Kind::Bool => "a boolean",
+        Kind::Integer => "a non negative integer",
+        Kind::Array(_) => "an array",
+        Kind::Object(_) | Kind::Map(_) | Kind::Tagged { .. } => "an object",
+        Kind::Any => "any value",
+    }
+}
+
+fn value_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Suggests the known key closest to a misspelled one
+fn closest(key: &str, fields: &[Field]) -> Option<&'static str> {
+    fields.iter()
+        .map(|field| (field.name, edit_distance(key, field.name)))
+        .filter(|(_, distance)| *distance <= 2)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(name, _)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Maps the JSON pointer of every value of a JSON document to its line
+pub fn json_lines(content: &str) -> HashMap<String, usize> {
+    let mut scanner = LineScanner { bytes: content.as_bytes(), pos: 0, line: 1, lines: HashMap::new() };
+    scanner.value(String::new());
+    scanner.lines
+}
+
+/// Walks a document serde already accepted, so it doesn't need to handle malformed JSON
+struct LineScanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+    lines: HashMap<String, usize>,
+}
+
+impl LineScanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            match b {
+                b'\n' => self.line += 1,
+                b' ' | b'\t' | b'\r' => {}
+                _ => return,
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self, pointer: String) {
+        self.skip_whitespace();
+        self.lines.insert(pointer.clone(), self.line);
+
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b'"') => {
+                            let key = self.string();
+                            self.skip_whitespace();
+                            self.pos += 1; // ':'
+                            self.value(child(&pointer, &key));
+                        }
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return;
+                        }
+                        _ => return,
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut index = 0;
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return;
+                        }
+                        Some(_) => {
+                            self.value(format!("{}/{}", pointer, index));
+                            index += 1;
+                        }
+                        None => return,
+                    }
+                }
+            }
+            Some(b'"') => {
+                self.string();
+            }
+            _ => {
+                while self.peek().is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace()) {
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Reads a string literal, decoding it when it holds escapes
+    fn string(&mut self) -> String {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'\\' => self.pos += 1,
+                b'"' => break,
+                _ => {}
+            }
+        }
+        let literal = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("\"\"");
+        serde_json::from_str(literal).unwrap_or_default()
+    }
+}
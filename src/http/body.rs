@@ -2,8 +2,12 @@ use serde_json;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use std::fmt;
-use std::str;
-use crate::http::header::{ParsedContentDisposition, ParsedContentType, Header, HeaderName, ContentType};
+use std::path::PathBuf;
+use crate::http::multipart::{MultipartParser, PartInfo, PartSink};
+use crate::http::deferred::Deferred;
+use crate::http::body_stream::BodyStream;
+use crate::http::event_stream::EventStream;
+use crate::http::upgrade::Upgrade;
 
 // ============= Type Definitions =============
 pub type JsonValue = serde_json::Value;
@@ -19,6 +23,14 @@ pub enum Body {
     FormUrlEncoded(FormUrlEncoded),
     Binary(BinaryData),
     Multipart(MultipartForm),
+    /// Events sent as they're published, the connection staying open
+    EventStream(EventStream),
+    /// A response given later, the connection waiting for it meanwhile
+    Deferred(Deferred),
+    /// Chunks sent as they're produced, with chunked transfer encoding
+    Stream(BodyStream),
+    /// The connection switching protocols, handed over once the head is sent
+    Upgrade(Upgrade),
     Empty,
 }
 
@@ -32,6 +44,10 @@ pub struct MultipartFile {
     pub filename: String,
     pub content_type: String,
     pub data: BinaryData,
+    /// Size of the file in bytes, whether held in `data` or spooled to `path`
+    pub size: usize,
+    /// Set when the file was streamed to disk instead of kept in `data`
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -75,42 +91,16 @@ impl MultipartForm {
         }
     }
 
-
-
     pub fn set_data(
         &mut self,
         data: BinaryData, 
         boundary: &str, 
     ) {
-        let boundary = format!("--{}", boundary);
-        let parts = split_multipart(&data, boundary.as_bytes());
-
-        for part in parts {
-            if let Some((headers, data)) = parse_part(&part) {
-                if let Some(cd_header) = headers.iter().find(|h| h.name == HeaderName::ContentDisposition) {
-                    if let Some(ct_header) = headers.iter().find(|h| h.name == HeaderName::ContentType) {
-                        let parsed_content_disposition = ParsedContentDisposition::parse_content_disposition(cd_header).unwrap();
-                        let parsed_content_type = ContentType::parse_content_type(ct_header).unwrap();
-                        
-                        if let Some(name) = parsed_content_disposition.params.get("name") {
-                            if let Some(filename) = parsed_content_disposition.params.get("filename") {
-                                let file = MultipartFile {
-                                    filename: filename.to_string(),
-                                    content_type: parsed_content_type.mime,
-                                    data
-                                };
-                                self.files.insert(name.to_string(), file);
-                            } else {
-                                if let Ok(text) = std::str::from_utf8(&data) {
-                                    self.fields.insert(name.to_string(), text.to_string());
-                                }
-                            }
-                        }
-                    }
-                }
-            } else {
-                println!("Failed to parse part");
-            }
+        let mut parser = MultipartParser::new(boundary);
+        let mut collector = FormCollector { form: self, current: None };
+
+        if let Err(e) = parser.feed(&data, &mut collector).and_then(|_| parser.finish()) {
+            println!("Failed to parse multipart body: {}", e);
         }
     }
 
@@ -248,10 +238,10 @@ impl Body {
             Body::Binary(data) => data.len(),
             Body::Multipart(data) => {
                 let fields_len: usize = data.fields.iter().map(|(k, v)| k.len() + v.len()).sum();
-                let files_len: usize = data.files.iter().map(|(k, v)| k.len() + v.filename.len() + v.content_type.len() + v.data.len()).sum();
+                let files_len: usize = data.files.iter().map(|(k, v)| k.len() + v.filename.len() + v.content_type.len() + v.size).sum();
                 fields_len + files_len
             }
-            Body::Empty => 0,
+            Body::EventStream(_) | Body::Deferred(_) | Body::Stream(_) | Body::Upgrade(_) | Body::Empty => 0,
         }
     }
 
@@ -348,83 +338,53 @@ impl fmt::Display for Body {
                     form.files.len()
                 )
             }
-            Body::Empty => write!(f, ""),
+            Body::EventStream(_) | Body::Deferred(_) | Body::Stream(_) | Body::Upgrade(_) | Body::Empty => write!(f, ""),
         }
     }
 }
 
-// ============= Utility functions for multipart parsing =============
-fn split_multipart(data: &[u8], boundary: &[u8]) -> Vec<Vec<u8>> {
-    let mut parts = Vec::new();
-    let mut current_pos = 0;
+// ============= In-memory multipart collection =============
+/// Collects parts into a `MultipartForm`, keeping file contents in memory
+struct FormCollector<'a> {
+    form: &'a mut MultipartForm,
+    current: Option<(PartInfo, BinaryData)>,
+}
 
-    while let Some(pos) = find_subsequence(&data[current_pos..], boundary) {
-        if current_pos > 0 {
-            parts.push(data[current_pos..current_pos + pos - 2].to_vec());
-        }
-        current_pos += pos + boundary.len();
+impl PartSink for FormCollector<'_> {
+    fn begin_part(&mut self, part: &PartInfo) -> Result<(), BodyError> {
+        self.current = Some((part.clone(), Vec::new()));
+        Ok(())
     }
 
-    parts
-}
-
-fn parse_part(part: &[u8]) -> Option<(Vec<Header>, Vec<u8>)> {
-    let mut headers = Vec::new();
-    let mut pos = 0;
-
-    // Chercher la fin des headers (séquence \r\n\r\n)
-    let headers_end = find_subsequence(part, b"\r\n\r\n")?;
-    
-    // Parser les headers
-    let headers_data = &part[..headers_end];
-    for line in headers_data.split(|&b| b == b'\n') {
-        let line = line.strip_suffix(b"\r").unwrap_or(line);
-        if line.is_empty() {
-            continue;
-        }
-        
-        if let Ok(header_str) = str::from_utf8(line) {
-            if let Some((name, value)) = header_str.split_once(':') {
-                headers.push(Header::from_str(name.trim(), value.trim()));
-            }
+    fn part_data(&mut self, data: &[u8]) -> Result<(), BodyError> {
+        if let Some((_, buffer)) = self.current.as_mut() {
+            buffer.extend_from_slice(data);
         }
+        Ok(())
     }
 
-    // Extraire les données après les headers
-    let content_start = headers_end + 4; // Skip \r\n\r\n
-    let content_data = if content_start < part.len() {
-        part[content_start..].to_vec()
-    } else {
-        Vec::new()
-    };
-
-    Some((headers, content_data))
-}
-
-// Fonction utilitaire pour vérifier si un content-type est du texte
-fn is_text_content_type(content_type: &str) -> bool {
-    content_type.starts_with("text/") || 
-    content_type == "application/json" ||
-    content_type == "application/javascript" ||
-    content_type == "application/xml" ||
-    content_type == "application/x-www-form-urlencoded"
-}
-
-// Fonction helper pour le traitement des données
-fn process_part_data(data: &[u8], content_type: &str) -> Vec<u8> {
-    if is_text_content_type(content_type) {
-        // Pour les fichiers texte, on nettoie les caractères de fin de ligne
-        data.iter()
-            .filter(|&&b| b != b'\r')
-            .copied()
-            .collect()
-    } else {
-        // Pour les fichiers binaires, on garde les données telles quelles
-        data.to_vec()
+    fn end_part(&mut self) -> Result<(), BodyError> {
+        let Some((part, data)) = self.current.take() else { return Ok(()) };
+        let Some(name) = part.name else { return Ok(()) };
+
+        match part.filename {
+            Some(filename) => {
+                let file = MultipartFile {
+                    filename,
+                    content_type: part.content_type
+                        .unwrap_or_else(|| "application/octet-stream".to_string()),
+                    size: data.len(),
+                    data,
+                    path: None,
+                };
+                self.form.files.insert(name, file);
+            }
+            None => {
+                let text = String::from_utf8(data)
+                    .map_err(|_| BodyError::InvalidUtf8(format!("field {}", name)))?;
+                self.form.fields.insert(name, text);
+            }
+        }
+        Ok(())
     }
 }
-
-fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    haystack.windows(needle.len())
-        .position(|window| window == needle)
-}
\ No newline at end of file
@@ -0,0 +1,148 @@
+//! Response bodies sent while they're produced
+//!
+//! A handler answering with [`Response::stream`] gives the chunks of its body
+//! instead of the whole of it, for output of unknown or unbounded length like
+//! exports, logs or data relayed from elsewhere. The event loop sends them
+//! with chunked transfer encoding as the client takes them, asking for the
+//! next chunk once the previous one was written.
+//!
+//! An iterator is pulled on the loop's thread, so each chunk must be quick to
+//! produce. Output that takes time, like the result of a query, is written
+//! from a thread of its own to the [`BodyWriter`] of [`BodyStream::channel`],
+//! which blocks while the client is slower than the writer.
+
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use crate::http::waker;
+
+/// Chunks a `BodyWriter` may write ahead of the client
+const CHANNEL_CHUNKS: usize = 16;
+
+type Chunks = Box<dyn Iterator<Item = io::Result<Vec<u8>>> + Send>;
+
+enum Source {
+    Iter(Chunks),
+    Channel(Receiver<io::Result<Vec<u8>>>),
+}
+
+/// What the body has to send next
+#[derive(Debug)]
+pub enum Chunk {
+    Data(Vec<u8>),
+    /// Nothing yet, the loop being woken once there is
+    Pending,
+    /// The body is complete
+    End,
+    /// The body can't be completed, the client then seeing it cut short
+    Failed(io::Error),
+}
+
+/// The chunks of a response body, taken once. Clones share them.
+#[derive(Clone)]
+pub struct BodyStream {
+    source: Arc<Mutex<Source>>,
+}
+
+impl BodyStream {
+    /// Sends the items of `chunks` until it ends or yields an error
+    pub fn new<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = io::Result<Vec<u8>>>,
+        I::IntoIter: Send + 'static,
+    {
+        BodyStream { source: Arc::new(Mutex::new(Source::Iter(Box::new(chunks.into_iter())))) }
+    }
+
+    /// Sends what is written to the returned writer, until it's dropped
+    pub fn channel() -> (Self, BodyWriter) {
+        let (sender, receiver) = mpsc::sync_channel(CHANNEL_CHUNKS);
+        let stream = BodyStream { source: Arc::new(Mutex::new(Source::Channel(receiver))) };
+        (stream, BodyWriter { sender: Some(sender) })
+    }
+
+    /// The next chunk to send, empty ones skipped
+    pub fn next_chunk(&self) -> Chunk {
+        let mut source = self.source.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let next = match &mut *source {
+                Source::Iter(chunks) => match chunks.next() {
+                    Some(next) => next,
+                    None => return Chunk::End,
+                },
+                Source::Channel(receiver) => match receiver.try_recv() {
+                    Ok(next) => next,
+                    Err(TryRecvError::Empty) => return Chunk::Pending,
+                    Err(TryRecvError::Disconnected) => return Chunk::End,
+                },
+            };
+            match next {
+                Ok(data) if data.is_empty() => continue,
+                Ok(data) => return Chunk::Data(data),
+                Err(e) => return Chunk::Failed(e),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream").finish_non_exhaustive()
+    }
+}
+
+/// Writes the body of a [`BodyStream::channel`], from any thread. The body
+/// ends when the writer is dropped.
+#[derive(Debug)]
+pub struct BodyWriter {
+    /// Taken when dropped, for the loop to find the channel closed once woken
+    sender: Option<SyncSender<io::Result<Vec<u8>>>>,
+}
+
+impl BodyWriter {
+    fn send(&self, chunk: io::Result<Vec<u8>>) -> io::Result<()> {
+        let Some(sender) = &self.sender else { return Ok(()) };
+        sender.send(chunk).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The client is gone"))?;
+        waker::wake();
+        Ok(())
+    }
+
+    /// Cuts the body short, the client seeing it incomplete
+    pub fn abort(self, error: io::Error) {
+        let _ = self.send(Err(error));
+    }
+}
+
+impl io::Write for BodyWriter {
+    /// Blocks while the client is behind, failing once it left
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.send(Ok(buf.to_vec()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BodyWriter {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        waker::wake();
+    }
+}
+
+/// `data` framed as a chunk of a chunked body
+pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+/// The chunk ending a chunked body
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
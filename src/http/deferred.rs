@@ -0,0 +1,111 @@
+//! Responses given after the handler returned
+//!
+//! A handler answering with [`Response::deferred`] hands the request's
+//! [`Deferred`] to whatever will answer it, like a chat room keeping its
+//! waiting clients. The connection is parked meanwhile: kept open but out of
+//! the way of the others, until the response is given from any thread or the
+//! timeout answers it, which makes long polling endpoints.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::http::header::Header;
+use crate::http::response::Response;
+use crate::http::status::HttpStatusCode;
+use crate::http::waker;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum State {
+    Waiting,
+    Answered(Response),
+    /// The response was sent, or the client left
+    Done,
+}
+
+/// A parked request, answered once with `complete`. Clones answer the same
+/// request.
+#[derive(Clone)]
+pub struct Deferred {
+    state: Arc<Mutex<State>>,
+    /// Wait after which the request gets `on_timeout`
+    pub timeout: Duration,
+    on_timeout: Option<Arc<Response>>,
+}
+
+impl Deferred {
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Deferred { state: Arc::new(Mutex::new(State::Waiting)), timeout, on_timeout: None }
+    }
+
+    /// Answers the requests still waiting after the timeout, `204` by default
+    pub fn on_timeout(mut self, response: Response) -> Self {
+        self.on_timeout = Some(Arc::new(response));
+        self
+    }
+
+    /// Answers the request with `response`, returning false when it was
+    /// answered already or its client left
+    pub fn complete(&self, response: Response) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !matches!(*state, State::Waiting) {
+            return false;
+        }
+        *state = State::Answered(response);
+        drop(state);
+        waker::wake();
+        true
+    }
+
+    /// Whether the request still waits for its response, which registries
+    /// use to forget the others
+    pub fn is_waiting(&self) -> bool {
+        matches!(*self.state.lock().unwrap_or_else(|e| e.into_inner()), State::Waiting)
+    }
+
+    /// The response given, to be sent
+    pub fn take(&self) -> Option<Response> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match std::mem::replace(&mut *state, State::Done) {
+            State::Answered(response) => Some(response),
+            previous => {
+                *state = previous;
+                None
+            }
+        }
+    }
+
+    /// The response of a request that waited too long, `None` when it was
+    /// given meanwhile
+    pub fn expire(&self) -> Option<Response> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match std::mem::replace(&mut *state, State::Done) {
+            State::Waiting => Some(self.on_timeout.as_deref().cloned().unwrap_or_else(|| {
+                Response::new(HttpStatusCode::NoContent, vec![Header::from_str("content-length", "0")], None)
+            })),
+            State::Answered(response) => Some(response),
+            State::Done => None,
+        }
+    }
+
+    /// Gives up on the request, its client having left
+    pub fn abandon(&self) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = State::Done;
+    }
+}
+
+impl Default for Deferred {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deferred").field("timeout", &self.timeout).field("waiting", &self.is_waiting()).finish()
+    }
+}
@@ -0,0 +1,47 @@
+//! Escaping of values put into generated pages
+//!
+//! File names, request paths and error messages come from clients. Whenever
+//! they are written into HTML or JavaScript the server generates, they go
+//! through these helpers so that they are shown as text and never run.
+
+use serde::Serialize;
+
+/// `text` safe to put in HTML content or in a quoted attribute value
+pub fn html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `text` as a double-quoted JavaScript string literal
+pub fn js_string(text: &str) -> String {
+    js_value(&text)
+}
+
+/// `value` as a JavaScript literal, safe inside a `<script>` element too: the
+/// characters that could close the element or start a comment are written as
+/// `\u` escapes, which JavaScript reads back unchanged
+pub fn js_value<T: Serialize + ?Sized>(value: &T) -> String {
+    let json = serde_json::to_string_pretty(value).unwrap_or_else(|_| "null".to_string());
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        match c {
+            '<' => escaped.push_str("\\u003c"),
+            '>' => escaped.push_str("\\u003e"),
+            '&' => escaped.push_str("\\u0026"),
+            '\u{2028}' => escaped.push_str("\\u2028"),
+            '\u{2029}' => escaped.push_str("\\u2029"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,239 @@
+//! Server-Sent Events
+//!
+//! Events are published to an [`EventChannel`], shared by the code producing
+//! them and the routes streaming them. A response whose body is an
+//! [`EventStream`] keeps its connection open, and the event loop sends it each
+//! event of the channel as it comes, with a comment now and then so that
+//! proxies don't take the quiet connection for a dead one. The channel keeps
+//! its last events, which a client reconnecting with `Last-Event-ID` gets
+//! first.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::http::request::Request;
+use crate::http::waker;
+
+pub const DEFAULT_HISTORY: usize = 100;
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+/// Bytes waiting for a client past which it's dropped, to reconnect with
+/// `Last-Event-ID` once it keeps up again
+const MAX_PENDING: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Event {
+    /// Sent back by reconnecting clients as `Last-Event-ID`
+    pub id: Option<String>,
+    /// The type clients listen to, "message" when `None`
+    pub event: Option<String>,
+    pub data: String,
+    /// Wait clients leave before reconnecting
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Event { data: data.to_string(), ..Event::default() }
+    }
+
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    pub fn with_event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The event in the `text/event-stream` format, each line of its data on
+    /// its own `data:` field
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Line breaks would end the field early
+        let field = |value: &str| value.replace(['\r', '\n'], "");
+        let mut text = String::new();
+        if let Some(id) = &self.id {
+            text.push_str(&format!("id: {}\n", field(id)));
+        }
+        if let Some(event) = &self.event {
+            text.push_str(&format!("event: {}\n", field(event)));
+        }
+        if let Some(retry) = self.retry {
+            text.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            text.push_str(&format!("data: {}\n", line));
+        }
+        text.push('\n');
+        text.into_bytes()
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    /// The last events with their sequence numbers, oldest first
+    events: VecDeque<(u64, Event)>,
+    /// Sequence number of the last event published
+    last: u64,
+}
+
+/// Events published to the streams subscribed to it
+#[derive(Debug)]
+pub struct EventChannel {
+    history: Mutex<History>,
+    /// Events kept for the clients reconnecting
+    history_size: usize,
+    subscribers: AtomicUsize,
+}
+
+impl EventChannel {
+    pub fn new() -> Arc<Self> {
+        Self::with_history(DEFAULT_HISTORY)
+    }
+
+    pub fn with_history(history_size: usize) -> Arc<Self> {
+        Arc::new(EventChannel { history: Mutex::new(History::default()), history_size, subscribers: AtomicUsize::new(0) })
+    }
+
+    /// Sends `event` to every stream subscribed to the channel
+    pub fn publish(&self, event: Event) {
+        {
+            let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+            history.last += 1;
+            let seq = history.last;
+            history.events.push_back((seq, event));
+            while history.events.len() > self.history_size.max(1) {
+                history.events.pop_front();
+            }
+        }
+        waker::wake();
+    }
+
+    /// Streams currently open on the channel
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.load(Ordering::SeqCst)
+    }
+
+    /// The sequence number streams start after: the event with `last_event_id`
+    /// when still kept, else the last one
+    fn cursor(&self, last_event_id: Option<&str>) -> u64 {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        last_event_id
+            .and_then(|id| history.events.iter().rev().find(|(_, event)| event.id.as_deref() == Some(id)))
+            .map_or(history.last, |(seq, _)| *seq)
+    }
+
+    /// The events kept that came after `cursor`
+    fn since(&self, cursor: u64) -> Vec<(u64, Event)> {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        if history.last <= cursor {
+            return Vec::new();
+        }
+        history.events.iter().filter(|(seq, _)| *seq > cursor).cloned().collect()
+    }
+}
+
+/// The body of a response streaming the events of a channel
+#[derive(Debug, Clone)]
+pub struct EventStream {
+    pub channel: Arc<EventChannel>,
+    /// The last event the client got, from its `Last-Event-ID`
+    pub last_event_id: Option<String>,
+    /// Silence after which a comment is sent
+    pub heartbeat: Duration,
+    /// Wait clients are told to leave before reconnecting
+    pub retry: Option<Duration>,
+}
+
+impl EventStream {
+    /// Streams the events of `channel` to the client of `request`, resuming
+    /// after the last one it got
+    pub fn new(channel: &Arc<EventChannel>, request: &Request) -> Self {
+        let last_event_id = request.headers.iter()
+            .find(|header| header.name.to_string().eq_ignore_ascii_case("last-event-id"))
+            .map(|header| header.value.value.trim().to_string())
+            .filter(|id| !id.is_empty());
+        EventStream { channel: channel.clone(), last_event_id, heartbeat: DEFAULT_HEARTBEAT, retry: None }
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Starts following the channel, after the event the client got last
+    pub fn subscribe(self) -> Subscription {
+        self.channel.subscribers.fetch_add(1, Ordering::SeqCst);
+        let mut subscription = Subscription {
+            cursor: self.channel.cursor(self.last_event_id.as_deref()),
+            channel: self.channel,
+            heartbeat: self.heartbeat,
+            last_sent: Instant::now(),
+            pending: Vec::new(),
+        };
+        if let Some(retry) = self.retry {
+            subscription.pending.extend_from_slice(format!("retry: {}\n\n", retry.as_millis()).as_bytes());
+        }
+        subscription
+    }
+}
+
+/// A stream following its channel
+#[derive(Debug)]
+pub struct Subscription {
+    channel: Arc<EventChannel>,
+    /// Sequence number of the last event queued
+    cursor: u64,
+    heartbeat: Duration,
+    last_sent: Instant,
+    /// Bytes the client didn't take yet
+    pending: Vec<u8>,
+}
+
+impl Subscription {
+    /// Queues the events published since the last poll, or a heartbeat after
+    /// a silence
+    pub fn poll(&mut self) {
+        for (seq, event) in self.channel.since(self.cursor) {
+            self.pending.extend_from_slice(&event.to_bytes());
+            self.cursor = seq;
+            self.last_sent = Instant::now();
+        }
+        if self.last_sent.elapsed() >= self.heartbeat {
+            self.pending.extend_from_slice(b": heartbeat\n\n");
+            self.last_sent = Instant::now();
+        }
+    }
+
+    /// The bytes waiting for the client
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    /// Whether the client takes the events slower than they're published
+    pub fn is_lagging(&self) -> bool {
+        self.pending.len() > MAX_PENDING
+    }
+
+    /// Forgets the `len` first bytes waiting, which the client took
+    pub fn sent(&mut self, len: usize) {
+        self.pending.drain(..len);
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.channel.subscribers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::hash::Hash;
 use std::time::SystemTime;
 use std::fmt;
@@ -18,13 +19,6 @@ mod content {
     pub use super::{ParsedContentType, ParsedContentDisposition, ContentType};
 }
 
-// ============= Cookie Handling =============
-mod cookie {
-    use super::*;
-    
-    pub use super::{Cookie, CookieOptions, SameSitePolicy};
-}
-
 // ============= Main Structures =============
 #[derive(Debug, Clone)]
 pub struct Header {
@@ -32,6 +26,11 @@ pub struct Header {
     pub value: HeaderValue,
 }
 
+/// The headers of a message in the order they came, duplicates kept, as
+/// `Cookie` and custom headers may be sent more than once
+#[derive(Debug, Clone, Default)]
+pub struct Headers(Vec<Header>);
+
 #[derive(Debug, Clone)]
 pub struct HeaderValue {
     pub value: String,
@@ -59,7 +58,10 @@ pub enum HeaderParsedValue {
     Server(String),
     Date(SystemTime),
     Custom(String),
+    /// A `Set-Cookie` value, attributes included
     Cookie(Cookie),
+    /// The cookies a `Cookie` header sends
+    Cookies(CookieJar),
     Raw,
 }
 
@@ -70,6 +72,7 @@ pub enum HeaderName {
     ContentLength,
     ContentDisposition,
     Connection,
+    KeepAlive,
     TransferEncoding,
     Cookie,
     SetCookie,
@@ -93,6 +96,8 @@ pub enum HeaderName {
     
     // Security headers
     StrictTransportSecurity,
+    Authorization,
+    WwwAuthenticate,
     
     // Custom header
     Custom(String),
@@ -126,6 +131,13 @@ pub struct Cookie {
     pub options: CookieOptions,
 }
 
+/// The cookies a client sends in its `Cookie` headers, as name and value
+/// pairs in the order they came
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct CookieOptions {
     pub http_only: bool,
@@ -150,6 +162,36 @@ pub enum Connection {
     Close,
 }
 
+/// Bytes `start` to `end`, both included, of a representation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// A media range of an `Accept` header, like `text/*`, and its weight
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    pub mime: String,
+    pub q: f32,
+}
+
+/// The media ranges of an `Accept` header, in the order they were sent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Accept {
+    pub ranges: Vec<MediaRange>,
+}
+
+/// What a `Range` header asks of a representation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No range the server serves, the whole representation is sent
+    Full,
+    Partial(ByteRange),
+    /// No byte of the representation is in the range
+    Unsatisfiable,
+}
+
 // ============= Header Implementations =============
 impl Header {
     pub fn new(name: HeaderName, value: HeaderValue) -> Header {
@@ -177,9 +219,104 @@ impl Header {
 
         Header::new(header_name, header_value)
     }
+}
+
+// ============= Headers Implementations =============
+impl Headers {
+    pub fn new() -> Headers {
+        Headers(Vec::new())
+    }
+
+    pub fn push(&mut self, header: Header) {
+        self.0.push(header);
+    }
+
+    /// The first header called `name`
+    pub fn get(&self, name: &HeaderName) -> Option<&Header> {
+        self.0.iter().find(|header| header.name.matches(name))
+    }
+
+    /// Every header called `name`, in the order they came
+    pub fn get_all(&self, name: &HeaderName) -> Vec<&Header> {
+        self.0.iter().filter(|header| header.name.matches(name)).collect()
+    }
+
+    /// The values of every header called `name`, split on commas as when
+    /// they are sent on one line
+    pub fn values(&self, name: &HeaderName) -> Vec<&str> {
+        self.get_all(name).into_iter()
+            .flat_map(|header| header.value.value.split(','))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
 
+    pub fn contains(&self, name: &HeaderName) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Header> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
+    pub fn into_vec(self) -> Vec<Header> {
+        self.0
+    }
+}
+
+impl From<Vec<Header>> for Headers {
+    fn from(headers: Vec<Header>) -> Self {
+        Headers(headers)
+    }
+}
+
+impl FromIterator<Header> for Headers {
+    fn from_iter<I: IntoIterator<Item = Header>>(headers: I) -> Self {
+        Headers(headers.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = Header;
+    type IntoIter = std::vec::IntoIter<Header>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = &'a Header;
+    type IntoIter = std::slice::Iter<'a, Header>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+// ============= Validation =============
+/// Whether `name` may be a header name, a token of RFC 9110
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// `value` without the control characters that could end a header line early,
+/// like CR, LF and NUL, so that values taken from requests or scripts can't add
+/// headers of their own. Tabs are kept.
+pub fn sanitize_value(value: &str) -> Cow<'_, str> {
+    if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+        Cow::Owned(value.chars().filter(|c| !c.is_ascii_control() || *c == '\t').collect())
+    } else {
+        Cow::Borrowed(value)
+    }
 }
 
 // ============= HeaderName Implementations =============
@@ -191,6 +328,7 @@ impl HeaderName {
             "content-disposition" => HeaderName::ContentDisposition,
             "transfer-encoding" => HeaderName::TransferEncoding,
             "connection" => HeaderName::Connection,
+            "keep-alive" => HeaderName::KeepAlive,
             "date" => HeaderName::Date,
             "host" => HeaderName::Host,
             "cookie" => HeaderName::Cookie,
@@ -205,10 +343,21 @@ impl HeaderName {
             "etag" => HeaderName::ETag,
             "last-modified" => HeaderName::LastModified,
             "strict-transport-security" => HeaderName::StrictTransportSecurity,
+            "authorization" => HeaderName::Authorization,
+            "www-authenticate" => HeaderName::WwwAuthenticate,
             _ => HeaderName::Custom(name.to_string()),
         }
     }
 
+    /// Whether `self` and `other` name the same header, custom names being
+    /// compared whatever their case
+    pub fn matches(&self, other: &HeaderName) -> bool {
+        match (self, other) {
+            (HeaderName::Custom(name), HeaderName::Custom(other)) => name.eq_ignore_ascii_case(other),
+            _ => self == other,
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             HeaderName::ContentType => "Content-Type",
@@ -216,6 +365,7 @@ impl HeaderName {
             HeaderName::ContentDisposition => "Content-Disposition",
             HeaderName::TransferEncoding => "Transfer-Encoding",
             HeaderName::Connection => "Connection",
+            HeaderName::KeepAlive => "Keep-Alive",
             HeaderName::Cookie => "Cookie",
             HeaderName::SetCookie => "Set-Cookie",
             HeaderName::Location => "Location",
@@ -230,6 +380,8 @@ impl HeaderName {
             HeaderName::ETag => "ETag",
             HeaderName::LastModified => "Last-Modified",
             HeaderName::StrictTransportSecurity => "Strict-Transport-Security",
+            HeaderName::Authorization => "Authorization",
+            HeaderName::WwwAuthenticate => "WWW-Authenticate",
             HeaderName::Custom(_) => "", // Returns empty string for custom headers
         }
     }
@@ -291,6 +443,151 @@ impl ParsedContentDisposition {
     }
 }
 
+// ============= Accept Implementation =============
+
+impl MediaRange {
+    /// 3 for a full type, 2 for `type/*` and 1 for `*/*`
+    fn specificity(&self) -> u8 {
+        match self.mime.split_once('/') {
+            Some(("*", _)) => 1,
+            Some((_, "*")) => 2,
+            _ => 3,
+        }
+    }
+
+    fn matches(&self, mime: &str) -> bool {
+        match self.mime.split_once('/') {
+            Some(("*", _)) => true,
+            Some((kind, "*")) => mime.split_once('/').is_some_and(|(other, _)| other.eq_ignore_ascii_case(kind)),
+            _ => self.mime.eq_ignore_ascii_case(mime),
+        }
+    }
+}
+
+impl Accept {
+    /// Parses the value of an `Accept` header. Entries that aren't media
+    /// ranges, or whose weight isn't a number, are left out.
+    pub fn parse(value: &str) -> Accept {
+        let ranges = value.split(',')
+            .filter_map(|entry| {
+                let mut params = entry.split(';');
+                let mime = params.next()?.trim().to_ascii_lowercase();
+                if !mime.contains('/') {
+                    return None;
+                }
+                let q = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                    Some(q) => q.trim().parse::<f32>().ok()?.clamp(0.0, 1.0),
+                    None => 1.0,
+                };
+                Some(MediaRange { mime, q })
+            })
+            .collect();
+        Accept { ranges }
+    }
+
+    /// Weight of `mime`, given by the most specific range it is in, 0 when none
+    pub fn weight(&self, mime: &str) -> f32 {
+        self.ranges.iter()
+            .filter(|range| range.matches(mime))
+            .max_by_key(|range| range.specificity())
+            .map_or(0.0, |range| range.q)
+    }
+
+    /// The type of `offered` weighing the most, the first one of those that
+    /// weigh the same. `None` when the client takes none of them.
+    pub fn preferred<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let mut best: Option<(&'a str, f32)> = None;
+        for mime in offered {
+            let weight = self.weight(mime);
+            if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+                best = Some((mime, weight));
+            }
+        }
+        best.map(|(mime, _)| mime)
+    }
+}
+
+// ============= Range Implementation =============
+
+impl ByteRange {
+    /// Parses the value of a `Range` header against a representation of `len`
+    /// bytes. Only a single range of bytes is served, any other request, lists
+    /// of ranges and malformed values included, being answered in full as
+    /// RFC 9110 allows.
+    pub fn parse(value: &str, len: u64) -> RangeRequest {
+        let Some((unit, spec)) = value.trim().split_once('=') else {
+            return RangeRequest::Full;
+        };
+        if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+            return RangeRequest::Full;
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return RangeRequest::Full;
+        };
+        // Signs `parse` would take aren't part of the syntax
+        let number = |digits: &str| if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse::<u64>().ok()
+        } else {
+            None
+        };
+
+        if first.is_empty() {
+            // The last `last` bytes
+            return match number(last) {
+                Some(0) => RangeRequest::Unsatisfiable,
+                Some(_) if len == 0 => RangeRequest::Unsatisfiable,
+                Some(suffix) => RangeRequest::Partial(ByteRange { start: len.saturating_sub(suffix), end: len - 1 }),
+                None => RangeRequest::Full,
+            };
+        }
+        let Some(start) = number(first) else {
+            return RangeRequest::Full;
+        };
+        let end = match last {
+            "" => u64::MAX,
+            last => match number(last) {
+                Some(end) if end >= start => end,
+                _ => return RangeRequest::Full,
+            },
+        };
+        if start >= len {
+            return RangeRequest::Unsatisfiable;
+        }
+        RangeRequest::Partial(ByteRange { start, end: end.min(len - 1) })
+    }
+
+    /// Value of the `Content-Range` header of the range of a representation of `len` bytes
+    pub fn content_range(&self, len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, len)
+    }
+}
+
+/// Whether the value of an `If-Range` header still designates the
+/// representation last modified at `last_modified`, the range being served
+/// then. Entity tags never match, those of static files being weak ones
+/// `If-Range` can't rely on.
+pub fn if_range_matches(value: &str, last_modified: SystemTime) -> bool {
+    let value = value.trim();
+    if value.starts_with('"') || value.starts_with("W/") {
+        return false;
+    }
+    // Dates are only exact to the second, hence the comparison of formatted ones
+    httpdate::parse_http_date(value)
+        .is_ok_and(|date| httpdate::fmt_http_date(date) == httpdate::fmt_http_date(last_modified))
+}
+
+/// Whether `etag` is one of the entity tags listed by an `If-None-Match`
+/// header, or the header is `*`. Tags are compared weakly, a `W/` prefix
+/// being ignored on both sides.
+pub fn if_none_match_matches(value: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag).to_string()
+    };
+    let etag = opaque(etag);
+    value.trim() == "*" || value.split(',').any(|tag| opaque(tag) == etag)
+}
+
 // ============= HeaderParsedValue Implementations =============
 impl HeaderParsedValue {
     pub fn from_str(header_name: &HeaderName, value: &str) -> Self {
@@ -319,7 +616,8 @@ impl HeaderParsedValue {
                 "close" => HeaderParsedValue::Connection(Connection::Close),
                 _ => HeaderParsedValue::Raw,
             },
-            HeaderName::Cookie => {
+            HeaderName::Cookie => HeaderParsedValue::Cookies(CookieJar::parse(value)),
+            HeaderName::SetCookie => {
                 if let Some(cookie) = Cookie::parse(value) {
                     HeaderParsedValue::Cookie(cookie)
                 } else {
@@ -335,6 +633,58 @@ impl HeaderParsedValue {
     }
 }
 
+// ============= CookieJar Implementations =============
+impl CookieJar {
+    /// Reads every `name=value` pair of a `Cookie` header value, like
+    /// "session_id=abc; theme=dark". Pairs without a name are skipped and
+    /// quoted values unquoted.
+    pub fn parse(value: &str) -> CookieJar {
+        let mut jar = CookieJar::default();
+        jar.add(value);
+        jar
+    }
+
+    /// The cookies of every header in `headers`, clients being allowed to
+    /// split them over several
+    pub fn from_headers(headers: &Headers) -> CookieJar {
+        let mut jar = CookieJar::default();
+        for header in headers {
+            jar.add(&header.value.value);
+        }
+        jar
+    }
+
+    fn add(&mut self, value: &str) {
+        for pair in value.split(';') {
+            let Some((name, value)) = pair.split_once('=') else { continue };
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+            self.cookies.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// The value of the first cookie called `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.iter().find(|(cookie, _)| cookie == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
 // ============= Cookie Implementations  =============
 impl Cookie {
     pub fn new(name: &str, value: &str) -> Cookie {
@@ -441,7 +791,7 @@ impl Default for CookieOptions {
 // ============= Display Implementations =============
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.name, self.value)
+        write!(f, "{}: {}", self.name, sanitize_value(&self.value.value))
     }
 }
 
@@ -2,7 +2,14 @@ pub mod response;
 pub mod request;
 pub mod header;
 pub mod body;
+pub mod multipart;
+pub mod event_stream;
+pub mod deferred;
+pub mod body_stream;
+pub mod upgrade;
+pub mod waker;
 pub mod status;
+pub mod escape;
 
 // #[cfg(test)]
 // mod tests;
\ No newline at end of file
@@ -0,0 +1,184 @@
+//! Incremental multipart/form-data parser
+//!
+//! The parser is fed arbitrary slices of a multipart body and reports parts to a
+//! `PartSink` as soon as their bytes are known, so large file parts never need to
+//! be held in memory.
+
+use std::str;
+use crate::http::body::BodyError;
+use crate::http::header::{Header, HeaderName, ParsedContentDisposition, ContentType};
+
+/// Metadata of one multipart part, taken from its headers
+#[derive(Debug, Clone, Default)]
+pub struct PartInfo {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl PartInfo {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+}
+
+/// Receives the parts produced by a `MultipartParser`
+pub trait PartSink {
+    fn begin_part(&mut self, part: &PartInfo) -> Result<(), BodyError>;
+    fn part_data(&mut self, data: &[u8]) -> Result<(), BodyError>;
+    fn end_part(&mut self) -> Result<(), BodyError>;
+}
+
+#[derive(Debug, PartialEq)]
+enum ParserState {
+    /// Skipping anything before the first boundary
+    Preamble,
+    /// Right after a boundary, deciding between a new part and the end marker
+    AfterBoundary,
+    /// Reading the headers of a part
+    Headers,
+    /// Streaming the content of a part
+    Body,
+    /// Closing boundary seen
+    Done,
+}
+
+pub struct MultipartParser {
+    /// `\r\n--boundary`, the delimiter preceding every part
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: ParserState,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str) -> Self {
+        MultipartParser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first boundary isn't preceded by a line break in the body
+            buffer: b"\r\n".to_vec(),
+            state: ParserState::Preamble,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == ParserState::Done
+    }
+
+    /// Feeds the next slice of the body, forwarding complete information to `sink`
+    pub fn feed(&mut self, data: &[u8], sink: &mut dyn PartSink) -> Result<(), BodyError> {
+        if self.state == ParserState::Done {
+            return Ok(());
+        }
+        self.buffer.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                ParserState::Preamble => match find_subsequence(&self.buffer, &self.delimiter) {
+                    Some(pos) => {
+                        self.buffer.drain(..pos + self.delimiter.len());
+                        self.state = ParserState::AfterBoundary;
+                    }
+                    None => {
+                        let keep = self.delimiter.len().min(self.buffer.len());
+                        self.buffer.drain(..self.buffer.len() - keep);
+                        return Ok(());
+                    }
+                },
+                ParserState::AfterBoundary => {
+                    if self.buffer.len() < 2 {
+                        return Ok(());
+                    }
+                    if self.buffer.starts_with(b"--") {
+                        self.buffer.clear();
+                        self.state = ParserState::Done;
+                        return Ok(());
+                    }
+                    // Transport padding may follow the boundary before its CRLF
+                    match find_subsequence(&self.buffer, b"\r\n") {
+                        Some(pos) => {
+                            self.buffer.drain(..pos + 2);
+                            self.state = ParserState::Headers;
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                ParserState::Headers => match find_subsequence(&self.buffer, b"\r\n\r\n") {
+                    Some(pos) => {
+                        let part = parse_part_headers(&self.buffer[..pos]);
+                        self.buffer.drain(..pos + 4);
+                        sink.begin_part(&part)?;
+                        self.state = ParserState::Body;
+                    }
+                    None => return Ok(()),
+                },
+                ParserState::Body => match find_subsequence(&self.buffer, &self.delimiter) {
+                    Some(pos) => {
+                        if pos > 0 {
+                            sink.part_data(&self.buffer[..pos])?;
+                        }
+                        sink.end_part()?;
+                        self.buffer.drain(..pos + self.delimiter.len());
+                        self.state = ParserState::AfterBoundary;
+                    }
+                    None => {
+                        // Hold back enough bytes to recognise a delimiter split across feeds
+                        let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+                        if safe > 0 {
+                            sink.part_data(&self.buffer[..safe])?;
+                            self.buffer.drain(..safe);
+                        }
+                        return Ok(());
+                    }
+                },
+                ParserState::Done => return Ok(()),
+            }
+        }
+    }
+
+    /// Checks that the body ended with the closing boundary
+    pub fn finish(&self) -> Result<(), BodyError> {
+        if self.is_done() {
+            Ok(())
+        } else {
+            Err(BodyError::MultipartError("Missing closing boundary".to_string()))
+        }
+    }
+}
+
+fn parse_part_headers(data: &[u8]) -> PartInfo {
+    let mut part = PartInfo::default();
+
+    for line in data.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Ok(line) = str::from_utf8(line) else { continue };
+        let Some((name, value)) = line.split_once(':') else { continue };
+
+        let header = Header::from_str(name.trim(), value.trim());
+        match header.name {
+            HeaderName::ContentDisposition => {
+                if let Some(disposition) = ParsedContentDisposition::parse_content_disposition(&header) {
+                    part.name = disposition.params.get("name").map(|v| unquote(v));
+                    part.filename = disposition.params.get("filename").map(|v| unquote(v));
+                }
+            }
+            HeaderName::ContentType => {
+                part.content_type = ContentType::parse_content_type(&header).map(|ct| ct.mime);
+            }
+            _ => {}
+        }
+    }
+
+    part
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches('"').to_string()
+}
+
+pub(crate) fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -1,8 +1,11 @@
 use crate::http::header::Header;
 use crate::http::body::{Body, FormUrlEncoded, BodyError};
-use crate::http::header::{HeaderName, HeaderParsedValue, HeaderValue, ContentType};
+use crate::http::header::{is_valid_name, Accept, HeaderName, Headers, HeaderParsedValue, HeaderValue, ContentType};
+use crate::server::session::session::Session;
 use httparse::Request as HttparseRequest;
 use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HttpMethod {
@@ -41,7 +44,29 @@ pub struct Request {
     pub uri: String,
     pub version: String,
     pub headers: Vec<Header>,
-    pub body: Option<Body>
+    pub body: Option<Body>,
+    /// The body as received, before parsing, which proxy routes forward unchanged
+    pub raw_body: Option<Arc<[u8]>>,
+    /// Address of the client that sent the request, when known, which a trusted
+    /// proxy may have named
+    pub client_addr: Option<IpAddr>,
+    /// Address the request came from, the client's or a proxy's
+    pub peer_addr: Option<IpAddr>,
+    /// Whether the request came over TLS
+    pub secure: bool,
+    /// Who sent the request, once the authentication of its route passed
+    pub identity: Option<Box<Identity>>,
+    /// The client's session, on the routes requiring one
+    pub session: Option<Box<Session>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    /// "Basic", "Digest" or "Bearer"
+    pub scheme: &'static str,
+    pub user: String,
+    /// Claims of a bearer token
+    pub claims: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
 pub struct RequestBuilder {
@@ -122,7 +147,13 @@ impl Request {
             uri,    
             version,
             headers,
-            body
+            body,
+            raw_body: None,
+            client_addr: None,
+            peer_addr: None,
+            secure: false,
+            identity: None,
+            session: None,
         }
     }
 
@@ -147,11 +178,135 @@ impl Request {
         self.headers.iter().find(|&h| h.name == name).cloned()
     }
 
+    /// Every header called `name`, in the order they came, for the headers
+    /// a client may send more than once like `Cookie`
+    pub fn get_headers(&self, name: HeaderName) -> Headers {
+        self.headers.iter().filter(|h| h.name.matches(&name)).cloned().collect()
+    }
+
+    /// The type of `offered` the client prefers according to its `Accept`
+    /// header, the first one when it sends none. `None` when it takes none of them.
+    pub fn negotiate<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        match self.get_header(HeaderName::Accept) {
+            Some(accept) => Accept::parse(&accept.value.value).preferred(offered),
+            None => offered.first().copied(),
+        }
+    }
+
+    /// Whether the client wants its connection kept open after the response.
+    /// HTTP/1.1 connections are unless the request says `Connection: close`,
+    /// HTTP/1.0 ones only when it says `Connection: keep-alive`.
+    pub fn wants_keep_alive(&self) -> bool {
+        let tokens = self.get_header(HeaderName::Connection)
+            .map(|header| header.value.value.split(',').map(|token| token.trim().to_ascii_lowercase()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if tokens.iter().any(|token| token == "close") {
+            return false;
+        }
+        self.version != "HTTP/1.0" || tokens.iter().any(|token| token == "keep-alive")
+    }
+
+    /// Whether the request carries a body, as received or as announced by its headers
+    pub fn has_body(&self) -> bool {
+        self.body.is_some()
+            || self.get_header(HeaderName::TransferEncoding).is_some()
+            || self.get_header(HeaderName::ContentLength)
+                .is_some_and(|header| header.value.value.trim().parse::<usize>().is_ok_and(|length| length > 0))
+    }
+
+
+}
+
+/// Versions requests may be sent with, others being answered with `505`
+pub const SUPPORTED_VERSIONS: [&str; 2] = ["HTTP/1.0", "HTTP/1.1"];
+
+/// Checks the version ending the request line of `head`. Lines that don't end
+/// with one are left to the parser, which refuses them.
+pub fn check_version(head: &[u8]) -> Result<(), String> {
+    let line = head.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let Some(version) = line.trim_end().rsplit(' ').next().filter(|token| token.starts_with("HTTP/")) else {
+        return Ok(());
+    };
+    if SUPPORTED_VERSIONS.contains(&version) {
+        return Ok(());
+    }
+    Err(format!("{} is not supported, only HTTP/1.0 and HTTP/1.1 are", version))
+}
+
+/// Headers a request may have at most, the parser reading no more
+pub const MAX_HEADERS: usize = 100;
+
+/// Checks `head`, whole or as much of it as came, against the `max_size` bytes
+/// and `max_count` headers a request may have. The request line counts in the size.
+pub fn check_header_limits(head: &[u8], max_size: usize, max_count: usize) -> Result<(), String> {
+    if head.len() > max_size {
+        return Err(format!("Request headers exceed {} bytes", max_size));
+    }
+    let count = head.split(|&b| b == b'\n')
+        .skip(1)
+        .filter(|line| !line.is_empty() && *line != b"\r")
+        .count();
+    if count > max_count {
+        return Err(format!("Request has more than {} headers", max_count));
+    }
+    Ok(())
+}
+
+/// Checks the head of a request, up to the blank line ending it, before anything
+/// relies on its headers. Refuses NUL bytes, CR or LF outside of line endings,
+/// invalid header names (folded lines included) and more than one Content-Length,
+/// which proxies could disagree on.
+pub fn validate_head(head: &[u8]) -> Result<(), String> {
+    if head.contains(&0) {
+        return Err("NUL byte in the request head".to_string());
+    }
+    // Every line then ends with a CR, the last one included
+    let head = head.strip_suffix(b"\n\r\n").unwrap_or(head);
+    let mut content_length = false;
+    for (number, line) in head.split(|&b| b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").ok_or("Bare LF in the request head")?;
+        if line.contains(&b'\r') {
+            return Err("Bare CR in the request head".to_string());
+        }
+        // The request line
+        if number == 0 {
+            continue;
+        }
+        let line = String::from_utf8_lossy(line);
+        let (name, value) = line.split_once(':').ok_or_else(|| format!("Header line {} has no colon", number))?;
+        if !is_valid_name(name) {
+            return Err(format!("Invalid header name on line {}", number));
+        }
+        if name.eq_ignore_ascii_case("content-length") {
+            let value = value.trim();
+            if content_length {
+                return Err("Duplicate Content-Length header".to_string());
+            }
+            content_length = true;
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err("Invalid Content-Length".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The body of a request with `headers`, read as its `Content-Type` says.
+/// Without one it's kept as sent, in binary, as RFC 9110 has recipients
+/// take such content as `application/octet-stream`.
+fn parse_body(headers: &[Header], data: &[u8]) -> Result<Body, BodyError> {
+    let Some(content_type) = headers.iter().find(|h| h.name == HeaderName::ContentType) else {
+        return Ok(Body::binary(data.to_vec()));
+    };
+    let parsed_content_type = ContentType::parse_content_type(content_type).unwrap();
+    let boundary = parsed_content_type.params.get("boundary").map(String::as_str);
 
+    Body::from_mime(&parsed_content_type.mime, data.to_vec(), boundary)
 }
 
 pub fn parse_request(request: &[u8]) -> Option<Request> {
-    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
     let mut req = HttparseRequest::new(&mut headers);
 
     let header_len = match req.parse(request) {
@@ -162,19 +317,11 @@ pub fn parse_request(request: &[u8]) -> Option<Request> {
 
     let headers = req.headers
         .iter()
-        .map(|h| Header::from_str(h.name, std::str::from_utf8(h.value).unwrap())).collect::<Vec<Header>>();
+        .map(|h| Header::from_str(h.name, &String::from_utf8_lossy(h.value))).collect::<Vec<Header>>();
 
 
     let result = if request.len() > header_len {
-        let body_data = &request[header_len..];
-        let content_type = headers.iter()
-            .find(|h| h.name == HeaderName::ContentType)?;
-
-        let parsed_content_type = ContentType::parse_content_type(content_type).unwrap();
-        let boundary = parsed_content_type.params.get("boundary")?;
-
-
-        Body::from_mime(&parsed_content_type.mime, body_data.to_vec(), Some(boundary))
+        parse_body(&headers, &request[header_len..])
     } else {
         Err(BodyError::EmptyBody("No body data found".to_string()))
     };
@@ -184,11 +331,16 @@ pub fn parse_request(request: &[u8]) -> Option<Request> {
         Err(_) => None
     };
 
-    Some(Request::new(
+    let mut parsed = Request::new(
         HttpMethod::from_str(req.method.unwrap()),
         req.path.unwrap().to_string(),
-        req.version.unwrap().to_string(),
+        // httparse gives the minor version only, the parse failing on others than 1.x
+        format!("HTTP/1.{}", req.version.unwrap()),
         headers,
         body
-    ))
+    );
+    if request.len() > header_len {
+        parsed.raw_body = Some(Arc::from(&request[header_len..]));
+    }
+    Some(parsed)
 }
\ No newline at end of file
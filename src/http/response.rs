@@ -1,8 +1,13 @@
+use std::io::Write;
 use std::vec;
 
 use crate::http::header::Header;
 use crate::http::body::Body;
+use crate::http::deferred::Deferred;
+use crate::http::body_stream::BodyStream;
+use crate::http::event_stream::EventStream;
 use crate::http::status::HttpStatusCode;
+use crate::http::upgrade::Upgrade;
 use crate::http::header::{HeaderName, HeaderValue, HeaderParsedValue, ContentType, Cookie, CookieOptions};
 
 #[derive(Clone)]
@@ -41,6 +46,11 @@ impl ResponseBuilder {
         self
     }
 
+    pub fn headers(mut self, headers: Vec<Header>) -> ResponseBuilder {
+        self.headers.extend(headers);
+        self
+    }
+
     pub fn setcookie(mut self, name: &str, value: &str) -> ResponseBuilder {
         let header = Header::from_str("set-cookie", &format!("{}={}", name, value));
         self.headers.push(header);
@@ -99,6 +109,50 @@ impl Response {
         )
     }
 
+    /// Streams the events of `stream`, without a length since the body lasts
+    /// as long as the connection
+    pub fn event_stream(stream: EventStream) -> Response {
+        Response::new(
+            HttpStatusCode::Ok,
+            vec![
+                Header::from_str("content-type", "text/event-stream"),
+                Header::from_str("cache-control", "no-cache"),
+            ],
+            Some(Body::EventStream(stream))
+        )
+    }
+
+    /// Parks the request until `deferred` is given its response
+    pub fn deferred(deferred: Deferred) -> Response {
+        Response::new(HttpStatusCode::Ok, Vec::new(), Some(Body::Deferred(deferred)))
+    }
+
+    /// Sends the chunks of `stream` as they're produced, the body's length
+    /// being unknown
+    pub fn stream(content_type: &str, stream: BodyStream) -> Response {
+        Response::new(
+            HttpStatusCode::Ok,
+            vec![
+                Header::from_str("content-type", content_type),
+                Header::from_str("transfer-encoding", "chunked"),
+            ],
+            Some(Body::Stream(stream))
+        )
+    }
+
+    /// Switches the connection to the protocol of `upgrade`, whose handler
+    /// gets the socket once this is sent
+    pub fn upgrade(upgrade: Upgrade) -> Response {
+        Response::new(
+            HttpStatusCode::SwitchingProtocols,
+            vec![
+                Header::from_str("upgrade", &upgrade.protocol),
+                Header::from_str("connection", "Upgrade"),
+            ],
+            Some(Body::Upgrade(upgrade))
+        )
+    }
+
     pub fn response_with_json(data: serde_json::Value, status: HttpStatusCode) -> Response {
         let body = Body::json(data);
 
@@ -130,6 +184,33 @@ impl Response {
         }
     }
 
+    /// Serializes the response into `out`, without the intermediate strings
+    /// of `to_string`. Binary bodies are written as they are.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.write_with(out, &[]);
+    }
+
+    /// Like `write_to`, with the `extra` headers after the response's own
+    pub fn write_with(&self, out: &mut Vec<u8>, extra: &[Header]) {
+        // Writes to a `Vec` can't fail
+        let _ = write!(out, "{} {}\r\n", self.version, self.status_code.clone() as u16);
+        for header in self.headers.iter().chain(extra) {
+            let _ = write!(out, "{}\r\n", header);
+        }
+        out.extend_from_slice(b"\r\n");
+        match &self.body {
+            Some(Body::Text(text)) => out.extend_from_slice(text.as_bytes()),
+            Some(Body::Binary(data)) => out.extend_from_slice(data),
+            Some(Body::Json(json)) => {
+                let _ = serde_json::to_writer(&mut *out, json);
+            }
+            Some(body) => {
+                let _ = write!(out, "{}", body);
+            }
+            None => {}
+        }
+    }
+
     pub fn to_string(self) -> String {
         let mut response = format!("{} {}\r\n", self.version, self.status_code as u16);
         for header in self.headers {
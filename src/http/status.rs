@@ -2,10 +2,14 @@ use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum HttpStatusCode {
+    SwitchingProtocols = 101,
     Ok = 200,
     Created = 201,
     Accepted = 202,
+    NonAuthoritativeInformation = 203,
     NoContent = 204,
+    PartialContent = 206,
+    MultipleChoices = 300,
     MovedPermanently = 301,
     Found = 302,
     SeeOther = 303,
@@ -27,6 +31,9 @@ pub enum HttpStatusCode {
     UnsupportedMediaType = 415,
     RangeNotSatisfiable = 416,
     ExpectationFailed = 417,
+    UnprocessableEntity = 422,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
     InternalServerError = 500,
     NotImplemented = 501,
     BadGateway = 502,
@@ -39,10 +46,14 @@ pub enum HttpStatusCode {
 impl HttpStatusCode {
     pub fn as_str(&self) -> &str {
         match self {
+            HttpStatusCode::SwitchingProtocols => "101 Switching Protocols",
             HttpStatusCode::Ok => "200 OK",
             HttpStatusCode::Created => "201 Created",
             HttpStatusCode::Accepted => "202 Accepted",
+            HttpStatusCode::NonAuthoritativeInformation => "203 Non-Authoritative Information",
             HttpStatusCode::NoContent => "204 No Content",
+            HttpStatusCode::PartialContent => "206 Partial Content",
+            HttpStatusCode::MultipleChoices => "300 Multiple Choices",
             HttpStatusCode::MovedPermanently => "301 Moved Permanently",
             HttpStatusCode::Found => "302 Found",
             HttpStatusCode::SeeOther => "303 See Other",
@@ -64,6 +75,9 @@ impl HttpStatusCode {
             HttpStatusCode::UnsupportedMediaType => "415 Unsupported Media Type",
             HttpStatusCode::RangeNotSatisfiable => "416 Range Not Satisfiable",
             HttpStatusCode::ExpectationFailed => "417 Expectation Failed",
+            HttpStatusCode::UnprocessableEntity => "422 Unprocessable Entity",
+            HttpStatusCode::TooManyRequests => "429 Too Many Requests",
+            HttpStatusCode::RequestHeaderFieldsTooLarge => "431 Request Header Fields Too Large",
             HttpStatusCode::InternalServerError => "500 Internal Server Error",
             HttpStatusCode::NotImplemented => "501 Not Implemented",
             HttpStatusCode::BadGateway => "502 Bad Gateway",
@@ -75,10 +89,14 @@ impl HttpStatusCode {
 
     pub fn from_code(code: u16) -> Option<HttpStatusCode> {
         match code {
+            101 => Some(HttpStatusCode::SwitchingProtocols),
             200 => Some(HttpStatusCode::Ok),
             201 => Some(HttpStatusCode::Created),
             202 => Some(HttpStatusCode::Accepted),
+            203 => Some(HttpStatusCode::NonAuthoritativeInformation),
             204 => Some(HttpStatusCode::NoContent),
+            206 => Some(HttpStatusCode::PartialContent),
+            300 => Some(HttpStatusCode::MultipleChoices),
             301 => Some(HttpStatusCode::MovedPermanently),
             302 => Some(HttpStatusCode::Found),
             303 => Some(HttpStatusCode::SeeOther),
@@ -100,6 +118,9 @@ impl HttpStatusCode {
             415 => Some(HttpStatusCode::UnsupportedMediaType),
             416 => Some(HttpStatusCode::RangeNotSatisfiable),
             417 => Some(HttpStatusCode::ExpectationFailed),
+            422 => Some(HttpStatusCode::UnprocessableEntity),
+            429 => Some(HttpStatusCode::TooManyRequests),
+            431 => Some(HttpStatusCode::RequestHeaderFieldsTooLarge),
             500 => Some(HttpStatusCode::InternalServerError),
             501 => Some(HttpStatusCode::NotImplemented),
             502 => Some(HttpStatusCode::BadGateway),
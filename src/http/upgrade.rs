@@ -0,0 +1,121 @@
+//! Connections switching to another protocol
+//!
+//! A response whose body is an [`Upgrade`] is sent as `101 Switching
+//! Protocols`, after which the server lets go of the connection: it leaves the
+//! event loop and its socket is handed to the upgrade's handler as an
+//! [`Upgraded`], which owns it from then on. WebSocket routes are served by the
+//! event loop instead, this is for the protocols it doesn't speak.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use crate::http::request::Request;
+
+/// The client socket once it no longer speaks HTTP, encrypted when the client
+/// came over TLS
+pub trait Socket: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Socket for T {}
+
+/// Takes over the connections switching to its protocol
+pub trait UpgradeHandler: Send + Sync {
+    /// Called on the event loop right after the `101` was sent. Work lasting
+    /// longer than the handshake belongs to a thread of its own.
+    fn on_upgrade(&self, upgraded: Upgraded);
+}
+
+impl<F: Fn(Upgraded) + Send + Sync> UpgradeHandler for F {
+    fn on_upgrade(&self, upgraded: Upgraded) {
+        self(upgraded)
+    }
+}
+
+/// The body of a response switching its connection to `protocol`
+#[derive(Clone)]
+pub struct Upgrade {
+    /// The token of the `Upgrade` header, like "h2c" or "my-protocol/2"
+    pub protocol: String,
+    pub handler: Arc<dyn UpgradeHandler>,
+}
+
+impl Upgrade {
+    pub fn new(protocol: &str, handler: impl UpgradeHandler + 'static) -> Self {
+        Upgrade { protocol: protocol.to_string(), handler: Arc::new(handler) }
+    }
+
+    /// Whether `request` asks to switch to the protocol
+    pub fn is_requested(&self, request: &Request) -> bool {
+        let has_token = |name: &str, token: &str| request.headers.iter()
+            .filter(|header| header.name.to_string().eq_ignore_ascii_case(name))
+            .any(|header| header.value.value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)));
+        has_token("upgrade", &self.protocol) && has_token("connection", "upgrade")
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade").field("protocol", &self.protocol).finish_non_exhaustive()
+    }
+}
+
+/// A connection the server let go of, closed once dropped
+pub struct Upgraded {
+    request: Request,
+    socket: Box<dyn Socket>,
+    fd: RawFd,
+}
+
+impl Upgraded {
+    /// `socket` is put back in blocking mode, as sockets usually are
+    pub fn new(request: Request, socket: Box<dyn Socket>, fd: RawFd) -> io::Result<Self> {
+        let upgraded = Upgraded { request, socket, fd };
+        upgraded.set_nonblocking(false)?;
+        Ok(upgraded)
+    }
+
+    /// The request that asked for the upgrade
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let flags = unsafe { libc::fcntl(self.fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let flags = if nonblocking { flags | libc::O_NONBLOCK } else { flags & !libc::O_NONBLOCK };
+        if unsafe { libc::fcntl(self.fd, libc::F_SETFL, flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+impl AsRawFd for Upgraded {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded").field("fd", &self.fd).field("uri", &self.request.uri).finish_non_exhaustive()
+    }
+}
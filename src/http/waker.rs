@@ -0,0 +1,32 @@
+//! Wakes the event loop from any thread, for what happens outside of it, like
+//! a published event or a deferred response given, to be handled right away
+//! rather than on the loop's next round
+
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+static WAKER: AtomicI32 = AtomicI32::new(-1);
+
+/// Has `fd`, an eventfd the event loop watches, written to on each wake
+pub fn set(fd: RawFd) {
+    WAKER.store(fd, Ordering::SeqCst);
+}
+
+/// Stops writing to `fd`, unless another waker replaced it
+pub fn clear(fd: RawFd) {
+    let _ = WAKER.compare_exchange(fd, -1, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+pub fn wake() {
+    let fd = WAKER.load(Ordering::SeqCst);
+    if fd >= 0 {
+        wake_fd(fd);
+    }
+}
+
+/// Wakes the loop watching `fd`, rather than the last one set
+pub fn wake_fd(fd: RawFd) {
+    unsafe {
+        libc::write(fd, 1u64.to_ne_bytes().as_ptr() as *const libc::c_void, 8);
+    }
+}
@@ -1,3 +1,7 @@
 pub mod http;
 pub mod server;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod testing;
+
+pub use server::builder::{HostBuilder, ServerBuilder};
+pub use server::shutdown::ServerHandle;
@@ -0,0 +1,49 @@
+//! Client address filtering
+//!
+//! Routes may restrict who reaches them with `allow` and `deny` lists of
+//! addresses or CIDR networks. A denied address is always refused; when an
+//! `allow` list is set, only the addresses it matches get through.
+
+use std::net::IpAddr;
+use ipnet::IpNet;
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, String> {
+        let parse = |entries: &[String]| entries.iter()
+            .map(|entry| parse_network(entry))
+            .collect::<Result<Vec<_>, _>>();
+        Ok(AccessList { allow: parse(allow)?, deny: parse(deny)? })
+    }
+
+    /// Refuses every client
+    pub fn deny_all() -> Self {
+        let everyone = ["0.0.0.0/0", "::/0"].iter().filter_map(|net| net.parse().ok()).collect();
+        AccessList { allow: Vec::new(), deny: everyone }
+    }
+
+    /// Whether a client may reach the route, an unknown address only passes
+    /// when both lists are empty
+    pub fn is_allowed(&self, client: Option<IpAddr>) -> bool {
+        let Some(client) = client.map(|ip| ip.to_canonical()) else {
+            return self.allow.is_empty() && self.deny.is_empty();
+        };
+        if self.deny.iter().any(|net| net.contains(&client)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&client))
+    }
+}
+
+/// Parses a CIDR network like "10.0.0.0/8", or a single address
+pub fn parse_network(entry: &str) -> Result<IpNet, String> {
+    let entry = entry.trim();
+    entry.parse::<IpNet>()
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("'{}' is not an IP address or CIDR network", entry))
+}
@@ -0,0 +1,177 @@
+//! HTTP Basic authentication
+//!
+//! Routes with `auth_basic` answer `401` with a `WWW-Authenticate` challenge until
+//! the client sends credentials matching an htpasswd style users file, or the
+//! users listed in the config itself. Only bcrypt hashes (`$2y$`, `$2b$`, `$2a$`)
+//! are accepted. The file is read again whenever it changes, so users can be
+//! added without restarting the server.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+use crate::http::header::HeaderName;
+use crate::http::request::Request;
+use crate::server::errors::HttpError;
+use crate::server::logger::logger;
+
+const BCRYPT_PREFIXES: [&str; 3] = ["$2y$", "$2b$", "$2a$"];
+
+#[derive(Debug, Default)]
+struct Users {
+    loaded: bool,
+    modified: Option<SystemTime>,
+    hashes: HashMap<String, String>,
+    /// Digest of the credentials last verified for each user, so bcrypt only
+    /// runs again when the password sent changes
+    verified: HashMap<String, [u8; 32]>,
+}
+
+/// Where users and their password hashes come from
+#[derive(Debug)]
+enum Credentials {
+    File(PathBuf),
+    Inline(HashMap<String, String>),
+}
+
+/// Checks the `Authorization` header of requests against a users file
+#[derive(Debug)]
+pub struct BasicAuth {
+    pub realm: String,
+    credentials: Credentials,
+    users: Mutex<Users>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str, users_file: PathBuf) -> Self {
+        Self::from_credentials(realm, Credentials::File(users_file))
+    }
+
+    /// Checks against `hashes`, bcrypt hashes per user name
+    pub fn with_users(realm: &str, hashes: HashMap<String, String>) -> Self {
+        Self::from_credentials(realm, Credentials::Inline(hashes))
+    }
+
+    fn from_credentials(realm: &str, credentials: Credentials) -> Self {
+        let auth = BasicAuth {
+            realm: realm.to_string(),
+            credentials,
+            users: Mutex::new(Users::default()),
+        };
+        // Reports an unreadable file at startup rather than on the first request
+        drop(auth.users());
+        auth
+    }
+
+    /// Returns the name of the authenticated user, or the error challenging the client
+    pub fn authenticate(&self, request: &Request) -> Result<String, HttpError> {
+        let (user, password) = request.get_header(HeaderName::Authorization)
+            .and_then(|header| parse_credentials(&header.value.value))
+            .ok_or_else(|| self.challenge("Authentication required"))?;
+
+        let mut users = self.users();
+        let digest: [u8; 32] = Sha256::digest(format!("{}:{}", user, password)).into();
+        if users.verified.get(&user) == Some(&digest) {
+            return Ok(user);
+        }
+
+        let valid = match users.hashes.get(&user) {
+            Some(hash) => bcrypt::verify(&password, hash).unwrap_or(false),
+            // Unknown users cost as much as known ones, so they can't be told apart
+            None => {
+                if let Some(hash) = users.hashes.values().next() {
+                    let _ = bcrypt::verify(&password, hash);
+                }
+                false
+            }
+        };
+        if !valid {
+            return Err(self.challenge("Invalid credentials"));
+        }
+
+        users.verified.insert(user.clone(), digest);
+        Ok(user)
+    }
+
+    fn challenge(&self, message: &str) -> HttpError {
+        HttpError::Unauthorized {
+            challenge: format!("Basic realm=\"{}\", charset=\"UTF-8\"", quoted(&self.realm)),
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns the users, reading the file again when it was modified
+    fn users(&self) -> MutexGuard<'_, Users> {
+        let mut users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+        let modified = match &self.credentials {
+            Credentials::File(path) => fs::metadata(path).and_then(|m| m.modified()).ok(),
+            Credentials::Inline(_) => None,
+        };
+        if users.loaded && users.modified == modified {
+            return users;
+        }
+
+        let hashes = match &self.credentials {
+            Credentials::File(path) => match fs::read_to_string(path) {
+                Ok(content) => parse_htpasswd(&content, path),
+                Err(e) => {
+                    logger().error(&format!(
+                        "Cannot read users file {}: {}, every request is refused", path.display(), e
+                    ), "BasicAuth");
+                    HashMap::new()
+                }
+            },
+            Credentials::Inline(hashes) => hashes.iter()
+                .filter(|(_, hash)| is_bcrypt(hash))
+                .map(|(user, hash)| (user.clone(), hash.clone()))
+                .collect(),
+        };
+        *users = Users { loaded: true, modified, hashes, verified: HashMap::new() };
+        users
+    }
+}
+
+/// Reads `user:hash` lines, skipping blank lines, comments and non bcrypt hashes
+fn parse_htpasswd(content: &str, path: &Path) -> HashMap<String, String> {
+    let logger = logger();
+    let mut hashes = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((user, hash)) if is_bcrypt(hash) => {
+                hashes.insert(user.to_string(), hash.to_string());
+            }
+            _ => logger.warn(&format!(
+                "{}:{}: ignored, only `user:bcrypt-hash` entries are supported", path.display(), number + 1
+            ), "BasicAuth"),
+        }
+    }
+    hashes
+}
+
+/// `value` made safe to send between the double quotes of a challenge
+pub fn quoted(value: &str) -> String {
+    value.replace(['"', '\\'], "")
+}
+
+pub fn is_bcrypt(hash: &str) -> bool {
+    BCRYPT_PREFIXES.iter().any(|prefix| hash.starts_with(prefix))
+}
+
+/// Decodes the user and password of a `Basic` authorization header
+fn parse_credentials(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
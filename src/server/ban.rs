@@ -0,0 +1,154 @@
+//! Temporary bans
+//!
+//! With a `ban` block, clients whose requests keep failing are banned for a
+//! while, like fail2ban does. Requests answered with a 4xx status and requests
+//! that can't even be parsed count as failures; once `max_failures` of them fall
+//! within `window`, the connections of the client are closed as soon as they
+//! are accepted until the ban ends. The admin API lists the bans and lifts them.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use ipnet::IpNet;
+use serde_json::{json, Value};
+use crate::server::logger::logger;
+
+/// Clients whose failures are counted before the ones without recent failures are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BanPolicy {
+    /// Failures within `window` banning a client
+    pub max_failures: usize,
+    pub window: Duration,
+    /// How long a ban lasts
+    pub duration: Duration,
+    /// Networks never banned
+    pub ignore: Vec<IpNet>,
+}
+
+#[derive(Debug)]
+struct Ban {
+    since: DateTime<Local>,
+    until: Instant,
+    failures: usize,
+    /// What the last failure was
+    reason: String,
+}
+
+#[derive(Debug, Default)]
+struct Bans {
+    policy: Option<BanPolicy>,
+    /// Times of the recent failures of every client
+    failures: HashMap<IpAddr, VecDeque<Instant>>,
+    banned: HashMap<IpAddr, Ban>,
+}
+
+static BANS: OnceLock<Mutex<Bans>> = OnceLock::new();
+
+fn bans() -> MutexGuard<'static, Bans> {
+    BANS.get_or_init(|| Mutex::new(Bans::default()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Applies `policy`, keeping the bans in progress. `None` disables bans and lifts them all.
+pub fn configure(policy: Option<BanPolicy>) {
+    let mut bans = bans();
+    if policy.is_none() {
+        bans.failures.clear();
+        bans.banned.clear();
+    }
+    bans.policy = policy;
+}
+
+/// Counts a failure of `client`, banning it once the policy's limit is reached.
+/// Returns whether it got banned.
+pub fn record_failure(client: IpAddr, reason: &str) -> bool {
+    let client = client.to_canonical();
+    let now = Instant::now();
+    let mut bans = bans();
+    let Some(policy) = bans.policy.clone() else { return false };
+    if policy.ignore.iter().any(|net| net.contains(&client))
+        || bans.banned.get(&client).is_some_and(|ban| ban.until > now)
+    {
+        return false;
+    }
+
+    if bans.failures.len() >= MAX_TRACKED_CLIENTS && !bans.failures.contains_key(&client) {
+        bans.failures.retain(|_, failures| failures.back().is_some_and(|last| now - *last <= policy.window));
+    }
+    let failures = bans.failures.entry(client).or_default();
+    while failures.front().is_some_and(|first| now - *first > policy.window) {
+        failures.pop_front();
+    }
+    failures.push_back(now);
+    let count = failures.len();
+    if count < policy.max_failures {
+        return false;
+    }
+
+    bans.failures.remove(&client);
+    bans.banned.insert(client, Ban {
+        since: Local::now(),
+        until: now + policy.duration,
+        failures: count,
+        reason: reason.to_string(),
+    });
+    drop(bans);
+    logger().warn(&format!(
+        "Banned {} for {}s after {} failures, the last one: {}", client, policy.duration.as_secs(), count, reason
+    ), "Ban");
+    true
+}
+
+/// Whether the connections of `client` are refused, forgetting ended bans
+pub fn is_banned(client: IpAddr) -> bool {
+    let client = client.to_canonical();
+    let mut bans = bans();
+    match bans.banned.get(&client) {
+        Some(ban) if ban.until > Instant::now() => true,
+        Some(_) => {
+            bans.banned.remove(&client);
+            false
+        }
+        None => false,
+    }
+}
+
+/// The bans in progress
+pub fn list() -> Value {
+    let now = Instant::now();
+    let mut bans = bans();
+    bans.banned.retain(|_, ban| ban.until > now);
+    let mut banned: Vec<_> = bans.banned.iter().collect();
+    banned.sort_by_key(|(client, _)| **client);
+    let banned: Vec<Value> = banned.into_iter()
+        .map(|(client, ban)| json!({
+            "client": client.to_string(),
+            "since": ban.since.to_rfc3339(),
+            "remaining_secs": ban.until.saturating_duration_since(now).as_secs(),
+            "failures": ban.failures,
+            "reason": ban.reason,
+        }))
+        .collect();
+    json!({ "bans": banned })
+}
+
+/// Lifts the ban of `client`, returning whether it was banned
+pub fn lift(client: IpAddr) -> bool {
+    let client = client.to_canonical();
+    let mut bans = bans();
+    bans.failures.remove(&client);
+    bans.banned.remove(&client).is_some_and(|ban| ban.until > Instant::now())
+}
+
+/// Lifts every ban, returning how many were in progress
+pub fn lift_all() -> usize {
+    let now = Instant::now();
+    let mut bans = bans();
+    bans.failures.clear();
+    bans.banned.drain().filter(|(_, ban)| ban.until > now).count()
+}
@@ -0,0 +1,58 @@
+//! Reused buffers
+//!
+//! Responses are serialized into buffers taken from a [`BufferPool`] and
+//! given back once written, so that a keep-alive connection doesn't allocate
+//! a new one for every response. The pool keeps a few buffers only, and none
+//! grown past `max_capacity` by a large body, which would otherwise stay
+//! allocated for as long as the server runs.
+
+use std::sync::Mutex;
+
+/// Buffers kept by the pool of responses
+const RESPONSE_BUFFERS: usize = 64;
+/// Capacity past which a response buffer is dropped rather than kept
+const RESPONSE_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// The buffers responses are serialized into
+pub static RESPONSES: BufferPool = BufferPool::new(RESPONSE_BUFFERS, RESPONSE_BUFFER_CAPACITY);
+
+/// A bounded stack of emptied buffers
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_capacity: usize,
+}
+
+impl BufferPool {
+    pub const fn new(max_buffers: usize, max_capacity: usize) -> Self {
+        BufferPool { buffers: Mutex::new(Vec::new()), max_buffers, max_capacity }
+    }
+
+    /// An empty buffer, allocated only when the pool has none left
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner()).pop().unwrap_or_default()
+    }
+
+    /// Gives `buffer` back, dropping it when the pool is full or the buffer
+    /// grew too large
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.max_capacity {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    /// Number of buffers waiting to be taken
+    pub fn len(&self) -> usize {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
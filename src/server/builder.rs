@@ -0,0 +1,404 @@
+//! Servers built in code
+//!
+//! The `localhost` binary builds its hosts from a config file. Programs
+//! embedding the server build them with a [`HostBuilder`] instead, and start
+//! them with a [`ServerBuilder`], without any config file or the
+//! `~/.cargo/localhost-cli` directories:
+//!
+//! ```no_run
+//! use localhost::server::builder::{HostBuilder, ServerBuilder};
+//!
+//! let host = HostBuilder::new("app.local")
+//!     .listen("127.0.0.1:8080")
+//!     .static_files("/", "/srv/app/public", Some("index.html"))
+//!     .build()
+//!     .unwrap();
+//! ServerBuilder::new().host(host).run().unwrap();
+//! ```
+
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use serde_json::Value;
+use crate::config::config::ConfigError;
+use crate::http::event_stream::EventChannel;
+use crate::http::header::Header;
+use crate::http::request::HttpMethod;
+use crate::http::response::Response;
+use crate::http::upgrade::Upgrade;
+use crate::server::compression::Compression;
+use crate::server::stream::request_stream::HeaderLimits;
+use crate::server::errors::ServerError;
+use crate::server::handlers::handlers::RouteHandler;
+use crate::server::host::Host;
+use crate::server::listen::ListenAddr;
+use crate::server::logger::{self, LogSettings};
+use crate::server::middleware::{Middleware, Middlewares};
+use crate::server::hooks::{Hooks, RequestInfo};
+use crate::server::notifier::Notifier;
+use crate::server::plugin;
+use crate::server::route::{Route, RouteMatcher};
+use crate::server::session::session::SessionManager;
+use crate::server::shutdown::ServerHandle;
+use crate::server::server::Server;
+use crate::server::static_files::{self, ErrorPages, ServerStaticFiles};
+use crate::server::standard_headers;
+use crate::server::uploader::Uploader;
+use crate::server::websocket::WebSocketEndpoint;
+
+/// A host put together in code
+pub struct HostBuilder {
+    server_name: String,
+    listen: Vec<String>,
+    routes: Vec<Route>,
+    static_files: Vec<(String, PathBuf, Option<String>)>,
+    session_manager: Option<SessionManager>,
+    error_pages: Option<ErrorPages>,
+    max_request_size: Option<usize>,
+    request_timeout: Option<Duration>,
+    keep_alive_requests: Option<usize>,
+    compression: Option<Compression>,
+    header_limits: Option<HeaderLimits>,
+    uploader: Option<Uploader>,
+    headers: Vec<Header>,
+    health_checks: bool,
+    handlers: Vec<(String, Vec<HttpMethod>, RouteHandler)>,
+    websockets: Vec<(String, WebSocketEndpoint)>,
+    event_streams: Vec<(String, Arc<EventChannel>)>,
+    upgrades: Vec<(String, Upgrade)>,
+    middlewares: Middlewares,
+    hooks: Hooks,
+    plugins: Vec<(String, Value)>,
+}
+
+impl HostBuilder {
+    pub fn new(server_name: &str) -> Self {
+        HostBuilder {
+            server_name: server_name.to_string(),
+            listen: Vec::new(),
+            routes: Vec::new(),
+            static_files: Vec::new(),
+            session_manager: None,
+            error_pages: None,
+            max_request_size: None,
+            request_timeout: None,
+            keep_alive_requests: None,
+            compression: None,
+            header_limits: None,
+            uploader: None,
+            headers: Vec::new(),
+            health_checks: false,
+            handlers: Vec::new(),
+            websockets: Vec::new(),
+            event_streams: Vec::new(),
+            upgrades: Vec::new(),
+            middlewares: Middlewares::new(),
+            hooks: Hooks::default(),
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Listens on `addr`, like "127.0.0.1:8080", "[::1]:8080", "8080" or
+    /// "unix:/run/app.sock"
+    pub fn listen(mut self, addr: &str) -> Self {
+        self.listen.push(addr.to_string());
+        self
+    }
+
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Serves the files of `directory` on `path`, relative directories being
+    /// under the sites directory
+    pub fn static_files(mut self, path: &str, directory: impl Into<PathBuf>, index: Option<&str>) -> Self {
+        self.static_files.push((path.to_string(), directory.into(), index.map(str::to_string)));
+        self
+    }
+
+    /// Keeps sessions with `manager`, its API served under `/api/session`
+    pub fn sessions(mut self, manager: SessionManager) -> Self {
+        self.session_manager = Some(manager);
+        self
+    }
+
+    pub fn error_pages(mut self, error_pages: ErrorPages) -> Self {
+        self.error_pages = Some(error_pages);
+        self
+    }
+
+    pub fn max_request_size(mut self, size: usize) -> Self {
+        self.max_request_size = Some(size);
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections once they carried `max` requests
+    pub fn keep_alive_requests(mut self, max: usize) -> Self {
+        self.keep_alive_requests = Some(max);
+        self
+    }
+
+    /// Refuses requests with larger or more headers with `431`
+    pub fn header_limits(mut self, limits: HeaderLimits) -> Self {
+        self.header_limits = Some(limits);
+        self
+    }
+
+    /// Gzips the responses of the clients accepting it
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn uploader(mut self, uploader: Uploader) -> Self {
+        self.uploader = Some(uploader);
+        self
+    }
+
+    /// Adds a header to every response of the host
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push(Header::from_str(name, value));
+        self
+    }
+
+    /// Answers `/healthz` and `/readyz`
+    pub fn health_checks(mut self) -> Self {
+        self.health_checks = true;
+        self
+    }
+
+    /// Answers the `methods` requests of `path` with user code
+    pub fn handler(mut self, path: &str, methods: Vec<HttpMethod>, handler: RouteHandler) -> Self {
+        self.handlers.push((path.to_string(), methods, handler));
+        self
+    }
+
+    pub fn websocket(mut self, path: &str, endpoint: WebSocketEndpoint) -> Self {
+        self.websockets.push((path.to_string(), endpoint));
+        self
+    }
+
+    pub fn event_stream(mut self, path: &str, channel: Arc<EventChannel>) -> Self {
+        self.event_streams.push((path.to_string(), channel));
+        self
+    }
+
+    pub fn upgrade(mut self, path: &str, upgrade: Upgrade) -> Self {
+        self.upgrades.push((path.to_string(), upgrade));
+        self
+    }
+
+    /// Runs `middleware` on the requests of every route, in the order added
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// Calls `hook` on every request once read
+    pub fn on_request(mut self, hook: impl Fn(&RequestInfo) + Send + Sync + 'static) -> Self {
+        self.hooks.on_request(hook);
+        self
+    }
+
+    /// Calls `hook` on every response before it is sent
+    pub fn on_response(mut self, hook: impl Fn(&RequestInfo, &mut Response) + Send + Sync + 'static) -> Self {
+        self.hooks.on_response(hook);
+        self
+    }
+
+    /// Calls `hook` on every request that failed
+    pub fn on_error(mut self, hook: impl Fn(&RequestInfo, &ServerError) + Send + Sync + 'static) -> Self {
+        self.hooks.on_error(hook);
+        self
+    }
+
+    /// Adds the registered plugin called `name`, once the rest of the host
+    /// is built
+    pub fn plugin(mut self, name: &str, settings: Value) -> Self {
+        self.plugins.push((name.to_string(), settings));
+        self
+    }
+
+    pub fn build(self) -> Result<Host, ServerError> {
+        let invalid = |message: String| ServerError::ConfigError(ConfigError::Critical(message));
+        let mut routes = self.routes;
+        for (path, directory, index) in self.static_files {
+            let files = ServerStaticFiles::new(directory, index, false, self.error_pages.clone())?;
+            routes.push(Route {
+                methods: vec![HttpMethod::GET, HttpMethod::HEAD],
+                static_files: Some(files),
+                matcher: Some(RouteMatcher::from_path(&path)),
+                path,
+                ..Route::default()
+            });
+        }
+
+        let mut host = Host::new("", &self.server_name, Vec::new(), routes, self.session_manager, self.error_pages, self.max_request_size)?;
+        for addr in &self.listen {
+            let addr = ListenAddr::parse(addr).map_err(|e| invalid(format!("Host {}: {}", self.server_name, e)))?;
+            host.add_listen(addr);
+        }
+        if host.listen.is_empty() {
+            return Err(invalid(format!("Host {} listens nowhere", self.server_name)));
+        }
+        if host.session_manager.is_some() {
+            host.add_session_api();
+        }
+        if self.health_checks {
+            host.add_health_checks();
+        }
+        if let Some(uploader) = self.uploader {
+            host.set_uploader(uploader);
+        }
+        if let Some(timeout) = self.request_timeout {
+            host.set_request_timeout(timeout);
+        }
+        if let Some(max) = self.keep_alive_requests {
+            host.set_keep_alive_requests(max);
+        }
+        if let Some(compression) = self.compression {
+            host.set_compression(compression);
+        }
+        if let Some(limits) = self.header_limits {
+            host.set_header_limits(limits);
+        }
+        host.set_headers(self.headers);
+        // After the session check
+        host.add_middleware(self.middlewares);
+        host.hooks = self.hooks;
+        for (path, methods, handler) in self.handlers {
+            host.add_handler(&path, methods, handler);
+        }
+        for (path, endpoint) in self.websockets {
+            host.add_websocket(&path, endpoint);
+        }
+        for (path, channel) in self.event_streams {
+            host.add_event_stream(&path, channel);
+        }
+        for (path, upgrade) in self.upgrades {
+            host.add_upgrade(&path, upgrade);
+        }
+        for (name, settings) in &self.plugins {
+            plugin::install(&mut host, name, settings)
+                .map_err(|e| invalid(format!("Host {}: plugin {}: {}", self.server_name, name, e)))?;
+        }
+        Ok(host)
+    }
+}
+
+/// A server put together in code
+#[derive(Default)]
+pub struct ServerBuilder {
+    hosts: Vec<Host>,
+    uploader: Option<Uploader>,
+    log_settings: Option<LogSettings>,
+    notifier: Option<Notifier>,
+    shutdown_timeout: Option<Duration>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn host(mut self, host: Host) -> Self {
+        self.hosts.push(host);
+        self
+    }
+
+    /// Uploader of the hosts and routes that don't set their own
+    pub fn uploader(mut self, uploader: Uploader) -> Self {
+        self.uploader = Some(uploader);
+        self
+    }
+
+    /// Name sent in the `Server` header of every response, an empty one
+    /// leaving the header out
+    pub fn server_header(self, name: &str) -> Self {
+        standard_headers::configure(Some(name.to_string()));
+        self
+    }
+
+    /// Directory relative site paths are under, instead of
+    /// `~/.cargo/localhost-cli/sites`. Hosts built before resolved theirs
+    /// already.
+    pub fn sites_dir(self, dir: impl Into<PathBuf>) -> Self {
+        static_files::set_sites_dir(dir);
+        self
+    }
+
+    /// Where the logs go, the standard output by default
+    pub fn logging(mut self, settings: LogSettings) -> Self {
+        self.log_settings = Some(settings);
+        self
+    }
+
+    pub fn notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// How long connections may take to finish once the server is shut
+    /// down, 30 seconds by default
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Binds the hosts' addresses, failing when one is taken
+    pub fn build(self) -> Result<Server, ServerError> {
+        if let Some(settings) = self.log_settings {
+            logger::configure(settings)
+                .map_err(|e| ServerError::ConfigError(ConfigError::Critical(format!("Cannot set up logging: {}", e))))?;
+        }
+        let mut server = Server::new(self.uploader)?;
+        for host in self.hosts {
+            server.add_host(host)?;
+        }
+        if let Some(notifier) = self.notifier {
+            server.set_notifier(notifier);
+        }
+        if let Some(timeout) = self.shutdown_timeout {
+            server.set_shutdown_timeout(timeout);
+        }
+        Ok(server)
+    }
+
+    /// Builds the server and serves its hosts on the current thread, until
+    /// a handle of it shuts it down
+    pub fn run(self) -> Result<(), ServerError> {
+        self.build()?.run()
+    }
+
+    /// Builds the server and serves its hosts on a thread of its own,
+    /// returning once their addresses are bound
+    pub fn spawn(self) -> Result<ServerHandle, ServerError> {
+        let (started, bound) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("localhost".to_string())
+            .spawn(move || {
+                let mut server = match self.build() {
+                    Ok(server) => server,
+                    Err(e) => {
+                        let _ = started.send(Err(e));
+                        return Ok(());
+                    }
+                };
+                let _ = started.send(Ok(server.handle()));
+                server.run()
+            })?;
+        match bound.recv() {
+            Ok(Ok(handle)) => Ok(handle.with_thread(thread)),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(ServerError::ConnectionError("The server thread ended before starting".to_string())),
+        }
+    }
+}
@@ -1,31 +1,68 @@
 use crate::http::request::Request;
 use crate::http::response::Response;
-use crate::http::header::Header;
+use crate::http::header::{is_valid_name, Header};
 use crate::http::body::Body;
 use crate::http::status::HttpStatusCode;
 use crate::server::errors::{ServerError, CGIError};
 use std::collections::HashMap;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interpreter running the scripts of routes that set none
+pub const DEFAULT_INTERPRETER: &str = "/usr/bin/python3";
+/// Extensions of the scripts run when a route sets none
+pub const DEFAULT_EXTENSIONS: [&str; 1] = ["py"];
+/// Time a script may run when a route sets no `timeout_secs`
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub struct CGIConfig {
     pub interpreter: String,
     pub script_dir: String,
-    pub allowed_extensions: Vec<String>
+    pub allowed_extensions: Vec<String>,
+    /// Time the script may run before it is killed and `504` answered
+    pub timeout: Duration,
+    /// Variables added to the environment of the script
+    pub env: HashMap<String, String>,
 }
 
 impl CGIConfig {
     pub fn new(script_dir: String) -> Self {
         CGIConfig {
-            interpreter: String::from("/usr/bin/python3"),
+            interpreter: String::from(DEFAULT_INTERPRETER),
             script_dir,
-            allowed_extensions: vec!["py".to_string()]
+            allowed_extensions: DEFAULT_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            timeout: DEFAULT_TIMEOUT,
+            env: HashMap::new(),
         }
     }
 
+    pub fn with_interpreter(mut self, interpreter: String) -> Self {
+        self.interpreter = interpreter;
+        self
+    }
+
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.allowed_extensions = extensions;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
     pub fn prepare_cgi_environment(&self, request: &Request) -> HashMap<String, String> {
-        let mut env = HashMap::new();
+        // The configured variables can't override the ones CGI defines
+        let mut env = self.env.clone();
 
         env.insert("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string());
         env.insert("SERVER_PROTOCOL".to_string(), request.version.to_string());
@@ -33,6 +70,9 @@ impl CGIConfig {
         env.insert("REQUEST_METHOD".to_string(), request.method.to_string());
         env.insert("SCRIPT_NAME".to_string(), request.uri.to_string());
         env.insert("QUERY_STRING".to_string(), "".to_string());
+        if let Some(client) = request.client_addr {
+            env.insert("REMOTE_ADDR".to_string(), client.to_string());
+        }
 
         // Headers HTTP -> Variables CGI
         for header in &request.headers {
@@ -42,6 +82,26 @@ impl CGIConfig {
                     .to_uppercase());
             env.insert(env_name, header.value.value.clone());
         }
+
+        if let Some(identity) = &request.identity {
+            env.insert("AUTH_TYPE".to_string(), identity.scheme.to_string());
+            env.insert("REMOTE_USER".to_string(), identity.user.clone());
+            if let Some(claims) = &identity.claims {
+                env.insert("JWT_CLAIMS".to_string(), serde_json::Value::Object(claims.clone()).to_string());
+                // Plain claims also get a variable each, like JWT_CLAIM_SUB
+                for (name, value) in claims {
+                    let value = match value {
+                        serde_json::Value::String(text) => text.clone(),
+                        serde_json::Value::Number(_) | serde_json::Value::Bool(_) => value.to_string(),
+                        _ => continue,
+                    };
+                    let name: String = name.chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+                        .collect();
+                    env.insert(format!("JWT_CLAIM_{}", name), value);
+                }
+            }
+        }
         env
     }
 
@@ -77,7 +137,8 @@ impl CGIConfig {
                 }
             } else {
                 let h_parts: Vec<&str> = line.splitn(2, ":").collect();
-                if h_parts.len() == 2 {
+                // Values are sanitized when the response is written, invalid names are dropped
+                if h_parts.len() == 2 && is_valid_name(h_parts[0].trim()) {
                     headers.push(Header::from_str(h_parts[0].trim(), h_parts[1].trim()));
                 }
             }
@@ -118,15 +179,48 @@ impl CGIConfig {
         Ok(())
     }
 
+    /// Runs the script, killing it once it exceeds the timeout
     pub fn execute_script(&self, script_path: &Path, env_vars: &HashMap<String, String>) 
         -> Result<Output, ServerError> {
-        Command::new(&self.interpreter)
+        let mut child = Command::new(&self.interpreter)
             .arg(script_path)
             .envs(env_vars)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| CGIError::ExecutionFailed(e.to_string()).into())
+            .spawn()
+            .map_err(|e| CGIError::ExecutionFailed(e.to_string()))?;
+
+        // Pipes are drained meanwhile, a script filling one would block forever
+        fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buffer);
+                }
+                buffer
+            })
+        }
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait().map_err(|e| CGIError::ExecutionFailed(e.to_string()))? {
+                Some(status) => break status,
+                None if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(CGIError::Timeout(self.timeout).into());
+                }
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
     }
 }
\ No newline at end of file
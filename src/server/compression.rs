@@ -0,0 +1,149 @@
+//! gzip compression of responses
+//!
+//! Hosts with a `compression` block gzip the responses of the types it lists
+//! for the clients whose `Accept-Encoding` takes gzip, once their middlewares
+//! are done with them. Bodies under `min_size` are sent as they are, gzip
+//! saving little on them, and so are the parts of ranges, the responses
+//! already encoded and those whose `Cache-Control` says `no-transform`.
+//! Streamed bodies aren't compressed, they're sent before they're known.
+//! Every response of a type compressed says `Vary: Accept-Encoding`, whether
+//! this one was or not. A gzipped response's `ETag` is made weak, its bytes
+//! not being those of the file. HEAD responses are compressed as GET ones
+//! are, so that they get the same headers, before losing their body.
+
+use std::borrow::Cow;
+use std::io::Write;
+use flate2::write::GzEncoder;
+use crate::http::body::Body;
+use crate::http::header::{Header, HeaderName};
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::status::HttpStatusCode;
+
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+pub const DEFAULT_TYPES: [&str; 7] = [
+    "text/html",
+    "text/plain",
+    "text/css",
+    "text/javascript",
+    "application/javascript",
+    "application/json",
+    "image/svg+xml",
+];
+
+/// Which responses of a host are compressed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    /// Smallest body compressed, in bytes
+    pub min_size: usize,
+    /// MIME types compressed, like "text/html" or "text/*"
+    pub types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+            types: DEFAULT_TYPES.iter().map(|mime| mime.to_string()).collect(),
+        }
+    }
+}
+
+impl Compression {
+    /// Gzips the body of `response` when `request` accepts it and the
+    /// response is worth compressing, updating its headers to match
+    pub fn apply(&self, request: &Request, response: &mut Response) {
+        if !self.compresses(response) {
+            return;
+        }
+        // Compressed or not, the response depends on the encodings taken, which
+        // shared caches must know not to serve one client's to another
+        let varies = header(&response.headers, "vary")
+            .is_some_and(|vary| vary.split(',').any(|name| name.trim().eq_ignore_ascii_case("accept-encoding")));
+        if !varies {
+            response.headers.push(Header::from_str("vary", "Accept-Encoding"));
+        }
+        if !header(&request.headers, "accept-encoding").is_some_and(accepts_gzip) {
+            return;
+        }
+        let body: Cow<[u8]> = match &response.body {
+            Some(Body::Text(text)) => Cow::Borrowed(text.as_bytes()),
+            Some(Body::Binary(data)) => Cow::Borrowed(data),
+            Some(Body::Json(json)) => match serde_json::to_vec(json) {
+                Ok(data) => Cow::Owned(data),
+                Err(_) => return,
+            },
+            _ => return,
+        };
+        if body.len() < self.min_size {
+            return;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 2), flate2::Compression::default());
+        let compressed = match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+            Ok(compressed) if compressed.len() < body.len() => compressed,
+            _ => return,
+        };
+
+        response.headers.retain(|header| header.name != HeaderName::ContentLength);
+        response.headers.push(Header::from_str("content-length", &compressed.len().to_string()));
+        response.headers.push(Header::from_str("content-encoding", "gzip"));
+        response.body = Some(Body::binary(compressed));
+        // The gzipped bytes differ from those the ETag names
+        for etag in response.headers.iter_mut().filter(|header| header.name == HeaderName::ETag) {
+            if !etag.value.value.starts_with("W/") {
+                *etag = Header::from_str("etag", &format!("W/{}", etag.value.value));
+            }
+        }
+    }
+
+    /// Whether the type and state of `response` allow compressing it
+    fn compresses(&self, response: &Response) -> bool {
+        if matches!(response.status_code, HttpStatusCode::PartialContent | HttpStatusCode::NoContent | HttpStatusCode::NotModified) {
+            return false;
+        }
+        if header(&response.headers, "content-encoding").is_some() {
+            return false;
+        }
+        let no_transform = header(&response.headers, "cache-control")
+            .is_some_and(|value| value.split(',').any(|directive| directive.trim().eq_ignore_ascii_case("no-transform")));
+        if no_transform {
+            return false;
+        }
+        let Some(content_type) = response.headers.iter().find(|header| header.name == HeaderName::ContentType) else {
+            return false;
+        };
+        let mime = content_type.value.value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+            Some(kind) => mime.split('/').next() == Some(kind),
+            None => allowed.eq_ignore_ascii_case(&mime),
+        })
+    }
+}
+
+/// Whether an `Accept-Encoding` value takes gzip, by name or with `*`,
+/// without a `q=0` refusing it
+pub fn accepts_gzip(value: &str) -> bool {
+    let mut wildcard = false;
+    for coding in value.split(',') {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let weight = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match name.as_str() {
+            "gzip" | "x-gzip" => return weight > 0.0,
+            "*" => wildcard = weight > 0.0,
+            _ => {}
+        }
+    }
+    wildcard
+}
+
+/// Value of the header `name`, whatever its case
+fn header<'a>(headers: &'a [Header], name: &str) -> Option<&'a str> {
+    headers.iter()
+        .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+        .map(|header| header.value.value.as_str())
+}
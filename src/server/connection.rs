@@ -1,23 +1,50 @@
+use std::net::SocketAddr;
 use std::os::unix::io::RawFd;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::io;
 use crate::http::{
+    header::{Header, HeaderName},
     request::Request,
-    request::parse_request
+    request::parse_request,
+    response::Response,
 };
 
 use libc::EPOLLIN;
 
+use crate::server::buffers;
+use crate::server::standard_headers;
+use crate::server::forwarded;
 use crate::server::stream::request_stream::{
     RequestStream,
     RequestState,
     RequestData,
+    HeadersTooLarge,
+    BodyTooLarge,
 };
 
+/// Bytes of an unparseable request shown in its error
+const MALFORMED_PREFIX: usize = 64;
+
+/// Idle time after which a connection is closed when its host sets none
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub enum ConnectionState {
     AwaitingRequest,
-    Complete(Request),
+    Complete(Box<Request>),
+    /// A malformed request, answered with `400` before closing
+    Invalid(String),
+    /// A request of another version than HTTP/1.0 or HTTP/1.1, answered with
+    /// `505` before closing
+    UnsupportedVersion(String),
+    /// A request whose headers are too large or too many, answered with
+    /// `431` before closing
+    HeadersTooLarge(String),
+    /// A request whose body is past the maximum request size, answered with
+    /// `413` before closing
+    BodyTooLarge(String),
     Error(String),
 }
 
@@ -28,6 +55,15 @@ pub struct Connection {
     pub reader: Box<dyn RequestStream>,
     pub state: ConnectionState,
     pub start_time: std::time::Instant,
+    pub peer_addr: Option<SocketAddr>,
+    /// Set by the stream once the client negotiated TLS
+    pub secure: Arc<AtomicBool>,
+    /// Requests received so far
+    pub requests: usize,
+    /// Idle time after which the connection is closed
+    pub idle_timeout: Duration,
+    /// Requests the connection may carry, unbounded when `None`
+    pub max_requests: Option<usize>,
 }
 
 impl Connection {
@@ -40,7 +76,51 @@ impl Connection {
             reader,
             state: ConnectionState::AwaitingRequest,
             start_time: std::time::Instant::now(),
+            peer_addr: None,
+            secure: Arc::new(AtomicBool::new(false)),
+            requests: 0,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_requests: None,
+        }
+    }
+
+    pub fn with_secure_flag(mut self, secure: Arc<AtomicBool>) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.peer_addr = Some(peer_addr);
+        self
+    }
+
+    /// Closes the connection once idle for `idle_timeout` or once it
+    /// carried `max_requests`, both told to the client in `Keep-Alive`
+    pub fn with_keep_alive(mut self, idle_timeout: Duration, max_requests: Option<usize>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self.max_requests = max_requests;
+        self
+    }
+
+    /// Whether the connection stays open once `request` is answered, HTTP/1.1
+    /// clients keeping it unless they say `Connection: close`, HTTP/1.0 ones
+    /// only when they say `Connection: keep-alive`
+    pub fn keeps_alive_after(&self, request: &Request) -> bool {
+        self.keep_alive && request.wants_keep_alive()
+    }
+
+    /// Whether the connection may carry another request after the current one
+    pub fn has_requests_left(&self) -> bool {
+        self.max_requests.is_none_or(|max| self.requests < max)
+    }
+
+    /// The `Keep-Alive` header of the responses keeping the connection open
+    pub fn keep_alive_header(&self) -> Header {
+        let mut value = format!("timeout={}", self.idle_timeout.as_secs());
+        if let Some(max) = self.max_requests {
+            value.push_str(&format!(", max={}", max.saturating_sub(self.requests)));
         }
+        Header::from_str("Keep-Alive", &value)
     }
 
     pub fn handle_event(&mut self, event: u32) -> io::Result<ConnectionState> {
@@ -52,11 +132,11 @@ impl Connection {
                             match self.process_complete_request(data) {
                                 Ok(request) => {
                                     
-                                    self.state = ConnectionState::Complete(request);
+                                    self.state = ConnectionState::Complete(Box::new(request));
                                     Ok(self.state.clone())
                                 }
                                 Err(e) => {
-                                    self.state = ConnectionState::Error(e.to_string());
+                                    self.state = failed(e);
                                     Ok(self.state.clone())
                                 }
                             }
@@ -74,7 +154,7 @@ impl Connection {
                     }
                 }
                 Err(e ) => {
-                    self.state = ConnectionState::Error(e.to_string());
+                    self.state = failed(e);
                     Ok(self.state.clone())
                 }
             }
@@ -84,15 +164,40 @@ impl Connection {
     }
 
     fn process_complete_request(&mut self, data: RequestData) -> io::Result<Request> {
-        match parse_request(&data.data) {
-            Some(request) => {
+        let parsed = parse_request(&data.data);
+        let malformed = parsed.is_none().then(|| malformed_prefix(&data.data));
+        // The request owns copies of what it needs, its bytes read the next one
+        self.reader.recycle(data.data);
+        match parsed {
+            Some(mut request) => {
+                if data.body.is_some() {
+                    request.body = data.body;
+                }
+                self.requests += 1;
+                // The last request allowed is answered with the connection closing
+                if !self.has_requests_left() {
+                    self.keep_alive = false;
+                }
+                request.peer_addr = self.peer_addr.map(|addr| addr.ip());
+                request.client_addr = request.peer_addr;
+                request.secure = self.secure.load(Ordering::SeqCst);
+                forwarded::resolve(&mut request);
                 self.reset();
                 Ok(request)
             },
-            None => Err(io::Error::new(io::ErrorKind::InvalidData, "failed parsed request"))
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unparseable request {}", malformed.unwrap_or_default()),
+            )),
         }
     }
 
+    /// Whether no request is in progress, the client not having sent any
+    /// byte of its next one
+    pub fn is_idle(&self) -> bool {
+        self.reader.is_idle()
+    }
+
     pub fn reset(&mut self) {
         self.reader.reset();
         self.state = ConnectionState::AwaitingRequest;
@@ -100,9 +205,53 @@ impl Connection {
     }
 
     pub fn send_response(&mut self, response: String) -> std::io::Result<()> {
-        if let Err(e) = self.reader.write(response.as_bytes()) {
+        self.write_response(response.as_bytes())
+    }
+
+    /// Sends `response`, serialized into a buffer of the pool rather than
+    /// a string of its own, with the `Date` and `Server` it lacks and the
+    /// `Keep-Alive` of the ones keeping the connection open
+    pub fn send(&mut self, response: &Response) -> io::Result<()> {
+        let mut extra = standard_headers::missing(response);
+        let kept_open = response.headers.iter().any(|header| header.name == HeaderName::Connection
+            && header.value.value.eq_ignore_ascii_case("keep-alive"));
+        let told = response.headers.iter().any(|header| header.name == HeaderName::KeepAlive);
+        if kept_open && !told {
+            extra.push(self.keep_alive_header());
+        }
+        let mut buffer = buffers::RESPONSES.take();
+        response.write_with(&mut buffer, &extra);
+        let result = self.write_response(&buffer);
+        buffers::RESPONSES.give(buffer);
+        result
+    }
+
+    fn write_response(&mut self, response: &[u8]) -> io::Result<()> {
+        if let Err(e) = self.reader.write(response) {
             println!("erreur to write: {}", e);
         };
         self.reader.flush()
     }
-}
\ No newline at end of file
+}
+
+/// The first bytes of `data`, escaped, to tell what was sent
+fn malformed_prefix(data: &[u8]) -> String {
+    let prefix = &data[..data.len().min(MALFORMED_PREFIX)];
+    let more = if data.len() > MALFORMED_PREFIX { "..." } else { "" };
+    format!("\"{}\"{}", prefix.escape_ascii(), more)
+}
+
+/// Tells requests the client got wrong from other failures
+fn failed(error: io::Error) -> ConnectionState {
+    if error.get_ref().is_some_and(|inner| inner.is::<HeadersTooLarge>()) {
+        return ConnectionState::HeadersTooLarge(error.to_string());
+    }
+    if error.get_ref().is_some_and(|inner| inner.is::<BodyTooLarge>()) {
+        return ConnectionState::BodyTooLarge(error.to_string());
+    }
+    match error.kind() {
+        io::ErrorKind::InvalidData => ConnectionState::Invalid(error.to_string()),
+        io::ErrorKind::Unsupported => ConnectionState::UnsupportedVersion(error.to_string()),
+        _ => ConnectionState::Error(error.to_string()),
+    }
+}
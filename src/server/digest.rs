@@ -0,0 +1,294 @@
+//! HTTP Digest authentication
+//!
+//! Routes with `auth_digest` challenge clients like `auth_basic` does, but the
+//! password never crosses the network: the client answers a nonce of the server
+//! with a hash of it and its credentials (RFC 7616, `qop=auth`). That suits
+//! plain HTTP deployments where Basic would send passwords in the clear.
+//!
+//! Users come from an htdigest style file of `user:realm:hash` lines, the hash
+//! being `H(user:realm:password)` with the configured algorithm, MD5 for the
+//! files `htdigest` writes. Nonces are kept in a small cache for `nonce_ttl`,
+//! each one only accepting request counts above the last seen to refuse
+//! replays. Past that, or once evicted, a correct answer gets a new challenge
+//! marked `stale` so the client retries without asking its user again.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use crate::http::header::HeaderName;
+use crate::http::request::Request;
+use crate::server::auth::quoted;
+use crate::server::errors::HttpError;
+use crate::server::logger::logger;
+
+/// Nonces remembered before the oldest are forgotten
+const NONCE_CAPACITY: usize = 4096;
+pub const DEFAULT_NONCE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DigestAlgorithm {
+    #[default]
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "MD5" => Some(DigestAlgorithm::Md5),
+            "SHA-256" => Some(DigestAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    /// Lowercase hex hash of `data`
+    pub fn hash(&self, data: &str) -> String {
+        let bytes = match self {
+            DigestAlgorithm::Md5 => Md5::digest(data).to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        };
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn hex_len(&self) -> usize {
+        match self {
+            DigestAlgorithm::Md5 => 32,
+            DigestAlgorithm::Sha256 => 64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Users {
+    loaded: bool,
+    modified: Option<SystemTime>,
+    /// `H(user:realm:password)` per user of the realm
+    hashes: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+struct Nonce {
+    issued: Instant,
+    /// Highest request count the client used it with
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+struct Nonces {
+    issued: HashMap<String, Nonce>,
+    /// Oldest first, to forget nonces past `NONCE_CAPACITY`
+    order: VecDeque<String>,
+}
+
+/// Checks `Digest` authorization headers against an htdigest users file
+#[derive(Debug)]
+pub struct DigestAuth {
+    pub realm: String,
+    pub algorithm: DigestAlgorithm,
+    users_file: PathBuf,
+    nonce_ttl: Duration,
+    users: Mutex<Users>,
+    nonces: Mutex<Nonces>,
+}
+
+impl DigestAuth {
+    pub fn new(realm: &str, users_file: PathBuf, algorithm: DigestAlgorithm, nonce_ttl: Duration) -> Self {
+        let auth = DigestAuth {
+            realm: realm.to_string(),
+            algorithm,
+            users_file,
+            nonce_ttl,
+            users: Mutex::new(Users::default()),
+            nonces: Mutex::new(Nonces::default()),
+        };
+        // Reports an unreadable file at startup rather than on the first request
+        drop(auth.users());
+        auth
+    }
+
+    /// Returns the name of the authenticated user, or the error challenging the client
+    pub fn authenticate(&self, request: &Request) -> Result<String, HttpError> {
+        let params = request.get_header(HeaderName::Authorization)
+            .and_then(|header| parse_params(&header.value.value))
+            .ok_or_else(|| self.challenge("Authentication required", false))?;
+        let param = |name: &str| params.get(name).map(String::as_str);
+
+        let (Some(user), Some(realm), Some(nonce), Some(uri), Some(response), Some(nc), Some(cnonce)) = (
+            param("username"), param("realm"), param("nonce"), param("uri"),
+            param("response"), param("nc"), param("cnonce"),
+        ) else {
+            return Err(self.challenge("Incomplete digest credentials", false));
+        };
+        let algorithm = param("algorithm").map_or(Some(DigestAlgorithm::Md5), DigestAlgorithm::parse);
+        if realm != self.realm || algorithm != Some(self.algorithm) || param("qop") != Some("auth") {
+            return Err(self.challenge("Digest credentials don't match the challenge", false));
+        }
+        // The URI hashed by the client must be the one requested
+        if uri != request.uri {
+            return Err(self.challenge("Digest URI doesn't match the request", false));
+        }
+        let Ok(count) = u32::from_str_radix(nc, 16) else {
+            return Err(self.challenge("Invalid nonce count", false));
+        };
+
+        let Some(ha1) = self.users().hashes.get(user).cloned() else {
+            return Err(self.challenge("Invalid credentials", false));
+        };
+        let ha2 = self.algorithm.hash(&format!("{}:{}", request.method, uri));
+        let expected = self.algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2));
+        if !constant_time_eq(expected.as_bytes(), response.to_ascii_lowercase().as_bytes()) {
+            return Err(self.challenge("Invalid credentials", false));
+        }
+
+        // The credentials are right, the nonce decides whether they are still accepted
+        let mut nonces = self.nonces();
+        match nonces.issued.get_mut(nonce) {
+            Some(issued) if issued.issued.elapsed() <= self.nonce_ttl => {
+                if count <= issued.count {
+                    drop(nonces);
+                    return Err(self.challenge("Nonce count reused", false));
+                }
+                issued.count = count;
+                Ok(user.to_string())
+            }
+            _ => {
+                drop(nonces);
+                Err(self.challenge("Nonce expired", true))
+            }
+        }
+    }
+
+    /// Issues a new nonce and the error carrying it
+    fn challenge(&self, message: &str, stale: bool) -> HttpError {
+        let nonce = uuid::Uuid::new_v4().simple().to_string();
+        let mut nonces = self.nonces();
+        let now = Instant::now();
+        let ttl = self.nonce_ttl;
+        let Nonces { issued, order } = &mut *nonces;
+        while order.front().is_some_and(|oldest| {
+            order.len() >= NONCE_CAPACITY || issued.get(oldest).is_none_or(|n| now - n.issued > ttl)
+        }) {
+            if let Some(oldest) = order.pop_front() {
+                issued.remove(&oldest);
+            }
+        }
+        issued.insert(nonce.clone(), Nonce { issued: now, count: 0 });
+        order.push_back(nonce.clone());
+
+        HttpError::Unauthorized {
+            challenge: format!(
+                "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"{}",
+                quoted(&self.realm),
+                self.algorithm.as_str(),
+                nonce,
+                if stale { ", stale=true" } else { "" },
+            ),
+            message: message.to_string(),
+        }
+    }
+
+    fn nonces(&self) -> MutexGuard<'_, Nonces> {
+        self.nonces.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the users, reading the file again when it was modified
+    fn users(&self) -> MutexGuard<'_, Users> {
+        let mut users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+        let modified = fs::metadata(&self.users_file).and_then(|m| m.modified()).ok();
+        if users.loaded && users.modified == modified {
+            return users;
+        }
+
+        let hashes = match fs::read_to_string(&self.users_file) {
+            Ok(content) => self.parse_htdigest(&content, &self.users_file),
+            Err(e) => {
+                logger().error(&format!(
+                    "Cannot read users file {}: {}, every request is refused", self.users_file.display(), e
+                ), "DigestAuth");
+                HashMap::new()
+            }
+        };
+        *users = Users { loaded: true, modified, hashes };
+        users
+    }
+
+    /// Reads the `user:realm:hash` lines of the realm, skipping blank lines,
+    /// comments and hashes of another algorithm
+    fn parse_htdigest(&self, content: &str, path: &Path) -> HashMap<String, String> {
+        let logger = logger();
+        let mut hashes = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ':');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(user), Some(realm), Some(hash))
+                    if hash.len() == self.algorithm.hex_len() && hash.bytes().all(|b| b.is_ascii_hexdigit()) =>
+                {
+                    if realm == self.realm {
+                        hashes.insert(user.to_string(), hash.to_ascii_lowercase());
+                    }
+                }
+                _ => logger.warn(&format!(
+                    "{}:{}: ignored, expected `user:realm:hash` with a {} hash",
+                    path.display(), number + 1, self.algorithm.as_str()
+                ), "DigestAuth"),
+            }
+        }
+        hashes
+    }
+}
+
+/// Parses the `name=value` pairs of a `Digest` authorization header, values
+/// being tokens or quoted strings
+fn parse_params(value: &str) -> Option<HashMap<String, String>> {
+    let (scheme, mut rest) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("digest") {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    loop {
+        rest = rest.trim_start_matches([' ', ',']);
+        if rest.is_empty() {
+            return Some(params);
+        }
+        let (name, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => value.push(chars.next()?.1),
+                    (i, '"') => break i + 1,
+                    (_, c) => value.push(c),
+                }
+            };
+            (value, &quoted[end..])
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (after[..end].trim().to_string(), &after[end..])
+        };
+        params.insert(name.trim().to_ascii_lowercase(), value);
+        rest = remaining;
+    }
+}
+
+/// Whether `a` and `b` are equal, in a time depending on their length only
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
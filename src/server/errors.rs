@@ -1,13 +1,24 @@
 use crate::config::config::ConfigError;
 use crate::http::body::Body;
+use crate::http::escape;
 use crate::http::header::Header;
+use crate::http::request::Request;
 use crate::http::response::{Response, ResponseBuilder};
 use crate::http::status::HttpStatusCode;
 use std::path::{PathBuf, Path};
 use std::env;
 use serde_json::json;
 
-use super::static_files::ServerStaticFiles;
+use super::static_files::{sites_dir, ServerStaticFiles};
+
+const TEXT_HTML: &str = "text/html";
+const APPLICATION_JSON: &str = "application/json";
+
+/// The format of `offered` an error is answered in, the first one when
+/// `request` accepts neither, as an error is answered anyway
+fn error_format(request: &Request, offered: [&'static str; 2]) -> &'static str {
+    request.negotiate(&offered).unwrap_or(offered[0])
+}
 
 #[derive(Debug)]
 pub enum ServerError {
@@ -43,17 +54,39 @@ pub enum UploaderError {
     FileNotFound(i32), // ID du fichier
     DeleteError(i32),  // ID du fichier
     DatabaseSyncError(String),
+    UploadNotFound(String), // ID de l'upload par morceaux
+    IntegrityError(String),
+    InvalidFileName(String),
+    /// The file belongs to another session
+    NotOwner(i32),
+    /// A file refused by an `UploadValidator`
+    ValidationFailed { validator: String, reason: String },
+    StorageQuotaExceeded { scope: String, used: u64, limit: u64 },
+    FileQuotaExceeded { scope: String, used: usize, limit: usize },
 }
 
 #[derive(Debug, Clone)]
 pub enum HttpError {
     BadRequest(String),
+    /// Credentials are missing or wrong, `challenge` is sent as `WWW-Authenticate`
+    Unauthorized { challenge: String, message: String },
     Forbidden(String),
     NotFound(String),
     MethodNotAllowed(String),
     PayloadTooLarge(String),
     UnsupportedMediaType(String),
+    UnprocessableEntity(String),
+    /// Rate limited, the client may retry after `retry_after` seconds
+    TooManyRequests { message: String, retry_after: u64 },
+    /// The request's headers are too large or too many
+    RequestHeaderFieldsTooLarge(String),
     InternalServerError(String),
+    /// An upstream, like a proxied server, couldn't be reached or answered wrong
+    BadGateway(String),
+    /// An upstream, like a CGI script, didn't answer in time
+    GatewayTimeout(String),
+    /// The request line names another version than HTTP/1.0 or HTTP/1.1
+    HttpVersionNotSupported(String),
     Found(String),
 }
 
@@ -65,6 +98,8 @@ pub enum CGIError {
     ExecutionFailed(String),
     ScriptOutputError(String),
     InvalidOutputFormat,
+    /// The script ran longer than its timeout and was killed
+    Timeout(std::time::Duration),
 }
 
 impl From<std::io::Error> for ServerError {
@@ -142,6 +177,16 @@ impl std::fmt::Display for UploaderError {
             UploaderError::FileNotFound(id) => write!(f, "File with ID {} not found", id),
             UploaderError::DeleteError(id) => write!(f, "Failed to delete file with ID: {}", id),
             UploaderError::DatabaseSyncError(msg) => write!(f, "Database sync error: {}", msg),
+            UploaderError::UploadNotFound(id) => write!(f, "Upload {} not found", id),
+            UploaderError::IntegrityError(msg) => write!(f, "Upload integrity check failed: {}", msg),
+            UploaderError::InvalidFileName(name) => write!(f, "Invalid file name: {}", name),
+            UploaderError::NotOwner(id) => write!(f, "File with ID {} belongs to another session", id),
+            UploaderError::ValidationFailed { validator, reason } =>
+                write!(f, "Upload rejected by {}: {}", validator, reason),
+            UploaderError::StorageQuotaExceeded { scope, used, limit } =>
+                write!(f, "Upload quota exceeded for {}: {} bytes would be used (max: {} bytes)", scope, used, limit),
+            UploaderError::FileQuotaExceeded { scope, used, limit } =>
+                write!(f, "File quota exceeded for {}: {} files would be stored (max: {} files)", scope, used, limit),
         }
     }
 }
@@ -150,12 +195,19 @@ impl std::fmt::Display for HttpError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             HttpError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
+            HttpError::Unauthorized { message, .. } => write!(f, "Unauthorized: {}", message),
             HttpError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
             HttpError::NotFound(msg) => write!(f, "Not found: {}", msg),
             HttpError::MethodNotAllowed(msg) => write!(f, "Method not allowed: {}", msg),
             HttpError::PayloadTooLarge(msg) => write!(f, "Payload too large: {}", msg),
             HttpError::UnsupportedMediaType(msg) => write!(f, "Unsupported media type: {}", msg),
+            HttpError::UnprocessableEntity(msg) => write!(f, "Unprocessable entity: {}", msg),
+            HttpError::TooManyRequests { message, .. } => write!(f, "Too many requests: {}", message),
+            HttpError::RequestHeaderFieldsTooLarge(msg) => write!(f, "Request header fields too large: {}", msg),
             HttpError::InternalServerError(msg) => write!(f, "Internal server error: {}", msg),
+            HttpError::BadGateway(msg) => write!(f, "Bad gateway: {}", msg),
+            HttpError::GatewayTimeout(msg) => write!(f, "Gateway timeout: {}", msg),
+            HttpError::HttpVersionNotSupported(msg) => write!(f, "HTTP version not supported: {}", msg),
             HttpError::Found(msg) => write!(f, "Found: {}", msg),
         }
     }
@@ -170,6 +222,7 @@ impl std::fmt::Display for CGIError {
             CGIError::ExecutionFailed(msg) => write!(f, "Failed to execute CGI script: {}", msg),
             CGIError::ScriptOutputError(msg) => write!(f, "CGI script error: {}", msg),
             CGIError::InvalidOutputFormat => write!(f, "Invalid CGI output format"),
+            CGIError::Timeout(timeout) => write!(f, "CGI script timed out after {}s", timeout.as_secs_f64()),
         }
     }
 }
@@ -186,17 +239,32 @@ impl ServerError {
             ServerError::UploaderError(UploaderError::FileTooLarge { .. }) => HttpStatusCode::PayloadTooLarge,
             ServerError::UploaderError(UploaderError::UnsupportedFileType(_)) => HttpStatusCode::UnsupportedMediaType,
             ServerError::UploaderError(UploaderError::FileNotFound(_)) => HttpStatusCode::NotFound,
+            ServerError::UploaderError(UploaderError::UploadNotFound(_)) => HttpStatusCode::NotFound,
+            ServerError::UploaderError(UploaderError::IntegrityError(_)) => HttpStatusCode::BadRequest,
+            ServerError::UploaderError(UploaderError::InvalidFileName(_)) => HttpStatusCode::BadRequest,
+            ServerError::UploaderError(UploaderError::NotOwner(_)) => HttpStatusCode::Forbidden,
+            ServerError::UploaderError(UploaderError::ValidationFailed { .. }) => HttpStatusCode::UnprocessableEntity,
+            ServerError::UploaderError(UploaderError::StorageQuotaExceeded { .. }) => HttpStatusCode::PayloadTooLarge,
+            ServerError::UploaderError(UploaderError::FileQuotaExceeded { .. }) => HttpStatusCode::Forbidden,
             ServerError::HttpError(HttpError::BadRequest(_)) => HttpStatusCode::BadRequest,
+            ServerError::HttpError(HttpError::Unauthorized { .. }) => HttpStatusCode::Unauthorized,
             ServerError::HttpError(HttpError::Forbidden(_)) => HttpStatusCode::Forbidden,
             ServerError::HttpError(HttpError::NotFound(_)) => HttpStatusCode::NotFound,
             ServerError::HttpError(HttpError::MethodNotAllowed(_)) => HttpStatusCode::MethodNotAllowed,
             ServerError::HttpError(HttpError::PayloadTooLarge(_)) => HttpStatusCode::PayloadTooLarge,
             ServerError::HttpError(HttpError::UnsupportedMediaType(_)) => HttpStatusCode::UnsupportedMediaType,
+            ServerError::HttpError(HttpError::UnprocessableEntity(_)) => HttpStatusCode::UnprocessableEntity,
+            ServerError::HttpError(HttpError::TooManyRequests { .. }) => HttpStatusCode::TooManyRequests,
+            ServerError::HttpError(HttpError::RequestHeaderFieldsTooLarge(_)) => HttpStatusCode::RequestHeaderFieldsTooLarge,
+            ServerError::HttpError(HttpError::BadGateway(_)) => HttpStatusCode::BadGateway,
+            ServerError::HttpError(HttpError::GatewayTimeout(_)) => HttpStatusCode::GatewayTimeout,
+            ServerError::HttpError(HttpError::HttpVersionNotSupported(_)) => HttpStatusCode::HTTPVersionNotSupported,
             ServerError::CGIError(CGIError::ScriptNotFound(_)) => HttpStatusCode::NotFound,
             ServerError::CGIError(CGIError::ExtensionNotAllowed(_)) => HttpStatusCode::Forbidden,
             ServerError::CGIError(CGIError::ExecutionFailed(_)) => HttpStatusCode::InternalServerError,
             ServerError::CGIError(CGIError::ScriptOutputError(_)) => HttpStatusCode::InternalServerError,
             ServerError::CGIError(CGIError::InvalidOutputFormat) => HttpStatusCode::InternalServerError,
+            ServerError::CGIError(CGIError::Timeout(_)) => HttpStatusCode::GatewayTimeout,
             _ => HttpStatusCode::InternalServerError,
         }
     }
@@ -217,7 +285,20 @@ impl ServerError {
             ServerError::UploaderError(UploaderError::FileNotFound(id)) => {
                 HttpError::NotFound(format!("File with ID {} not found", id))
             },
+            ServerError::UploaderError(UploaderError::UploadNotFound(id)) => {
+                HttpError::NotFound(format!("Upload {} not found", id))
+            },
+            ServerError::UploaderError(UploaderError::IntegrityError(msg)) => {
+                HttpError::BadRequest(format!("Upload integrity check failed: {}", msg))
+            },
+            ServerError::UploaderError(UploaderError::InvalidFileName(name)) => {
+                HttpError::BadRequest(format!("Invalid file name: {}", name))
+            },
+            ServerError::UploaderError(UploaderError::ValidationFailed { validator, reason }) => {
+                HttpError::UnprocessableEntity(format!("Upload rejected by {}: {}", validator, reason))
+            },
             ServerError::HttpError(e) => e.clone(),
+            ServerError::CGIError(e @ CGIError::Timeout(_)) => HttpError::GatewayTimeout(format!("{}", e)),
             ServerError::CGIError(e) => HttpError::InternalServerError(format!("{}", e)),
             _ => HttpError::InternalServerError("Internal server error".to_string()),
         }.to_response(None)
@@ -227,6 +308,11 @@ impl ServerError {
 
 impl HttpError {
     pub fn new(e: ServerError) -> Self {
+        // These carry response headers, which the status alone doesn't
+        if let ServerError::HttpError(error @ (HttpError::Unauthorized { .. } | HttpError::TooManyRequests { .. })) = e {
+            return error;
+        }
+
         let status = e.to_http_status();
         let message = format!("{}", e);
         match status {
@@ -236,7 +322,12 @@ impl HttpError {
             HttpStatusCode::MethodNotAllowed => HttpError::MethodNotAllowed(message.to_string()),
             HttpStatusCode::PayloadTooLarge => HttpError::PayloadTooLarge(message.to_string()),
             HttpStatusCode::UnsupportedMediaType => HttpError::UnsupportedMediaType(message.to_string()),
+            HttpStatusCode::UnprocessableEntity => HttpError::UnprocessableEntity(message.to_string()),
             HttpStatusCode::InternalServerError => HttpError::InternalServerError(message.to_string()),
+            HttpStatusCode::BadGateway => HttpError::BadGateway(message.to_string()),
+            HttpStatusCode::GatewayTimeout => HttpError::GatewayTimeout(message.to_string()),
+            HttpStatusCode::RequestHeaderFieldsTooLarge => HttpError::RequestHeaderFieldsTooLarge(message.to_string()),
+            HttpStatusCode::HTTPVersionNotSupported => HttpError::HttpVersionNotSupported(message.to_string()),
             _ => HttpError::InternalServerError(message.to_string()),
         }
     }
@@ -244,12 +335,19 @@ impl HttpError {
     pub fn status_code(&self) -> HttpStatusCode {
         match self {
             HttpError::BadRequest(_) => HttpStatusCode::BadRequest,
+            HttpError::Unauthorized { .. } => HttpStatusCode::Unauthorized,
             HttpError::Forbidden(_) => HttpStatusCode::Forbidden,
             HttpError::NotFound(_) => HttpStatusCode::NotFound,
             HttpError::MethodNotAllowed(_) => HttpStatusCode::MethodNotAllowed,
             HttpError::PayloadTooLarge(_) => HttpStatusCode::PayloadTooLarge,
             HttpError::UnsupportedMediaType(_) => HttpStatusCode::UnsupportedMediaType,
+            HttpError::UnprocessableEntity(_) => HttpStatusCode::UnprocessableEntity,
+            HttpError::TooManyRequests { .. } => HttpStatusCode::TooManyRequests,
+            HttpError::RequestHeaderFieldsTooLarge(_) => HttpStatusCode::RequestHeaderFieldsTooLarge,
             HttpError::InternalServerError(_) => HttpStatusCode::InternalServerError,
+            HttpError::BadGateway(_) => HttpStatusCode::BadGateway,
+            HttpError::GatewayTimeout(_) => HttpStatusCode::GatewayTimeout,
+            HttpError::HttpVersionNotSupported(_) => HttpStatusCode::HTTPVersionNotSupported,
             HttpError::Found(_) => HttpStatusCode::Found,
         }
     }
@@ -257,18 +355,62 @@ impl HttpError {
     fn message(&self) -> &str {
         match self {
             HttpError::BadRequest(msg) => msg,
+            HttpError::Unauthorized { message, .. } => message,
             HttpError::Forbidden(msg) => msg,
             HttpError::NotFound(msg) => msg,
             HttpError::MethodNotAllowed(msg) => msg,
             HttpError::PayloadTooLarge(msg) => msg,
             HttpError::UnsupportedMediaType(msg) => msg,
+            HttpError::UnprocessableEntity(msg) => msg,
+            HttpError::TooManyRequests { message, .. } => message,
+            HttpError::RequestHeaderFieldsTooLarge(msg) => msg,
             HttpError::InternalServerError(msg) => msg,
+            HttpError::BadGateway(msg) => msg,
+            HttpError::GatewayTimeout(msg) => msg,
+            HttpError::HttpVersionNotSupported(msg) => msg,
             HttpError::Found(msg) => msg,
         }
     }
 
 
     pub fn to_response(&self, static_files: Option<&mut ServerStaticFiles>) -> Response {
+        self.with_headers(self.error_response(static_files))
+    }
+
+    /// Like `to_response`, in the format the client prefers between HTML and
+    /// JSON. Those without a preference get the error pages of routes serving
+    /// files, and JSON from the others.
+    pub fn to_response_for(&self, request: &Request, static_files: Option<&mut ServerStaticFiles>) -> Response {
+        if let HttpError::Found(_) = self {
+            return self.to_response(None);
+        }
+        let offered = match static_files {
+            Some(_) => [TEXT_HTML, APPLICATION_JSON],
+            None => [APPLICATION_JSON, TEXT_HTML],
+        };
+        let response = match error_format(request, offered) {
+            TEXT_HTML if static_files.is_none() => self.html_response(),
+            TEXT_HTML => self.error_response(static_files),
+            _ => self.error_response(None),
+        };
+        self.with_headers(response)
+    }
+
+    /// Adds the headers some errors come with to their `response`
+    fn with_headers(&self, mut response: Response) -> Response {
+        match self {
+            HttpError::Unauthorized { challenge, .. } => {
+                response.headers.push(Header::from_str("www-authenticate", challenge));
+            }
+            HttpError::TooManyRequests { retry_after, .. } => {
+                response.headers.push(Header::from_str("retry-after", &retry_after.to_string()));
+            }
+            _ => {}
+        }
+        response
+    }
+
+    fn error_response(&self, static_files: Option<&mut ServerStaticFiles>) -> Response {
         let message = self.message();
         let status = self.status_code();
         let status_code = status.as_str();
@@ -303,12 +445,12 @@ impl HttpError {
                     let (before_head, after_head) = html_str.split_at(head_pos);
                     format!(
                         "{}<script>
-                        window.ERROR_CODE = '{}';  // Just the numeric part 
-                        window.ERROR_MESSAGE = '{}';
+                        window.ERROR_CODE = {};  // Just the numeric part 
+                        window.ERROR_MESSAGE = {};
                         </script>{}",
                         before_head, 
-                        status_code.split_whitespace().next().unwrap_or(status_code), // Extract just the numeric code
-                        message.replace("'", "\\'"), 
+                        escape::js_string(status_code.split_whitespace().next().unwrap_or(status_code)), // Extract just the numeric code
+                        escape::js_string(message),
                         after_head
                     )
                 } else {
@@ -352,6 +494,47 @@ impl HttpError {
     }
 
 
+    /// A page of its own, for the routes without error pages
+    fn html_response(&self) -> Response {
+        let status = self.status_code();
+        let html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>{status}</title></head>\n<body>\n<h1>{status}</h1>\n<p>{message}</p>\n</body>\n</html>\n",
+            status = escape::html(status.as_str()),
+            message = escape::html(self.message()),
+        );
+        ResponseBuilder::new()
+            .status_code(status)
+            .header(Header::from_str("content-type", "text/html; charset=UTF-8"))
+            .header(Header::from_str("content-length", &html.len().to_string()))
+            .body(Body::text(&html))
+            .build()
+    }
+
+    /// The host's `page` for this error, a path under the sites directory,
+    /// else a page of its own, for requests too malformed to negotiate with
+    pub fn page_response(&self, page: Option<&str>) -> Response {
+        let content = page.and_then(|page| std::fs::read(format!("{}/{}", sites_dir(), page)).ok());
+        let response = match content {
+            Some(content) => ResponseBuilder::new()
+                .status_code(self.status_code())
+                .header(Header::from_str("content-type", "text/html; charset=UTF-8"))
+                .header(Header::from_str("content-length", &content.len().to_string()))
+                .body(Body::text(&String::from_utf8_lossy(&content)))
+                .build(),
+            None => self.html_response(),
+        };
+        self.with_headers(response)
+    }
+
+    /// The host's 404 `page` for a request matching no route, or JSON for the
+    /// clients preferring it, negotiated as `to_response_for` does
+    pub fn not_found_for(request: &Request, page: Option<String>) -> Response {
+        match error_format(request, [TEXT_HTML, APPLICATION_JSON]) {
+            APPLICATION_JSON => HttpError::NotFound("Route not found".to_string()).to_response(None),
+            _ => HttpError::not_found(page),
+        }
+    }
+
     pub fn not_found(page: Option<String>) -> Response {
         if let Some(error_page_path) = page {
             // Try to read the custom error page file
@@ -417,7 +600,3 @@ impl HttpError {
             .build()
     }
 }
-
-pub fn sites_dir() -> String {
-    format!("{}/.cargo/localhost-cli/sites", env!("HOME"))
-}
\ No newline at end of file
@@ -0,0 +1,185 @@
+//! Typed access to the parts of a request, for handlers
+//!
+//! Extractors read the query string, body, path parameters or session of a
+//! request into the types a handler works with. Failing to, they return the
+//! error answering the client: `400` for a request that can't be read, `422`
+//! for one that reads but doesn't hold what was expected, `415` for a body of
+//! the wrong type and `403` when the route keeps no session for the client.
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct NewUser { name: String, age: u8 }
+//!
+//! RouteHandler::from_fn(|request, route| {
+//!     let (params, Json(user)) = extract::<(PathParams, Json<NewUser>)>(request, route)?;
+//!     let team: u32 = params.parse("team")?;
+//!     ...
+//! })
+//! ```
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::str::FromStr;
+use serde::de::DeserializeOwned;
+use crate::http::body::Body;
+use crate::http::header::HeaderName;
+use crate::http::request::Request;
+use crate::server::errors::{HttpError, ServerError, SessionError};
+use crate::server::route::{Route, RouteMatcher};
+use crate::server::session::session::Session;
+
+/// Something a handler can read from its request
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request, route: &Route) -> Result<Self, ServerError>;
+}
+
+/// Reads `T` from `request`, which may be a tuple of extractors
+pub fn extract<T: FromRequest>(request: &Request, route: &Route) -> Result<T, ServerError> {
+    T::from_request(request, route)
+}
+
+/// The query string, deserialized. An empty one reads like `?`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request, _route: &Route) -> Result<Self, ServerError> {
+        let query = request.uri.split_once('?').map(|(_, query)| query).unwrap_or_default();
+        serde_urlencoded::from_str(query)
+            .map(Query)
+            .map_err(|e| HttpError::BadRequest(format!("Invalid query string: {}", e)).into())
+    }
+}
+
+/// A JSON body, deserialized. Malformed JSON is a bad request, while JSON not
+/// matching `T` is unprocessable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request, _route: &Route) -> Result<Self, ServerError> {
+        if !has_media_type(request, "application/json") {
+            return Err(HttpError::UnsupportedMediaType("Expected a JSON body".to_string()).into());
+        }
+        let rejected = |e: serde_json::Error| -> ServerError {
+            if e.is_data() {
+                HttpError::UnprocessableEntity(format!("Unexpected JSON: {}", e)).into()
+            } else {
+                HttpError::BadRequest(format!("Invalid JSON: {}", e)).into()
+            }
+        };
+        // The raw body is read when there's one, the parsed body then having
+        // been dropped if it was malformed
+        match (&request.raw_body, &request.body) {
+            (Some(raw), _) => serde_json::from_slice(raw).map(Json).map_err(rejected),
+            (None, Some(Body::Json(value))) => T::deserialize(value).map(Json).map_err(rejected),
+            _ => Err(HttpError::BadRequest("Missing JSON body".to_string()).into()),
+        }
+    }
+}
+
+/// The fields of a URL-encoded or multipart form, files aside, deserialized.
+/// Fields being text, those that can't be read as `T` are unprocessable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormData<T = HashMap<String, String>>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for FormData<T> {
+    fn from_request(request: &Request, _route: &Route) -> Result<Self, ServerError> {
+        let encoded = match (&request.body, &request.raw_body) {
+            (Some(Body::Multipart(form)), _) => serde_urlencoded::to_string(&form.fields)
+                .map_err(|e| HttpError::BadRequest(format!("Invalid form: {}", e)))?,
+            (_, Some(raw)) if has_media_type(request, "application/x-www-form-urlencoded") => {
+                String::from_utf8(raw.to_vec()).map_err(|_| HttpError::BadRequest("Invalid form: not UTF-8".to_string()))?
+            }
+            (_, None) if has_media_type(request, "application/x-www-form-urlencoded") => String::new(),
+            _ => return Err(HttpError::UnsupportedMediaType("Expected a form".to_string()).into()),
+        };
+        serde_urlencoded::from_str(&encoded)
+            .map(FormData)
+            .map_err(|e| HttpError::UnprocessableEntity(format!("Unexpected form: {}", e)).into())
+    }
+}
+
+/// The values of the `:name` segments of the route's path
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathParams(pub HashMap<String, String>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// The parameter called `name`, parsed. A missing or unparsable one is a
+    /// bad request.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, ServerError> {
+        let value = self.get(name)
+            .ok_or_else(|| HttpError::BadRequest(format!("Missing path parameter {}", name)))?;
+        value.parse()
+            .map_err(|_| HttpError::BadRequest(format!("Invalid path parameter {}: {}", name, value)).into())
+    }
+}
+
+impl FromRequest for PathParams {
+    fn from_request(request: &Request, route: &Route) -> Result<Self, ServerError> {
+        let path = request.uri.split('?').next().unwrap_or_default();
+        let params = match &route.matcher {
+            Some(matcher @ RouteMatcher::Dynamic(_)) => matcher.extract_params(path),
+            _ => RouteMatcher::from_path(&route.path).extract_params(path),
+        };
+        Ok(PathParams(params))
+    }
+}
+
+/// The client's session, on the routes requiring one
+#[derive(Debug, Clone)]
+pub struct SessionData(pub Session);
+
+impl SessionData {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.data.get(key).map(String::as_str)
+    }
+}
+
+impl Deref for SessionData {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.0
+    }
+}
+
+impl FromRequest for SessionData {
+    fn from_request(request: &Request, _route: &Route) -> Result<Self, ServerError> {
+        request.session.as_deref()
+            .map(|session| SessionData(session.clone()))
+            .ok_or_else(|| SessionError::AuthenticationRequired.into())
+    }
+}
+
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(request: &Request, route: &Route) -> Result<Self, ServerError> {
+        Ok(T::from_request(request, route).ok())
+    }
+}
+
+macro_rules! tuple_from_request {
+    ($($extractor:ident),+) => {
+        impl<$($extractor: FromRequest),+> FromRequest for ($($extractor,)+) {
+            fn from_request(request: &Request, route: &Route) -> Result<Self, ServerError> {
+                Ok(($($extractor::from_request(request, route)?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_request!(A, B);
+tuple_from_request!(A, B, C);
+tuple_from_request!(A, B, C, D);
+
+/// Whether the request's body is declared of `media_type`, its parameters
+/// like the charset ignored
+fn has_media_type(request: &Request, media_type: &str) -> bool {
+    request.get_header(HeaderName::ContentType)
+        .and_then(|header| header.value.value.split(';').next().map(|value| value.trim().eq_ignore_ascii_case(media_type)))
+        .unwrap_or(false)
+}
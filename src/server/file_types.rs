@@ -0,0 +1,181 @@
+//! Upload type policy
+//!
+//! Decides which files may be uploaded from their extension, their declared MIME
+//! type and the MIME type sniffed from their first bytes, so a renamed executable
+//! can't pass as an image.
+//!
+//! It also resolves the MIME type of a file from its extension, for static files
+//! and uploads sent without a type, the top-level `types` config section taking
+//! precedence over the built-in table.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::RwLock;
+use crate::server::errors::UploaderError;
+
+/// Number of leading bytes needed by `sniff_mime_type`
+pub const SNIFF_LEN: usize = 16;
+
+/// MIME types and prefixes accepted when no allow-list is configured
+const DEFAULT_ALLOWED_TYPES: [&str; 8] = [
+    "text/", "image/", "application/pdf", "application/json",
+    "application/msword", "application/vnd.openxmlformats-officedocument",
+    "audio/", "video/"
+];
+
+/// Formats stored in a zip container, which sniff as `application/zip`
+const ZIP_BASED_TYPES: [&str; 4] = [
+    "application/vnd.openxmlformats-officedocument",
+    "application/vnd.oasis.opendocument",
+    "application/epub+zip",
+    "application/java-archive",
+];
+
+/// Signatures recognised by `sniff_mime_type`, as (offset, magic bytes, MIME type)
+const SIGNATURES: [(usize, &[u8], &str); 18] = [
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (8, b"WEBP", "image/webp"),
+    (0, b"BM", "image/bmp"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"MZ", "application/x-msdownload"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\xca\xfe\xba\xbe", "application/x-mach-binary"),
+    (0, b"#!", "application/x-sh"),
+];
+
+/// Allow and deny lists applied to uploaded files
+#[derive(Debug, Clone)]
+pub struct UploadTypePolicy {
+    /// MIME types, or prefixes ending with `/`, accepted for upload
+    pub allowed_types: Vec<String>,
+    /// MIME types or prefixes rejected even when allowed
+    pub denied_types: Vec<String>,
+    /// Extensions accepted for upload, any extension when `None`
+    pub allowed_extensions: Option<Vec<String>>,
+    /// Extensions always rejected
+    pub denied_extensions: Vec<String>,
+}
+
+impl Default for UploadTypePolicy {
+    fn default() -> Self {
+        UploadTypePolicy {
+            allowed_types: DEFAULT_ALLOWED_TYPES.iter().map(|t| t.to_string()).collect(),
+            denied_types: Vec::new(),
+            allowed_extensions: None,
+            denied_extensions: Vec::new(),
+        }
+    }
+}
+
+impl UploadTypePolicy {
+    pub fn is_allowed_mime_type(&self, mime_type: &str) -> bool {
+        let mime_type = mime_type.to_ascii_lowercase();
+        let matches = |list: &[String]| list.iter()
+            .any(|entry| mime_type.starts_with(entry.to_ascii_lowercase().as_str()));
+        matches(&self.allowed_types) && !matches(&self.denied_types)
+    }
+
+    pub fn is_allowed_extension(&self, filename: &str) -> bool {
+        let extension = extension_of(filename);
+        let matches = |list: &[String]| list.iter()
+            .any(|entry| normalize_extension(entry) == extension);
+
+        if matches(&self.denied_extensions) {
+            return false;
+        }
+        self.allowed_extensions.as_ref().is_none_or(|allowed| matches(allowed))
+    }
+
+    /// Checks a file from its name and declared type, before its content is known
+    pub fn check_declared(&self, filename: &str, content_type: &str) -> Result<(), UploaderError> {
+        if !self.is_allowed_extension(filename) {
+            return Err(UploaderError::UnsupportedFileType(format!(
+                "extension '.{}' is not allowed", extension_of(filename)
+            )));
+        }
+        if !self.is_allowed_mime_type(content_type) {
+            return Err(UploaderError::UnsupportedFileType(content_type.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks a file, including the type sniffed from the first bytes of its content
+    pub fn check(&self, filename: &str, content_type: &str, head: &[u8]) -> Result<(), UploaderError> {
+        self.check_declared(filename, content_type)?;
+
+        let Some(detected) = sniff_mime_type(head) else { return Ok(()) };
+        if is_consistent(content_type, detected) || self.is_allowed_mime_type(detected) {
+            return Ok(());
+        }
+        Err(UploaderError::UnsupportedFileType(format!(
+            "content detected as {} but declared as {}", detected, content_type
+        )))
+    }
+}
+
+/// Extensions mapped to the MIME type of the `types` config section
+static TYPE_OVERRIDES: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// Installs the `types` config section, listing extensions per MIME type
+pub fn set_type_overrides(types: &HashMap<String, Vec<String>>) {
+    let overrides = types.iter()
+        .flat_map(|(mime, extensions)| extensions.iter().map(move |ext| (normalize_extension(ext), mime.trim().to_string())))
+        .collect();
+    *TYPE_OVERRIDES.write().unwrap_or_else(|e| e.into_inner()) = overrides;
+}
+
+/// The MIME type of a file from its extension, `application/octet-stream` when unknown
+pub fn mime_type_of(path: &Path) -> String {
+    let extension = extension_of(&path.to_string_lossy());
+    if let Some(mime) = TYPE_OVERRIDES.read().unwrap_or_else(|e| e.into_inner()).get(&extension) {
+        return mime.clone();
+    }
+    mime_guess::from_path(path).first_or_octet_stream().to_string()
+}
+
+/// The type of an uploaded file, resolved from its name when the client sent
+/// none or only the generic `application/octet-stream`
+pub fn resolve_content_type(filename: &str, declared: Option<&str>) -> String {
+    match declared.map(str::trim) {
+        Some(declared) if !declared.is_empty() && !declared.eq_ignore_ascii_case("application/octet-stream") => {
+            declared.to_string()
+        }
+        _ => mime_type_of(Path::new(filename.trim_matches('"'))),
+    }
+}
+
+/// Identifies the type of a file from its magic bytes
+pub fn sniff_mime_type(head: &[u8]) -> Option<&'static str> {
+    SIGNATURES.iter()
+        .find(|(offset, magic, _)| head.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| *mime)
+}
+
+/// Whether `detected` is what a file declared as `declared` looks like on disk
+fn is_consistent(declared: &str, detected: &str) -> bool {
+    let declared = declared.to_ascii_lowercase();
+    declared == detected
+        || (detected == "application/zip" && ZIP_BASED_TYPES.iter().any(|t| declared.starts_with(t)))
+}
+
+fn extension_of(filename: &str) -> String {
+    Path::new(filename.trim_matches('"'))
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn normalize_extension(extension: &str) -> String {
+    extension.trim().trim_start_matches('.').to_ascii_lowercase()
+}
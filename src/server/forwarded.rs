@@ -0,0 +1,53 @@
+//! Forwarded headers
+//!
+//! Behind a reverse proxy, every request comes from the proxy's address. The
+//! proxies listed in `trusted_proxies` are believed when they name the client
+//! in `X-Forwarded-For`: the request then counts as sent by the last address of
+//! that header which isn't a trusted proxy itself, for the logs, bans, rate
+//! limits, access lists and CGI scripts alike. The header sent by anyone else
+//! is ignored, since clients can write whatever they like in it.
+
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use ipnet::IpNet;
+use crate::http::request::Request;
+
+static TRUSTED: OnceLock<Mutex<Vec<IpNet>>> = OnceLock::new();
+
+fn trusted() -> MutexGuard<'static, Vec<IpNet>> {
+    TRUSTED.get_or_init(|| Mutex::new(Vec::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Replaces the networks of the trusted proxies, none trusting every peer's own address
+pub fn configure(networks: Vec<IpNet>) {
+    *trusted() = networks;
+}
+
+pub fn is_trusted(addr: IpAddr) -> bool {
+    let addr = addr.to_canonical();
+    trusted().iter().any(|net| net.contains(&addr))
+}
+
+/// The `X-Forwarded-*` header `name` of `request`, when sent by a trusted proxy
+pub fn trusted_header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request.peer_addr.filter(|peer| is_trusted(*peer))?;
+    request.headers.iter()
+        .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+        .map(|header| header.value.value.as_str())
+}
+
+/// Makes the client named by a trusted proxy the one `request` is from
+pub fn resolve(request: &mut Request) {
+    let Some(forwarded_for) = trusted_header(request, "x-forwarded-for") else { return };
+    // Each proxy appends the address it got the request from, the client's being
+    // the last one no trusted proxy added
+    let client = forwarded_for.split(',')
+        .rev()
+        .map_while(|entry| entry.trim().parse::<IpAddr>().ok())
+        .find(|addr| !is_trusted(*addr));
+    if let Some(client) = client {
+        request.client_addr = Some(client);
+    }
+}
@@ -1,6 +1,8 @@
 /// This module provides the core handler functionality for the HTTP server.
 /// It implements different types of request handlers following a common interface.
 pub mod handlers {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
     use crate::http::request::Request;
     use crate::http::response::Response;
     use crate::server::route::Route;
@@ -21,12 +23,55 @@ pub mod handlers {
         fn serve_http(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError>;
     }
 
+    /// Closures taking the request and its route serve as handlers too
+    impl<F> Handler for F
+    where
+        F: FnMut(&Request, &Route) -> Result<Response, ServerError>,
+    {
+        fn serve_http(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+            self(request, route)
+        }
+    }
+
+    /// User code a route is bound to, shared by the clones of the route
+    #[derive(Clone)]
+    pub struct RouteHandler {
+        handler: Arc<Mutex<dyn Handler + Send>>,
+    }
+
+    impl RouteHandler {
+        pub fn new(handler: impl Handler + Send + 'static) -> Self {
+            RouteHandler { handler: Arc::new(Mutex::new(handler)) }
+        }
+
+        /// Binds a closure, whose argument types are inferred unlike when it's
+        /// passed as any `Handler`
+        pub fn from_fn<F>(handler: F) -> Self
+        where
+            F: Fn(&Request, &Route) -> Result<Response, ServerError> + Send + 'static,
+        {
+            Self::new(handler)
+        }
+    }
+
+    impl Handler for RouteHandler {
+        fn serve_http(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+            self.handler.lock().unwrap_or_else(|e| e.into_inner()).serve_http(request, route)
+        }
+    }
+
+    impl fmt::Debug for RouteHandler {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("RouteHandler").finish_non_exhaustive()
+        }
+    }
+
     /// Handlers for serving static files from the filesystem
     pub mod static_files_api {
         use super::*;
         use crate::http::{
             body::Body,
-            header::Header,
+            header::{if_none_match_matches, if_range_matches, ByteRange, Header, RangeRequest},
             response::{Response, ResponseBuilder},
             status::HttpStatusCode,
         };
@@ -57,32 +102,106 @@ pub mod handlers {
                 &mut self,
                 request: &Request,
             ) -> Result<Response, ServerError> {
+                // Clients having the file already are answered from its
+                // metadata, the file left unread
+                if let Some(if_none_match) = header(request, "if-none-match") {
+                    let unchanged = self.static_files.stat(&request.uri)
+                        && self.static_files.etag.as_deref().is_some_and(|etag| if_none_match_matches(if_none_match, etag));
+                    if unchanged {
+                        return Ok(ResponseBuilder::new()
+                            .status_code(HttpStatusCode::NotModified)
+                            .headers(self.validators())
+                            .build());
+                    }
+                }
+
                 match self.static_files.serve_static(&request.uri) {
                     Ok((content, mime, file_status)) => {
                         let mime_str = mime.as_deref().unwrap_or("text/plain");
                         let content_type = Header::from_mime(mime_str);
 
-                        let body = Body::from_mime(mime_str, content, None).unwrap();
-                        let content_length = Header::from_str("content-length", &body.body_len().to_string());
+                        if file_status == FileStatus::NotFound {
+                            let body = Body::from_mime(mime_str, content, None).unwrap();
+                            let content_length = Header::from_str("content-length", &body.body_len().to_string());
+                            return Ok(ResponseBuilder::new()
+                                .status_code(HttpStatusCode::NotFound)
+                                .header(content_type)
+                                .header(content_length)
+                                .body(body)
+                                .build());
+                        }
+
+                        let mut builder = ResponseBuilder::new()
+                            .header(content_type)
+                            .header(Header::from_str("accept-ranges", "bytes"))
+                            .headers(self.validators());
 
-                        // Determine response status based on file_status
-                        let status_code = if file_status == FileStatus::NotFound {
-                            HttpStatusCode::NotFound
-                        } else {
-                            HttpStatusCode::Ok
+                        let (status_code, body) = match self.range(request, content.len() as u64) {
+                            RangeRequest::Full => {
+                                (HttpStatusCode::Ok, Body::from_mime(mime_str, content, None).unwrap())
+                            }
+                            RangeRequest::Partial(range) => {
+                                builder = builder.header(Header::from_str("content-range", &range.content_range(content.len() as u64)));
+                                // The bytes of the file as they are, whatever its type
+                                let part = content[range.start as usize..=range.end as usize].to_vec();
+                                (HttpStatusCode::PartialContent, Body::binary(part))
+                            }
+                            RangeRequest::Unsatisfiable => {
+                                return Ok(builder
+                                    .status_code(HttpStatusCode::RangeNotSatisfiable)
+                                    .header(Header::from_str("content-range", &format!("bytes */{}", content.len())))
+                                    .header(Header::from_str("content-length", "0"))
+                                    .build());
+                            }
                         };
 
-                        // Build and return the response
-                        Ok(ResponseBuilder::new()
+                        // HEAD gets the same response, its body dropped once
+                        // the host is done with it
+                        Ok(builder
                             .status_code(status_code)
-                            .header(content_type)
-                            .header(content_length)
+                            .header(Header::from_str("content-length", &body.body_len().to_string()))
                             .body(body)
                             .build())
                     }
                     Err(e) => Err(e), // Pass ServerError directly
                 }
             }
+
+            /// The validators of the file last served or stated, all a client
+            /// that has it already gets
+            fn validators(&self) -> Vec<Header> {
+                let mut validators = Vec::new();
+                if let Some(etag) = &self.static_files.etag {
+                    validators.push(Header::from_str("etag", etag));
+                }
+                if let Some(modified) = self.static_files.modified {
+                    validators.push(Header::from_str("last-modified", &httpdate::fmt_http_date(modified)));
+                }
+                validators
+            }
+
+            /// The range of the file of `len` bytes the request asks for, the
+            /// whole file when an `If-Range` header names another version of it
+            fn range(&self, request: &Request, len: u64) -> RangeRequest {
+                let Some(range) = header(request, "range") else {
+                    return RangeRequest::Full;
+                };
+                if let Some(if_range) = header(request, "if-range") {
+                    let current = self.static_files.modified
+                        .is_some_and(|modified| if_range_matches(if_range, modified));
+                    if !current {
+                        return RangeRequest::Full;
+                    }
+                }
+                ByteRange::parse(range, len)
+            }
+        }
+
+        /// Value of the request's header `name`, whatever its case
+        fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+            request.headers.iter()
+                .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+                .map(|header| header.value.value.as_str())
         }
     }
 
@@ -93,7 +212,6 @@ pub mod handlers {
         use crate::server::cgi::CGIConfig;
         use crate::server::errors::{HttpError, ServerError};
         use std::path::Path;
-        use std::process::{Command, Stdio};
 
         /// Handles requests for executing CGI scripts
         pub struct CGIHandler {
@@ -141,16 +259,7 @@ pub mod handlers {
                 let env_vars = self.cgi_config.prepare_cgi_environment(request);
 
                 // Exécuter le script CGI
-                let output = Command::new(&self.cgi_config.interpreter)
-                    .arg(script_path)
-                    .envs(&env_vars)
-                    .stdin(Stdio::null())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .output()
-                    .map_err(|e| HttpError::InternalServerError(
-                        format!("Failed to execute CGI script: {}", e)
-                    ))?;
+                let output = self.cgi_config.execute_script(script_path, &env_vars)?;
 
                 if !output.status.success() {
                     let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -177,14 +286,18 @@ pub mod handlers {
             response::Response,
             status::HttpStatusCode,
         };
-        use crate::server::errors::{ServerError, HttpError};
-        use crate::server::uploader::Uploader;
+        use crate::server::errors::{ServerError, HttpError, UploaderError};
+        use crate::server::uploader::{File, FileUpdate, Uploader};
+        use crate::server::file_types;
+        use chrono::{DateTime, SecondsFormat, Utc};
+        use std::time::SystemTime;
         use crate::server::route::Route;
         use serde_json::json;
         
 
         pub struct FileAPIHandler {
             uploader: Uploader,
+            max_file_size: Option<usize>,
         }
 
         impl Handler for FileAPIHandler {
@@ -193,6 +306,7 @@ pub mod handlers {
                     HttpMethod::GET => self.handle_get(request, route),
                     HttpMethod::POST => self.handle_post(request, route),
                     HttpMethod::DELETE => self.handle_delete(request, route),
+                    HttpMethod::PATCH => self.handle_patch(request, route),
                     _ => Err(HttpError::MethodNotAllowed(format!(
                         "Method {} not allowed for file API", 
                         request.method
@@ -203,11 +317,30 @@ pub mod handlers {
 
         impl FileAPIHandler {
             pub fn new(uploader: Uploader) -> Result<Self, ServerError> {
-                Ok(FileAPIHandler { uploader })
+                Ok(FileAPIHandler { uploader, max_file_size: None })
+            }
+
+            /// Rejects uploaded files larger than `max_file_size` bytes
+            pub fn with_max_file_size(mut self, max_file_size: Option<usize>) -> Self {
+                self.max_file_size = max_file_size;
+                self
+            }
+
+            fn validate_size(&self, size: usize) -> Result<(), ServerError> {
+                match self.max_file_size {
+                    Some(max_size) if size > max_size => {
+                        Err(UploaderError::FileTooLarge { size, max_size }.into())
+                    }
+                    _ => Ok(()),
+                }
             }
 
             // Request handlers
             fn handle_get(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+                if request.uri == "/api/files/quota" {
+                    return self.handle_quota(route);
+                }
+
                 if request.uri != "/api/files/list" {
                     return Err(HttpError::NotFound(format!(
                         "API route not found: {}", 
@@ -215,25 +348,25 @@ pub mod handlers {
                     )).into());
                 }
 
-                match self.uploader.sync_database() {
+                // Expired files are purged in the background, don't list the ones still pending
+                match self.uploader.purge_expired().and_then(|_| self.uploader.sync_database()) {
                     Ok(_) => {
                         let files = self.uploader.list_files();
                         let files_json = json!({
-                            "files": files.iter().map(|file| json!({
-                                "id": file.id,
-                                "name": file.name,
-                                "path": file.path.to_string_lossy(),
-                                "size": file.size
-                            })).collect::<Vec<_>>()
+                            "files": files.iter().map(|file| file_json(file, &self.uploader)).collect::<Vec<_>>()
                         });
 
                         Ok(Response::response_with_json(files_json, HttpStatusCode::Ok))
                     }
-                    Err(e) => Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut())),
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
                 }
             }
 
             fn handle_post(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+                if let Some(path) = request.uri.strip_prefix("/api/files/chunks/") {
+                    return self.handle_chunk_post(request, route, path);
+                }
+
                 if request.uri != "/api/files/upload" {
                     return Err(HttpError::NotFound(format!(
                         "API route not found: {}", 
@@ -245,22 +378,35 @@ pub mod handlers {
                     Some(Body::Multipart(form)) => {
                         let mut uploaded_files = Vec::new();
 
-                        for (_, file) in &form.files {
-                            // Validate file type
-                            self.uploader.validate_mime_type(&file.content_type)?;
-                            
-                            // Add file through uploader
-                            match self.uploader.add_file(file.filename.clone(), &file.data) {
+                        // Check every part before anything is stored
+                        for file in form.files.values() {
+                            let checked = self.validate_size(file.size)
+                                .and_then(|_| self.uploader.validate_multipart_file(file));
+                            if let Err(e) = checked {
+                                Uploader::discard_spooled(form);
+                                return rejection_response(e, &file.filename);
+                            }
+                        }
+
+                        let total_size = form.files.values().map(|f| f.size as u64).sum();
+                        if let Err(e) = self.uploader.check_quota(form.files.len(), total_size) {
+                            Uploader::discard_spooled(form);
+                            return quota_error_response(e);
+                        }
+
+                        for file in form.files.values() {
+                            // Streamed parts are already on disk, just move them in place
+                            let stored = match &file.path {
+                                Some(path) => self.uploader.add_spooled_file(file.filename.clone(), path),
+                                None => self.uploader.add_file(file.filename.clone(), &file.data),
+                            };
+
+                            match stored {
                                 Ok(new_file) => {
-                                    uploaded_files.push(json!({
-                                        "id": new_file.id,
-                                        "name": new_file.name,
-                                        "path": new_file.path.to_string_lossy(),
-                                        "size": new_file.size
-                                    }));
+                                    uploaded_files.push(file_json(&new_file, &self.uploader));
                                 }
                                 Err(e) => {
-                                    return Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut()));
+                                    return Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut()));
                                 }
                                 
                             }
@@ -279,7 +425,163 @@ pub mod handlers {
                 }
             }
 
+            /// Chunked upload API: `start`, `{id}/{index}` and `{id}/finalize`
+            fn handle_chunk_post(&mut self, request: &Request, route: &Route, path: &str) -> Result<Response, ServerError> {
+                let result = match path.split('/').collect::<Vec<_>>().as_slice() {
+                    ["start"] => self.start_chunked_upload(request),
+                    [upload_id, "finalize"] => {
+                        let filename = self.uploader.get_chunked_upload(upload_id).map(|upload| upload.filename);
+                        match self.uploader.finalize_chunked_upload(upload_id) {
+                            Ok(file) => Ok(Response::response_with_json(json!({
+                                "message": "File uploaded successfully",
+                                "file": file_json(&file, &self.uploader)
+                            }), HttpStatusCode::Ok)),
+                            Err(e) => rejection_response(e, &filename.unwrap_or_default()),
+                        }
+                    }
+                    [upload_id, index] => {
+                        let index = index.parse::<usize>()
+                            .map_err(|_| HttpError::BadRequest("Invalid chunk index".to_string()))?;
+                        let data = chunk_data(request)?;
+                        self.uploader.write_chunk(upload_id, index, data)
+                            .map(|received| Response::response_with_json(json!({
+                                "upload_id": upload_id,
+                                "chunk": index,
+                                "received": received
+                            }), HttpStatusCode::Ok))
+                    }
+                    _ => return Err(HttpError::NotFound(format!(
+                        "API route not found: {}",
+                        request.uri
+                    )).into()),
+                };
+
+                match result {
+                    Ok(response) => Ok(response),
+                    Err(ServerError::HttpError(e)) => Err(e.into()),
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                }
+            }
+
+            fn start_chunked_upload(&mut self, request: &Request) -> Result<Response, ServerError> {
+                let params = match &request.body {
+                    Some(Body::Json(params)) => params,
+                    _ => return Err(HttpError::BadRequest(
+                        "Invalid request format: expected JSON".to_string()
+                    ).into()),
+                };
+
+                let filename = params.get("filename").and_then(|v| v.as_str())
+                    .ok_or_else(|| HttpError::BadRequest("Missing filename".to_string()))?;
+                let size = params.get("size").and_then(|v| v.as_u64())
+                    .ok_or_else(|| HttpError::BadRequest("Missing size".to_string()))? as usize;
+                let content_type = file_types::resolve_content_type(
+                    filename,
+                    params.get("content_type").and_then(|v| v.as_str()),
+                );
+                let sha256 = params.get("sha256").and_then(|v| v.as_str()).map(str::to_string);
+
+                self.validate_size(size)?;
+                if let Err(e) = self.uploader.validate_file_type(filename, &content_type) {
+                    return rejection_response(e, filename);
+                }
+                if let Err(e) = self.uploader.check_quota(1, size as u64) {
+                    return quota_error_response(e);
+                }
+
+                let upload = self.uploader.start_chunked_upload(filename.to_string(), content_type, size, sha256)?;
+                Ok(Response::response_with_json(json!({
+                    "upload_id": upload.id,
+                    "size": upload.size,
+                    "chunk_url": format!("/api/files/chunks/{}", upload.id)
+                }), HttpStatusCode::Created))
+            }
+
+            /// Reports the space used against the configured quota
+            fn handle_quota(&mut self, route: &Route) -> Result<Response, ServerError> {
+                if let Err(e) = self.uploader.sync_database() {
+                    return Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut()));
+                }
+
+                let quota = self.uploader.quota.clone().unwrap_or_default();
+                let host = self.uploader.usage(None);
+                let session = self.uploader.owner().map(|owner| {
+                    let usage = self.uploader.usage(Some(owner));
+                    json!({
+                        "files": usage.files,
+                        "bytes": usage.bytes,
+                        "max_files": quota.session_max_files,
+                        "max_bytes": quota.session_max_bytes
+                    })
+                });
+
+                Ok(Response::response_with_json(json!({
+                    "host": {
+                        "files": host.files,
+                        "bytes": host.bytes,
+                        "max_files": quota.max_files,
+                        "max_bytes": quota.max_bytes
+                    },
+                    "session": session
+                }), HttpStatusCode::Ok))
+            }
+
+            /// Renames a file or updates its description and tags
+            fn handle_patch(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+                let file_id = request
+                    .uri
+                    .strip_prefix("/api/files/")
+                    .and_then(|id| id.parse::<i32>().ok())
+                    .ok_or_else(|| HttpError::BadRequest("Invalid file ID".to_string()))?;
+
+                let params = match &request.body {
+                    Some(Body::Json(params)) => params,
+                    _ => return Err(HttpError::BadRequest(
+                        "Invalid request format: expected JSON".to_string()
+                    ).into()),
+                };
+
+                let name = match params.get("name") {
+                    None => None,
+                    Some(serde_json::Value::String(name)) => Some(name.clone()),
+                    Some(_) => return Err(HttpError::BadRequest("name must be a string".to_string()).into()),
+                };
+                // A null description removes it
+                let description = match params.get("description") {
+                    None => None,
+                    Some(serde_json::Value::Null) => Some(String::new()),
+                    Some(serde_json::Value::String(description)) => Some(description.clone()),
+                    Some(_) => return Err(HttpError::BadRequest("description must be a string".to_string()).into()),
+                };
+                let tags = match params.get("tags") {
+                    None => None,
+                    Some(serde_json::Value::Array(tags)) => Some(tags.iter()
+                        .map(|tag| tag.as_str().map(str::to_string))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| HttpError::BadRequest("tags must be strings".to_string()))?),
+                    Some(_) => return Err(HttpError::BadRequest("tags must be an array".to_string()).into()),
+                };
+
+                match self.uploader.update_file(file_id, FileUpdate { name, description, tags }) {
+                    Ok(file) => Ok(Response::response_with_json(json!({
+                        "message": "File updated successfully",
+                        "file": file_json(&file, &self.uploader)
+                    }), HttpStatusCode::Ok)),
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                }
+            }
+
             fn handle_delete(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+                if let Some(upload_id) = request.uri.strip_prefix("/api/files/chunks/") {
+                    return match self.uploader.abort_chunked_upload(upload_id) {
+                        Ok(_) => Ok(Response::response_with_json(json!({
+                            "message": "Upload aborted",
+                            "upload_id": upload_id
+                        }), HttpStatusCode::Ok)),
+                        Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                    };
+                }
+
                 if !request.uri.starts_with("/api/files/delete/") {
                     return Err(HttpError::NotFound(format!(
                         "API route not found: {}", 
@@ -302,8 +604,79 @@ pub mod handlers {
 
                         Ok(Response::response_with_json(body, HttpStatusCode::Ok))
                     }
-                    Err(e) => Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut())),
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                }
+            }
+        }
+
+        /// JSON description of a stored file, as returned by the file API
+        fn file_json(file: &File, uploader: &Uploader) -> serde_json::Value {
+            json!({
+                "id": file.id,
+                "name": file.name,
+                "path": file.path.to_string_lossy(),
+                "size": file.size,
+                "sha256": file.sha256,
+                "uploaded_at": rfc3339(file.uploaded_at),
+                "expires_at": uploader.expires_at(file).map(rfc3339),
+                "description": file.description,
+                "tags": file.tags
+            })
+        }
+
+        fn rfc3339(time: SystemTime) -> String {
+            DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+        }
+
+        /// Explains quota rejections as JSON, other errors go through the usual error pages
+        fn quota_error_response(error: ServerError) -> Result<Response, ServerError> {
+            match &error {
+                ServerError::UploaderError(UploaderError::StorageQuotaExceeded { scope, used, limit }) => {
+                    Ok(Response::response_with_json(json!({
+                        "error": error.to_string(),
+                        "quota": "bytes",
+                        "scope": scope,
+                        "used": used,
+                        "limit": limit
+                    }), error.to_http_status()))
+                }
+                ServerError::UploaderError(UploaderError::FileQuotaExceeded { scope, used, limit }) => {
+                    Ok(Response::response_with_json(json!({
+                        "error": error.to_string(),
+                        "quota": "files",
+                        "scope": scope,
+                        "used": used,
+                        "limit": limit
+                    }), error.to_http_status()))
                 }
+                _ => Err(error),
+            }
+        }
+
+        /// Explains why a file was refused as JSON, other errors go through the usual error pages
+        fn rejection_response(error: ServerError, filename: &str) -> Result<Response, ServerError> {
+            let (validator, reason) = match &error {
+                ServerError::UploaderError(UploaderError::UnsupportedFileType(reason)) => ("type_policy", reason),
+                ServerError::UploaderError(UploaderError::ValidationFailed { validator, reason }) => (validator.as_str(), reason),
+                _ => return Err(error),
+            };
+
+            Ok(Response::response_with_json(json!({
+                "error": error.to_string(),
+                "validator": validator,
+                "reason": reason,
+                "file": filename
+            }), error.to_http_status()))
+        }
+
+        /// Raw bytes of a chunk, whatever Content-Type the client sent it with
+        fn chunk_data(request: &Request) -> Result<&[u8], ServerError> {
+            match &request.body {
+                Some(Body::Binary(data)) => Ok(data),
+                Some(Body::Text(text)) => Ok(text.as_bytes()),
+                _ => Err(HttpError::BadRequest(
+                    "Invalid request format: expected raw chunk data".to_string()
+                ).into()),
             }
         }
     }
@@ -317,20 +690,23 @@ pub mod handlers {
             request::{Request, HttpMethod},
             response::{Response, ResponseBuilder},
             status::HttpStatusCode,
-            header::Header,
+            header::{Header, HeaderName},
             body::Body,
         };
         use crate::server::route::Route;
         use crate::server::errors::{ServerError, HttpError, SessionError};
 
         pub struct SessionHandler<'a> {
-            session_manager: &'a mut SessionManager,
+            session_manager: &'a SessionManager,
         }
 
         impl<'a> Handler for SessionHandler<'a> {
             fn serve_http(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
                 match request.method {
                     HttpMethod::POST => self.handle_create_session(request, route),
+                    HttpMethod::DELETE if request.uri == "/api/session/logout-all" => {
+                        self.handle_logout_everywhere(request, route)
+                    }
                     HttpMethod::DELETE => self.handle_destroy_session(request, route),
                     _ => Err(HttpError::MethodNotAllowed(format!(
                         "Method {} not allowed for session API", 
@@ -340,8 +716,27 @@ pub mod handlers {
             }
         }
 
+        /// The account a session is created for: the user the route
+        /// authenticated, never one the client merely names
+        fn session_user(request: &Request) -> Option<String> {
+            request.identity.as_ref()
+                .map(|identity| identity.user.clone())
+                .filter(|user| !user.is_empty())
+        }
+
+        /// Whether the client asked for a persistent remember-me token
+        fn wants_remember(request: &Request) -> bool {
+            match &request.body {
+                Some(Body::Json(json)) => json.get("remember").and_then(|r| r.as_bool()).unwrap_or(false),
+                Some(Body::FormUrlEncoded(form)) => {
+                    matches!(form.get("remember").map(String::as_str), Some("true" | "on" | "1"))
+                }
+                _ => false,
+            }
+        }
+
         impl<'a> SessionHandler<'a> {
-            pub fn new(session_manager: &'a mut SessionManager) -> Self {
+            pub fn new(session_manager: &'a SessionManager) -> Self {
                 SessionHandler { session_manager }
             }
 
@@ -353,28 +748,37 @@ pub mod handlers {
                     )).into());
                 }
 
-                match self.session_manager.create_session() {
-                    Ok((session, cookie_header)) => {
-                        match self.session_manager.store.set(session.clone()) {
-                            Ok(_) => {
-                                let body = Body::json(json!({
-                                    "message": "Session created",
-                                    "session_id": session.id
-                                }));
+                let user = session_user(request);
+                let created = match &user {
+                    Some(user) => self.session_manager.create_user_session(user),
+                    None => self.session_manager.create_session(),
+                };
 
-                                Ok(ResponseBuilder::new()
-                                    .status_code(HttpStatusCode::Ok)
-                                    .header(cookie_header)
-                                    .header(Header::from_str("content-type", "application/json"))
-                                    .header(Header::from_str("content-length", &body.body_len().to_string()))
-                                    .body(body)
-                                    .build())
+                match created {
+                    Ok((session, cookie_header)) => {
+                        let mut builder = ResponseBuilder::new();
+                        // Only an authenticated user is remembered
+                        if let Some(user) = user.filter(|_| wants_remember(request) && self.session_manager.remember_enabled()) {
+                            match self.session_manager.issue_remember_token(&user) {
+                                Ok(remember_cookie) => builder = builder.header(remember_cookie),
+                                Err(e) => return Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
                             }
-                            Err(e) => Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut())),
-                            
                         }
+
+                        let body = Body::json(json!({
+                            "message": "Session created",
+                            "session_id": session.id
+                        }));
+
+                        Ok(builder
+                            .status_code(HttpStatusCode::Ok)
+                            .header(cookie_header)
+                            .header(Header::from_str("content-type", "application/json"))
+                            .header(Header::from_str("content-length", &body.body_len().to_string()))
+                            .body(body)
+                            .build())
                     }
-                    Err(e) => Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut())),
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
                 }
             }
 
@@ -387,10 +791,15 @@ pub mod handlers {
                     )).into());
                 }
     
-                let cookie_header = request.headers.iter().find(|h| h.name.to_string() == "cookie");
+                let cookies = request.get_headers(HeaderName::Cookie);
                 
-                match self.session_manager.get_session(cookie_header) {
+                match self.session_manager.get_session(&cookies) {
                     Ok(Some(session)) => {
+                        let remember_cookie = match self.session_manager.revoke_remember_token(&cookies) {
+                            Ok(header) => header,
+                            Err(e) => return Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                        };
+
                         match self.session_manager.destroy_session(&session.id) {
                             Ok(cookie_header) => {
                                 let body = Body::json(json!({
@@ -398,7 +807,12 @@ pub mod handlers {
                                     "session_id": session.id
                                 }));
 
-                                Ok(ResponseBuilder::new()
+                                let mut builder = ResponseBuilder::new();
+                                if let Some(header) = remember_cookie {
+                                    builder = builder.header(header);
+                                }
+
+                                Ok(builder
                                     .status_code(HttpStatusCode::Ok)
                                     .header(cookie_header)
                                     .header(Header::from_str("content-type", "application/json"))
@@ -406,16 +820,248 @@ pub mod handlers {
                                     .body(body)
                                     .build())
                             }
-                            Err(e) => Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut())),
+                            Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
                         }
                     }
                     Ok(None) => Ok(Response::response_with_json(json!({
                         "message": "No valid session found"
                     }), HttpStatusCode::Unauthorized)),
-                    Err(e) => Ok(HttpError::new(e).to_response(route.static_files.clone().as_mut())),
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                }
+            }
+
+            fn handle_logout_everywhere(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+                let cookies = request.get_headers(HeaderName::Cookie);
+
+                let user = match self.session_manager.get_session(&cookies) {
+                    Ok(Some(Session { user: Some(user), .. })) => user,
+                    Ok(_) => return Ok(Response::response_with_json(json!({
+                        "message": "No user session found"
+                    }), HttpStatusCode::Unauthorized)),
+                    Err(e) => return Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                };
+
+                match self.session_manager.destroy_user_sessions(&user) {
+                    Ok(cookie_headers) => {
+                        let body = Body::json(json!({
+                            "message": "Logged out from all sessions",
+                            "user": user
+                        }));
+
+                        let mut builder = ResponseBuilder::new().status_code(HttpStatusCode::Ok);
+                        for header in cookie_headers {
+                            builder = builder.header(header);
+                        }
+
+                        Ok(builder
+                            .header(Header::from_str("content-type", "application/json"))
+                            .header(Header::from_str("content-length", &body.body_len().to_string()))
+                            .body(body)
+                            .build())
+                    }
+                    Err(e) => Ok(HttpError::new(e).to_response_for(request, route.static_files.clone().as_mut())),
+                }
+            }
+        }
+    }
+
+    /// Handlers of the admin API, mounted under `/api/admin` on the hosts enabling it
+    pub mod admin_api {
+        use super::*;
+        use std::net::IpAddr;
+        use serde_json::{json, Value};
+        use crate::http::{
+            body::Body,
+            header::HeaderName,
+            request::HttpMethod,
+            status::HttpStatusCode,
+        };
+        use crate::server::errors::HttpError;
+        use crate::server::logger::{self, logger, LogLevel};
+        use crate::server::{ban, proxy_cache, status};
+
+        pub struct AdminHandler;
+
+        impl Handler for AdminHandler {
+            fn serve_http(&mut self, request: &Request, _route: &Route) -> Result<Response, ServerError> {
+                let path = request.uri.split('?').next().unwrap_or_default();
+                match (&request.method, path) {
+                    (HttpMethod::GET, "/api/admin/log-level") => Ok(Response::response_with_json(json!({
+                        "level": logger::level().as_str().to_ascii_lowercase(),
+                    }), HttpStatusCode::Ok)),
+                    (HttpMethod::PUT, "/api/admin/log-level") => self.set_log_level(request),
+                    (HttpMethod::GET, "/api/admin/status") => Ok(self.status_page(request)),
+                    (HttpMethod::GET, "/api/admin/stats") => Ok(Response::response_with_json(status::route_stats(), HttpStatusCode::Ok)),
+                    (HttpMethod::GET, "/api/admin/bans") => Ok(Response::response_with_json(ban::list(), HttpStatusCode::Ok)),
+                    (HttpMethod::DELETE, "/api/admin/bans") => Ok(self.lift_bans(request, None)),
+                    (HttpMethod::DELETE, uri) if uri.starts_with("/api/admin/bans/") => {
+                        let client = &uri["/api/admin/bans/".len()..];
+                        let client = client.parse().map_err(|_| HttpError::BadRequest(format!(
+                            "'{}' is not an IP address", client
+                        )))?;
+                        Ok(self.lift_bans(request, Some(client)))
+                    }
+                    (HttpMethod::DELETE, "/api/admin/cache") => Ok(self.purge_cache(request)),
+                    _ => Err(HttpError::NotFound(format!("API route not found: {}", request.uri)).into()),
+                }
+            }
+        }
+
+        impl AdminHandler {
+            /// The server status, as HTML for browsers and JSON otherwise
+            fn status_page(&self, request: &Request) -> Response {
+                let snapshot = status::snapshot();
+                let wants_html = request.get_header(HeaderName::Accept)
+                    .is_some_and(|accept| accept.value.value.contains("text/html"));
+                if !wants_html {
+                    return Response::response_with_json(snapshot, HttpStatusCode::Ok);
+                }
+                Response::response_with_html(&status::render_html(&snapshot), HttpStatusCode::Ok)
+            }
+
+            /// Lifts the ban of `client`, or every ban
+            fn lift_bans(&self, request: &Request, client: Option<IpAddr>) -> Response {
+                let lifted = match client {
+                    Some(client) => usize::from(ban::lift(client)),
+                    None => ban::lift_all(),
+                };
+                let admin = request.client_addr.map_or("unknown client".to_string(), |ip| ip.to_string());
+                let lifted_bans = client.map_or("Every ban".to_string(), |ip| format!("The ban of {}", ip));
+                logger().warn(&format!("{} lifted by {}", lifted_bans, admin), "Admin");
+                Response::response_with_json(json!({ "lifted": lifted }), HttpStatusCode::Ok)
+            }
+
+            /// Empties the proxy caches, of the responses under a JSON or
+            /// url-encoded `path` field when the request has one
+            fn purge_cache(&self, request: &Request) -> Response {
+                let prefix = match &request.body {
+                    Some(Body::Json(json)) => json.get("path").and_then(Value::as_str).map(str::to_string),
+                    Some(Body::FormUrlEncoded(form)) => form.get("path").cloned(),
+                    _ => None,
+                };
+                let purged = proxy_cache::purge(prefix.as_deref());
+                let admin = request.client_addr.map_or("unknown client".to_string(), |ip| ip.to_string());
+                let responses = prefix.as_ref().map_or("Every cached response".to_string(), |prefix| {
+                    format!("Cached responses under {}", prefix)
+                });
+                logger().warn(&format!("{} purged by {}", responses, admin), "Admin");
+                Response::response_with_json(json!({ "purged": purged }), HttpStatusCode::Ok)
+            }
+
+            /// Changes the level of every logger until the next reload, from a JSON
+            /// or url-encoded `level` field
+            fn set_log_level(&self, request: &Request) -> Result<Response, ServerError> {
+                let value = match &request.body {
+                    Some(Body::Json(json)) => json.get("level").and_then(Value::as_str).map(str::to_string),
+                    Some(Body::FormUrlEncoded(form)) => form.get("level").cloned(),
+                    _ => None,
+                }
+                .ok_or_else(|| HttpError::BadRequest("Missing level".to_string()))?;
+                let level = LogLevel::parse(&value).ok_or_else(|| HttpError::UnprocessableEntity(format!(
+                    "'{}' is not a log level, expected error, warn, info, debug or trace", value
+                )))?;
+
+                let previous = logger::level();
+                logger::set_level(level);
+                // Logged as a warning so it shows up whatever the new level
+                let client = request.client_addr.map_or("unknown client".to_string(), |ip| ip.to_string());
+                logger().warn(&format!(
+                    "Log level changed from {} to {} by {}", previous.as_str(), level.as_str(), client
+                ), "Admin");
+
+                Ok(Response::response_with_json(json!({
+                    "level": level.as_str().to_ascii_lowercase(),
+                    "previous": previous.as_str().to_ascii_lowercase(),
+                }), HttpStatusCode::Ok))
+            }
+        }
+    }
+
+    pub mod health_api {
+        use super::*;
+        use std::collections::HashSet;
+        use std::path::Path;
+        use serde_json::{json, Map, Value};
+        use crate::http::status::HttpStatusCode;
+        use crate::server::host::Host;
+        use crate::server::server;
+
+        /// Answers `/healthz` and `/readyz` with the state of a host: its uptime,
+        /// whether each of its addresses is listened on, and whether its session
+        /// store and upload directories can be used
+        pub struct HealthHandler<'a> {
+            host: &'a Host,
+        }
+
+        impl<'a> Handler for HealthHandler<'a> {
+            fn serve_http(&mut self, request: &Request, _route: &Route) -> Result<Response, ServerError> {
+                let (report, healthy) = self.report();
+                // A failing check takes the host out of rotation, it is still alive though
+                let status = if healthy || request.uri.split('?').next() == Some("/healthz") {
+                    HttpStatusCode::Ok
+                } else {
+                    HttpStatusCode::ServiceUnavailable
+                };
+                Ok(Response::response_with_json(report, status))
+            }
+        }
+
+        impl<'a> HealthHandler<'a> {
+            pub fn new(host: &'a Host) -> Self {
+                HealthHandler { host }
+            }
+
+            /// The report of the host and whether every check passed
+            fn report(&self) -> (Value, bool) {
+                let listeners: Vec<Value> = self.host.listen.iter()
+                    .map(|addr| json!({
+                        "address": addr.to_string(),
+                        "listening": self.host.listeners.iter().any(|listener| &listener.addr == addr),
+                    }))
+                    .collect();
+                let mut healthy = listeners.iter().all(|listener| listener["listening"] == true);
+
+                let mut checks = Map::new();
+                if let Some(manager) = &self.host.session_manager {
+                    let result = manager.store().and_then(|store| store.get("").map(|_| ()));
+                    checks.insert("sessions".to_string(), check(result.map_err(|e| e.to_string())));
+                }
+                let mut upload_dirs = HashSet::new();
+                let uploaders = self.host.uploader.iter()
+                    .chain(self.host.routes.iter().filter_map(|route| route.uploader.as_ref()));
+                for uploader in uploaders {
+                    if upload_dirs.insert(&uploader.upload_dir) {
+                        let name = format!("uploads:{}", uploader.upload_dir.display());
+                        checks.insert(name, check(writable(&uploader.upload_dir)));
+                    }
                 }
+                healthy &= checks.values().all(|check| check["ok"] == true);
+
+                let report = json!({
+                    "status": if healthy { "ok" } else { "unavailable" },
+                    "host": self.host.server_name,
+                    "uptime_secs": server::uptime().as_secs(),
+                    "listeners": listeners,
+                    "checks": checks,
+                });
+                (report, healthy)
+            }
+        }
+
+        fn check(result: Result<(), String>) -> Value {
+            match result {
+                Ok(()) => json!({ "ok": true }),
+                Err(error) => json!({ "ok": false, "error": error }),
             }
         }
+
+        /// Creates and removes a file in `dir`
+        fn writable(dir: &Path) -> Result<(), String> {
+            let probe = dir.join(format!(".healthz-{}", uuid::Uuid::new_v4()));
+            std::fs::write(&probe, b"")
+                .and_then(|()| std::fs::remove_file(&probe))
+                .map_err(|e| format!("{} is not writable: {}", dir.display(), e))
+        }
     }
 
     // Re-export the handlers for easier access
@@ -423,4 +1069,6 @@ pub mod handlers {
     pub use file_api::FileAPIHandler;
     pub use static_files_api::StaticFileHandler;
     pub use session_api::SessionHandler;
+    pub use admin_api::AdminHandler;
+    pub use health_api::HealthHandler;
 }
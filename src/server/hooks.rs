@@ -0,0 +1,97 @@
+//! Callbacks on every request of a host
+//!
+//! Hooks see the requests of a host whatever their route, even when none
+//! matched, with the time they came in: `on_request` once a request was read,
+//! `on_error` when it failed, and `on_response` right before the response is
+//! sent, which it may add headers to. They suit analytics better than a
+//! middleware, which only sees the requests of the routes it's added to.
+//! Proxied responses are written as the upstream sends them, and aren't seen.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::server::errors::ServerError;
+
+/// What the hooks know of a request
+#[derive(Debug, Clone, Copy)]
+pub struct RequestInfo<'a> {
+    pub request: &'a Request,
+    /// Name of the host the request came to
+    pub host: &'a str,
+    /// Path of the route serving it, `None` when no route matched
+    pub route: Option<&'a str>,
+    /// When the request was read
+    pub started: Instant,
+}
+
+impl<'a> RequestInfo<'a> {
+    pub fn new(request: &'a Request, host: &'a str, route: Option<&'a str>, started: Instant) -> Self {
+        RequestInfo { request, host, route, started }
+    }
+
+    /// Time spent on the request so far
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+type RequestHook = Arc<dyn Fn(&RequestInfo) + Send + Sync>;
+type ResponseHook = Arc<dyn Fn(&RequestInfo, &mut Response) + Send + Sync>;
+type ErrorHook = Arc<dyn Fn(&RequestInfo, &ServerError) + Send + Sync>;
+
+/// The hooks of a host, called in the order they were added
+#[derive(Clone, Default)]
+pub struct Hooks {
+    on_request: Vec<RequestHook>,
+    on_response: Vec<ResponseHook>,
+    on_error: Vec<ErrorHook>,
+}
+
+impl Hooks {
+    pub fn on_request(&mut self, hook: impl Fn(&RequestInfo) + Send + Sync + 'static) {
+        self.on_request.push(Arc::new(hook));
+    }
+
+    pub fn on_response(&mut self, hook: impl Fn(&RequestInfo, &mut Response) + Send + Sync + 'static) {
+        self.on_response.push(Arc::new(hook));
+    }
+
+    /// Errors are answered with their error page, seen by `on_response` after
+    pub fn on_error(&mut self, hook: impl Fn(&RequestInfo, &ServerError) + Send + Sync + 'static) {
+        self.on_error.push(Arc::new(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.on_request.is_empty() && self.on_response.is_empty() && self.on_error.is_empty()
+    }
+
+    pub fn request(&self, info: &RequestInfo) {
+        for hook in &self.on_request {
+            hook(info);
+        }
+    }
+
+    pub fn response(&self, info: &RequestInfo, response: &mut Response) {
+        for hook in &self.on_response {
+            hook(info, response);
+        }
+    }
+
+    pub fn error(&self, info: &RequestInfo, error: &ServerError) {
+        for hook in &self.on_error {
+            hook(info, error);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_request", &self.on_request.len())
+            .field("on_response", &self.on_response.len())
+            .field("on_error", &self.on_error.len())
+            .finish()
+    }
+}
@@ -1,67 +1,88 @@
-use std::collections::HashMap;
-use std::net::{TcpListener, TcpStream};
+use std::net::SocketAddr;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
-use crate::server::route::Route;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::server::route::{Route, RouteMatcher};
 use crate::server::errors::ServerError;
 use crate::server::uploader::Uploader;
-use crate::server::logger::{Logger, LogLevel};
+use crate::server::logger::{self, logger, AuditEntry, Logger};
 use crate::server::handlers::handlers::{
     Handler,
     StaticFileHandler,
     FileAPIHandler,
     CGIHandler,
     SessionHandler,
+    AdminHandler,
+    HealthHandler,
+    RouteHandler,
 };
 use crate::server::static_files::ErrorPages;
+use crate::server::rewrite::{self, Rewrite, RewriteRule};
+use crate::server::listen::{ClientStream, ListenAddr, ListenSocket};
+use crate::server::access::AccessList;
+use crate::server::rate_limit::RateLimiter;
+use crate::server::auth::BasicAuth;
+use crate::server::websocket::WebSocketEndpoint;
+use crate::server::middleware::{AccessLog, Context, Middleware, Middlewares};
+use crate::server::hooks::Hooks;
+use crate::server::compression::Compression;
+use crate::server::stream::request_stream::HeaderLimits;
+use sha2::{Digest, Sha256};
 use crate::http::{
+    body::Body,
+    event_stream::{EventChannel, EventStream},
+    upgrade::Upgrade,
     request::{Request, HttpMethod},
-    response::Response,
+    response::{Response, ResponseBuilder},
     status::HttpStatusCode,
-    header::Header
+    header::{Header, HeaderName, Headers}
 };
 
 use crate::server::errors::{HttpError, SessionError};
 
-use crate::server::session::session::SessionManager;
+use crate::server::session::session::{SessionManager, SessionMiddleware};
+
+/// Seconds browsers may keep the answer to a CORS preflight
+const PREFLIGHT_MAX_AGE: &str = "86400";
 
 #[derive(Debug)]
 pub struct HostListener {
     pub fd: RawFd,
-    pub listener: TcpListener,
-    pub port: String,
+    pub socket: ListenSocket,
+    pub addr: ListenAddr,
 }
 
 impl Clone for HostListener {
     fn clone(&self) -> HostListener {
         HostListener {
             fd: self.fd,
-            listener: self.listener.try_clone().unwrap(),
-            port: self.port.clone(),
+            socket: self.socket.try_clone().unwrap(),
+            addr: self.addr.clone(),
         }
     }
 }
 
 impl HostListener {
-    pub fn new(port: String, server_address: String) -> Self {
-        let addr = format!("{}:{}", server_address, port);
-        let listener = TcpListener::bind(&addr).expect("Failed to bind to address");
-        listener.set_nonblocking(true).unwrap();
-        let fd = listener.as_raw_fd();
+    pub fn new(addr: ListenAddr) -> std::io::Result<Self> {
+        let socket = ListenSocket::bind(&addr)?;
+        let fd = socket.as_raw_fd();
 
-        HostListener {
+        Ok(HostListener {
             fd,
-            listener,
-            port,
-        }
+            socket,
+            addr,
+        })
     }
 
-    pub fn accept_connection(&self) -> std::io::Result<TcpStream> {
-        let logger = Logger::new(LogLevel::INFO);
-        let (stream, addr) = self.listener.accept()?;
-        logger.info(&format!("Accepted connection from {}", addr), "HostListener");
-        stream.set_nonblocking(true)?;
-        Ok(stream)
+    pub fn accept_connection(&self) -> std::io::Result<(ClientStream, Option<SocketAddr>)> {
+        let logger = logger();
+        let (stream, peer) = self.socket.accept()?;
+        match peer {
+            Some(addr) => logger.info(&format!("Accepted connection from {}", addr), "HostListener"),
+            None => logger.info(&format!("Accepted connection on {}", self.addr), "HostListener"),
+        }
+        Ok((stream, peer))
     }
 }
 
@@ -69,12 +90,40 @@ impl HostListener {
 pub struct Host {
     pub server_address: String,
     pub server_name: String,
+    /// Ports to listen on, bound when the host is added to the server
+    pub ports: Vec<String>,
+    /// Every address listened on, the ports on `server_address` included
+    pub listen: Vec<ListenAddr>,
     pub listeners: Vec<HostListener>,
     pub routes: Vec<Route>,
     pub session_manager: Option<SessionManager>,
     pub logger: Logger,
     pub error_pages: Option<ErrorPages>,
     pub max_request_size: Option<usize>,
+    /// Size and number of request headers allowed, the reader's defaults when `None`
+    pub header_limits: Option<HeaderLimits>,
+    /// Uploader for the routes that don't configure their own
+    pub uploader: Option<Uploader>,
+    /// Idle time after which a connection is closed, the server's default when `None`
+    pub request_timeout: Option<Duration>,
+    /// Requests a connection may carry before it's closed, unbounded when `None`
+    pub keep_alive_requests: Option<usize>,
+    /// Headers added to every response of the host
+    pub headers: Vec<Header>,
+    /// Serves HTTPS on every port when set
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    /// Plain HTTP requests to a TLS host are redirected to HTTPS rather than refused
+    pub redirect_http: bool,
+    /// Tried in order on every request before its route is looked up
+    pub rewrites: Vec<RewriteRule>,
+    /// Limiter of every request, checked before routing
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// Run on the requests of every route, before the route's own checks.
+    /// Starts with the access log, then the session check of hosts keeping
+    /// sessions, then compression once set.
+    pub middlewares: Middlewares,
+    /// Called on every request, whatever its route
+    pub hooks: Hooks,
 }
 
 /// Core Host implementation
@@ -88,29 +137,204 @@ impl Host {
         error_pages: Option<ErrorPages>,
         max_request_size: Option<usize>,
     ) -> Result<Self, std::io::Error> {
-        let mut listeners = Vec::new();
-        let logger = Logger::new(LogLevel::INFO);
+        let logger = logger().for_host(server_name);
+        let listen = ports.iter()
+            .map(|port| ListenAddr::from_parts(server_address, port))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
 
-        for port in ports {
-            listeners.push(HostListener::new(port, server_address.to_string()));
+        let mut middlewares = Middlewares::new();
+        middlewares.push(AccessLog::new(server_name));
+        if let Some(manager) = &session_manager {
+            middlewares.push(SessionMiddleware::new(manager.clone()));
         }
-        
+
         Ok(Host {
             server_address: server_address.to_string(),
             server_name: server_name.to_string(),
-            listeners,
+            ports,
+            listen,
+            listeners: Vec::new(),
             routes,
             session_manager,
             logger,
             error_pages,
             max_request_size,
+            header_limits: None,
+            uploader: None,
+            request_timeout: None,
+            keep_alive_requests: None,
+            headers: Vec::new(),
+            tls: None,
+            redirect_http: false,
+            rewrites: Vec::new(),
+            rate_limit: None,
+            middlewares,
+            hooks: Hooks::default(),
         })
     }
 
+    /// Listens on `addr` too, once the host is added to the server
+    pub fn add_listen(&mut self, addr: ListenAddr) {
+        if !self.listen.contains(&addr) {
+            self.listen.push(addr);
+        }
+    }
+
     pub fn add_route(&mut self, route: Route) {
         self.routes.push(route);
     }
 
+    /// Runs `middleware` on the requests of every route, after the ones
+    /// added before
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.middlewares.push(middleware);
+    }
+
+    pub fn set_uploader(&mut self, uploader: Uploader) {
+        self.uploader = Some(uploader);
+    }
+
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = Some(timeout);
+    }
+
+    pub fn set_keep_alive_requests(&mut self, max: usize) {
+        self.keep_alive_requests = Some(max);
+    }
+
+    pub fn set_header_limits(&mut self, limits: HeaderLimits) {
+        self.header_limits = Some(limits);
+    }
+
+    pub fn set_headers(&mut self, headers: Vec<Header>) {
+        self.headers = headers;
+    }
+
+    pub fn set_tls(&mut self, config: Arc<rustls::ServerConfig>, redirect_http: bool) {
+        self.tls = Some(config);
+        self.redirect_http = redirect_http;
+    }
+
+    pub fn set_rewrites(&mut self, rewrites: Vec<RewriteRule>) {
+        self.rewrites = rewrites;
+    }
+
+    pub fn set_rate_limit(&mut self, limiter: Arc<RateLimiter>) {
+        self.rate_limit = Some(limiter);
+    }
+
+    /// Gzips the responses of the clients accepting it, on their way back
+    /// through the middlewares registered until now
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.middlewares.push(compression);
+    }
+
+    /// Returns the `429` to answer when the server-wide limiter has no token
+    /// left for the client of `request`
+    pub fn throttle(&self, request: &Request) -> Option<Response> {
+        let error = self.rate_limit.as_ref()?.check_client(request).err()?;
+        self.logger.debug(&format!("Throttling {}: {}", request.uri, error), "Host");
+        Some(error.to_response(None))
+    }
+
+    /// Applies the first matching rewrite rule, changing the URI of `request` or
+    /// returning the redirect to answer
+    pub fn rewrite(&self, request: &mut Request) -> Option<Response> {
+        match rewrite::rewrite(&self.rewrites, &request.uri)? {
+            Rewrite::Internal(uri) => {
+                self.logger.debug(&format!("Rewriting {} to {}", request.uri, uri), "Host");
+                request.uri = uri;
+                None
+            }
+            Rewrite::Redirect { location, permanent } => {
+                self.logger.info(&format!("Rewriting {} to redirect to {}", request.uri, location), "Host");
+                let status = if permanent { HttpStatusCode::MovedPermanently } else { HttpStatusCode::Found };
+                Some(ResponseBuilder::new()
+                    .status_code(status)
+                    .header(Header::from_str("location", &location))
+                    .header(Header::from_str("content-length", "0"))
+                    .body(Body::empty())
+                    .build())
+            }
+        }
+    }
+
+    /// Answers a plain HTTP request sent to a TLS host
+    pub fn plain_http_response(&self, request: &Request) -> Response {
+        if !self.redirect_http {
+            return HttpError::BadRequest("The plain HTTP request was sent to an HTTPS port".to_string())
+                .to_response(None);
+        }
+        let authority = request.get_header(HeaderName::Host)
+            .map(|header| header.value.value.clone())
+            .unwrap_or_else(|| self.server_name.clone());
+        ResponseBuilder::new()
+            .status_code(HttpStatusCode::MovedPermanently)
+            .header(Header::from_str("location", &format!("https://{}{}", authority, request.uri)))
+            .header(Header::from_str("content-length", "0"))
+            .body(Body::empty())
+            .build()
+    }
+
+    /// Returns the configured headers of `route`'s responses, the route's
+    /// replacing the host's ones of the same name
+    pub fn added_headers(&self, route: &Route) -> Vec<Header> {
+        let overridden = |header: &Header| route.headers.iter()
+            .any(|own| own.name.to_string().eq_ignore_ascii_case(&header.name.to_string()));
+        self.headers.iter()
+            .filter(|header| !overridden(header))
+            .chain(route.headers.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Identifies who uploads: the session's user, else the session itself, else
+    /// the user the route authenticated
+    fn upload_owner(&self, request: &Request) -> Option<String> {
+        let cookies = request.get_headers(HeaderName::Cookie);
+        let session = self.session_manager.as_ref()
+            .and_then(|manager| manager.get_session(&cookies).ok().flatten());
+        match session {
+            Some(session) => Some(session.user.unwrap_or(session.id)),
+            None => request.identity.as_ref().map(|identity| identity.user.clone()).filter(|user| !user.is_empty()),
+        }
+    }
+
+    /// The session id `request`'s cookie carries, read before the request is
+    /// handled since destroying the session forgets it
+    pub fn request_session(&self, request: &Request) -> Option<String> {
+        let manager = self.session_manager.as_ref()?;
+        manager.session_id(&request.get_headers(HeaderName::Cookie))
+    }
+
+    /// Records `request` to the audit log when it opens or closes sessions,
+    /// changes files or calls the admin API. The actor's session is the one
+    /// `response` opens, else `session`, the one the request carried.
+    pub fn audit(&self, request: &Request, session: Option<&str>, response: &Response) {
+        let Some(action) = audited_action(request) else { return };
+        let opened = self.session_manager.as_ref().and_then(|manager| {
+            response.headers.iter()
+                .filter(|header| header.name == HeaderName::SetCookie)
+                .find_map(|header| manager.session_id(&Headers::from(vec![header.clone()])))
+        });
+        let status = response.status_code.clone() as u16;
+        logger::audit(AuditEntry {
+            action: action.to_string(),
+            client: request.client_addr,
+            session: opened.as_deref().or(session).map(session_fingerprint),
+            user: request.identity.as_ref().map(|identity| identity.user.clone()).filter(|user| !user.is_empty()),
+            host: Some(self.server_name.clone()),
+            target: Some(format!("{} {}", request.method, request.uri)),
+            error: (status >= 400).then(|| format!("answered {}", status)),
+        });
+    }
+
+    /// Returns the uploader serving `route`: its own, else the host's
+    pub fn uploader_for<'a>(&'a self, route: &'a Route) -> Option<&'a Uploader> {
+        route.uploader.as_ref().or(self.uploader.as_ref())
+    }
+
     pub fn get_listener(&self, fd: RawFd) -> Option<&HostListener> {
         self.listeners.iter().find(|listener| listener.fd == fd)
     }
@@ -120,6 +344,7 @@ impl Host {
     }
 
     pub fn get_route(&self, path: &str) -> Option<&Route> {
+        let path = path.split('?').next().unwrap_or_default();
         if let Some(route) = self.routes.iter().find(|r| r.path == path) {
             return Some(route);
         }
@@ -145,13 +370,25 @@ impl Host {
             }
         }
 
+        // Proxy routes also take the paths under theirs, the longest one winning
+        let proxied = self.routes.iter()
+            .filter(|route| route.proxy.is_some())
+            .filter(|route| {
+                let prefix = route.path.trim_end_matches('/');
+                let path = path.split('?').next().unwrap_or_default();
+                path == prefix || path.starts_with(&format!("{}/", prefix))
+            })
+            .max_by_key(|route| route.path.len());
+        if proxied.is_some() {
+            return proxied;
+        }
+
         let file_route = self.routes.iter().find(|r| {
             if let Some(files) = r.static_files.as_ref() {
                 let path_file = Path::new(path.trim_start_matches("/"));
-                let rr = files.is_directory_contain_file(path_file);
-                return rr;
+                files.is_directory_contain_file(path_file)
             } else {
-                return false;
+                false
             }
         });
 
@@ -168,12 +405,7 @@ impl Host {
             path: "/api/session/create".to_string(),
             methods: vec![HttpMethod::POST],
             session_required: Some(false),
-            redirect: None,
-            session_redirect: None,
-            static_files: None,
-            cgi_config: None,
-            matcher: None,
-            params: HashMap::new(),
+            ..Route::default()
         };
     
         // Route for deleting a session
@@ -181,51 +413,239 @@ impl Host {
             path: "/api/session/delete".to_string(),
             methods: vec![HttpMethod::DELETE],
             session_required: Some(true),
-            session_redirect: None,
-            redirect: None,
-            static_files: None,
-            cgi_config: None,
-            matcher: None,
-            params: HashMap::new(),
+            ..Route::default()
+        };
+    
+        // Route for destroying every session of the current user
+        let logout_everywhere_route = Route {
+            path: "/api/session/logout-all".to_string(),
+            methods: vec![HttpMethod::DELETE],
+            session_required: Some(true),
+            ..Route::default()
         };
     
         // Add routes to this host
         self.add_route(create_session_route);
         self.add_route(delete_session_route);
+        self.add_route(logout_everywhere_route);
     }
 
 
-    pub fn route_request(&mut self, request: &Request, route: &Route, uploader: Option<Uploader>) -> Result<Response, ServerError> {
+    /// Mounts the admin API, reachable by the clients `access` lets through
+    /// and, when set, authenticated by `auth`
+    pub fn add_admin_api(&mut self, access: AccessList, auth: Option<Arc<BasicAuth>>) {
+        let routes = [
+            ("/api/admin/log-level", vec![HttpMethod::GET, HttpMethod::PUT]),
+            ("/api/admin/status", vec![HttpMethod::GET]),
+            ("/api/admin/stats", vec![HttpMethod::GET]),
+            ("/api/admin/bans", vec![HttpMethod::GET, HttpMethod::DELETE]),
+            ("/api/admin/bans/:client", vec![HttpMethod::DELETE]),
+            ("/api/admin/cache", vec![HttpMethod::DELETE]),
+        ];
+        for (path, methods) in routes {
+            let mut middlewares = Middlewares::new();
+            if let Some(auth) = &auth {
+                middlewares.push(auth.clone());
+            }
+            self.add_route(Route {
+                path: path.to_string(),
+                methods,
+                access: Some(access.clone()),
+                admin: true,
+                middlewares,
+                ..Route::default()
+            });
+        }
+    }
+
+    /// Mounts `/healthz` and `/readyz`, for load balancers and orchestrators
+    pub fn add_health_checks(&mut self) {
+        for path in ["/healthz", "/readyz"] {
+            self.add_route(Route {
+                path: path.to_string(),
+                methods: vec![HttpMethod::GET],
+                ..Route::default()
+            });
+        }
+    }
+
+    /// Serves WebSocket connections on `path` with `endpoint`
+    pub fn add_websocket(&mut self, path: &str, endpoint: WebSocketEndpoint) {
+        self.add_route(Route {
+            path: path.to_string(),
+            methods: vec![HttpMethod::GET],
+            websocket: Some(endpoint),
+            ..Route::default()
+        });
+    }
+
+    /// Streams the events published to `channel` to the clients of `path`
+    pub fn add_event_stream(&mut self, path: &str, channel: Arc<EventChannel>) {
+        self.add_route(Route {
+            path: path.to_string(),
+            methods: vec![HttpMethod::GET],
+            events: Some(channel),
+            ..Route::default()
+        });
+    }
+
+    /// Answers the `methods` requests of `path` with `handler`
+    pub fn add_handler(&mut self, path: &str, methods: Vec<HttpMethod>, handler: RouteHandler) {
+        self.add_route(Route {
+            path: path.to_string(),
+            methods,
+            matcher: Some(RouteMatcher::from_path(path)),
+            handler: Some(handler),
+            ..Route::default()
+        });
+    }
+
+    /// Hands the connections of `path` switching to the protocol of `upgrade`
+    /// over to its handler
+    pub fn add_upgrade(&mut self, path: &str, upgrade: Upgrade) {
+        self.add_route(Route {
+            path: path.to_string(),
+            methods: vec![HttpMethod::GET],
+            upgrade: Some(upgrade),
+            ..Route::default()
+        });
+    }
+
+    pub fn route_request(&mut self, request: &mut Request, route: &Route, uploader: Option<Uploader>) -> Result<Response, ServerError> {
+        let mut context = Context::default();
+        let mut response = match self.before(request, route, &mut context)? {
+            Some(response) => response,
+            None => self.dispatch(request, route, uploader)?,
+        };
+        self.after(request, route, &context, &mut response);
+        Ok(response)
+    }
+
+    /// Runs the middlewares of the host, the checks of `route` and its own
+    /// middlewares, its credentials first, then the checks of what the
+    /// request asks. Returns the response when one of them answered instead
+    /// of the handler.
+    pub fn before(&self, request: &mut Request, route: &Route, context: &mut Context) -> Result<Option<Response>, ServerError> {
+        if let Some(response) = self.middlewares.before(request, route, context)? {
+            return Ok(Some(response));
+        }
+        if let Some(response) = self.admit(request, route)? {
+            return Ok(Some(response));
+        }
+        if let Some(response) = route.middlewares.before(request, route, context)? {
+            return Ok(Some(response));
+        }
+        self.check(request, route)
+    }
+
+    /// Hands `response` back through the middlewares of `route`, then the
+    /// host's ones, the headers they asked for added first. A HEAD response
+    /// loses its body last, its headers being those of the GET.
+    pub fn after(&self, request: &Request, route: &Route, context: &Context, response: &mut Response) {
+        response.headers.extend(context.headers.iter().cloned());
+        route.middlewares.after(request, route, context, response);
+        self.middlewares.after(request, route, context, response);
+        if request.method == HttpMethod::HEAD {
+            response.body = None;
+        }
+    }
+
+    /// Runs the checks of `route` a request goes through before its
+    /// middlewares: who may reach the route and how often. CORS preflights
+    /// are answered there.
+    pub fn admit(&self, request: &mut Request, route: &Route) -> Result<Option<Response>, ServerError> {
+        if let Some(access) = &route.access {
+            if !access.is_allowed(request.client_addr) {
+                let client = request.client_addr.map_or("unknown client".to_string(), |ip| ip.to_string());
+                return Err(HttpError::Forbidden(format!("Access to {} denied for {}", route.path, client)).into());
+            }
+        }
+
+        if let Some(limiter) = &route.rate_limit {
+            limiter.check(request)?;
+        }
+
+        // Answered before the route's middlewares ask for credentials, CORS
+        // preflights never send any. The session middleware lets these through
+        // for the same reason
+        if request.method == HttpMethod::OPTIONS && route.answers_options() {
+            return Ok(Some(self.options(request, route)));
+        }
+        Ok(None)
+    }
+
+    /// Checks what an admitted request asks of `route`, its method and body.
+    /// Returns the response when the route redirects instead.
+    fn check(&self, request: &Request, route: &Route) -> Result<Option<Response>, ServerError> {
         // Handle redirects
         if request.uri == route.path {
             if let Some(redirect) = &route.redirect {
                 if let Some(listing) = &route.static_files {
                     if !listing.is_directory_contain_file(Path::new(&listing.directory.join(&request.uri.trim_start_matches("/")))) {
                         self.logger.info(&format!("Redirecting to {}", redirect), "Host");
-                        return Ok(self.redirect(&redirect));
+                        return Ok(Some(self.redirect(redirect)));
                     }
                 } else {
                     self.logger.info(&format!("Redirecting to {}", redirect), "Host");
-                    return Ok(self.redirect(&redirect));
+                    return Ok(Some(self.redirect(redirect)));
                 }
             }
         }
 
         // Check if method is allowed for this route
-        if !route.methods.contains(&request.method) && !route.methods.is_empty() {
+        if !route.is_method_allowed(&request.method) {
             return Err(HttpError::MethodNotAllowed(format!(
                 "Method {} not allowed for route {}", 
                 request.method, route.path
             )).into());
         }
 
+        if request.has_body() {
+            let content_type = request.get_header(HeaderName::ContentType).map(|header| header.value.value);
+            if !route.accepts_content_type(content_type.as_deref()) {
+                return Err(HttpError::UnsupportedMediaType(match content_type {
+                    Some(content_type) => format!("Route {} doesn't accept {} bodies", route.path, content_type),
+                    None => format!("Route {} needs a Content-Type", route.path),
+                }).into());
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hands an admitted request to the handler of `route`. Proxy routes are
+    /// served by the event loop instead, which waits for their upstream.
+    pub fn dispatch(&mut self, request: &Request, route: &Route, uploader: Option<Uploader>) -> Result<Response, ServerError> {
+        if route.proxy.is_some() {
+            return Err(HttpError::InternalServerError(format!("Route {} is proxied", route.path)).into());
+        }
+        if route.websocket.is_some() {
+            return Err(HttpError::InternalServerError(format!("Route {} takes WebSocket connections", route.path)).into());
+        }
+        if let Some(channel) = &route.events {
+            return Ok(Response::event_stream(EventStream::new(channel, request)));
+        }
+        if let Some(upgrade) = &route.upgrade {
+            if !upgrade.is_requested(request) {
+                return Err(HttpError::BadRequest(format!("Route {} only takes {} connections", route.path, upgrade.protocol)).into());
+            }
+            return Ok(Response::upgrade(upgrade.clone()));
+        }
+        if let Some(handler) = &route.handler {
+            if !route.is_method_allowed(&request.method) {
+                return Err(HttpError::MethodNotAllowed(format!("Route {} doesn't take {} requests", route.path, request.method)).into());
+            }
+            return handler.clone().serve_http(request, route);
+        }
+
         // Route the request to the appropriate handler
         match (&request.method, &request.uri) {
             // Handle file API endpoints with FileApiHandler
             (_, uri) if uri.starts_with("/api/files") => {
-                if let Some(uploader) = uploader {
+                if let Some(uploader) = self.uploader_for(route).cloned().or(uploader) {
                     // Create and use the file API handler
-                    let mut handler = FileAPIHandler::new(uploader.clone())?;
+                    let max_file_size = uploader.max_file_size.or(route.max_body_size).or(self.max_request_size);
+                    let mut handler = FileAPIHandler::new(uploader.with_owner(self.upload_owner(request)))?
+                        .with_max_file_size(max_file_size);
                     handler.serve_http(request, route)
                 } else {
                     // Return service unavailable if uploader is not configured
@@ -233,9 +653,17 @@ impl Host {
                 }
             },
 
+            // Only the routes add_admin_api mounted, behind their checks
+            _ if route.admin => AdminHandler.serve_http(request, route),
+
+            // Unless the config serves its own files there
+            (_, uri) if matches!(uri.split('?').next(), Some("/healthz" | "/readyz")) && route.static_files.is_none() && route.cgi_config.is_none() => {
+                HealthHandler::new(self).serve_http(request, route)
+            },
+
             // Handle session requests with SessionHandler
             (_, uri) if uri.starts_with("/api/session") => {
-                if let Some(session_manager) = self.session_manager.as_mut() {
+                if let Some(session_manager) = self.session_manager.as_ref() {
                     let mut handler = SessionHandler::new(session_manager);
                     handler.serve_http(request, route)
                 } else {
@@ -263,6 +691,32 @@ impl Host {
         }
     }
 
+    /// Lists the methods of `route` in an `Allow` header, and in the headers
+    /// a CORS preflight expects. Of the headers the preflight asks for, only
+    /// the route's `cors_allowed_headers` are allowed. Origins are allowed by
+    /// the configured `Access-Control-Allow-Origin` header.
+    fn options(&self, request: &Request, route: &Route) -> Response {
+        let allowed = route.allowed_methods().iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        let header = |name: &str| request.headers.iter()
+            .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+            .map(|header| header.value.value.clone());
+
+        let mut headers = vec![Header::from_str("allow", &allowed)];
+        if header("origin").is_some() && header("access-control-request-method").is_some() {
+            headers.push(Header::from_str("access-control-allow-methods", &allowed));
+            let requested = header("access-control-request-headers").unwrap_or_default();
+            let allowed_headers = requested.split(',')
+                .map(str::trim)
+                .filter(|name| route.cors_allowed_headers.iter().any(|allowed| allowed.eq_ignore_ascii_case(name)))
+                .collect::<Vec<_>>();
+            if !allowed_headers.is_empty() {
+                headers.push(Header::from_str("access-control-allow-headers", &allowed_headers.join(", ")));
+            }
+            headers.push(Header::from_str("access-control-max-age", PREFLIGHT_MAX_AGE));
+        }
+        Response::new(HttpStatusCode::NoContent, headers, None)
+    }
+
     fn redirect(&self, redirect: &str) -> Response {
         Response::new(
             HttpStatusCode::MovedPermanently,
@@ -273,3 +727,26 @@ impl Host {
 
 }
 
+/// The audit log action `request` performs, `None` when it isn't recorded
+fn audited_action(request: &Request) -> Option<&'static str> {
+    let uri = request.uri.split('?').next().unwrap_or_default();
+    let under = |prefix: &str| uri == prefix || uri.starts_with(&format!("{}/", prefix));
+    match &request.method {
+        _ if under("/api/admin") => Some("admin"),
+        HttpMethod::POST if uri == "/api/session/create" => Some("session.create"),
+        HttpMethod::DELETE if uri == "/api/session/delete" => Some("session.destroy"),
+        HttpMethod::DELETE if uri == "/api/session/logout-all" => Some("session.destroy_all"),
+        HttpMethod::POST | HttpMethod::PUT if under("/api/files") => Some("file.upload"),
+        HttpMethod::PATCH if under("/api/files") => Some("file.update"),
+        HttpMethod::DELETE if under("/api/files/chunks") => Some("file.upload_abort"),
+        HttpMethod::DELETE if under("/api/files") => Some("file.delete"),
+        _ => None,
+    }
+}
+
+/// Identifies a session in the audit log without writing down its id, which
+/// would let anyone reading the log take it over
+fn session_fingerprint(id: &str) -> String {
+    Sha256::digest(id.as_bytes()).iter().take(6).map(|b| format!("{:02x}", b)).collect()
+}
+
@@ -0,0 +1,95 @@
+//! Hosts file entries
+//!
+//! With `manage_hosts_file`, the server names each of its hosts in the hosts
+//! file, so that their names resolve to their address. Entries it writes end
+//! with a marker comment: only those are removed, when the server stops or a
+//! host is deleted, leaving the lines written by hand alone. Editing
+//! `/etc/hosts` needs root, failures are left to the caller to report.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The system's hosts file
+pub const DEFAULT_HOSTS_FILE: &str = "/etc/hosts";
+/// Comment ending the entries the server wrote
+pub const MARKER: &str = "# added by localhost-server";
+
+/// A hosts file, and the names added to it by this process
+#[derive(Debug)]
+pub struct HostsFile {
+    path: PathBuf,
+    added: Mutex<Vec<String>>,
+}
+
+impl HostsFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        HostsFile { path: path.into(), added: Mutex::new(Vec::new()) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds a marked entry resolving `name` to `ip`, returning false when the
+    /// file names it already
+    pub fn add(&self, name: &str, ip: &str) -> io::Result<bool> {
+        let content = fs::read_to_string(&self.path)?;
+        if content.lines().any(|line| names(line).contains(&name)) {
+            return Ok(false);
+        }
+
+        let mut updated = content;
+        if !updated.is_empty() && !updated.ends_with('\n') {
+            updated.push('\n');
+        }
+        updated.push_str(&format!("{}      {} {}\n", ip, name, MARKER));
+        fs::write(&self.path, updated)?;
+        self.added.lock().unwrap_or_else(|e| e.into_inner()).push(name.to_string());
+        Ok(true)
+    }
+
+    /// Removes the marked entries naming `name`, returning whether there was
+    /// any. The file is only written when it changes.
+    pub fn remove(&self, name: &str) -> io::Result<bool> {
+        let content = fs::read_to_string(&self.path)?;
+        let kept: Vec<&str> = content.lines()
+            .filter(|line| !(is_marked(line) && names(line).contains(&name)))
+            .collect();
+        if kept.len() == content.lines().count() {
+            return Ok(false);
+        }
+
+        let mut updated = kept.join("\n");
+        if !updated.is_empty() {
+            updated.push('\n');
+        }
+        fs::write(&self.path, updated)?;
+        self.added.lock().unwrap_or_else(|e| e.into_inner()).retain(|added| added != name);
+        Ok(true)
+    }
+
+    /// Removes the entries added by this process, returning the names removed
+    pub fn remove_added(&self) -> io::Result<Vec<String>> {
+        let added = self.added.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut removed = Vec::new();
+        for name in added {
+            if self.remove(&name)? {
+                removed.push(name);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Whether `line` is an entry the server wrote
+fn is_marked(line: &str) -> bool {
+    line.trim_end().ends_with(MARKER)
+}
+
+/// The host names of `line`, none for comments
+fn names(line: &str) -> Vec<&str> {
+    let entry = line.split('#').next().unwrap_or_default();
+    entry.split_whitespace().skip(1).collect()
+}
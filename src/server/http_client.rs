@@ -0,0 +1,191 @@
+//! Outgoing HTTP requests
+//!
+//! The server sends a few requests of its own, like webhook notifications and
+//! downloads of JSON Web Key Sets. This is just enough of an HTTP/1.1 client for
+//! them: one request per connection, TLS for `https://` URLs trusting the
+//! certificate authorities of the Mozilla root program, and bounded waits.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Larger response bodies are refused
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// An `http://` or `https://` URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpUrl {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let url = url.trim();
+        let (https, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("'{}' is not an http:// or https:// URL", url));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // The colons of an IPv6 address are between brackets
+            Some((host, port)) if !port.contains(']') => {
+                let port = port.parse::<u16>().map_err(|_| format!("'{}' has an invalid port", url))?;
+                (host, port)
+            }
+            _ => (authority, if https { 443 } else { 80 }),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("'{}' has no host", url));
+        }
+        Ok(HttpUrl { https, host: host.to_string(), port, path: path.to_string() })
+    }
+
+    /// The host and port as given in a `Host` header, the port left out when it's the scheme's default
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        match (self.https, self.port) {
+            (true, 443) | (false, 80) => host,
+            _ => format!("{}:{}", host, self.port),
+        }
+    }
+
+    /// Sends a `method` request with an optional `(content type, body)` and
+    /// reads the whole response
+    pub fn send(&self, method: &str, body: Option<(&str, &str)>) -> io::Result<HttpResponse> {
+        let addr = (self.host.as_str(), self.port).to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", self.host)))?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: localhost\r\nAccept: application/json\r\nConnection: close\r\n",
+            method, self.path, self.authority()
+        );
+        match body {
+            Some((content_type, body)) => request.push_str(&format!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}", content_type, body.len(), body
+            )),
+            None => request.push_str("\r\n"),
+        }
+
+        if self.https {
+            let name = ServerName::try_from(self.host.clone()).map_err(io::Error::other)?;
+            let connection = ClientConnection::new(client_config()?, name).map_err(io::Error::other)?;
+            exchange(StreamOwned::new(connection, stream), &request)
+        } else {
+            exchange(stream, &request)
+        }
+    }
+}
+
+/// Writes `request` and reads the response, until the connection closes unless
+/// its length is given
+fn exchange<S: Read + Write>(mut stream: S, request: &str) -> io::Result<HttpResponse> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    let mut reader = BufReader::new(stream);
+
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("invalid status line '{}'", status_line.trim())))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.to_ascii_lowercase().contains("chunked");
+            }
+        }
+    }
+
+    let body = if status == 204 || status == 304 {
+        Vec::new()
+    } else if chunked {
+        read_chunked(&mut reader)?
+    } else if let Some(length) = content_length {
+        if length > MAX_BODY_SIZE {
+            return Err(invalid(format!("the response body of {} bytes is too large", length)));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        body
+    } else {
+        let mut body = Vec::new();
+        reader.take(MAX_BODY_SIZE as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > MAX_BODY_SIZE {
+            return Err(invalid("the response body is too large".to_string()));
+        }
+        body
+    };
+    Ok(HttpResponse { status, body })
+}
+
+pub(crate) fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line)?;
+        let size = size_line.split(';').next()
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(|| invalid(format!("invalid chunk size '{}'", size_line.trim())))?;
+        if size == 0 {
+            return Ok(body);
+        }
+        if body.len() + size > MAX_BODY_SIZE {
+            return Err(invalid("the response body is too large".to_string()));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        // The line break ending the chunk
+        reader.read_line(&mut String::new())?;
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Trusts the certificate authorities of the Mozilla root program
+fn client_config() -> io::Result<Arc<ClientConfig>> {
+    let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
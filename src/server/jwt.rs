@@ -0,0 +1,268 @@
+//! JWT bearer tokens
+//!
+//! Routes with `jwt` only let through requests carrying `Authorization: Bearer`
+//! with a JSON Web Token from a trusted issuer, like an external identity
+//! provider. Its signature is checked with a shared secret, a PEM public key, or
+//! the keys published at a JWKS URL, fetched at startup and again when a token
+//! names a key not seen yet. The expiry, issuer and audience are checked too.
+//!
+//! A missing or invalid token gets `401` with a `WWW-Authenticate: Bearer`
+//! challenge, a valid one lacking a required scope `403`. The claims of the
+//! tokens accepted go with the request to the handlers and to CGI scripts.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde_json::{Map, Value};
+use crate::http::header::HeaderName;
+use crate::http::request::{Identity, Request};
+use crate::server::auth::quoted;
+use crate::server::errors::HttpError;
+use crate::server::http_client::HttpUrl;
+use crate::server::logger::logger;
+
+/// Least time between two downloads of a JWKS
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What token signatures are checked with
+#[derive(Debug, Clone, PartialEq)]
+pub enum JwtKey {
+    /// Shared secret of the HS algorithms
+    Secret(String),
+    /// PEM public key of the RS, PS, ES and EdDSA algorithms
+    PemFile(PathBuf),
+    /// JSON Web Key Set, its keys picked by the `kid` of tokens
+    Jwks(HttpUrl),
+}
+
+enum Keys {
+    Static(DecodingKey),
+    Jwks(Arc<Jwks>),
+}
+
+struct Jwks {
+    url: HttpUrl,
+    /// Per key id, keys without one under ""
+    keys: Mutex<HashMap<String, DecodingKey>>,
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl Jwks {
+    fn fetch(&self) -> Result<(), String> {
+        let response = self.url.send("GET", None).map_err(|e| e.to_string())?;
+        if response.status != 200 {
+            return Err(format!("answered with status {}", response.status));
+        }
+        let set: JwkSet = serde_json::from_slice(&response.body).map_err(|e| e.to_string())?;
+        let keys: HashMap<String, DecodingKey> = set.keys.iter()
+            .filter_map(|jwk| Some((jwk.common.key_id.clone().unwrap_or_default(), DecodingKey::from_jwk(jwk).ok()?)))
+            .collect();
+        *self.keys.lock().unwrap_or_else(|e| e.into_inner()) = keys;
+        Ok(())
+    }
+
+    /// Downloads the keys again from a separate thread, at most once per
+    /// `JWKS_REFRESH_INTERVAL`
+    fn refresh(self: &Arc<Self>) {
+        let mut last_fetch = self.last_fetch.lock().unwrap_or_else(|e| e.into_inner());
+        if last_fetch.is_some_and(|fetched| fetched.elapsed() < JWKS_REFRESH_INTERVAL) {
+            return;
+        }
+        *last_fetch = Some(Instant::now());
+        let jwks = Arc::clone(self);
+        let spawned = thread::Builder::new().name("jwks".to_string()).spawn(move || {
+            if let Err(e) = jwks.fetch() {
+                logger().error(&format!("Cannot refresh the JWKS of {}: {}", jwks.url.host, e), "JwtAuth");
+            }
+        });
+        if let Err(e) = spawned {
+            logger().error(&format!("Cannot refresh the JWKS of {}: {}", self.url.host, e), "JwtAuth");
+        }
+    }
+
+    fn key(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        let keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        match kid {
+            Some(kid) => keys.get(kid).cloned(),
+            None if keys.len() == 1 => keys.values().next().cloned(),
+            None => None,
+        }
+    }
+}
+
+/// Checks the bearer tokens of requests
+pub struct JwtAuth {
+    algorithms: Vec<Algorithm>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    /// Scopes a token must all grant
+    scopes: Vec<String>,
+    keys: Keys,
+}
+
+impl fmt::Debug for JwtAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtAuth")
+            .field("algorithms", &self.algorithms)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwtAuth {
+    /// Fails when the key can't be used with `algorithms`. The keys of a JWKS
+    /// that can't be downloaded yet are only reported.
+    pub fn new(key: JwtKey, algorithms: Vec<Algorithm>) -> Result<Self, String> {
+        let Some(&first) = algorithms.first() else {
+            return Err("no algorithm is accepted".to_string());
+        };
+        if let JwtKey::Secret(_) | JwtKey::PemFile(_) = key {
+            if algorithms.iter().any(|algorithm| family(*algorithm) != family(first)) {
+                return Err("a single key can't verify algorithms of different families".to_string());
+            }
+        }
+
+        let keys = match key {
+            JwtKey::Secret(secret) if family(first) == "HMAC" => Keys::Static(DecodingKey::from_secret(secret.as_bytes())),
+            JwtKey::Secret(_) => return Err(format!("{:?} needs a public key, not a secret", first)),
+            JwtKey::PemFile(path) => {
+                let pem = fs::read(&path).map_err(|e| format!("key '{}' can't be read: {}", path.display(), e))?;
+                let key = match family(first) {
+                    "RSA" => DecodingKey::from_rsa_pem(&pem),
+                    "EC" => DecodingKey::from_ec_pem(&pem),
+                    "ED" => DecodingKey::from_ed_pem(&pem),
+                    _ => return Err(format!("{:?} needs a secret, not a key file", first)),
+                };
+                Keys::Static(key.map_err(|e| format!("key '{}' is not a PEM public key: {}", path.display(), e))?)
+            }
+            JwtKey::Jwks(url) => {
+                let jwks = Arc::new(Jwks { url, keys: Mutex::new(HashMap::new()), last_fetch: Mutex::new(Some(Instant::now())) });
+                if let Err(e) = jwks.fetch() {
+                    logger().error(&format!("Cannot fetch the JWKS of {}: {}", jwks.url.host, e), "JwtAuth");
+                }
+                Keys::Jwks(jwks)
+            }
+        };
+        Ok(JwtAuth { algorithms, issuer: None, audience: None, scopes: Vec::new(), keys })
+    }
+
+    /// Only accepts tokens with this `iss` claim
+    pub fn with_issuer(mut self, issuer: Option<String>) -> Self {
+        self.issuer = issuer;
+        self
+    }
+
+    /// Only accepts tokens with this `aud` claim
+    pub fn with_audience(mut self, audience: Option<String>) -> Self {
+        self.audience = audience;
+        self
+    }
+
+    /// Forbids tokens that don't grant all of `scopes`, in their `scope` or `scp` claim
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// Returns who the token was issued to, or the error refusing the request
+    pub fn authenticate(&self, request: &Request) -> Result<Identity, HttpError> {
+        let token = request.get_header(HeaderName::Authorization)
+            .and_then(|header| {
+                let (scheme, token) = header.value.value.trim().split_once(' ')?;
+                scheme.eq_ignore_ascii_case("bearer").then(|| token.trim().to_string())
+            })
+            .ok_or_else(|| HttpError::Unauthorized {
+                challenge: "Bearer".to_string(),
+                message: "Bearer token required".to_string(),
+            })?;
+
+        let header = decode_header(&token).map_err(|_| invalid_token("The token is malformed"))?;
+        if !self.algorithms.contains(&header.alg) {
+            return Err(invalid_token(&format!("Tokens signed with {:?} are not accepted", header.alg)));
+        }
+        let jwks_key;
+        let key = match &self.keys {
+            Keys::Static(key) => key,
+            Keys::Jwks(jwks) => {
+                jwks_key = jwks.key(header.kid.as_deref()).ok_or_else(|| {
+                    // The provider may have rotated its keys
+                    jwks.refresh();
+                    invalid_token("The token was signed with an unknown key")
+                })?;
+                &jwks_key
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_nbf = true;
+        validation.validate_aud = self.audience.is_some();
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+            validation.required_spec_claims.insert("aud".to_string());
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            validation.required_spec_claims.insert("iss".to_string());
+        }
+        let claims = decode::<Map<String, Value>>(&token, key, &validation)
+            .map_err(|e| invalid_token(&match e.kind() {
+                ErrorKind::ExpiredSignature => "The token expired".to_string(),
+                ErrorKind::ImmatureSignature => "The token is not valid yet".to_string(),
+                ErrorKind::InvalidIssuer => "The token was issued by another issuer".to_string(),
+                ErrorKind::InvalidAudience => "The token is meant for another audience".to_string(),
+                ErrorKind::InvalidSignature => "The token signature is invalid".to_string(),
+                ErrorKind::MissingRequiredClaim(claim) => format!("The token has no {} claim", claim),
+                _ => "The token is invalid".to_string(),
+            }))?
+            .claims;
+
+        let granted = granted_scopes(&claims);
+        if let Some(missing) = self.scopes.iter().find(|scope| !granted.contains(&scope.as_str())) {
+            return Err(HttpError::Forbidden(format!("The token doesn't grant the {} scope", missing)));
+        }
+
+        let user = claims.get("sub").and_then(Value::as_str).unwrap_or_default().to_string();
+        Ok(Identity { scheme: "Bearer", user, claims: Some(claims) })
+    }
+}
+
+fn invalid_token(message: &str) -> HttpError {
+    HttpError::Unauthorized {
+        challenge: format!("Bearer error=\"invalid_token\", error_description=\"{}\"", quoted(message)),
+        message: message.to_string(),
+    }
+}
+
+/// Scopes of the space separated `scope` claim, or of `scp` as a list or a string
+fn granted_scopes(claims: &Map<String, Value>) -> Vec<&str> {
+    match (claims.get("scope"), claims.get("scp")) {
+        (Some(Value::String(scopes)), _) | (None, Some(Value::String(scopes))) => scopes.split_whitespace().collect(),
+        (None, Some(Value::Array(scopes))) => scopes.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `algorithm` is verified with a shared secret
+pub fn is_hmac(algorithm: Algorithm) -> bool {
+    family(algorithm) == "HMAC"
+}
+
+/// Algorithms sharing a family verify with the same kind of key
+fn family(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => "HMAC",
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512
+        | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => "RSA",
+        Algorithm::ES256 | Algorithm::ES384 => "EC",
+        Algorithm::EdDSA => "ED",
+    }
+}
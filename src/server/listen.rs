@@ -0,0 +1,184 @@
+//! Listening addresses
+//!
+//! A host listens on the entries of its `listen` list, each an `address:port`
+//! pair (IPv6 addresses between brackets), a bare port bound on every IPv4
+//! address, or `unix:<path>` for a Unix domain socket. The older
+//! `server_address` and `ports` keys add one TCP address per port.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// Parses `127.0.0.1:8080`, `[::1]:8080`, `8080` or `unix:/run/app.sock`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.trim().is_empty() {
+                return Err(format!("'{}' has no socket path", value));
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path.trim())));
+        }
+        if let Ok(port) = value.parse::<u16>() {
+            return Ok(ListenAddr::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)));
+        }
+        value.parse::<SocketAddr>()
+            .map(ListenAddr::Tcp)
+            .map_err(|_| format!("'{}' is not an address:port pair, a port or unix:<path>", value))
+    }
+
+    /// The TCP address of `port` on `address`, as set by `server_address` and `ports`
+    pub fn from_parts(address: &str, port: &str) -> Result<Self, String> {
+        let ip = address.trim().parse::<IpAddr>()
+            .map_err(|_| format!("'{}' is not an IP address", address))?;
+        let port = port.trim().parse::<u16>()
+            .map_err(|_| format!("'{}' is not a port", port))?;
+        Ok(ListenAddr::Tcp(SocketAddr::new(ip, port)))
+    }
+
+    /// The IP address clients reach, `None` for Unix sockets
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            ListenAddr::Tcp(addr) => Some(addr.ip()),
+            ListenAddr::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A bound listening socket
+#[derive(Debug)]
+pub enum ListenSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ListenSocket {
+    /// Binds `addr` without blocking. A socket file left by a previous run is
+    /// replaced, any other file at the path is an error.
+    pub fn bind(addr: &ListenAddr) -> io::Result<Self> {
+        let socket = match addr {
+            ListenAddr::Tcp(addr) => ListenSocket::Tcp(TcpListener::bind(addr)?),
+            ListenAddr::Unix(path) => {
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                ListenSocket::Unix(UnixListener::bind(path)?)
+            }
+        };
+        match &socket {
+            ListenSocket::Tcp(listener) => listener.set_nonblocking(true)?,
+            ListenSocket::Unix(listener) => listener.set_nonblocking(true)?,
+        }
+        Ok(socket)
+    }
+
+    /// Accepts a client, whose address is only known over TCP
+    pub fn accept(&self) -> io::Result<(ClientStream, Option<SocketAddr>)> {
+        let (stream, peer) = match self {
+            ListenSocket::Tcp(listener) => {
+                let (stream, addr) = listener.accept()?;
+                (ClientStream::Tcp(stream), Some(addr))
+            }
+            ListenSocket::Unix(listener) => (ClientStream::Unix(listener.accept()?.0), None),
+        };
+        stream.set_nonblocking(true)?;
+        Ok((stream, peer))
+    }
+
+    /// The address bound, with the port picked by the system for port 0
+    pub fn local_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            ListenSocket::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            ListenSocket::Unix(listener) => {
+                let addr = listener.local_addr()?;
+                let path = addr.as_pathname().map(PathBuf::from).unwrap_or_default();
+                Ok(ListenAddr::Unix(path))
+            }
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            ListenSocket::Tcp(listener) => ListenSocket::Tcp(listener.try_clone()?),
+            ListenSocket::Unix(listener) => ListenSocket::Unix(listener.try_clone()?),
+        })
+    }
+}
+
+impl AsRawFd for ListenSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ListenSocket::Tcp(listener) => listener.as_raw_fd(),
+            ListenSocket::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// An accepted client connection
+#[derive(Debug)]
+pub enum ClientStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            ClientStream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl AsRawFd for ClientStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ClientStream::Tcp(stream) => stream.as_raw_fd(),
+            ClientStream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => stream.read(buf),
+            ClientStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(stream) => stream.write(buf),
+            ClientStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(stream) => stream.flush(),
+            ClientStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
@@ -1,8 +1,49 @@
+//! Logging
+//!
+//! Modules get their logger from [`logger()`], which follows the settings of the
+//! `logging` config section installed with [`configure`]: the level, text or JSON
+//! lines, and the files written instead of the standard output. Requests go to a
+//! separate access log when one is set. Files are rotated once they reach
+//! `rotate.max_size`, `app.log` becoming `app.log.1` and so on.
+//!
+//! With a syslog or journald target, messages are handed to the system instead
+//! of being written to the standard output or `file`.
+//!
+//! Errors are also kept for the admin status page.
+//!
+//! The level can be changed while the server runs with [`set_level`], until the
+//! settings are configured again.
+//!
+//! Hosts may have their own access and error logs. The requests of such a host,
+//! and the messages of the loggers bound to it with [`Logger::for_host`], are
+//! written there instead.
+//!
+//! Messages are formatted and written by a logging thread, so a slow terminal or
+//! disk never holds up the event loop. They wait in a bounded queue: when it is
+//! full the new ones are dropped and counted, and the count is logged once the
+//! thread catches up. [`flush`] waits for the queued messages to be written.
+//!
+//! Actions worth answering for later, like sessions opened, files deleted or
+//! admin calls, are recorded with [`audit`] to the `audit_log` when one is set.
+//! That file is only ever appended to, never rotated, and its records are never
+//! dropped: they wait for room in the queue instead.
+
+use std::collections::HashMap;
 use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread;
 use colored::*;
-use chrono::Local;
+use chrono::{DateTime, Local};
+use crate::server::status;
+use crate::server::syslog::{Journald, Syslog, SyslogAddress};
 
-#[derive(PartialOrd, PartialEq, Debug)]
+#[derive(PartialOrd, PartialEq, Debug, Clone, Copy)]
 pub enum LogLevel {
     ERROR,
     WARN,
@@ -11,6 +52,45 @@ pub enum LogLevel {
     TRACE,
 }
 
+impl LogLevel {
+    pub fn parse(value: &str) -> Option<LogLevel> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(LogLevel::ERROR),
+            "warn" | "warning" => Some(LogLevel::WARN),
+            "info" => Some(LogLevel::INFO),
+            "debug" => Some(LogLevel::DEBUG),
+            "trace" => Some(LogLevel::TRACE),
+            _ => None,
+        }
+    }
+
+    /// Reads a `RUST_LOG` style filter like "info" or "warn,localhost=debug",
+    /// where the directive naming this crate wins over the global one
+    pub fn from_filter(filter: &str) -> Option<LogLevel> {
+        let mut global = None;
+        for directive in filter.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                Some((target, level)) if target.trim() == env!("CARGO_CRATE_NAME") => return LogLevel::parse(level),
+                Some(_) => {}
+                None => global = LogLevel::parse(directive).or(global),
+            }
+        }
+        global
+    }
+
+    const ALL: [LogLevel; 5] = [LogLevel::ERROR, LogLevel::WARN, LogLevel::INFO, LogLevel::DEBUG, LogLevel::TRACE];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::ERROR => "ERROR",
+            LogLevel::WARN => "WARN",
+            LogLevel::INFO => "INFO",
+            LogLevel::DEBUG => "DEBUG",
+            LogLevel::TRACE => "TRACE",
+        }
+    }
+}
+
 impl fmt::Display for LogLevel {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let padding = 5;
@@ -24,40 +104,499 @@ impl fmt::Display for LogLevel {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    /// Colored lines on a terminal, plain ones in files
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<LogFormat> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// When log files are rotated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation {
+    /// Size in bytes a file may reach
+    pub max_size: u64,
+    /// Rotated files kept besides the current one
+    pub keep: usize,
+}
+
+/// Where messages go, the requests still being written to the access logs
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum LogTarget {
+    /// The standard output, or `file` when set
+    #[default]
+    Standard,
+    Syslog { address: SyslogAddress, facility: u8, app_name: String },
+    Journald { identifier: String },
+}
+
+/// Log files of a host, replacing the server's ones for its requests and messages
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HostLogs {
+    pub access_log: Option<PathBuf>,
+    pub error_log: Option<PathBuf>,
+}
+
+/// Settings of the `logging` config section
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    pub level: LogLevel,
+    pub format: LogFormat,
+    /// Written instead of the standard output
+    pub file: Option<PathBuf>,
+    /// Where requests are logged, with the other messages when unset
+    pub access_log: Option<PathBuf>,
+    pub rotate: Option<Rotation>,
+    /// Where [`audit`] records go, not recorded when unset
+    pub audit_log: Option<PathBuf>,
+    /// Per server name
+    pub hosts: HashMap<String, HostLogs>,
+    pub target: LogTarget,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: LogLevel::DEBUG,
+            format: LogFormat::Text,
+            file: None,
+            access_log: None,
+            rotate: None,
+            audit_log: None,
+            hosts: HashMap::new(),
+            target: LogTarget::Standard,
+        }
+    }
+}
+
+/// A request as written to the access log
+#[derive(Debug)]
+pub struct AccessEntry<'a> {
+    pub client: Option<IpAddr>,
+    pub host: &'a str,
+    pub method: String,
+    pub uri: &'a str,
+    pub status: u16,
+    pub bytes: usize,
+}
+
+/// An action recorded to the audit log
+#[derive(Debug, Clone, Default)]
+pub struct AuditEntry {
+    /// Like "session.create" or "config.reload"
+    pub action: String,
+    /// Who asked for it, when it came from a request
+    pub client: Option<IpAddr>,
+    /// Fingerprint of the session the request carried
+    pub session: Option<String>,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    /// What the action applied to, like the request line
+    pub target: Option<String>,
+    /// Why it failed, `None` when it succeeded
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotate: Option<Rotation>,
+}
+
+impl LogFile {
+    fn open(path: &Path, rotate: Option<Rotation>) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { path: path.to_path_buf(), file, size, rotate })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if let Some(rotation) = self.rotate {
+            if self.size > 0 && self.size + line.len() as u64 + 1 > rotation.max_size {
+                self.rotate(rotation.keep)?;
+            }
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Shifts `file.N` to `file.N+1`, dropping the ones past `keep`, then starts a new file
+    fn rotate(&mut self, keep: usize) -> io::Result<()> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(numbered(keep));
+            for n in (1..keep).rev() {
+                if numbered(n).exists() {
+                    fs::rename(numbered(n), numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        *self = LogFile::open(&self.path.clone(), self.rotate)?;
+        Ok(())
+    }
+}
+
+type SharedFile = Arc<Mutex<LogFile>>;
+
+#[derive(Debug, Default)]
+struct HostOutput {
+    access: Option<SharedFile>,
+    error: Option<SharedFile>,
+}
+
+#[derive(Debug)]
+enum SystemLog {
+    Syslog(Syslog),
+    Journald(Journald),
+}
+
+#[derive(Debug, Default)]
+struct Output {
+    settings: LogSettings,
+    file: Option<SharedFile>,
+    access: Option<SharedFile>,
+    audit: Option<SharedFile>,
+    hosts: HashMap<String, HostOutput>,
+    system: Option<SystemLog>,
+}
+
+impl Output {
+    fn write(&self, level: LogLevel, message: &str, module: &str, host: Option<&str>, now: DateTime<Local>) {
+        let host_file = host.and_then(|host| self.hosts.get(host)).and_then(|output| output.error.as_ref());
+        if let (None, Some(system)) = (host_file, &self.system) {
+            let sent = match system {
+                SystemLog::Syslog(syslog) => syslog.send(level, module, message, now),
+                SystemLog::Journald(journald) => journald.send(level, module, message),
+            };
+            if let Err(e) = sent {
+                eprintln!("Cannot send log message to the system log ({}): {}", e, message);
+            }
+            return;
+        }
+        match (host_file.or(self.file.as_ref()), self.settings.format) {
+            (None, LogFormat::Text) => {
+                let timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f").to_string().dimmed();
+                let module_name = format!("{:>15}", module).cyan();
+                let message = match level {
+                    LogLevel::ERROR => message.red().to_string(),
+                    LogLevel::WARN => message.yellow().to_string(),
+                    LogLevel::INFO => message.to_string(),
+                    LogLevel::DEBUG => message.blue().to_string(),
+                    LogLevel::TRACE => message.magenta().to_string(),
+                };
+                println!("{} {} {} │ {}", timestamp, level, module_name, message);
+            }
+            (file, format) => {
+                let timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f");
+                let line = match format {
+                    LogFormat::Text => format!("{} {:5} {:>15} │ {}", timestamp, level.as_str(), module, message),
+                    LogFormat::Json => serde_json::json!({
+                        "time": now.to_rfc3339(),
+                        "level": level.as_str(),
+                        "module": module,
+                        "host": host,
+                        "message": message,
+                    }).to_string(),
+                };
+                match file {
+                    Some(file) => Self::append(file, &line),
+                    None => println!("{}", line),
+                }
+            }
+        }
+    }
+
+    /// The access log of `host`, or of the server
+    fn access_file(&self, host: &str) -> Option<&SharedFile> {
+        self.hosts.get(host).and_then(|host| host.access.as_ref()).or(self.access.as_ref())
+    }
+
+    fn write_access(&self, entry: &AccessRecord, now: DateTime<Local>) {
+        let Some(file) = self.access_file(&entry.host) else {
+            return;
+        };
+        let client = entry.client.map(|ip| ip.to_string()).unwrap_or_else(|| "-".to_string());
+        let line = match self.settings.format {
+            LogFormat::Text => format!(
+                "{} - - [{}] \"{} {}\" {} {} \"{}\"",
+                client, now.format("%d/%b/%Y:%H:%M:%S %z"), entry.method, entry.uri, entry.status, entry.bytes, entry.host
+            ),
+            LogFormat::Json => serde_json::json!({
+                "time": now.to_rfc3339(),
+                "client": entry.client.map(|ip| ip.to_string()),
+                "host": entry.host,
+                "method": entry.method,
+                "uri": entry.uri,
+                "status": entry.status,
+                "bytes": entry.bytes,
+            }).to_string(),
+        };
+        Self::append(file, &line);
+    }
+
+    fn write_audit(&self, entry: &AuditEntry, now: DateTime<Local>) {
+        let Some(file) = &self.audit else { return };
+        let outcome = if entry.error.is_some() { "failure" } else { "success" };
+        let line = match self.settings.format {
+            LogFormat::Text => {
+                let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+                let mut line = format!(
+                    "[{}] {} {} client={} session={} user={} host={} target=\"{}\"",
+                    now.to_rfc3339(), entry.action, outcome,
+                    entry.client.map(|ip| ip.to_string()).unwrap_or_else(|| "-".to_string()),
+                    field(&entry.session), field(&entry.user), field(&entry.host), field(&entry.target),
+                );
+                if let Some(error) = &entry.error {
+                    line.push_str(&format!(" error=\"{}\"", error));
+                }
+                line
+            }
+            LogFormat::Json => serde_json::json!({
+                "time": now.to_rfc3339(),
+                "action": entry.action,
+                "outcome": outcome,
+                "client": entry.client.map(|ip| ip.to_string()),
+                "session": entry.session,
+                "user": entry.user,
+                "host": entry.host,
+                "target": entry.target,
+                "error": entry.error,
+            }).to_string(),
+        };
+        Self::append(file, &line);
+    }
+
+    fn append(file: &Mutex<LogFile>, line: &str) {
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_line(line) {
+            eprintln!("Cannot write to log file {}: {}", file.path.display(), e);
+        }
+    }
+}
+
+/// Owned copy of an [`AccessEntry`], sent to the logging thread
 #[derive(Debug)]
+struct AccessRecord {
+    client: Option<IpAddr>,
+    host: String,
+    method: String,
+    uri: String,
+    status: u16,
+    bytes: usize,
+}
+
+/// What the logging thread is asked to do
+enum Record {
+    Message { level: LogLevel, message: String, module: String, host: Option<String>, time: DateTime<Local> },
+    Access { entry: AccessRecord, time: DateTime<Local> },
+    Audit { entry: AuditEntry, time: DateTime<Local> },
+    /// Answered once the records queued before it are written
+    Flush(SyncSender<()>),
+}
+
+impl Record {
+    fn write(self, output: &Output) {
+        match self {
+            Record::Message { level, message, module, host, time } => output.write(level, &message, &module, host.as_deref(), time),
+            Record::Access { entry, time } => output.write_access(&entry, time),
+            Record::Audit { entry, time } => output.write_audit(&entry, time),
+            Record::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Records waiting for the logging thread before new ones are dropped
+const QUEUE_CAPACITY: usize = 8192;
+
+static QUEUE: OnceLock<SyncSender<Record>> = OnceLock::new();
+/// Records dropped since the logging thread last reported them
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// The queue of the logging thread, started on first use. Should the thread
+/// fail to start, its receiver is gone and records are written by the caller.
+fn queue() -> &'static SyncSender<Record> {
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        if let Err(e) = thread::Builder::new().name("logger".to_string()).spawn(move || write_records(receiver)) {
+            eprintln!("Cannot start the logging thread, logging synchronously: {}", e);
+        }
+        sender
+    })
+}
+
+fn write_records(receiver: Receiver<Record>) {
+    for record in receiver {
+        let output = output();
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let message = format!("{} log messages dropped, the output could not keep up", dropped);
+            output.write(LogLevel::WARN, &message, "Logger", None, Local::now());
+        }
+        record.write(&output);
+    }
+}
+
+/// Queues `record` without blocking, dropping it when the queue is full
+fn submit(record: Record) {
+    match queue().try_send(record) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(TrySendError::Disconnected(record)) => record.write(&output()),
+    }
+}
+
+/// Records `entry` to the audit log when one is configured. Unlike messages,
+/// it waits for room in the queue rather than being dropped.
+pub fn audit(entry: AuditEntry) {
+    if output().audit.is_none() {
+        return;
+    }
+    let record = Record::Audit { entry, time: Local::now() };
+    if let Err(mpsc::SendError(record)) = queue().send(record) {
+        record.write(&output());
+    }
+}
+
+/// Waits until the messages logged so far are written
+pub fn flush() {
+    let Some(queue) = QUEUE.get() else { return };
+    let (done, written) = mpsc::sync_channel(1);
+    if queue.send(Record::Flush(done)).is_ok() {
+        let _ = written.recv();
+    }
+}
+
+static OUTPUT: RwLock<Option<Arc<Output>>> = RwLock::new(None);
+/// Index in `LogLevel::ALL` of the level in effect
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::DEBUG as u8);
+
+/// The level messages are logged at, unless a logger overrides it
+pub fn level() -> LogLevel {
+    LogLevel::ALL[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+/// Changes the level of every logger without touching the rest of the settings
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn output() -> Arc<Output> {
+    if let Some(output) = OUTPUT.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return output.clone();
+    }
+    OUTPUT.write().unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(|| Arc::new(Output::default()))
+        .clone()
+}
+
+/// Installs the settings followed by every logger, opening their files first.
+/// A file named several times is opened once, so its rotation stays consistent.
+/// Messages logged before are written with the previous settings.
+pub fn configure(settings: LogSettings) -> io::Result<()> {
+    flush();
+    let mut opened: HashMap<PathBuf, SharedFile> = HashMap::new();
+    let mut open = |path: &Option<PathBuf>| -> io::Result<Option<SharedFile>> {
+        let Some(path) = path else { return Ok(None) };
+        if let Some(file) = opened.get(path) {
+            return Ok(Some(file.clone()));
+        }
+        let file = Arc::new(Mutex::new(LogFile::open(path, settings.rotate)?));
+        opened.insert(path.clone(), file.clone());
+        Ok(Some(file))
+    };
+
+    let file = open(&settings.file)?;
+    let access = open(&settings.access_log)?;
+    // Opened apart so it is never rotated, even when named as another log too
+    let audit = match &settings.audit_log {
+        Some(path) => Some(Arc::new(Mutex::new(LogFile::open(path, None)?))),
+        None => None,
+    };
+    let mut hosts = HashMap::new();
+    for (name, logs) in &settings.hosts {
+        hosts.insert(name.clone(), HostOutput { access: open(&logs.access_log)?, error: open(&logs.error_log)? });
+    }
+    set_level(settings.level);
+    let system = match &settings.target {
+        LogTarget::Standard => None,
+        LogTarget::Syslog { address, facility, app_name } => Some(SystemLog::Syslog(Syslog::connect(address, *facility, app_name)?)),
+        LogTarget::Journald { identifier } => Some(SystemLog::Journald(Journald::connect(identifier)?)),
+    };
+    let output = Output { file, access, audit, hosts, system, settings };
+    *OUTPUT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(output));
+    Ok(())
+}
+
+/// The logger following the configured settings
+pub fn logger() -> Logger {
+    Logger { level: None, host: None }
+}
+
+#[derive(Debug, Clone)]
 pub struct Logger {
-    level: LogLevel,
+    /// Overrides the configured level
+    level: Option<LogLevel>,
+    /// Server name whose error log receives the messages, when it has one
+    host: Option<String>,
 }
 
 impl Logger {
     pub fn new(level: LogLevel) -> Self {
-        Logger { level }
+        Logger { level: Some(level), host: None }
+    }
+
+    /// Writes to the error log of `server_name` when one is configured
+    pub fn for_host(mut self, server_name: &str) -> Self {
+        self.host = Some(server_name.to_string());
+        self
     }
 
     pub fn log(&self, level: LogLevel, message: &str, module: &str) {
-        if level <= self.level {
-            let now = Local::now();
-            let timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f").to_string().dimmed();
-            let module_name = format!("{:>15}", module).cyan();
-            
-            let log_entry = format!(
-                "{} {} {} │ {}", 
-                timestamp,
+        if level <= self.level.unwrap_or_else(self::level) {
+            if level == LogLevel::ERROR {
+                status::record_error(self.host.as_deref(), module, message);
+            }
+            submit(Record::Message {
                 level,
-                module_name,
-                message
-            );
-
-            println!("{}", log_entry);
+                message: message.to_string(),
+                module: module.to_string(),
+                host: self.host.clone(),
+                time: Local::now(),
+            });
         }
     }
 
     pub fn error(&self, message: &str, module: &str) {
-        self.log(LogLevel::ERROR, &message.red().to_string(), module);
+        self.log(LogLevel::ERROR, message, module);
     }
 
     pub fn warn(&self, message: &str, module: &str) {
-        self.log(LogLevel::WARN, &message.yellow().to_string(), module);
+        self.log(LogLevel::WARN, message, module);
     }
 
     pub fn info(&self, message: &str, module: &str) {
@@ -65,10 +604,28 @@ impl Logger {
     }
 
     pub fn debug(&self, message: &str, module: &str) {
-        self.log(LogLevel::DEBUG, &message.blue().to_string(), module);
+        self.log(LogLevel::DEBUG, message, module);
     }
 
     pub fn trace(&self, message: &str, module: &str) {
-        self.log(LogLevel::TRACE, &message.magenta().to_string(), module);
+        self.log(LogLevel::TRACE, message, module);
     }
-}
\ No newline at end of file
+
+    /// Logs a request, to the access log of its host or of the server when one is configured
+    pub fn access(&self, entry: &AccessEntry, module: &str) {
+        if output().access_file(entry.host).is_none() {
+            let logger = Logger { host: Some(entry.host.to_string()), ..self.clone() };
+            logger.info(&format!("{} - {} - {}", entry.method, entry.uri, entry.status), module);
+            return;
+        }
+        let entry = AccessRecord {
+            client: entry.client,
+            host: entry.host.to_string(),
+            method: entry.method.clone(),
+            uri: entry.uri.to_string(),
+            status: entry.status,
+            bytes: entry.bytes,
+        };
+        submit(Record::Access { entry, time: Local::now() });
+    }
+}
@@ -0,0 +1,171 @@
+//! Code running around the handlers of a host or route
+//!
+//! A [`Middleware`] sees every request of the host or route it is added to
+//! before its handler does, and every response after. The host's middlewares
+//! run first, then the checks of who may reach the route (its access list
+//! and rate limit), then the route's middlewares, then the checks of its
+//! methods and body types. Responses go back through them the other way round.
+//!
+//! The server's own features are middlewares registered when hosts and routes
+//! are built. Every host starts with an [`AccessLog`], followed by the
+//! [`SessionMiddleware`](crate::server::session::session::SessionMiddleware)
+//! of hosts keeping sessions and the [`Compression`] of those compressing.
+//! Routes start with their credentials, [`BasicAuth`], [`DigestAuth`] or
+//! [`JwtAuth`].
+
+use std::fmt;
+use std::sync::Arc;
+use crate::http::body::Body;
+use crate::http::header::Header;
+use crate::http::request::{Identity, Request};
+use crate::http::response::Response;
+use crate::server::auth::BasicAuth;
+use crate::server::compression::Compression;
+use crate::server::digest::DigestAuth;
+use crate::server::errors::ServerError;
+use crate::server::jwt::JwtAuth;
+use crate::server::logger::logger;
+use crate::server::route::Route;
+use crate::server::server::log_access;
+use crate::server::session::session::Session;
+
+pub trait Middleware: Send + Sync {
+    /// Called before the handler. Answering with a response, or failing,
+    /// keeps the request from going further.
+    fn before(&self, _request: &mut Request, _route: &Route, _context: &mut Context) -> Result<Option<Response>, ServerError> {
+        Ok(None)
+    }
+
+    /// Called on every response of the route, its error pages included.
+    /// Proxied responses and WebSocket handshakes aren't seen, nor the body of
+    /// deferred responses, given later.
+    fn after(&self, _request: &Request, _route: &Route, _context: &Context, _response: &mut Response) {}
+}
+
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn before(&self, request: &mut Request, route: &Route, context: &mut Context) -> Result<Option<Response>, ServerError> {
+        (**self).before(request, route, context)
+    }
+
+    fn after(&self, request: &Request, route: &Route, context: &Context, response: &mut Response) {
+        (**self).after(request, route, context, response)
+    }
+}
+
+/// What the middlewares of a request share
+#[derive(Debug, Default)]
+pub struct Context {
+    /// Added to the response, like the cookies of a session restored on the way
+    pub headers: Vec<Header>,
+    /// The client's session, on the routes requiring one
+    pub session: Option<Session>,
+}
+
+/// Middlewares, in the order their `before` runs
+#[derive(Clone, Default)]
+pub struct Middlewares {
+    chain: Vec<Arc<dyn Middleware>>,
+}
+
+impl Middlewares {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.chain.push(Arc::new(middleware));
+    }
+
+    pub fn len(&self) -> usize {
+        self.chain.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    /// Runs the `before` of each middleware until one answers or fails
+    pub fn before(&self, request: &mut Request, route: &Route, context: &mut Context) -> Result<Option<Response>, ServerError> {
+        for middleware in &self.chain {
+            if let Some(response) = middleware.before(request, route, context)? {
+                return Ok(Some(response));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs the `after` of each middleware, the last one first
+    pub fn after(&self, request: &Request, route: &Route, context: &Context, response: &mut Response) {
+        for middleware in self.chain.iter().rev() {
+            middleware.after(request, route, context, response);
+        }
+    }
+}
+
+/// Middlewares run one after the other, like a single one
+impl Middleware for Middlewares {
+    fn before(&self, request: &mut Request, route: &Route, context: &mut Context) -> Result<Option<Response>, ServerError> {
+        Middlewares::before(self, request, route, context)
+    }
+
+    fn after(&self, request: &Request, route: &Route, context: &Context, response: &mut Response) {
+        Middlewares::after(self, request, route, context, response)
+    }
+}
+
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Middlewares").field("len", &self.chain.len()).finish()
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &mut Request, _route: &Route, _context: &mut Context) -> Result<Option<Response>, ServerError> {
+        let user = self.authenticate(request)?;
+        request.identity = Some(Box::new(Identity { scheme: "Basic", user, claims: None }));
+        Ok(None)
+    }
+}
+
+impl Middleware for DigestAuth {
+    fn before(&self, request: &mut Request, _route: &Route, _context: &mut Context) -> Result<Option<Response>, ServerError> {
+        let user = self.authenticate(request)?;
+        request.identity = Some(Box::new(Identity { scheme: "Digest", user, claims: None }));
+        Ok(None)
+    }
+}
+
+impl Middleware for JwtAuth {
+    fn before(&self, request: &mut Request, _route: &Route, _context: &mut Context) -> Result<Option<Response>, ServerError> {
+        request.identity = Some(Box::new(self.authenticate(request)?));
+        Ok(None)
+    }
+}
+
+/// Gzips the responses once the middlewares registered after it are done
+impl Middleware for Compression {
+    fn after(&self, request: &Request, _route: &Route, _context: &Context, response: &mut Response) {
+        self.apply(request, response);
+    }
+}
+
+/// Logs the responses of a host to the access log, as they leave the chain
+pub struct AccessLog {
+    host: String,
+}
+
+impl AccessLog {
+    pub fn new(host: &str) -> Self {
+        AccessLog { host: host.to_string() }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn after(&self, request: &Request, _route: &Route, _context: &Context, response: &mut Response) {
+        // Logged once given, by the event loop
+        if matches!(response.body, Some(Body::Deferred(_))) {
+            return;
+        }
+        log_access(&logger(), request, &self.host, response);
+    }
+}
@@ -5,8 +5,42 @@ pub mod route;
 pub mod static_files;
 pub mod cgi;
 pub mod handlers;
+pub mod extract;
+pub mod templates;
 pub mod logger;
 pub mod uploader;
+pub mod file_types;
+pub mod upload_validator;
+pub mod auth;
+pub mod digest;
+pub mod jwt;
+pub mod access;
+pub mod rate_limit;
+pub mod ban;
+pub mod forwarded;
+pub mod tls;
+pub mod rewrite;
+pub mod listen;
+pub mod syslog;
+pub mod hosts_file;
+pub mod status;
+pub mod notifier;
+pub mod http_client;
+pub mod proxy;
+pub mod proxy_cache;
+pub mod compression;
+pub mod standard_headers;
+pub mod websocket;
+pub mod rooms;
+pub mod middleware;
+pub mod hooks;
+pub mod builder;
+pub mod shutdown;
+pub mod plugin;
 pub mod errors;
 pub mod stream;
-pub mod session;
\ No newline at end of file
+pub mod buffers;
+pub mod session;
+
+#[cfg(test)]
+mod tests;
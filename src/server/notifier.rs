@@ -0,0 +1,205 @@
+//! Webhook notifications
+//!
+//! With a `notify` block, the server POSTs a JSON message to a webhook when a
+//! host answers too many requests with a 5xx status over the last minute, or
+//! when one of its addresses isn't listened on. Slack and Discord get the
+//! message in the shape they expect, other webhooks a generic object. An alert
+//! is sent at most once per `interval` for a host, the next one telling how many
+//! were held back meanwhile. Requests go out from a separate thread, so a slow
+//! webhook never holds up the event loop.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::Local;
+use serde_json::json;
+use crate::server::host::Host;
+use crate::server::http_client::HttpUrl;
+use crate::server::logger::logger;
+use crate::server::status;
+
+/// Notifications waiting for the webhook before new ones are dropped
+const QUEUE_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WebhookFormat {
+    /// `{"event", "host", "message", "suppressed", "time"}`
+    #[default]
+    Generic,
+    Slack,
+    Discord,
+}
+
+impl WebhookFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "generic" => Some(WebhookFormat::Generic),
+            "slack" => Some(WebhookFormat::Slack),
+            "discord" => Some(WebhookFormat::Discord),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    /// Too many requests answered with a 5xx status
+    ErrorRate,
+    /// An address of the host isn't listened on
+    ListenerDown,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::ErrorRate => "error_rate",
+            AlertKind::ListenerDown => "listener_down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub host: String,
+    pub message: String,
+}
+
+/// An `http://` or `https://` URL that alerts are POSTed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    url: HttpUrl,
+}
+
+impl Webhook {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        Ok(Webhook { url: HttpUrl::parse(url)? })
+    }
+
+    /// Sends `body` as JSON, failing unless the webhook answers with a 2xx status
+    pub fn post(&self, body: &str) -> io::Result<()> {
+        let response = self.url.send("POST", Some(("application/json", body)))?;
+        match response.status {
+            200..=299 => Ok(()),
+            status => Err(io::Error::other(format!("the webhook answered with status {}", status))),
+        }
+    }
+}
+
+/// When hosts raise an error rate alert
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// Share of the requests of the last minute answered with a 5xx status, in percent
+    pub error_percent: u64,
+    /// Requests of the last minute below which the error rate isn't checked
+    pub min_requests: u64,
+}
+
+pub struct Notifier {
+    format: WebhookFormat,
+    thresholds: Thresholds,
+    /// Least time between two alerts of the same kind for a host
+    interval: Duration,
+    last_sent: HashMap<(String, AlertKind), Instant>,
+    /// Alerts held back since the last one sent
+    suppressed: HashMap<(String, AlertKind), u64>,
+    queue: SyncSender<String>,
+}
+
+impl Notifier {
+    /// Starts the thread posting to `webhook`
+    pub fn new(webhook: Webhook, format: WebhookFormat, thresholds: Thresholds, interval: Duration) -> io::Result<Self> {
+        let (queue, payloads) = mpsc::sync_channel::<String>(QUEUE_CAPACITY);
+        thread::Builder::new().name("notifier".to_string()).spawn(move || {
+            for payload in payloads {
+                if let Err(e) = webhook.post(&payload) {
+                    logger().error(&format!("Cannot notify webhook {}: {}", webhook.url.host, e), "Notifier");
+                }
+            }
+        })?;
+        Ok(Notifier {
+            format,
+            thresholds,
+            interval,
+            last_sent: HashMap::new(),
+            suppressed: HashMap::new(),
+            queue,
+        })
+    }
+
+    /// Raises the alerts of `hosts`: addresses not listened on, and error rates
+    /// past the threshold over the last minute
+    pub fn check(&mut self, hosts: &[Host]) {
+        for host in hosts {
+            let down: Vec<String> = host.listen.iter()
+                .filter(|addr| !host.listeners.iter().any(|listener| &listener.addr == *addr))
+                .map(|addr| addr.to_string())
+                .collect();
+            if !down.is_empty() {
+                self.notify(Alert {
+                    kind: AlertKind::ListenerDown,
+                    host: host.server_name.clone(),
+                    message: format!("Host {} is not listening on {}", host.server_name, down.join(", ")),
+                });
+            }
+
+            let (requests, errors) = status::recent_requests(&host.server_name);
+            if requests > 0 && requests >= self.thresholds.min_requests
+                && errors * 100 >= self.thresholds.error_percent * requests
+            {
+                self.notify(Alert {
+                    kind: AlertKind::ErrorRate,
+                    host: host.server_name.clone(),
+                    message: format!(
+                        "Host {} answered {} of its last {} requests with a server error",
+                        host.server_name, errors, requests
+                    ),
+                });
+            }
+        }
+    }
+
+    /// Queues `alert` unless one of the same kind was sent for its host less
+    /// than `interval` ago. Returns whether it was queued.
+    pub fn notify(&mut self, alert: Alert) -> bool {
+        let key = (alert.host.clone(), alert.kind);
+        if self.last_sent.get(&key).is_some_and(|sent| sent.elapsed() < self.interval) {
+            *self.suppressed.entry(key).or_default() += 1;
+            return false;
+        }
+
+        let suppressed = self.suppressed.get(&key).copied().unwrap_or(0);
+        match self.queue.try_send(self.payload(&alert, suppressed)) {
+            Ok(()) => {
+                self.suppressed.remove(&key);
+                self.last_sent.insert(key, Instant::now());
+                true
+            }
+            Err(_) => {
+                logger().warn(&format!("Dropped alert for {}, the webhook is not keeping up", alert.host), "Notifier");
+                false
+            }
+        }
+    }
+
+    fn payload(&self, alert: &Alert, suppressed: u64) -> String {
+        let mut text = alert.message.clone();
+        if suppressed > 0 {
+            text.push_str(&format!(" ({} similar alerts held back)", suppressed));
+        }
+        match self.format {
+            WebhookFormat::Slack => json!({ "text": text }),
+            WebhookFormat::Discord => json!({ "content": text }),
+            WebhookFormat::Generic => json!({
+                "event": alert.kind.as_str(),
+                "host": alert.host,
+                "message": alert.message,
+                "suppressed": suppressed,
+                "time": Local::now().to_rfc3339(),
+            }),
+        }
+        .to_string()
+    }
+}
@@ -0,0 +1,196 @@
+//! Handlers, middlewares and session stores from other crates
+//!
+//! A [`Plugin`] is registered under its name, before the config is loaded,
+//! by the program embedding the server or by a shared library listed in the
+//! `plugin_libraries` of the config. Hosts then name the plugins they use in
+//! their `plugins`, with the settings each gets, and a host's `session.store`
+//! may name a plugin keeping its sessions:
+//!
+//! ```json
+//! {
+//!     "plugin_libraries": ["/usr/lib/localhost/libmetrics.so"],
+//!     "servers": [{
+//!         "server_name": "app.local",
+//!         "plugins": [{ "name": "metrics", "settings": { "path": "/metrics" } }],
+//!         "session": { "store": { "name": "redis", "settings": { "url": "redis://127.0.0.1" } } }
+//!     }]
+//! }
+//! ```
+//!
+//! Shared libraries are loaded with the `dynamic-plugins` feature. They export
+//! their plugins with [`export_plugins!`](crate::export_plugins), and must be
+//! built by the same compiler against the same version of this crate, Rust
+//! having no stable ABI: the API version they were built for is checked, not
+//! the compiler.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use serde_json::Value;
+use crate::http::request::HttpMethod;
+use crate::server::handlers::handlers::RouteHandler;
+use crate::server::hooks::Hooks;
+use crate::server::host::Host;
+use crate::server::middleware::Middleware;
+use crate::server::route::Route;
+use crate::server::session::session::SharedSessionStore;
+
+/// Bumped whenever [`Plugin`] or [`PluginHost`] change, libraries built for
+/// another version being refused
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+pub trait Plugin: Send + Sync {
+    /// The name hosts use the plugin by
+    fn name(&self) -> &str;
+
+    /// Adds what the plugin provides to a host naming it in its `plugins`,
+    /// `settings` being the ones the host gives it, `null` when none
+    fn register(&self, _host: &mut PluginHost, _settings: &Value) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The store keeping the sessions of a host whose `session.store` names
+    /// the plugin, `None` when it keeps none
+    fn session_store(&self, _settings: &Value) -> Result<Option<SharedSessionStore>, String> {
+        Ok(None)
+    }
+}
+
+/// What a plugin may add to a host
+pub struct PluginHost<'a> {
+    host: &'a mut Host,
+}
+
+impl PluginHost<'_> {
+    pub fn server_name(&self) -> &str {
+        &self.host.server_name
+    }
+
+    pub fn route(&mut self, route: Route) {
+        self.host.add_route(route);
+    }
+
+    pub fn handler(&mut self, path: &str, methods: Vec<HttpMethod>, handler: RouteHandler) {
+        self.host.add_handler(path, methods, handler);
+    }
+
+    /// Runs `middleware` on the requests of every route, after the ones the
+    /// host had
+    pub fn middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.host.add_middleware(middleware);
+    }
+
+    pub fn hooks(&mut self) -> &mut Hooks {
+        &mut self.host.hooks
+    }
+}
+
+static PLUGINS: RwLock<BTreeMap<String, Arc<dyn Plugin>>> = RwLock::new(BTreeMap::new());
+
+/// Makes `plugin` available to the hosts, replacing the one of the same name
+pub fn register(plugin: impl Plugin + 'static) {
+    register_arc(Arc::new(plugin));
+}
+
+fn register_arc(plugin: Arc<dyn Plugin>) {
+    let mut plugins = PLUGINS.write().unwrap_or_else(|e| e.into_inner());
+    plugins.insert(plugin.name().to_string(), plugin);
+}
+
+pub fn plugin(name: &str) -> Option<Arc<dyn Plugin>> {
+    PLUGINS.read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+}
+
+/// The registered plugins, by name
+pub fn names() -> Vec<String> {
+    PLUGINS.read().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect()
+}
+
+/// Adds the plugin called `name` to `host`
+pub fn install(host: &mut Host, name: &str, settings: &Value) -> Result<(), String> {
+    let plugin = plugin(name).ok_or_else(|| format!("No plugin is called {}", name))?;
+    plugin.register(&mut PluginHost { host }, settings)
+}
+
+/// The session store of the plugin called `name`
+pub fn session_store(name: &str, settings: &Value) -> Result<SharedSessionStore, String> {
+    let plugin = plugin(name).ok_or_else(|| format!("No plugin is called {}", name))?;
+    plugin.session_store(settings)?.ok_or_else(|| format!("Plugin {} keeps no sessions", name))
+}
+
+/// Exports the plugins of a shared library, for the servers loading it
+///
+/// ```ignore
+/// localhost::export_plugins![Metrics::default(), RedisSessions];
+/// ```
+#[macro_export]
+macro_rules! export_plugins {
+    ($($plugin:expr),* $(,)?) => {
+        #[no_mangle]
+        pub extern "C" fn localhost_plugin_api_version() -> u32 {
+            $crate::server::plugin::PLUGIN_API_VERSION
+        }
+
+        #[no_mangle]
+        pub fn localhost_plugins() -> Vec<Box<dyn $crate::server::plugin::Plugin>> {
+            vec![$(Box::new($plugin)),*]
+        }
+    };
+}
+
+/// Registers the plugins exported by the shared library at `path`, returning
+/// their names. The library stays loaded for as long as the process runs, and
+/// loading it again does nothing.
+#[cfg(feature = "dynamic-plugins")]
+pub fn load_library(path: &std::path::Path) -> Result<Vec<String>, String> {
+    use std::collections::HashSet;
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::Mutex;
+
+    static LOADED: Mutex<Option<HashSet<std::path::PathBuf>>> = Mutex::new(None);
+
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    if loaded.get_or_insert_with(HashSet::new).contains(path) {
+        return Ok(Vec::new());
+    }
+    let last_error = || {
+        let error = unsafe { libc::dlerror() };
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+        }
+    };
+    let file = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    let library = unsafe { libc::dlopen(file.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if library.is_null() {
+        return Err(last_error());
+    }
+    let symbol = |name: &CStr| {
+        let symbol = unsafe { libc::dlsym(library, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    };
+
+    let Some(version) = symbol(c"localhost_plugin_api_version") else {
+        unsafe { libc::dlclose(library) };
+        return Err("The library exports no plugins".to_string());
+    };
+    let version: extern "C" fn() -> u32 = unsafe { std::mem::transmute(version) };
+    if version() != PLUGIN_API_VERSION {
+        unsafe { libc::dlclose(library) };
+        return Err(format!("The library was built for plugin API {}, not {}", version(), PLUGIN_API_VERSION));
+    }
+    let Some(plugins) = symbol(c"localhost_plugins") else {
+        unsafe { libc::dlclose(library) };
+        return Err("The library exports no plugins".to_string());
+    };
+    let plugins: fn() -> Vec<Box<dyn Plugin>> = unsafe { std::mem::transmute(plugins) };
+
+    let mut names = Vec::new();
+    for plugin in plugins() {
+        names.push(plugin.name().to_string());
+        register_arc(Arc::from(plugin));
+    }
+    loaded.get_or_insert_with(HashSet::new).insert(path.to_path_buf());
+    Ok(names)
+}
@@ -0,0 +1,1334 @@
+//! Reverse proxy
+//!
+//! Routes with a `proxy` forward their requests to an upstream HTTP server and
+//! stream its responses back to the client. Upstream sockets are non-blocking
+//! and watched by the event loop next to the client connections: an
+//! [`Exchange`] is one request on its way, advanced each time its socket is
+//! ready, so a slow upstream never holds up the other clients.
+//!
+//! Upstreams that can't be reached or don't answer with HTTP get the client a
+//! `502`, those that don't answer in time a `504`.
+//!
+//! Responses are streamed by default, the upstream being read no faster than
+//! the client takes the response. With [`Buffering::Buffered`], they are held
+//! until complete instead, so an upstream failing midway can still be retried
+//! or answered for with an error.
+//!
+//! A route may spread its requests over several upstreams, each request going
+//! to the one its [`Balance`] strategy picks. With a [`HealthCheck`], each
+//! upstream is probed in the background, and those failing their probes get no
+//! requests until they pass them again.
+//!
+//! With a [`RetryPolicy`], idempotent requests whose upstream failed before
+//! the client got anything are sent again, to another upstream when there is
+//! one, after a backoff doubling with each attempt.
+//!
+//! With a [`ConnectionPool`], upstream connections are kept open once their
+//! response ended and reused by the next idempotent requests, instead of
+//! connecting anew for each one.
+//!
+//! WebSocket handshakes are forwarded too. Once the upstream accepted one, the
+//! exchange becomes a tunnel passing bytes both ways, until either side closes
+//! its connection or both sent their close frame.
+//!
+//! With a [`ProxyCache`], the responses to GET requests are kept as the
+//! upstream allows, and those forwarded get an `X-Cache: MISS` header.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::http::header::{sanitize_value, Header, HeaderName};
+use crate::http::request::{HttpMethod, Request};
+use crate::server::connection::Connection;
+use crate::server::errors::HttpError;
+use crate::server::forwarded;
+use crate::server::http_client::HttpUrl;
+use crate::server::logger::logger;
+use crate::server::proxy_cache::{Capture, ProxyCache};
+use crate::server::websocket;
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest response head accepted from an upstream
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const READ_SIZE: usize = 16 * 1024;
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+pub const DEFAULT_MAX_BUFFERED: usize = 1024 * 1024;
+
+/// Headers about a single connection, never forwarded, along with the ones
+/// its `Connection` header names
+const HOP_BY_HOP: [&str; 8] = [
+    "connection", "keep-alive", "proxy-connection", "proxy-authenticate",
+    "proxy-authorization", "te", "trailer", "upgrade",
+];
+
+/// How the upstream of each request is picked
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Balance {
+    /// In turn, each upstream as often as its weight says
+    #[default]
+    RoundRobin,
+    /// The one with the fewest requests on their way for its weight
+    LeastConnections,
+    /// Always the same for a client address, so its state stays on one upstream
+    IpHash,
+}
+
+impl Balance {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "round-robin" => Some(Balance::RoundRobin),
+            "least-connections" => Some(Balance::LeastConnections),
+            "ip-hash" => Some(Balance::IpHash),
+            _ => None,
+        }
+    }
+}
+
+/// How upstreams are probed
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    /// Path requested from each upstream, which is healthy when answering 2xx or 3xx
+    pub path: String,
+    pub interval: Duration,
+    /// Probes in a row an unhealthy upstream has to pass to get requests again
+    pub healthy_threshold: usize,
+    /// Probes in a row a healthy upstream has to fail to be taken out
+    pub unhealthy_threshold: usize,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            path: "/".to_string(),
+            interval: Duration::from_secs(10),
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// How responses go from the upstream to the client
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Buffering {
+    /// Passed on as they arrive, for event streams and large downloads
+    #[default]
+    Streaming,
+    /// Held until complete, up to a size past which they are streamed
+    Buffered,
+}
+
+impl Buffering {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "streaming" => Some(Buffering::Streaming),
+            "buffered" => Some(Buffering::Buffered),
+            _ => None,
+        }
+    }
+}
+
+/// What makes a request go to the next upstream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryOn {
+    /// The upstream couldn't be reached, or failed before answering
+    Error,
+    /// The upstream didn't answer in time
+    Timeout,
+    /// The upstream answered with this status
+    Status(u16),
+}
+
+impl RetryOn {
+    /// Parses "error", "timeout", or a 5xx status like "http_503"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(RetryOn::Error),
+            "timeout" => Some(RetryOn::Timeout),
+            name => name.strip_prefix("http_")
+                .and_then(|status| status.parse().ok())
+                .filter(|status| (500..600).contains(status))
+                .map(RetryOn::Status),
+        }
+    }
+}
+
+/// How failed requests are sent again
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Tries in all, the first one included
+    pub attempts: usize,
+    /// Wait before the first retry, doubled for each next one
+    pub backoff: Duration,
+    pub next_upstream: Vec<RetryOn>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 2,
+            backoff: Duration::from_millis(100),
+            next_upstream: vec![RetryOn::Error, RetryOn::Timeout],
+        }
+    }
+}
+
+/// How many upstream connections are kept open between requests, and how long
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionPool {
+    /// Idle connections kept for each upstream
+    pub max_per_upstream: usize,
+    /// Idle connections are closed after this long
+    pub idle_timeout: Duration,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        ConnectionPool {
+            max_per_upstream: 8,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Health {
+    healthy: bool,
+    /// Probes passed, or failed, in a row
+    passed: usize,
+    failed: usize,
+    last_probe: Option<Instant>,
+    probing: bool,
+    last_error: Option<String>,
+}
+
+/// A server requests are forwarded to
+#[derive(Debug)]
+pub struct Upstream {
+    pub url: HttpUrl,
+    /// Share of the requests, relative to the other upstreams' weights
+    pub weight: usize,
+    /// Requests forwarded and not answered yet
+    active: AtomicUsize,
+    health: Mutex<Health>,
+    /// Connections waiting for a request, with when they were left idle
+    idle: Mutex<Vec<(TcpStream, Instant)>>,
+}
+
+impl Upstream {
+    pub fn new(url: HttpUrl, weight: usize) -> Self {
+        Upstream {
+            url,
+            weight: weight.max(1),
+            active: AtomicUsize::new(0),
+            health: Mutex::new(Health {
+                healthy: true,
+                passed: 0,
+                failed: 0,
+                last_probe: None,
+                probing: false,
+                last_error: None,
+            }),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Whether the upstream gets requests, which it does until it fails its probes
+    pub fn is_healthy(&self) -> bool {
+        self.health().healthy
+    }
+
+    fn health(&self) -> std::sync::MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn idle(&self) -> std::sync::MutexGuard<'_, Vec<(TcpStream, Instant)>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts the outcome of a probe, the upstream changing state once
+    /// enough of them in a row agree
+    pub fn record_probe(&self, check: &HealthCheck, outcome: Result<(), String>) {
+        let mut health = self.health();
+        health.probing = false;
+        match outcome {
+            Ok(()) => {
+                health.passed += 1;
+                health.failed = 0;
+                health.last_error = None;
+                if !health.healthy && health.passed >= check.healthy_threshold {
+                    health.healthy = true;
+                    logger().info(&format!("Upstream {} is healthy again", self.url.authority()), "Proxy");
+                }
+            }
+            Err(e) => {
+                health.failed += 1;
+                health.passed = 0;
+                if health.healthy && health.failed >= check.unhealthy_threshold {
+                    health.healthy = false;
+                    logger().warn(&format!("Upstream {} is unhealthy, taken out of rotation: {}", self.url.authority(), e), "Proxy");
+                }
+                health.last_error = Some(e);
+            }
+        }
+    }
+
+    /// Requests `check.path`, 2xx and 3xx answers passing
+    fn probe(&self, check: &HealthCheck) -> Result<(), String> {
+        let url = HttpUrl { path: check.path.clone(), ..self.url.clone() };
+        let response = url.send("GET", None).map_err(|e| e.to_string())?;
+        match response.status {
+            200..=399 => Ok(()),
+            status => Err(format!("{} answered with status {}", check.path, status)),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let health = self.health();
+        json!({
+            "url": format!("http://{}{}", self.url.authority(), self.url.path),
+            "weight": self.weight,
+            "active": self.active(),
+            "idle": self.idle().len(),
+            "healthy": health.healthy,
+            "last_error": health.last_error,
+        })
+    }
+}
+
+/// Where a route's requests are forwarded
+#[derive(Debug)]
+pub struct Proxy {
+    pub upstreams: Vec<Upstream>,
+    pub balance: Balance,
+    pub health_check: Option<HealthCheck>,
+    pub retry: Option<RetryPolicy>,
+    pub pool: Option<ConnectionPool>,
+    pub buffering: Buffering,
+    /// Bytes waiting for a slow client past which the upstream isn't read
+    pub buffer_size: usize,
+    /// Largest response held whole when buffered
+    pub max_buffered: usize,
+    /// Responses answering requests instead of the upstreams
+    pub cache: Option<Arc<ProxyCache>>,
+    /// Requests balanced in turn so far
+    turn: AtomicUsize,
+    /// Time allowed to connect to the upstream
+    pub connect_timeout: Duration,
+    /// Longest wait for the upstream, for its response to start and then between its parts
+    pub timeout: Duration,
+}
+
+impl Proxy {
+    pub fn new(upstream: HttpUrl) -> Self {
+        Self::balanced(vec![Upstream::new(upstream, 1)], Balance::default())
+    }
+
+    /// Spreads the requests over `upstreams`, which can't be empty
+    pub fn balanced(upstreams: Vec<Upstream>, balance: Balance) -> Self {
+        assert!(!upstreams.is_empty(), "a proxy needs an upstream");
+        Proxy {
+            upstreams,
+            balance,
+            health_check: None,
+            retry: None,
+            pool: None,
+            buffering: Buffering::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            cache: None,
+            turn: AtomicUsize::new(0),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_health_check(mut self, check: HealthCheck) -> Self {
+        self.health_check = Some(check);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn with_buffering(mut self, buffering: Buffering) -> Self {
+        self.buffering = buffering;
+        self
+    }
+
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(1);
+        self
+    }
+
+    pub fn with_max_buffered(mut self, size: usize) -> Self {
+        self.max_buffered = size;
+        self
+    }
+
+    pub fn with_pool(mut self, pool: ConnectionPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn with_cache(mut self, cache: Arc<ProxyCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Probes, each from its own thread, the upstreams whose last probe is
+    /// older than the check interval
+    pub fn check_health(self: &Arc<Self>) {
+        let Some(check) = &self.health_check else { return };
+        for (index, upstream) in self.upstreams.iter().enumerate() {
+            {
+                let mut health = upstream.health();
+                if health.probing || health.last_probe.is_some_and(|probed| probed.elapsed() < check.interval) {
+                    continue;
+                }
+                health.probing = true;
+                health.last_probe = Some(Instant::now());
+            }
+            let proxy = Arc::clone(self);
+            let spawned = thread::Builder::new().name("health-check".to_string()).spawn(move || {
+                let upstream = &proxy.upstreams[index];
+                if let Some(check) = &proxy.health_check {
+                    upstream.record_probe(check, upstream.probe(check));
+                }
+            });
+            if let Err(e) = spawned {
+                upstream.health().probing = false;
+                logger().error(&format!("Cannot probe upstream {}: {}", upstream.url.authority(), e), "Proxy");
+            }
+        }
+    }
+
+    /// A connection to upstream `index` an earlier request left open, unless
+    /// it timed out or the upstream closed it since
+    fn take_idle(&self, index: usize) -> Option<TcpStream> {
+        let pool = self.pool.as_ref()?;
+        let mut idle = self.upstreams[index].idle();
+        while let Some((stream, since)) = idle.pop() {
+            // Anything to read would be the upstream closing, or sending what nobody asked for
+            let open = matches!(stream.peek(&mut [0]), Err(e) if e.kind() == io::ErrorKind::WouldBlock);
+            if open && since.elapsed() < pool.idle_timeout {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    /// Keeps `stream`, whose response ended, open for the next request to
+    /// upstream `index`, when the pool has room for it
+    fn release(&self, index: usize, stream: TcpStream) {
+        let Some(pool) = &self.pool else { return };
+        let mut idle = self.upstreams[index].idle();
+        if idle.len() < pool.max_per_upstream {
+            idle.push((stream, Instant::now()));
+        }
+    }
+
+    /// Closes the connections left idle for longer than the pool allows
+    pub fn close_idle(&self) {
+        let Some(pool) = &self.pool else { return };
+        for upstream in &self.upstreams {
+            upstream.idle().retain(|(_, since)| since.elapsed() < pool.idle_timeout);
+        }
+    }
+
+    /// Index of the upstream the next request of `client` goes to, None when
+    /// none of them is healthy
+    pub fn choose(&self, client: Option<IpAddr>) -> Option<usize> {
+        self.choose_other(client, &[])
+    }
+
+    /// Like [`Proxy::choose`], leaving out the upstreams `tried` unless no
+    /// other is healthy
+    pub fn choose_other(&self, client: Option<IpAddr>, tried: &[usize]) -> Option<usize> {
+        let mut healthy: Vec<usize> = (0..self.upstreams.len()).filter(|&index| self.upstreams[index].is_healthy()).collect();
+        if healthy.iter().any(|index| !tried.contains(index)) {
+            healthy.retain(|index| !tried.contains(index));
+        }
+        let total: usize = healthy.iter().map(|&index| self.upstreams[index].weight).sum();
+        if total == 0 {
+            return None;
+        }
+        // The upstream holding the `slot`th share of the total weight
+        let by_weight = |slot: usize| {
+            let mut slot = slot % total;
+            healthy.iter().copied().find(|&index| {
+                let weight = self.upstreams[index].weight;
+                let found = slot < weight;
+                slot = slot.saturating_sub(weight);
+                found
+            })
+        };
+
+        match (self.balance, client) {
+            (Balance::IpHash, Some(client)) => {
+                let mut hasher = DefaultHasher::new();
+                client.hash(&mut hasher);
+                by_weight(hasher.finish() as usize)
+            }
+            (Balance::LeastConnections, _) => {
+                // Fewest active requests per unit of weight, compared without dividing
+                let start = self.turn.fetch_add(1, Ordering::SeqCst) % healthy.len();
+                (0..healthy.len())
+                    .map(|offset| healthy[(start + offset) % healthy.len()])
+                    .min_by(|&a, &b| {
+                        let (a, b) = (&self.upstreams[a], &self.upstreams[b]);
+                        (a.active() * b.weight).cmp(&(b.active() * a.weight))
+                    })
+            }
+            _ => by_weight(self.turn.fetch_add(1, Ordering::SeqCst)),
+        }
+    }
+
+    /// The URI `request_uri` has on `upstream`: unchanged when its URL has no
+    /// path, else with the route's `route_path` replaced by that path
+    pub fn upstream_uri(upstream: &HttpUrl, route_path: &str, request_uri: &str) -> String {
+        let base = upstream.path.trim_end_matches('/');
+        if base.is_empty() {
+            return request_uri.to_string();
+        }
+        let rest = request_uri.strip_prefix(route_path.trim_end_matches('/')).unwrap_or(request_uri);
+        match rest.chars().next() {
+            None => base.to_string(),
+            Some('/' | '?') => format!("{}{}", base, rest),
+            Some(_) => format!("{}/{}", base, rest),
+        }
+    }
+
+    /// The bytes sent to `upstream` for `request`: its head without the
+    /// hop-by-hop headers, then its body as received. Unless `keep_alive`, the
+    /// upstream closes the connection once it answered, which ends responses
+    /// of unknown length.
+    ///
+    /// The `X-Forwarded-*` headers tell the upstream who the client is, what
+    /// it asked for and how, adding to those of trusted proxies.
+    pub fn upstream_request(upstream: &HttpUrl, route_path: &str, request: &Request, keep_alive: bool) -> Vec<u8> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\n",
+            request.method, Self::upstream_uri(upstream, route_path, &request.uri), upstream.authority()
+        );
+        let listed = request.get_header(HeaderName::Connection)
+            .map(|header| connection_tokens(&header.value.value))
+            .unwrap_or_default();
+        for header in &request.headers {
+            let name = header.name.to_string().to_ascii_lowercase();
+            // The body is sent whole, with its own length
+            let replaced = ["host", "content-length", "transfer-encoding", "expect"].contains(&name.as_str())
+                || name.starts_with("x-forwarded-");
+            if replaced || HOP_BY_HOP.contains(&name.as_str()) || listed.contains(&name) {
+                continue;
+            }
+            head.push_str(&format!("{}\r\n", header));
+        }
+        for (name, value) in forwarded_headers(request) {
+            head.push_str(&format!("{}: {}\r\n", name, sanitize_value(&value)));
+        }
+        let body = request.raw_body.as_deref().unwrap_or_default();
+        if !body.is_empty() || request.has_body() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        if is_websocket_upgrade(request) {
+            head.push_str("Upgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+        } else if keep_alive {
+            head.push_str("Connection: keep-alive\r\n\r\n");
+        } else {
+            head.push_str("Connection: close\r\n\r\n");
+        }
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Connecting,
+    Sending,
+    AwaitingHead,
+    Streaming,
+    /// Passing WebSocket frames both ways
+    Tunnel,
+}
+
+/// How an exchange stands after its socket was ready
+#[derive(Debug)]
+pub enum Progress {
+    /// Waiting for the upstream
+    Pending,
+    /// The whole response was sent, and the client connection may serve
+    /// another request when `keep_alive`
+    Done { keep_alive: bool },
+    /// Nothing was sent to the client yet, which gets the error instead
+    Failed(HttpError),
+    /// Like `Failed`, but the request may be sent again through [`Exchange::retry`]
+    Retry(String),
+    /// The response was cut short after its head was sent, the client
+    /// connection has to be closed
+    Aborted(String),
+}
+
+/// A request forwarded to an upstream, until its response is sent back
+pub struct Exchange {
+    stream: TcpStream,
+    proxy: Arc<Proxy>,
+    /// Index of the upstream in the proxy's
+    upstream_index: usize,
+    /// Connection of the client waiting for the response
+    pub client_fd: RawFd,
+    phase: Phase,
+    /// The request as sent upstream, and how much of it was
+    outgoing: Vec<u8>,
+    sent: usize,
+    /// The response head, until it's complete
+    head: Vec<u8>,
+    /// Body bytes the client still expects, `None` when the upstream closing ends the body
+    remaining: Option<usize>,
+    /// Follows a chunked body, which ends with its last chunk
+    chunks: Option<ChunkScanner>,
+    /// Whether the connection came from the pool
+    reused: bool,
+    /// Whether the upstream agreed to keep the connection open after its response
+    upstream_keep_alive: bool,
+    /// Set once the response ended, for the connection to go back to the pool
+    reusable: bool,
+    /// Bytes the client couldn't take yet
+    to_client: Vec<u8>,
+    /// The buffered response, head then body, until it's complete
+    held: Option<Vec<u8>>,
+    /// Set once the response ended with bytes still waiting for the client,
+    /// to whether its connection is kept open afterwards
+    finished: Option<bool>,
+    /// Whether the request asked for a WebSocket connection
+    upgrade: bool,
+    /// Frames sent by the client, then by the upstream, once tunneled
+    client_frames: FrameScanner,
+    upstream_frames: FrameScanner,
+    /// Whether the client asked to keep its connection open
+    client_keep_alive: bool,
+    keep_alive: bool,
+    /// Added to the response, replacing the upstream's ones of the same name
+    headers: Vec<Header>,
+    /// Added to the response besides the upstream's cookies
+    cookies: Vec<Header>,
+    /// The response as kept for the cache, while it may be
+    capture: Option<Capture>,
+    upstream: String,
+    deadline: Instant,
+    timeout: Duration,
+    /// Upstreams tried so far, this one last
+    tried: Vec<usize>,
+    /// The forwarded request, for the logs
+    pub request: Request,
+    pub route_path: String,
+    pub started: Instant,
+    /// Status the upstream answered with
+    pub status: Option<u16>,
+    /// Body bytes sent to the client
+    pub bytes: usize,
+}
+
+impl Exchange {
+    /// Starts connecting to the upstream of `proxy` picked for `request`, which
+    /// matched the route at `route_path`
+    pub fn start(proxy: &Arc<Proxy>, route_path: &str, request: Request) -> Result<Self, HttpError> {
+        Self::attempt(proxy, route_path, request, Vec::new())
+    }
+
+    /// Sends the request again, to an upstream not tried yet when there is one
+    pub fn retry(&self) -> Result<Self, HttpError> {
+        let mut next = Self::attempt(&self.proxy, &self.route_path, self.request.clone(), self.tried.clone())?;
+        next.client_fd = self.client_fd;
+        next.client_keep_alive = self.client_keep_alive;
+        next.headers = self.headers.clone();
+        next.cookies = self.cookies.clone();
+        next.started = self.started;
+        Ok(next)
+    }
+
+    /// How long to wait before sending the request again, doubling with each attempt
+    pub fn backoff(&self) -> Duration {
+        let backoff = self.proxy.retry.as_ref().map(|retry| retry.backoff).unwrap_or_default();
+        backoff.saturating_mul(1 << (self.tried.len() - 1).min(16))
+    }
+
+    fn attempt(proxy: &Arc<Proxy>, route_path: &str, request: Request, mut tried: Vec<usize>) -> Result<Self, HttpError> {
+        let upstream_index = proxy.choose_other(request.client_addr, &tried)
+            .ok_or_else(|| HttpError::BadGateway("No upstream is healthy".to_string()))?;
+        tried.push(upstream_index);
+        let url = &proxy.upstreams[upstream_index].url;
+        let upstream = format!("{}:{}", url.host, url.port);
+        // A request may be sent on a connection the upstream is about to close,
+        // and only those that can be sent twice are retried then
+        let pooled = is_idempotent(&request.method) && !is_websocket_upgrade(&request);
+        let idle = pooled.then(|| proxy.take_idle(upstream_index)).flatten();
+        let reused = idle.is_some();
+        let stream = match idle {
+            Some(stream) => stream,
+            None => {
+                let addr = (url.host.as_str(), url.port).to_socket_addrs().ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .ok_or_else(|| HttpError::BadGateway(format!("Cannot resolve upstream {}", upstream)))?;
+                connect(addr).map_err(|e| HttpError::BadGateway(format!("Cannot connect to upstream {}: {}", upstream, e)))?
+            }
+        };
+        proxy.upstreams[upstream_index].active.fetch_add(1, Ordering::SeqCst);
+
+        Ok(Exchange {
+            stream,
+            proxy: proxy.clone(),
+            upstream_index,
+            client_fd: -1,
+            phase: if reused { Phase::Sending } else { Phase::Connecting },
+            outgoing: Proxy::upstream_request(url, route_path, &request, pooled && proxy.pool.is_some()),
+            sent: 0,
+            head: Vec::new(),
+            remaining: None,
+            chunks: None,
+            reused,
+            upstream_keep_alive: false,
+            reusable: false,
+            to_client: Vec::new(),
+            held: None,
+            finished: None,
+            upgrade: is_websocket_upgrade(&request),
+            client_frames: FrameScanner::default(),
+            upstream_frames: FrameScanner::default(),
+            client_keep_alive: false,
+            keep_alive: false,
+            headers: Vec::new(),
+            cookies: Vec::new(),
+            capture: None,
+            upstream,
+            deadline: Instant::now() + if reused { proxy.timeout } else { proxy.connect_timeout },
+            timeout: proxy.timeout,
+            request,
+            route_path: route_path.to_string(),
+            tried,
+            started: Instant::now(),
+            status: None,
+            bytes: 0,
+        })
+    }
+
+    /// Sends the response to the connection `client_fd`, kept open afterwards
+    /// when `keep_alive` and the response length is known
+    pub fn for_client(mut self, client_fd: RawFd, keep_alive: bool) -> Self {
+        self.client_fd = client_fd;
+        self.client_keep_alive = keep_alive;
+        self
+    }
+
+    /// Adds `headers` to the response, replacing the upstream's ones of the same name
+    pub fn with_headers(mut self, headers: Vec<Header>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Adds the `Set-Cookie` headers in `cookies` to the response
+    pub fn with_cookies(mut self, cookies: Vec<Header>) -> Self {
+        self.cookies = cookies;
+        self
+    }
+
+    /// The upstream socket, to watch for readiness
+    pub fn fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    /// The upstream the request was forwarded to
+    pub fn upstream(&self) -> &Upstream {
+        &self.proxy.upstreams[self.upstream_index]
+    }
+
+    pub fn timed_out(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Whether the upstream accepted a WebSocket connection, whose client
+    /// frames go through [`Exchange::relay_from_client`]
+    pub fn is_tunnel(&self) -> bool {
+        self.phase == Phase::Tunnel
+    }
+
+    /// Hands the response over to the proxy cache, once it was sent whole
+    pub fn cache_response(&mut self) {
+        if let (Some(cache), Some(capture)) = (&self.proxy.cache, self.capture.take()) {
+            cache.keep(capture);
+        }
+    }
+
+    /// What becomes of the client once the upstream took too long
+    pub fn time_out(&self) -> Progress {
+        match self.phase {
+            Phase::Streaming if self.held.is_none() => Progress::Aborted(format!("Upstream {} stopped sending the response", self.upstream)),
+            Phase::Tunnel => Progress::Aborted(format!("WebSocket tunnel to {} was idle too long", self.upstream)),
+            _ => {
+                let message = format!("Upstream {} didn't answer in time", self.upstream);
+                if self.may_retry(RetryOn::Timeout) {
+                    return Progress::Retry(message);
+                }
+                Progress::Failed(HttpError::GatewayTimeout(message))
+            }
+        }
+    }
+
+    /// Whether the request may go to the next upstream after failing for `reason`:
+    /// it has to be idempotent, with attempts left
+    fn may_retry(&self, reason: RetryOn) -> bool {
+        let Some(retry) = &self.proxy.retry else { return false };
+        is_idempotent(&self.request.method) && self.tried.len() < retry.attempts && retry.next_upstream.contains(&reason)
+    }
+
+    /// Moves the exchange on as far as the upstream socket allows, writing what
+    /// the upstream answered to `client`
+    pub fn advance(&mut self, client: &mut Connection) -> Progress {
+        if let Some(keep_alive) = self.finished {
+            return self.done(keep_alive);
+        }
+        if self.phase == Phase::Connecting {
+            match self.stream.take_error() {
+                Ok(None) => {}
+                Ok(Some(e)) | Err(e) => return self.failed(format!("cannot be reached: {}", e)),
+            }
+            // Still connecting
+            if self.stream.peer_addr().is_err() {
+                return Progress::Pending;
+            }
+            self.phase = Phase::Sending;
+            self.deadline = Instant::now() + self.timeout;
+        }
+
+        if self.phase == Phase::Sending {
+            while self.sent < self.outgoing.len() {
+                match self.stream.write(&self.outgoing[self.sent..]) {
+                    Ok(0) => return self.failed("closed the connection before the request was sent".to_string()),
+                    Ok(written) => self.sent += written,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Progress::Pending,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return self.failed(format!("didn't accept the request: {}", e)),
+                }
+            }
+            self.outgoing = Vec::new();
+            self.phase = Phase::AwaitingHead;
+            self.deadline = Instant::now() + self.timeout;
+        }
+
+        // Client frames the upstream couldn't take at once
+        if self.phase == Phase::Tunnel {
+            if let Err(progress) = self.send_pending() {
+                return progress;
+            }
+        }
+
+        let mut buffer = [0; READ_SIZE];
+        loop {
+            // Read again once the client took what waits for it
+            if self.to_client.len() >= self.proxy.buffer_size {
+                return Progress::Pending;
+            }
+            let read = match self.stream.read(&mut buffer) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Progress::Pending,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return self.failed(format!("failed while answering: {}", e)),
+            };
+            self.deadline = Instant::now() + self.timeout;
+            if read == 0 {
+                return self.upstream_closed(client);
+            }
+            let progress = match self.phase {
+                Phase::AwaitingHead => {
+                    self.head.extend_from_slice(&buffer[..read]);
+                    self.receive_head(client)
+                }
+                Phase::Tunnel => self.relay_to_client(&buffer[..read], client),
+                _ => self.forward(&buffer[..read], client),
+            };
+            if let Some(progress) = progress {
+                return progress;
+            }
+        }
+    }
+
+    /// Moves the exchange on once the client connection is ready: sends the
+    /// client what waited for room, reads what waited upstream meanwhile, and
+    /// passes on what a WebSocket client sent
+    pub fn client_ready(&mut self, client: &mut Connection) -> Progress {
+        if let Err(progress) = self.flush_client(client) {
+            return progress;
+        }
+        if self.phase == Phase::Tunnel {
+            match self.relay_from_client(client) {
+                Progress::Pending => {}
+                progress => return progress,
+            }
+        }
+        self.advance(client)
+    }
+
+    /// Queues `data` for the client, and sends it what it takes now
+    fn send_to_client(&mut self, data: &[u8], client: &mut Connection) -> Result<(), Progress> {
+        self.to_client.extend_from_slice(data);
+        self.flush_client(client)
+    }
+
+    fn flush_client(&mut self, client: &mut Connection) -> Result<(), Progress> {
+        while !self.to_client.is_empty() {
+            match client.reader.write_some(&self.to_client) {
+                Ok(0) => return Err(Progress::Aborted("The client stopped taking the response".to_string())),
+                Ok(written) => {
+                    self.to_client.drain(..written);
+                    client.start_time = Instant::now();
+                    // The upstream waits on the client, not the other way round
+                    self.deadline = Instant::now() + self.timeout;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Progress::Aborted(format!("Cannot send the response to the client: {}", e))),
+            }
+        }
+        Ok(())
+    }
+
+    /// The response ended, which the client is done with once it took all of it
+    fn done(&mut self, keep_alive: bool) -> Progress {
+        if self.to_client.is_empty() {
+            return Progress::Done { keep_alive };
+        }
+        self.finished = Some(keep_alive);
+        Progress::Pending
+    }
+
+    /// Passes what the client sent on to the upstream, once tunneled
+    pub fn relay_from_client(&mut self, client: &mut Connection) -> Progress {
+        let mut buffer = [0; READ_SIZE];
+        loop {
+            let read = match client.reader.read_raw(&mut buffer) {
+                Ok(0) => return Progress::Done { keep_alive: false },
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Progress::Pending,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Progress::Aborted(format!("Cannot read from the WebSocket client: {}", e)),
+            };
+            self.deadline = Instant::now() + self.timeout;
+            self.client_frames.scan(&buffer[..read]);
+            self.outgoing.extend_from_slice(&buffer[..read]);
+            if let Err(progress) = self.send_pending() {
+                return progress;
+            }
+            if self.closed_both_ways() {
+                return self.done(false);
+            }
+        }
+    }
+
+    /// Writes the client frames the upstream can take now, the rest waiting
+    /// for its socket to be writable again
+    fn send_pending(&mut self) -> Result<(), Progress> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(self.failed("closed the WebSocket connection".to_string())),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.failed(format!("failed while tunneling: {}", e))),
+            }
+        }
+        Ok(())
+    }
+
+    fn relay_to_client(&mut self, data: &[u8], client: &mut Connection) -> Option<Progress> {
+        self.upstream_frames.scan(data);
+        if let Err(progress) = self.send_to_client(data, client) {
+            return Some(progress);
+        }
+        self.bytes += data.len();
+        self.closed_both_ways().then(|| self.done(false))
+    }
+
+    /// Whether each side sent its close frame, which ends the WebSocket connection
+    fn closed_both_ways(&self) -> bool {
+        self.client_frames.closed && self.upstream_frames.closed
+    }
+
+    fn failed(&self, problem: String) -> Progress {
+        let message = format!("Upstream {} {}", self.upstream, problem);
+        // A buffered response isn't sent yet
+        let answering = matches!(self.phase, Phase::Streaming | Phase::Tunnel) && self.held.is_none();
+        match self.phase {
+            _ if answering => Progress::Aborted(message),
+            // The upstream closed the pooled connection before it got the request
+            _ if self.reused && self.status.is_none() && self.head.is_empty() => Progress::Retry(message),
+            _ if self.may_retry(RetryOn::Error) => Progress::Retry(message),
+            _ => Progress::Failed(HttpError::BadGateway(message)),
+        }
+    }
+
+    fn upstream_closed(&mut self, client: &mut Connection) -> Progress {
+        match (self.phase, self.remaining) {
+            (Phase::Streaming, None) if self.chunks.is_some() => {
+                self.failed("closed the connection before the last chunk of the response".to_string())
+            }
+            (Phase::Streaming, Some(remaining)) => {
+                self.failed(format!("closed the connection {} bytes before the end of the response", remaining))
+            }
+            // The body ended with the connection
+            (Phase::Streaming, None) | (Phase::Tunnel, _) => {
+                if let Some(held) = self.held.take() {
+                    if let Err(progress) = self.send_to_client(&held, client) {
+                        return progress;
+                    }
+                }
+                self.done(false)
+            }
+            _ => self.failed("closed the connection without answering".to_string()),
+        }
+    }
+
+    /// Once the response head is complete, sends it to the client with the
+    /// body bytes that came along
+    fn receive_head(&mut self, client: &mut Connection) -> Option<Progress> {
+        let Some(end) = self.head.windows(4).position(|window| window == b"\r\n\r\n").map(|end| end + 4) else {
+            return (self.head.len() > MAX_HEAD_SIZE)
+                .then(|| self.failed(format!("sent a response head over {} bytes", MAX_HEAD_SIZE)));
+        };
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut response = httparse::Response::new(&mut headers);
+        if !matches!(response.parse(&self.head[..end]), Ok(httparse::Status::Complete(_))) {
+            return Some(self.failed("sent an invalid response".to_string()));
+        }
+        let status = response.code.unwrap_or_default();
+        let upgraded = status == 101;
+        if upgraded && !self.upgrade {
+            return Some(self.failed("switched protocols unasked".to_string()));
+        }
+        // Interim responses, like 100 Continue, are followed by the actual one
+        if (100..200).contains(&status) && !upgraded {
+            self.head.drain(..end);
+            return self.receive_head(client);
+        }
+        if self.may_retry(RetryOn::Status(status)) {
+            return Some(Progress::Retry(format!("Upstream {} answered with status {}", self.upstream, status)));
+        }
+
+        let listed: Vec<String> = response.headers.iter()
+            .filter(|header| header.name.eq_ignore_ascii_case("connection"))
+            .flat_map(|header| connection_tokens(&String::from_utf8_lossy(header.value)))
+            .collect();
+        let mut content_length = None;
+        let mut chunked = false;
+        let reason = response.reason.unwrap_or_default();
+        let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason);
+        let mut kept = Vec::new();
+        for header in response.headers.iter() {
+            let name = header.name.to_ascii_lowercase();
+            let value = String::from_utf8_lossy(header.value);
+            match name.as_str() {
+                "content-length" => content_length = value.trim().parse::<usize>().ok(),
+                "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+                _ => {}
+            }
+            if HOP_BY_HOP.contains(&name.as_str()) || listed.contains(&name) {
+                continue;
+            }
+            kept.push((header.name.to_string(), value.to_string()));
+            if self.headers.iter().any(|own| own.name.to_string().eq_ignore_ascii_case(&name)) {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", header.name, sanitize_value(&value)));
+        }
+
+        let bodyless = self.request.method == HttpMethod::HEAD || status == 204 || status == 304;
+        self.remaining = if bodyless { Some(0) } else if chunked { None } else { content_length };
+        self.chunks = (!bodyless && chunked).then(ChunkScanner::default);
+        // Without a length, only the connection closing tells the client the body ended
+        let delimited = self.remaining.is_some() || self.chunks.is_some();
+        self.keep_alive = self.client_keep_alive && delimited && !upgraded;
+        self.upstream_keep_alive = self.proxy.pool.is_some() && delimited && !upgraded
+            && response.version == Some(1) && !listed.iter().any(|token| token == "close");
+        if let Some(cache) = self.proxy.cache.as_ref().filter(|_| !upgraded) {
+            self.capture = cache.capture(&self.request, status, reason, kept, self.remaining, chunked);
+            head.push_str("X-Cache: MISS\r\n");
+        }
+        for header in self.headers.iter().chain(&self.cookies) {
+            head.push_str(&format!("{}\r\n", header));
+        }
+        if upgraded {
+            head.push_str("Upgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+        } else if self.keep_alive {
+            head.push_str(&format!("{}\r\nConnection: keep-alive\r\n\r\n", client.keep_alive_header()));
+        } else {
+            head.push_str("Connection: close\r\n\r\n");
+        }
+
+        self.status = Some(status);
+        self.phase = if upgraded { Phase::Tunnel } else { Phase::Streaming };
+        if self.proxy.buffering == Buffering::Buffered && !upgraded {
+            self.held = Some(head.into_bytes());
+        } else if let Err(progress) = self.send_to_client(head.as_bytes(), client) {
+            return Some(progress);
+        }
+        let body = self.head.split_off(end);
+        self.head = Vec::new();
+        if upgraded {
+            return self.relay_to_client(&body, client);
+        }
+        self.forward(&body, client)
+    }
+
+    /// Sends body bytes to the client, up to the end of the body the upstream announced
+    fn forward(&mut self, data: &[u8], client: &mut Connection) -> Option<Progress> {
+        let (data, ended) = match (self.remaining, &mut self.chunks) {
+            (Some(remaining), _) => (&data[..data.len().min(remaining)], data.len() >= remaining),
+            (None, Some(chunks)) => match chunks.scan(data) {
+                Some(end) => (&data[..end], true),
+                None => (data, false),
+            },
+            (None, None) => (data, false),
+        };
+        if let Some(capture) = &mut self.capture {
+            if !capture.push(data) {
+                self.capture = None;
+            }
+        }
+        if !data.is_empty() {
+            let max_buffered = self.proxy.max_buffered;
+            if let Some(held) = self.held.as_mut().filter(|held| held.len() + data.len() <= max_buffered) {
+                held.extend_from_slice(data);
+            } else {
+                // Too large to be held whole, streamed from here
+                let held = self.held.take().unwrap_or_default();
+                if let Err(progress) = self.send_to_client(&held, client).and_then(|_| self.send_to_client(data, client)) {
+                    return Some(progress);
+                }
+            }
+            self.bytes += data.len();
+        }
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= data.len();
+        }
+        if !ended {
+            return None;
+        }
+        if let Some(held) = self.held.take() {
+            if let Err(progress) = self.send_to_client(&held, client) {
+                return Some(progress);
+            }
+        }
+        self.reusable = self.upstream_keep_alive;
+        Some(self.done(self.keep_alive))
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        self.upstream().active.fetch_sub(1, Ordering::SeqCst);
+        if self.reusable {
+            if let Ok(stream) = self.stream.try_clone() {
+                self.proxy.release(self.upstream_index, stream);
+            }
+        }
+    }
+}
+
+/// Follows a chunked body, to tell where its last chunk ends
+#[derive(Debug, Default)]
+struct ChunkScanner {
+    /// The line being read, a chunk size or a trailer
+    line: Vec<u8>,
+    /// Bytes of the current chunk not seen yet, with the CRLF ending it
+    data_left: usize,
+    /// Whether the last chunk was seen, trailers following it
+    last_chunk: bool,
+    /// Set on a size that isn't hexadecimal, the body then ending with the connection
+    invalid: bool,
+}
+
+impl ChunkScanner {
+    /// Where in `data` the body ends, if it does
+    fn scan(&mut self, data: &[u8]) -> Option<usize> {
+        let mut pos = 0;
+        while pos < data.len() && !self.invalid {
+            if self.data_left > 0 {
+                let skipped = (data.len() - pos).min(self.data_left);
+                self.data_left -= skipped;
+                pos += skipped;
+                continue;
+            }
+            let byte = data[pos];
+            pos += 1;
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line).trim().to_string();
+            self.line.clear();
+            if self.last_chunk {
+                // The empty line after the trailers
+                if line.is_empty() {
+                    return Some(pos);
+                }
+                continue;
+            }
+            let size = line.split(';').next().unwrap_or_default().trim();
+            match usize::from_str_radix(size, 16) {
+                Ok(0) => self.last_chunk = true,
+                Ok(size) => self.data_left = size.saturating_add(2),
+                Err(_) => self.invalid = true,
+            }
+        }
+        None
+    }
+}
+
+/// Follows the frames going one way through a WebSocket tunnel, to notice
+/// the close frame among them
+#[derive(Debug, Default)]
+struct FrameScanner {
+    /// The head of the current frame, until it's complete
+    head: Vec<u8>,
+    /// Payload bytes of the current frame not seen yet
+    payload_left: u64,
+    closed: bool,
+}
+
+impl FrameScanner {
+    fn scan(&mut self, mut data: &[u8]) {
+        while !data.is_empty() && !self.closed {
+            if self.payload_left > 0 {
+                let skipped = data.len().min(self.payload_left.try_into().unwrap_or(usize::MAX));
+                self.payload_left -= skipped as u64;
+                data = &data[skipped..];
+                continue;
+            }
+            self.head.push(data[0]);
+            data = &data[1..];
+            if let Some(head) = websocket::frame_head(&self.head) {
+                self.closed = head.opcode == 0x8;
+                self.payload_left = head.payload_len;
+                self.head.clear();
+            }
+        }
+    }
+}
+
+/// The `X-Forwarded-*` headers sent upstream for `request`
+fn forwarded_headers(request: &Request) -> Vec<(&'static str, String)> {
+    let mut headers = Vec::new();
+    // The peer is added to the addresses the proxies before it saw
+    let peer = request.peer_addr.or(request.client_addr).map(|addr| addr.to_canonical().to_string());
+    let forwarded_for = match (forwarded::trusted_header(request, "x-forwarded-for"), peer) {
+        (Some(chain), Some(peer)) => Some(format!("{}, {}", chain, peer)),
+        (chain, peer) => peer.or(chain.map(str::to_string)),
+    };
+    if let Some(forwarded_for) = forwarded_for {
+        headers.push(("X-Forwarded-For", forwarded_for));
+    }
+    let proto = forwarded::trusted_header(request, "x-forwarded-proto")
+        .unwrap_or(if request.secure { "https" } else { "http" });
+    headers.push(("X-Forwarded-Proto", proto.to_string()));
+    let host = forwarded::trusted_header(request, "x-forwarded-host").map(str::to_string)
+        .or_else(|| request.get_header(HeaderName::Host).map(|header| header.value.value));
+    if let Some(host) = host {
+        headers.push(("X-Forwarded-Host", host));
+    }
+    headers
+}
+
+/// Whether a request with `method` can be sent twice to the same effect
+fn is_idempotent(method: &HttpMethod) -> bool {
+    matches!(method, HttpMethod::GET | HttpMethod::HEAD | HttpMethod::PUT | HttpMethod::DELETE | HttpMethod::OPTIONS | HttpMethod::TRACE)
+}
+
+/// Whether `request` is a WebSocket handshake
+pub fn is_websocket_upgrade(request: &Request) -> bool {
+    request.method == HttpMethod::GET && request.headers.iter().any(|header| {
+        header.name.to_string().eq_ignore_ascii_case("upgrade")
+            && header.value.value.to_ascii_lowercase().contains("websocket")
+    })
+}
+
+/// The lowercase header names listed by a `Connection` header
+fn connection_tokens(value: &str) -> Vec<String> {
+    value.split(',').map(|token| token.trim().to_ascii_lowercase()).filter(|token| !token.is_empty()).collect()
+}
+
+/// Starts connecting a non-blocking socket to `addr`, the connection being
+/// established once the socket is writable
+fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owns the socket from here, closing it should connecting fail
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let result = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr { s_addr: u32::from_ne_bytes(addr.ip().octets()) },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                libc::connect(fd, &sockaddr as *const _ as *const libc::sockaddr, size_of_val(&sockaddr) as libc::socklen_t)
+            }
+        }
+        SocketAddr::V6(addr) => {
+            let sockaddr = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr { s6_addr: addr.ip().octets() },
+                sin6_scope_id: addr.scope_id(),
+            };
+            unsafe {
+                libc::connect(fd, &sockaddr as *const _ as *const libc::sockaddr, size_of_val(&sockaddr) as libc::socklen_t)
+            }
+        }
+    };
+    if result < 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(error);
+        }
+    }
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
@@ -0,0 +1,445 @@
+//! Proxy response cache
+//!
+//! Proxy routes with a `cache` keep the responses their upstream allows to
+//! share, and answer the same GET requests with them until they expire instead
+//! of forwarding them. Entries are held in memory up to a size, the least
+//! recently used making room for new ones. With a directory, they are also
+//! written to disk, where they outlive restarts and memory evictions.
+//!
+//! An entry is keyed by the method, host and URI of its request, plus the
+//! values of the request headers its response `Vary` header names. It lives as
+//! long as the `s-maxage` or `max-age` of its response `Cache-Control` says,
+//! else for the cache's `ttl`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::http::body::Body;
+use crate::http::body_stream::BodyStream;
+use crate::http::header::{sanitize_value, Header, HeaderName};
+use crate::http::request::{HttpMethod, Request};
+use crate::http::response::Response;
+use crate::http::status::HttpStatusCode;
+use crate::server::http_client::read_chunked;
+use crate::server::logger::logger;
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_MEMORY: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_ENTRY_SIZE: usize = 1024 * 1024;
+
+/// Statuses whose responses are kept
+const CACHEABLE_STATUSES: [u16; 6] = [200, 203, 300, 301, 404, 410];
+
+/// How much a cache keeps, and for how long
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheSettings {
+    /// Lifetime of the responses whose `Cache-Control` doesn't set one
+    pub ttl: Duration,
+    /// Bytes the responses held in memory may take together
+    pub max_memory: usize,
+    /// Largest response body kept
+    pub max_entry_size: usize,
+    /// Where responses are also written, when set
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            ttl: DEFAULT_TTL,
+            max_memory: DEFAULT_MAX_MEMORY,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            dir: None,
+        }
+    }
+}
+
+/// A response kept by the cache, as the upstream sent it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    key: String,
+    /// URI of the request, for purging by path
+    uri: String,
+    pub status: u16,
+    reason: String,
+    /// The upstream's headers, without the hop-by-hop ones
+    headers: Vec<(String, String)>,
+    /// Written after the rest on disk
+    #[serde(skip)]
+    pub body: Vec<u8>,
+    /// Seconds since the epoch
+    stored: u64,
+    expires: u64,
+}
+
+impl CachedResponse {
+    fn is_fresh(&self) -> bool {
+        now() < self.expires
+    }
+
+    /// Bytes the response takes in memory, roughly
+    fn size(&self) -> usize {
+        self.body.len() + self.headers.iter().map(|(name, value)| name.len() + value.len() + 4).sum::<usize>()
+    }
+
+    /// The response sent to a client, with `headers` replacing the stored
+    /// ones of the same name. Its body comes as a stream, for the connection
+    /// to send it however long the client takes, a chunked one decoded and
+    /// sent with its length.
+    pub fn to_response(&self, headers: Vec<Header>) -> Response {
+        let chunked = self.headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("transfer-encoding") && value.to_ascii_lowercase().contains("chunked")
+        });
+        let body = if chunked {
+            read_chunked(&mut self.body.as_slice()).unwrap_or_default()
+        } else {
+            self.body.clone()
+        };
+
+        let mut sent = Vec::with_capacity(self.headers.len() + headers.len() + 3);
+        for (name, value) in &self.headers {
+            let replaced = headers.iter().any(|own| own.name.to_string().eq_ignore_ascii_case(name));
+            if replaced || ["age", "content-length", "transfer-encoding"].iter().any(|own| name.eq_ignore_ascii_case(own)) {
+                continue;
+            }
+            sent.push(Header::from_str(name, &sanitize_value(value)));
+        }
+        sent.push(Header::from_str("Content-Length", &body.len().to_string()));
+        sent.push(Header::from_str("Age", &now().saturating_sub(self.stored).to_string()));
+        sent.push(Header::from_str("X-Cache", "HIT"));
+        sent.extend(headers);
+
+        // Only the statuses known here are kept
+        let status = HttpStatusCode::from_code(self.status).unwrap_or(HttpStatusCode::Ok);
+        Response::new(status, sent, Some(Body::Stream(BodyStream::new(std::iter::once(Ok(body))))))
+    }
+}
+
+/// A response on its way to the client, kept for the cache once complete
+#[derive(Debug)]
+pub struct Capture {
+    response: CachedResponse,
+    base_key: String,
+    /// Lowercase names of the request headers the response varies on
+    vary: Vec<String>,
+    chunked: bool,
+    max_size: usize,
+}
+
+impl Capture {
+    /// Adds body bytes, false once the body outgrew the largest entry
+    pub fn push(&mut self, data: &[u8]) -> bool {
+        if self.response.body.len() + data.len() > self.max_size {
+            return false;
+        }
+        self.response.body.extend_from_slice(data);
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Arc<CachedResponse>,
+    used: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+    /// Names of the headers the responses to each request vary on, by key without them
+    variants: HashMap<String, Vec<String>>,
+    /// Bytes taken by the entries
+    memory: usize,
+}
+
+/// The responses of a proxy route
+#[derive(Debug)]
+pub struct ProxyCache {
+    pub settings: CacheSettings,
+    store: Mutex<Store>,
+}
+
+/// Every cache alive, for purging
+fn caches() -> &'static Mutex<Vec<Weak<ProxyCache>>> {
+    static CACHES: OnceLock<Mutex<Vec<Weak<ProxyCache>>>> = OnceLock::new();
+    CACHES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Removes the entries whose URI starts with `prefix`, or every entry, from
+/// all the caches, returning how many were removed
+pub fn purge(prefix: Option<&str>) -> usize {
+    let mut caches = caches().lock().unwrap_or_else(|e| e.into_inner());
+    caches.retain(|cache| cache.strong_count() > 0);
+    // Caches sharing a directory, as across a reload, count its entries once
+    let purged: HashSet<String> = caches.iter().filter_map(Weak::upgrade).flat_map(|cache| cache.purge(prefix)).collect();
+    purged.len()
+}
+
+impl ProxyCache {
+    pub fn new(settings: CacheSettings) -> Arc<Self> {
+        let cache = Arc::new(ProxyCache { settings, store: Mutex::new(Store::default()) });
+        caches().lock().unwrap_or_else(|e| e.into_inner()).push(Arc::downgrade(&cache));
+        cache
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The fresh response kept for `request`, from memory or else from disk
+    pub fn lookup(&self, request: &Request) -> Option<Arc<CachedResponse>> {
+        let base_key = base_key(request)?;
+        let mut store = self.store();
+        if let (false, Some(dir)) = (store.variants.contains_key(&base_key), &self.settings.dir) {
+            if let Ok(names) = fs::read_to_string(entry_path(dir, &base_key).with_extension("vary")) {
+                store.variants.insert(base_key.clone(), names.lines().map(str::to_string).collect());
+            }
+        }
+        let vary = store.variants.get(&base_key).cloned().unwrap_or_default();
+        let key = variant_key(&base_key, &vary, request);
+
+        if let Some(entry) = store.entries.get_mut(&key) {
+            if entry.response.is_fresh() {
+                entry.used = Instant::now();
+                return Some(entry.response.clone());
+            }
+            if let Some(entry) = store.entries.remove(&key) {
+                store.memory -= entry.response.size();
+            }
+        }
+
+        let dir = self.settings.dir.as_ref()?;
+        let path = entry_path(dir, &key);
+        let response = read_entry(&path).filter(|response| response.key == key);
+        match response {
+            Some(response) if response.is_fresh() => {
+                let response = Arc::new(response);
+                self.insert(&mut store, response.clone());
+                Some(response)
+            }
+            _ => {
+                let _ = fs::remove_file(path);
+                None
+            }
+        }
+    }
+
+    /// Starts keeping the response to `request` the upstream is sending, when
+    /// both allow it. `length` is the body length the upstream announced.
+    pub fn capture(
+        &self,
+        request: &Request,
+        status: u16,
+        reason: &str,
+        headers: Vec<(String, String)>,
+        length: Option<usize>,
+        chunked: bool,
+    ) -> Option<Capture> {
+        let base_key = base_key(request)?;
+        if !CACHEABLE_STATUSES.contains(&status) || length.is_some_and(|length| length > self.settings.max_entry_size) {
+            return None;
+        }
+        if values(&headers, "set-cookie").next().is_some() {
+            return None;
+        }
+        let ttl = lifetime(values(&headers, "cache-control"), self.settings.ttl)?;
+        let mut vary: Vec<String> = values(&headers, "vary")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if vary.iter().any(|name| name == "*") {
+            return None;
+        }
+        vary.sort();
+        vary.dedup();
+
+        let stored = now();
+        Some(Capture {
+            response: CachedResponse {
+                key: variant_key(&base_key, &vary, request),
+                uri: request.uri.clone(),
+                status,
+                reason: reason.to_string(),
+                headers,
+                body: Vec::new(),
+                stored,
+                expires: stored + ttl.as_secs(),
+            },
+            base_key,
+            vary,
+            chunked,
+            max_size: self.settings.max_entry_size,
+        })
+    }
+
+    /// Keeps a response the upstream finished sending
+    pub fn keep(&self, capture: Capture) {
+        // A chunked body is complete with its last chunk only
+        if capture.chunked && !capture.response.body.ends_with(b"0\r\n\r\n") {
+            return;
+        }
+        let response = Arc::new(capture.response);
+        if let Some(dir) = &self.settings.dir {
+            if let Err(e) = write_entry(dir, &response, &capture.base_key, &capture.vary) {
+                logger().warn(&format!("Cannot write cached response of {} to {}: {}", response.uri, dir.display(), e), "Proxy");
+            }
+        }
+        let mut store = self.store();
+        store.variants.insert(capture.base_key, capture.vary);
+        self.insert(&mut store, response);
+    }
+
+    /// Holds `response` in memory, evicting the least recently used entries
+    /// it doesn't leave room for
+    fn insert(&self, store: &mut Store, response: Arc<CachedResponse>) {
+        let size = response.size();
+        if size > self.settings.max_memory {
+            return;
+        }
+        if let Some(previous) = store.entries.remove(&response.key) {
+            store.memory -= previous.response.size();
+        }
+        while store.memory + size > self.settings.max_memory {
+            let Some(oldest) = store.entries.iter().min_by_key(|(_, entry)| entry.used).map(|(key, _)| key.clone()) else { break };
+            if let Some(entry) = store.entries.remove(&oldest) {
+                store.memory -= entry.response.size();
+            }
+        }
+        store.memory += size;
+        store.entries.insert(response.key.clone(), Entry { response, used: Instant::now() });
+    }
+
+    /// Removes the entries whose URI starts with `prefix`, or every entry,
+    /// returning their keys
+    pub fn purge(&self, prefix: Option<&str>) -> HashSet<String> {
+        let matches = |uri: &str| prefix.is_none_or(|prefix| uri.starts_with(prefix));
+        let mut purged = HashSet::new();
+        let mut store = self.store();
+        let keys: Vec<String> = store.entries.iter()
+            .filter(|(_, entry)| matches(&entry.response.uri))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            if let Some(entry) = store.entries.remove(&key) {
+                store.memory -= entry.response.size();
+            }
+            purged.insert(key);
+        }
+        if prefix.is_none() {
+            store.variants.clear();
+        }
+
+        if let Some(entries) = self.settings.dir.as_ref().and_then(|dir| fs::read_dir(dir).ok()) {
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if prefix.is_none() && path.extension().is_some_and(|extension| extension == "vary") {
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+                let Some(response) = read_entry(&path) else { continue };
+                if matches(&response.uri) && fs::remove_file(&path).is_ok() {
+                    purged.insert(response.key);
+                }
+            }
+        }
+        purged
+    }
+}
+
+/// The key of the responses to `request` before their variants, None when
+/// the request can't be answered from the cache
+fn base_key(request: &Request) -> Option<String> {
+    if request.method != HttpMethod::GET || request.get_header(HeaderName::Authorization).is_some() {
+        return None;
+    }
+    let refused = request.headers.iter()
+        .filter(|header| header.name.to_string().eq_ignore_ascii_case("cache-control"))
+        .any(|header| {
+            let value = header.value.value.to_ascii_lowercase();
+            value.contains("no-store") || value.contains("no-cache")
+        });
+    if refused || crate::server::proxy::is_websocket_upgrade(request) {
+        return None;
+    }
+    let host = request.get_header(HeaderName::Host).map(|header| header.value.value.to_ascii_lowercase()).unwrap_or_default();
+    Some(format!("{} {}{}", request.method, host, request.uri))
+}
+
+/// `base_key` followed by the values `request` has for the `vary` headers
+fn variant_key(base_key: &str, vary: &[String], request: &Request) -> String {
+    let mut key = base_key.to_string();
+    for name in vary {
+        let value = request.headers.iter()
+            .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+            .map(|header| header.value.value.trim().to_string())
+            .unwrap_or_default();
+        key.push_str(&format!("\n{}: {}", name, value));
+    }
+    key
+}
+
+/// The values of the `name` headers among `headers`
+fn values<'a>(headers: &'a [(String, String)], name: &'a str) -> impl Iterator<Item = &'a str> {
+    headers.iter().filter(move |(own, _)| own.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+/// How long a response with the `Cache-Control` header values `directives`
+/// is kept, None when it must not be
+fn lifetime<'a>(directives: impl Iterator<Item = &'a str>, ttl: Duration) -> Option<Duration> {
+    let mut max_age = None;
+    let mut shared_max_age = None;
+    for directive in directives.flat_map(|value| value.split(',')) {
+        let directive = directive.trim().to_ascii_lowercase();
+        let (name, value) = directive.split_once('=').map_or((directive.as_str(), None), |(name, value)| (name, Some(value)));
+        let seconds = || value.and_then(|value| value.trim_matches('"').parse::<u64>().ok()).map(Duration::from_secs);
+        match name.trim() {
+            "no-store" | "no-cache" | "private" => return None,
+            "max-age" => max_age = seconds(),
+            "s-maxage" => shared_max_age = seconds(),
+            _ => {}
+        }
+    }
+    Some(shared_max_age.or(max_age).unwrap_or(ttl)).filter(|lifetime| !lifetime.is_zero())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    let name: String = Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    dir.join(name)
+}
+
+/// An entry file holds the response as a JSON line, then its body. The
+/// headers it varies on are listed in a `.vary` file named after `base_key`.
+fn read_entry(path: &Path) -> Option<CachedResponse> {
+    let bytes = fs::read(path).ok()?;
+    let end = bytes.iter().position(|&b| b == b'\n')?;
+    let mut response: CachedResponse = serde_json::from_slice(&bytes[..end]).ok()?;
+    response.body = bytes[end + 1..].to_vec();
+    Some(response)
+}
+
+fn write_entry(dir: &Path, response: &CachedResponse, base_key: &str, vary: &[String]) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let names = entry_path(dir, base_key).with_extension("vary");
+    if vary.is_empty() {
+        let _ = fs::remove_file(names);
+    } else {
+        fs::write(names, vary.join("\n"))?;
+    }
+    let path = entry_path(dir, &response.key);
+    // Renamed once complete, so readers never see half an entry
+    let partial = path.with_extension("part");
+    let mut file = fs::File::create(&partial)?;
+    file.write_all(serde_json::to_string(response)?.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.write_all(&response.body)?;
+    fs::rename(partial, path)
+}
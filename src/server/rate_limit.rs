@@ -0,0 +1,132 @@
+//! Request rate limiting
+//!
+//! Limiters are token buckets. Every key, being a client address, a header value
+//! or the whole route, starts with `burst + 1` tokens refilled at `rate` per
+//! second, and a request finding its bucket empty is answered `429` with a
+//! `Retry-After` header. Routes referencing the same named limiter share its buckets.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::http::request::Request;
+use crate::server::errors::HttpError;
+
+/// Number of buckets kept before the full ones are dropped
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// What requests are counted by
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitKey {
+    /// The client address
+    Ip,
+    /// The value of a request header, like an API key
+    Header(String),
+    /// Every request together
+    Global,
+}
+
+impl LimitKey {
+    /// Parses `ip`, `global` or `header:<name>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "ip" => Ok(LimitKey::Ip),
+            "global" => Ok(LimitKey::Global),
+            other => match other.strip_prefix("header:") {
+                Some(name) if !name.trim().is_empty() => Ok(LimitKey::Header(name.trim().to_string())),
+                _ => Err(format!("'{}' is not a limit key, expected 'ip', 'global' or 'header:<name>'", value)),
+            },
+        }
+    }
+
+    fn of(&self, request: &Request) -> String {
+        match self {
+            LimitKey::Ip => request.client_addr
+                .map(|ip| ip.to_canonical().to_string())
+                .unwrap_or_default(),
+            LimitKey::Header(name) => request.headers.iter()
+                .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+                .map(|header| header.value.value.clone())
+                .unwrap_or_default(),
+            LimitKey::Global => String::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    pub name: String,
+    /// Requests allowed per second
+    pub rate: f64,
+    /// Requests allowed above the rate in a burst
+    pub burst: u32,
+    pub key: LimitKey,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &str, rate: f64, burst: u32, key: LimitKey) -> Self {
+        RateLimiter { name: name.to_string(), rate, burst, key, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token for `request`, or returns the `429` to answer
+    pub fn check(&self, request: &Request) -> Result<(), HttpError> {
+        self.check_by(&self.key, request)
+    }
+
+    /// Like `check`, counting `request` by its client address whatever the
+    /// key, as requests not routed yet are
+    pub fn check_client(&self, request: &Request) -> Result<(), HttpError> {
+        self.check_by(&LimitKey::Ip, request)
+    }
+
+    fn check_by(&self, key: &LimitKey, request: &Request) -> Result<(), HttpError> {
+        self.try_acquire(&key.of(request), Instant::now()).map_err(|wait| HttpError::TooManyRequests {
+            message: format!("Rate limit '{}' exceeded", self.name),
+            retry_after: wait.as_secs_f64().ceil().max(1.0) as u64,
+        })
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long until one is available
+    pub fn try_acquire(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let capacity = self.burst as f64 + 1.0;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            let rate = self.rate;
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// Parses a rate like "10r/s", "30r/m" or "500r/h" into requests per second
+pub fn parse_rate(value: &str) -> Option<f64> {
+    let (count, unit) = value.trim().split_once('/')?;
+    let count: f64 = count.trim().trim_end_matches('r').parse().ok()?;
+    let per_seconds = match unit.trim() {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    (count > 0.0 && count.is_finite()).then_some(count / per_seconds)
+}
@@ -0,0 +1,84 @@
+//! URL rewriting
+//!
+//! Hosts may list `rewrite` rules, tried in order on the path of every request
+//! before its route is looked up. The first rule whose pattern matches replaces
+//! the path, `$1` or `${name}` in the replacement standing for the captured
+//! groups. With the `last` flag the rewritten path is then routed as if the
+//! client had asked for it, while `redirect` and `permanent` send the client
+//! there with a `302` or a `301`. The query string is kept, after the one of the
+//! replacement when it has its own.
+
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RewriteFlag {
+    /// Routes the rewritten path
+    Last,
+    /// Answers `302 Found`
+    Redirect,
+    /// Answers `301 Moved Permanently`
+    Permanent,
+}
+
+impl RewriteFlag {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "last" => Ok(RewriteFlag::Last),
+            "redirect" => Ok(RewriteFlag::Redirect),
+            "permanent" => Ok(RewriteFlag::Permanent),
+            _ => Err(format!("'{}' is not a rewrite flag, expected 'last', 'redirect' or 'permanent'", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub pattern: Regex,
+    pub replacement: String,
+    pub flag: RewriteFlag,
+}
+
+/// What a matching rule does with a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rewrite {
+    /// The request is routed to this URI instead
+    Internal(String),
+    /// The client is sent to this location
+    Redirect { location: String, permanent: bool },
+}
+
+impl RewriteRule {
+    /// Compiles a rule, `flag` defaulting to `last`
+    pub fn new(pattern: &str, replacement: &str, flag: Option<&str>) -> Result<Self, String> {
+        let pattern = Regex::new(pattern)
+            .map_err(|e| format!("pattern '{}' is not a valid regex: {}", pattern, e))?;
+        let flag = flag.map(RewriteFlag::parse).transpose()?.unwrap_or(RewriteFlag::Last);
+        Ok(RewriteRule { pattern, replacement: replacement.to_string(), flag })
+    }
+
+    /// Rewrites `uri` when the rule matches its path
+    pub fn apply(&self, uri: &str) -> Option<Rewrite> {
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (uri, None),
+        };
+        let captures = self.pattern.captures(path)?;
+        let mut target = String::new();
+        captures.expand(&self.replacement, &mut target);
+        if let Some(query) = query.filter(|query| !query.is_empty()) {
+            target.push(if target.contains('?') { '&' } else { '?' });
+            target.push_str(query);
+        }
+
+        Some(match self.flag {
+            RewriteFlag::Last => Rewrite::Internal(target),
+            RewriteFlag::Redirect => Rewrite::Redirect { location: target, permanent: false },
+            RewriteFlag::Permanent => Rewrite::Redirect { location: target, permanent: true },
+        })
+    }
+}
+
+/// Applies the first of `rules` matching `uri`
+pub fn rewrite(rules: &[RewriteRule], uri: &str) -> Option<Rewrite> {
+    rules.iter().find_map(|rule| rule.apply(uri))
+}
@@ -0,0 +1,108 @@
+//! Named groups of WebSocket connections
+//!
+//! A [`Rooms`] registry is shared by the handlers of one or more WebSocket
+//! routes. Connections join and leave rooms by name, and a message broadcast
+//! to a room is queued for each of its members. A member whose queue is full,
+//! its client being too slow, misses the message rather than holding the
+//! others back. Closed connections leave their rooms on their own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use crate::server::websocket::{Message, SendError, WebSocket, WebSocketSender};
+
+/// What became of a message broadcast to a room
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Delivery {
+    /// Members the message was queued for
+    pub sent: usize,
+    /// Members whose queue was full
+    pub dropped: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: Mutex<BTreeMap<String, HashMap<u64, WebSocketSender>>>,
+}
+
+impl Rooms {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Rooms::default())
+    }
+
+    pub fn join(&self, room: &str, socket: &WebSocket) {
+        let mut rooms = self.rooms.lock().unwrap_or_else(|e| e.into_inner());
+        rooms.entry(room.to_string()).or_default().insert(socket.id(), socket.sender());
+    }
+
+    pub fn leave(&self, room: &str, socket: &WebSocket) {
+        let mut rooms = self.rooms.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(members) = rooms.get_mut(room) {
+            members.remove(&socket.id());
+            if members.is_empty() {
+                rooms.remove(room);
+            }
+        }
+    }
+
+    /// Leaves every room, as a handler's `on_close` would
+    pub fn leave_all(&self, socket: &WebSocket) {
+        let mut rooms = self.rooms.lock().unwrap_or_else(|e| e.into_inner());
+        rooms.retain(|_, members| {
+            members.remove(&socket.id());
+            !members.is_empty()
+        });
+    }
+
+    /// Queues `message` for every member of `room`
+    pub fn broadcast(&self, room: &str, message: Message) -> Delivery {
+        self.send_to(room, message, None)
+    }
+
+    /// Queues `message` for every member of `room` but `sender`, like the
+    /// author of a chat message
+    pub fn broadcast_except(&self, room: &str, message: Message, sender: &WebSocket) -> Delivery {
+        self.send_to(room, message, Some(sender.id()))
+    }
+
+    /// Connections currently in `room`
+    pub fn members(&self, room: &str) -> usize {
+        let mut rooms = self.rooms.lock().unwrap_or_else(|e| e.into_inner());
+        forget_closed(&mut rooms);
+        rooms.get(room).map_or(0, HashMap::len)
+    }
+
+    /// The rooms with members, by name
+    pub fn names(&self) -> Vec<String> {
+        let mut rooms = self.rooms.lock().unwrap_or_else(|e| e.into_inner());
+        forget_closed(&mut rooms);
+        rooms.keys().cloned().collect()
+    }
+
+    fn send_to(&self, room: &str, message: Message, except: Option<u64>) -> Delivery {
+        let mut rooms = self.rooms.lock().unwrap_or_else(|e| e.into_inner());
+        let mut delivery = Delivery::default();
+        let Some(members) = rooms.get_mut(room) else { return delivery };
+        members.retain(|id, member| {
+            if Some(*id) == except {
+                return true;
+            }
+            match member.send(message.clone()) {
+                Ok(()) => delivery.sent += 1,
+                Err(SendError::Full) => delivery.dropped += 1,
+                Err(SendError::Closed) => return false,
+            }
+            true
+        });
+        if members.is_empty() {
+            rooms.remove(room);
+        }
+        delivery
+    }
+}
+
+fn forget_closed(rooms: &mut BTreeMap<String, HashMap<u64, WebSocketSender>>) {
+    rooms.retain(|_, members| {
+        members.retain(|_, member| !member.is_closed());
+        !members.is_empty()
+    });
+}
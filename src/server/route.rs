@@ -1,6 +1,16 @@
 use crate::server::static_files::ServerStaticFiles;
 use crate::server::cgi::CGIConfig;
+use crate::server::uploader::Uploader;
+use crate::server::access::AccessList;
+use crate::server::rate_limit::RateLimiter;
+use crate::server::proxy::Proxy;
+use crate::server::websocket::WebSocketEndpoint;
+use crate::server::handlers::handlers::RouteHandler;
+use crate::server::middleware::Middlewares;
 use crate::http::request::HttpMethod;
+use crate::http::event_stream::EventChannel;
+use crate::http::upgrade::Upgrade;
+use crate::http::header::Header;
 use std::collections::HashMap;
 //use regex::Regex;
 use std::sync::Arc;
@@ -75,7 +85,7 @@ impl RouteMatcher {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Route {
     pub path: String,
     pub methods: Vec<HttpMethod>,
@@ -86,10 +96,87 @@ pub struct Route {
     pub session_redirect: Option<String>,
     pub matcher: Option<RouteMatcher>,
     pub params: HashMap<String, String>,
+    /// Route-level override of the host's `client_max_body_size`
+    pub max_body_size: Option<usize>,
+    /// Route-level uploader, used instead of the host's one
+    pub uploader: Option<Uploader>,
+    /// Headers added to the route's responses, replacing the host's ones of the same name
+    pub headers: Vec<Header>,
+    /// Client addresses allowed to reach the route
+    pub access: Option<AccessList>,
+    /// Limiter throttling the route, shared with the other routes using it
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// Served by the admin API, only set by `Host::add_admin_api`
+    pub admin: bool,
+    /// Media types like "application/json" or "image/*" request bodies may
+    /// have, any when empty
+    pub content_types: Vec<String>,
+    /// Request headers CORS preflights are allowed to ask for, none besides
+    /// the ones browsers always allow when empty
+    pub cors_allowed_headers: Vec<String>,
+    /// Upstream server the route's requests are forwarded to, its paths included
+    pub proxy: Option<Arc<Proxy>>,
+    /// Handler taking the route's WebSocket connections once upgraded
+    pub websocket: Option<WebSocketEndpoint>,
+    /// Channel whose events the route streams to its clients
+    pub events: Option<Arc<EventChannel>>,
+    /// Protocol the route's connections switch to, their socket then handed over
+    pub upgrade: Option<Upgrade>,
+    /// User code answering the route's requests
+    pub handler: Option<RouteHandler>,
+    /// Run after the host's ones and the route's own checks
+    pub middlewares: Middlewares,
 }
 
 impl Route {
+    /// Whether the route answers `method`. Static files answer HEAD like
+    /// GET, without the body, and OPTIONS is answered by the host unless the
+    /// route is proxied.
     pub fn is_method_allowed(&self, method: &HttpMethod) -> bool {
         self.methods.contains(method)
+            || (*method == HttpMethod::HEAD && self.serves_files() && self.methods.contains(&HttpMethod::GET))
+            || (*method == HttpMethod::OPTIONS && self.proxy.is_none())
+    }
+
+    /// Whether the host answers the route's OPTIONS requests itself, before
+    /// any credentials or session are asked for
+    pub fn answers_options(&self) -> bool {
+        !self.methods.contains(&HttpMethod::OPTIONS) && self.proxy.is_none()
+    }
+
+    /// The methods the route answers, as listed by an `Allow` header
+    pub fn allowed_methods(&self) -> Vec<HttpMethod> {
+        let mut methods = self.methods.clone();
+        for implied in [HttpMethod::HEAD, HttpMethod::OPTIONS] {
+            if !methods.contains(&implied) && self.is_method_allowed(&implied) {
+                methods.push(implied);
+            }
+        }
+        methods
+    }
+
+    /// Whether the route's requests are answered with its static files
+    fn serves_files(&self) -> bool {
+        self.static_files.is_some() && self.cgi_config.is_none() && self.handler.is_none() && self.proxy.is_none()
     }   
+
+    /// Whether a body of `content_type` may be sent to the route, its parameters
+    /// like the charset ignored
+    pub fn accepts_content_type(&self, content_type: Option<&str>) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+        let Some(media_type) = content_type.and_then(|value| value.split(';').next()) else {
+            return false;
+        };
+        let media_type = media_type.trim().to_ascii_lowercase();
+        self.content_types.iter().any(|accepted| {
+            let accepted = accepted.trim().to_ascii_lowercase();
+            match accepted.strip_suffix("/*") {
+                Some("*") => true,
+                Some(prefix) => media_type.split_once('/').is_some_and(|(kind, _)| kind == prefix),
+                None => media_type == accepted,
+            }
+        })
+    }
 }
\ No newline at end of file
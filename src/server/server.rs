@@ -1,55 +1,174 @@
 use std::os::fd::AsRawFd;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Instant, Duration};
 use std::{collections::HashMap, os::unix::io::RawFd};
 use crate::http::{
+    body::Body,
+    body_stream::{encode_chunk, BodyStream, Chunk, LAST_CHUNK},
+    deferred::Deferred,
+    event_stream::Subscription,
     header::{Header, HeaderName},
-    request::Request,
+    request::{HttpMethod, Request},
+    response::Response,
+    upgrade::{Upgrade, Upgraded},
+    waker,
 };
 
 use crate::server::{
-    host::Host,
-    uploader::Uploader,
+    host::{Host, HostListener},
+    listen::ClientStream,
+    uploader::{Uploader, MultipartSpooler, SpooledParts},
     errors::ServerError,
-    connection::{Connection, ConnectionState},
-    logger::{Logger, LogLevel},
+    connection::{Connection, ConnectionState, DEFAULT_IDLE_TIMEOUT},
+    logger::{self, logger, AccessEntry, AuditEntry, Logger},
+    status::{self, ConnectionPhase},
+    ban,
+    notifier::Notifier,
+    proxy::{Exchange, Progress},
+    websocket::{self, Session},
 };
 
-use crate::server::stream::request_stream::unifiedReader::UnifiedReader;
-use crate::server::session::session::SessionMiddleware;
+use crate::server::stream::request_stream::{BodySink, RequestStream, unifiedReader::UnifiedReader};
+use crate::server::tls::HostStream;
+use crate::server::middleware::Context;
+use crate::server::hooks::RequestInfo;
+use crate::server::shutdown::{ServerHandle, Shutdown};
 use crate::server::errors::{HttpError, SessionError};
 
 use libc::{
     epoll_create1, epoll_ctl, epoll_event, epoll_wait, 
-    EPOLLET, EPOLLIN,
-    EPOLL_CTL_ADD, EPOLL_CTL_DEL,
+    EPOLLET, EPOLLIN, EPOLLOUT,
+    EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD,
+    SIGHUP, SIGINT, SIGTERM,
 };
 
 
 const EPOLL_EVENTS: u32 = (EPOLLIN | EPOLLET) as u32;
-const TIMEOUT_DURATION: Duration = Duration::from_secs(60);
 const MAX_EVENTS: usize = 1024;
+/// Longest wait for events, so periodic tasks run on an idle server
+const EPOLL_TIMEOUT_MS: i32 = 1000;
+/// Chunks taken from a streamed body per round, so that an endless body
+/// doesn't keep the loop from the other clients
+const CHUNKS_PER_ROUND: usize = 16;
+const UPLOAD_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Set by the SIGHUP handler, cleared when the event loop reloads the hosts
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_reload(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Set by the SIGTERM and SIGINT handlers, the event loop then shuts down
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// When the first server of the process was created
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Time since the first server of the process was created
+pub fn uptime() -> Duration {
+    STARTED.get_or_init(Instant::now).elapsed()
+}
+
+/// Builds the hosts of the current configuration, listeners not bound yet
+pub type Reloader = Box<dyn FnMut() -> Result<Vec<Host>, ServerError>>;
 
 pub struct Server {
     hosts: Vec<Host>,
     connections: HashMap<RawFd, Connection>,
+    /// Requests forwarded by proxy routes, by upstream socket
+    exchanges: HashMap<RawFd, Exchange>,
+    /// Failed forwarded requests waiting for their backoff to be sent again
+    retries: Vec<(Instant, Exchange)>,
+    /// Connections upgraded to a WebSocket served by their route
+    sockets: HashMap<RawFd, Session>,
+    /// Connections streaming the events of a channel
+    streams: HashMap<RawFd, Subscription>,
+    /// Connections waiting for the deferred response of their request
+    parked: HashMap<RawFd, Parked>,
+    /// Connections sending a body as it's produced
+    outputs: HashMap<RawFd, Output>,
     epoll_fd: RawFd,
+    /// Written to when an event is published or a deferred response given,
+    /// waking the loop to send it
+    waker_fd: RawFd,
     logger: Logger,
     uploader: Option<Uploader>,
-    session_middleware: SessionMiddleware,
+    last_upload_purge: Instant,
+    reloader: Option<Reloader>,
+    notifier: Option<Notifier>,
+    last_alert_check: Instant,
+    shutdown: Arc<Shutdown>,
+    /// How long connections may take to finish once a shutdown was asked for
+    shutdown_timeout: Duration,
+    /// Set once shutting down, until when connections may finish
+    draining: Option<Instant>,
+}
+
+/// A response whose body is sent as it's produced
+struct Output {
+    body: BodyStream,
+    /// Chunk the client didn't take all of yet
+    pending: Vec<u8>,
+    keep_alive: bool,
+    /// Whether the body is framed in chunks, rather than sent bare after its
+    /// `Content-Length`
+    chunked: bool,
+    /// Set once the last chunk is pending
+    ended: bool,
+    /// Set when the round ended with chunks left to take, the next one then
+    /// being due right away
+    ready: bool,
+}
+
+/// A request whose handler answered with a deferred response
+struct Parked {
+    deferred: Deferred,
+    request: Request,
+    host_name: String,
+    route_path: String,
+    /// Session cookies and the headers the route adds
+    headers: Vec<Header>,
+    keep_alive: bool,
+    started: Instant,
+    deadline: Instant,
 }
 
 impl Server {
     pub fn new(uploader: Option<Uploader>) -> Result<Self, ServerError> {
         let epoll_fd = Self::create_epoll()?;
-        let logger = Logger::new(LogLevel::DEBUG);
+        let waker_fd = Self::create_waker(epoll_fd)?;
+        let logger = logger();
+        STARTED.get_or_init(Instant::now);
 
         Ok(Server {
             hosts: Vec::new(),
             connections: HashMap::new(),
+            exchanges: HashMap::new(),
+            retries: Vec::new(),
+            sockets: HashMap::new(),
+            streams: HashMap::new(),
+            parked: HashMap::new(),
+            outputs: HashMap::new(),
             epoll_fd,
+            waker_fd,
             logger,
             uploader,
-            session_middleware: SessionMiddleware{},
+            last_upload_purge: Instant::now(),
+            reloader: None,
+            notifier: None,
+            last_alert_check: Instant::now(),
+            shutdown: Shutdown::new(waker_fd),
+            shutdown_timeout: SHUTDOWN_TIMEOUT,
+            draining: None,
         })
     }
 
@@ -62,35 +181,136 @@ impl Server {
 
         Ok(epoll_fd)
     }
+
+    fn create_waker(epoll_fd: RawFd) -> Result<RawFd, ServerError> {
+        let waker_fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if waker_fd < 0 {
+            return Err(ServerError::EpollError("Failed to create the event loop waker"));
+        }
+        let mut event = epoll_event { events: EPOLL_EVENTS, u64: waker_fd as u64 };
+        if unsafe { epoll_ctl(epoll_fd, EPOLL_CTL_ADD, waker_fd, &mut event) } < 0 {
+            unsafe { libc::close(waker_fd) };
+            return Err(ServerError::EpollError("Failed to add the event loop waker to epoll"));
+        }
+        waker::set(waker_fd);
+        Ok(waker_fd)
+    }
 }
 
 /// Host management implementation
 impl Server {
-    pub fn add_host(&mut self, host: Host) -> Result<(), ServerError> {
-        self.register_host_with_epoll(&host)?;    
+    /// Binds the ports of `host` and starts serving it
+    pub fn add_host(&mut self, mut host: Host) -> Result<(), ServerError> {
+        self.bind_host(&mut host, &mut Vec::new())?;
         self.hosts.push(host);
+        status::set_hosts(&self.hosts);
         Ok(())
     }
 
-    fn register_host_with_epoll(&self, host: &Host) -> Result<(), ServerError> {
+    /// Reloads the hosts on SIGHUP with the ones built by `reloader`
+    pub fn set_reloader(&mut self, reloader: Reloader) {
+        unsafe {
+            libc::signal(SIGHUP, request_reload as *const () as libc::sighandler_t);
+        }
+        self.reloader = Some(reloader);
+    }
 
-        for listener in &host.listeners {
-            let mut event = epoll_event {
-                events: EPOLL_EVENTS,
-                u64: listener.fd as u64
-            };
+    /// Shuts down gracefully on SIGTERM and SIGINT, as a handle would
+    pub fn stop_on_signals(&mut self) {
+        unsafe {
+            libc::signal(SIGTERM, request_stop as *const () as libc::sighandler_t);
+            libc::signal(SIGINT, request_stop as *const () as libc::sighandler_t);
+        }
+    }
 
-            unsafe {
-                if epoll_ctl(self.epoll_fd, EPOLL_CTL_ADD, listener.fd, &mut event) < 0 {
-                    return Err(ServerError::EpollError("Failed to add listener to epoll"));
-                }
+    /// Sends alerts about the hosts through `notifier`
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    /// Replaces the hosts with `hosts`. Listeners whose address and port are still
+    /// used are kept, so only changed addresses are rebound, and sessions survive
+    /// for the hosts keeping their name.
+    pub fn reload(&mut self, hosts: Vec<Host>) {
+        let mut previous = std::mem::take(&mut self.hosts);
+        let mut available: Vec<HostListener> = previous.iter_mut()
+            .flat_map(|host| host.listeners.drain(..))
+            .collect();
+
+        for mut host in hosts {
+            let old_manager = previous.iter()
+                .find(|old| old.server_name == host.server_name)
+                .and_then(|old| old.session_manager.as_ref());
+            if let (Some(old_manager), Some(manager)) = (old_manager, host.session_manager.as_mut()) {
+                manager.store = old_manager.store.clone();
+                manager.remember_store = old_manager.remember_store.clone();
+            }
+            if let Err(e) = self.bind_host(&mut host, &mut available) {
+                self.logger.error(&format!("Host {} can't listen: {}", host.server_name, e), "Server");
             }
+            self.hosts.push(host);
+        }
+
+        for listener in available {
+            self.unregister_listener(&listener);
+            self.logger.info(&format!("Stopped listening on {}", listener.addr), "Server");
         }
+        status::set_hosts(&self.hosts);
+    }
+
+    /// Gives `host` a listener on each of its addresses, taken from `available`
+    /// when one is already bound to the same address
+    fn bind_host(&self, host: &mut Host, available: &mut Vec<HostListener>) -> Result<(), ServerError> {
+        for addr in host.listen.clone() {
+            if host.listeners.iter().any(|listener| listener.addr == addr) {
+                continue;
+            }
+            if let Some(index) = available.iter().position(|listener| listener.addr == addr) {
+                host.listeners.push(available.swap_remove(index));
+                continue;
+            }
 
+            let listener = HostListener::new(addr.clone())
+                .map_err(|e| ServerError::ConnectionError(format!("Failed to bind {}: {}", addr, e)))?;
+            self.register_listener(&listener)?;
+            self.logger.info(&format!("Listening on {}", listener.addr), "Server");
+            host.listeners.push(listener);
+        }
         Ok(())
     }
 
+    fn register_listener(&self, listener: &HostListener) -> Result<(), ServerError> {
+        let mut event = epoll_event {
+            events: EPOLL_EVENTS,
+            u64: listener.fd as u64
+        };
+
+        unsafe {
+            if epoll_ctl(self.epoll_fd, EPOLL_CTL_ADD, listener.fd, &mut event) < 0 {
+                return Err(ServerError::EpollError("Failed to add listener to epoll"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unregister_listener(&self, listener: &HostListener) {
+        unsafe {
+            if epoll_ctl(self.epoll_fd, EPOLL_CTL_DEL, listener.fd, std::ptr::null_mut()) < 0 {
+                self.logger.error(&format!("Failed to remove listener {} from epoll", listener.fd), "Server");
+            }
+        }
+    }
+
+    /// Accepts every connection waiting, the listener's events being
+    /// edge-triggered: one left in the backlog would wait for another client
     fn handle_new_connection(&mut self, fd: RawFd) -> Result<(), ServerError> {
+        while self.accept_next(fd)? {}
+        Ok(())
+    }
+
+    /// Accepts the next connection, returning false once there is none
+    fn accept_next(&mut self, fd: RawFd) -> Result<bool, ServerError> {
         // Find host
         let host = self.find_host_by_fd(fd)
             .ok_or_else(|| {
@@ -107,14 +327,20 @@ impl Server {
 
             
             // Accept connection
-        let stream = match listener.accept_connection() {
-            Ok(s) => s,
+        let (stream, peer_addr) = match listener.accept_connection() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
             Err(e) => {
                 self.logger.error(&format!("Failed to accept connection: {}", e), "Server");
                 return Err(ServerError::ConnectionError(e.to_string()));
             }
         };
 
+        // Banned clients are dropped before anything is read
+        if peer_addr.is_some_and(|addr| ban::is_banned(addr.ip())) {
+            return Ok(true);
+        }
+
         // Set non-blocking
         if let Err(e) = stream.set_nonblocking(true) {
             self.logger.error(&format!("Failed to set non-blocking: {}", e), "Server");
@@ -134,20 +360,36 @@ impl Server {
             }
         }
 
-        let mut reader = UnifiedReader::new(stream);
+        let secure = Arc::new(AtomicBool::new(false));
+        let reader: Box<dyn RequestStream> = match (stream, &host.tls) {
+            (ClientStream::Tcp(stream), Some(config)) => {
+                let stream = HostStream::new(stream, config.clone(), secure.clone());
+                Box::new(configure_reader(UnifiedReader::new(stream), host, self.uploader.as_ref()))
+            }
+            (ClientStream::Unix(stream), Some(_)) => {
+                // Unix sockets never leave the machine, so they count as secure
+                secure.store(true, Ordering::SeqCst);
+                Box::new(configure_reader(UnifiedReader::new(stream), host, self.uploader.as_ref()))
+            }
+            (stream, None) => Box::new(configure_reader(UnifiedReader::new(stream), host, self.uploader.as_ref())),
+        };
 
-        if let Some(size) = host.max_request_size {
-            reader.set_max_request_size(size);
+        let idle_timeout = host.request_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT);
+        let mut connection = Connection::new(client_fd, host.server_name.clone(), reader)
+            .with_secure_flag(secure)
+            .with_keep_alive(idle_timeout, host.keep_alive_requests);
+        if let Some(peer_addr) = peer_addr {
+            connection = connection.with_peer_addr(peer_addr);
         }
-
-        let connection = Connection::new(client_fd, host.server_name.clone(), Box::new(reader));
-        self.logger.debug(&format!("New connection on host: {} - {}", host.server_name, listener.port), "server");
+        self.logger.debug(&format!("New connection on host: {} - {}", host.server_name, listener.addr), "server");
+        status::connection_opened(client_fd, peer_addr, &host.server_name);
         self.connections.insert(client_fd, connection);
         
-        Ok(())
+        Ok(true)
     }
 
     fn handle_connection_event(&mut self, fd: RawFd, events: u32, host_index: usize) -> Result<(), ServerError> {
+        let draining = self.draining;
         let connection = self.connections.get_mut(&fd)
             .ok_or(ServerError::ConnectionError("Connection not found".to_string()))?;
         let mut should_close = false;
@@ -156,64 +398,207 @@ impl Server {
             Ok(state) => {
                 match state {
                     ConnectionState::Complete(request) => {
+                        let mut request = *request;
+                        // Parts spooled to disk don't outlive the request, refused or not
+                        let _spooled = SpooledParts::of(&request);
+                        // Banned while the connection was kept alive
+                        if request.client_addr.is_some_and(ban::is_banned) {
+                            return self.close_connection(fd);
+                        }
+                        status::connection_phase(fd, ConnectionPhase::Writing);
+                        let started = Instant::now();
                         let host = &mut self.hosts[host_index];
-                        if let Some(route) = host.get_route(&request.uri).cloned() {
-                            if let Some(session_manager) = host.session_manager.as_mut() {
-                                match self.session_middleware.process(&request, &route, session_manager) {
-                                    Ok(session) => {
-                                        if let Some(s) = session {
-                                        }
-                                    },
-                                    Err(e) => {
-                                        if let Err(e) = connection.send_response(e.to_response().to_string()) {
-                                            if e.kind() != std::io::ErrorKind::WouldBlock {
-                                                self.logger.error(&format!("Failed to send response: {}", e), "Server");
-                                                should_close = true;
-                                            }
+                        if host.tls.is_some() && !request.secure {
+                            let mut response = host.plain_http_response(&request);
+                            response.headers.push(connection_header(false));
+                            let info = RequestInfo::new(&request, &host.server_name, None, started);
+                            host.hooks.request(&info);
+                            host.hooks.response(&info, &mut response);
+                            log_access(&self.logger, &request, &host.server_name, &response);
+                            let _ = connection.send(&response);
+                            return self.close_connection(fd);
+                        }
+                        let answered = host.throttle(&request).or_else(|| host.rewrite(&mut request));
+                        let route = match answered {
+                            Some(_) => None,
+                            None => host.get_route(&request.uri).cloned(),
+                        };
+                        host.hooks.request(&RequestInfo::new(&request, &host.server_name, route.as_ref().map(|route| route.path.as_str()), started));
+                        if let Some(mut response) = answered {
+                            response.headers.push(connection_header(connection.keeps_alive_after(&request)));
+                            host.hooks.response(&RequestInfo::new(&request, &host.server_name, None, started), &mut response);
+                            log_access(&self.logger, &request, &host.server_name, &response);
+                            if let Err(e) = connection.send(&response) {
+                                if e.kind() != std::io::ErrorKind::WouldBlock {
+                                    self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                                    return self.close_connection(fd);
+                                }
+                            }
+                        } else if let Some(route) = route {
+                            let session = host.request_session(&request);
+                            let mut context = Context::default();
+                            let mut admitted = host.before(&mut request, &route, &mut context);
+                            if let (Ok(None), Some(proxy)) = (&admitted, &route.proxy) {
+                                let keep_alive = connection.keeps_alive_after(&request);
+                                if let Some(cached) = proxy.cache.as_ref().and_then(|cache| cache.lookup(&request)) {
+                                    let mut headers = host.added_headers(&route);
+                                    headers.extend(context.headers);
+                                    let mut response = cached.to_response(headers);
+                                    response.headers.push(connection_header(keep_alive));
+                                    host.hooks.response(&RequestInfo::new(&request, &host.server_name, Some(&route.path), started), &mut response);
+                                    let Some(Body::Stream(body)) = response.body.clone() else {
+                                        return self.close_connection(fd);
+                                    };
+                                    let status = response.status_code.clone() as u16;
+                                    status::record_route(&host.server_name, &route.path, status, started.elapsed());
+                                    record_access(&self.logger, AccessEntry {
+                                        client: request.client_addr,
+                                        host: &host.server_name,
+                                        method: request.method.to_string(),
+                                        uri: &request.uri,
+                                        status,
+                                        bytes: cached.body.len(),
+                                    });
+                                    // The body, up to the largest entry, is sent as the client takes it
+                                    if let Err(e) = connection.send(&response) {
+                                        self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                                        return self.close_connection(fd);
+                                    }
+                                    return self.start_output(fd, body, keep_alive, false);
+                                }
+                                // The response comes once the upstream answered
+                                match Exchange::start(proxy, &route.path, request.clone()) {
+                                    Ok(exchange) => {
+                                        let exchange = exchange
+                                            .for_client(fd, keep_alive)
+                                            .with_headers(host.added_headers(&route))
+                                            .with_cookies(context.headers);
+                                        return self.start_exchange(exchange);
+                                    }
+                                    Err(error) => admitted = Err(error.into()),
+                                }
+                            }
+                            if let (Ok(None), Some(endpoint)) = (&admitted, &route.websocket) {
+                                match websocket::handshake(&request) {
+                                    Ok(mut response) => {
+                                        response.headers.extend(context.headers);
+                                        response.headers.extend(host.added_headers(&route));
+                                        host.hooks.response(&RequestInfo::new(&request, &host.server_name, Some(&route.path), started), &mut response);
+                                        status::record_route(&host.server_name, &route.path, 101, started.elapsed());
+                                        log_access(&self.logger, &request, &host.server_name, &response);
+                                        if let Err(e) = connection.send(&response) {
+                                            self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                                            return self.close_connection(fd);
                                         }
-                                        return Ok(());
+                                        status::connection_phase(fd, ConnectionPhase::WebSocket);
+                                        let session = Session::open(endpoint, request);
+                                        self.logger.info(&format!("WebSocket {} opened on {}", session.id(), route.path), "WebSocket");
+                                        self.sockets.insert(fd, session);
+                                        // Frames may wait for a slow client
+                                        self.watch_client(fd, true);
+                                        return self.handle_socket_event(fd);
                                     }
+                                    Err(error) => admitted = Err(error.into()),
                                 }
                             }
-
-                            match host.route_request(&request, &route, self.uploader.clone()) {
+                            let result = match admitted {
+                                Ok(Some(response)) => Ok(response),
+                                Ok(None) => host.dispatch(&request, &route, self.uploader.clone()),
+                                Err(error) => Err(error),
+                            };
+                            match result {
                                 Ok(mut response) => {
-                                    let connection_header = if connection.keep_alive && want_keep_alive(request.clone()) {
-                                        "keep-alive"
-                                    } else {
-                                        "close"
-                                    };
-                                    response.headers.extend(vec![
-                                        Header::from_str("Connection", connection_header),
-                                        Header::from_str("Access-Control-Allow-Origin", "*"),
-                                        Header::from_str("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS"),
-                                        Header::from_str("Access-Control-Allow-Headers", "Content-Type"),
-                                    ]);
-
+                                    host.after(&request, &route, &context, &mut response);
+                                    host.audit(&request, session.as_deref(), &response);
+                                    // The body lasts as long as the connection
+                                    if let Some(Body::EventStream(stream)) = &response.body {
+                                        let subscription = stream.clone().subscribe();
+                                        response.headers.extend(host.added_headers(&route));
+                                        host.hooks.response(&RequestInfo::new(&request, &host.server_name, Some(&route.path), started), &mut response);
+                                        status::record_route(&host.server_name, &route.path, response.status_code.clone() as u16, started.elapsed());
+                                        if let Err(e) = connection.send(&response) {
+                                            self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                                            return self.close_connection(fd);
+                                        }
+                                        status::connection_phase(fd, ConnectionPhase::EventStream);
+                                        self.streams.insert(fd, subscription);
+                                        self.watch_client(fd, true);
+                                        return self.handle_stream_event(fd);
+                                    }
+                                    // The connection leaves the server once switched
+                                    if let Some(Body::Upgrade(upgrade)) = &response.body {
+                                        let upgrade = upgrade.clone();
+                                        response.headers.extend(host.added_headers(&route));
+                                        host.hooks.response(&RequestInfo::new(&request, &host.server_name, Some(&route.path), started), &mut response);
+                                        status::record_route(&host.server_name, &route.path, response.status_code.clone() as u16, started.elapsed());
+                                        if let Err(e) = connection.send(&response) {
+                                            self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                                            return self.close_connection(fd);
+                                        }
+                                        return self.hand_over(fd, request, upgrade);
+                                    }
+                                    // The head is sent right away, the chunks as they come
+                                    if let Some(Body::Stream(body)) = &response.body {
+                                        let body = body.clone();
+                                        let chunked = chunks_for(&request, &mut response);
+                                        let keep_alive = chunked && connection.keeps_alive_after(&request);
+                                        response.headers.push(connection_header(keep_alive));
+                                        response.headers.extend(host.added_headers(&route));
+                                        host.hooks.response(&RequestInfo::new(&request, &host.server_name, Some(&route.path), started), &mut response);
+                                        status::record_route(&host.server_name, &route.path, response.status_code.clone() as u16, started.elapsed());
+                                        if let Err(e) = connection.send(&response) {
+                                            self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                                            return self.close_connection(fd);
+                                        }
+                                        return self.start_output(fd, body, keep_alive, chunked);
+                                    }
+                                    // Sent once given, the connection waiting meanwhile
+                                    if let Some(Body::Deferred(deferred)) = &response.body {
+                                        let deferred = deferred.clone();
+                                        let mut headers = response.headers;
+                                        headers.extend(host.added_headers(&route));
+                                        status::connection_phase(fd, ConnectionPhase::Parked);
+                                        self.parked.insert(fd, Parked {
+                                            deadline: started + deferred.timeout,
+                                            deferred,
+                                            keep_alive: connection.keeps_alive_after(&request),
+                                            request,
+                                            host_name: host.server_name.clone(),
+                                            route_path: route.path.clone(),
+                                            headers,
+                                            started,
+                                        });
+                                        return self.check_parked();
+                                    }
+                                    response.headers.push(connection_header(connection.keeps_alive_after(&request)));
+                                    response.headers.extend(host.added_headers(&route));
+                                    host.hooks.response(&RequestInfo::new(&request, &host.server_name, Some(&route.path), started), &mut response);
+                                    status::record_route(&host.server_name, &route.path, response.status_code.clone() as u16, started.elapsed());
 
-                                    if let Err(e) = connection.send_response(response.clone().to_string()) {
+                                    if let Err(e) = connection.send(&response) {
                                         if e.kind() != std::io::ErrorKind::WouldBlock {
                                             self.logger.error(&format!("Failed to send response: {}", e), "Server");
                                             should_close = true;
                                         }
                                     }
 
-                                    let message = format!("{} - {} - {}", 
-                                        request.method,
-                                        &request.uri, 
-                                        response.status_code.as_str()
-                                    );
-                                    self.logger.info(&message, "Server");
-
                                     // Reset connection state for next request on the same connection
                                     if connection.keep_alive && !should_close {
                                         connection.reset();
                                     }
                                 },
                                 Err(error) => {
-                                    self.logger.error(&error.to_string(), "Server");
-                                    let response = HttpError::new(error).to_response(route.static_files.clone().as_mut());
-                                    if let Err(e) = connection.send_response(response.to_string()) {
+                                    host.logger.error(&error.to_string(), "Server");
+                                    let info = RequestInfo::new(&request, &host.server_name, Some(&route.path), started);
+                                    host.hooks.error(&info, &error);
+                                    let mut response = HttpError::new(error).to_response_for(&request, route.static_files.clone().as_mut());
+                                    response.headers.push(connection_header(connection.keeps_alive_after(&request)));
+                                    host.after(&request, &route, &context, &mut response);
+                                    response.headers.extend(host.added_headers(&route));
+                                    host.hooks.response(&info, &mut response);
+                                    host.audit(&request, session.as_deref(), &response);
+                                    status::record_route(&host.server_name, &route.path, response.status_code.clone() as u16, started.elapsed());
+                                    if let Err(e) = connection.send(&response) {
                                         if e.kind() != std::io::ErrorKind::WouldBlock {
                                             self.logger.error(&format!("Failed to send response: {}", e), "Server");
                                             should_close = true;
@@ -230,8 +615,12 @@ impl Server {
                                 None
                             };
 
-                            let response = HttpError::not_found(error_page);
-                            if let Err(e) = connection.send_response(response.to_string()) {
+                            // API clients get JSON rather than the host's page
+                            let mut response = HttpError::not_found_for(&request, error_page);
+                            response.headers.push(connection_header(connection.keeps_alive_after(&request)));
+                            host.hooks.response(&RequestInfo::new(&request, &host.server_name, None, started), &mut response);
+                            log_access(&self.logger, &request, &host.server_name, &response);
+                            if let Err(e) = connection.send(&response) {
                                 if e.kind() != std::io::ErrorKind::WouldBlock && 
                                 e.kind() != std::io::ErrorKind::ConnectionReset && 
                                 e.kind() != std::io::ErrorKind::BrokenPipe {
@@ -239,15 +628,57 @@ impl Server {
                                  should_close = true;
                              }
                             }
-                            self.logger.warn(&format!("Route not found: {}", request.uri), "Server");
+                            host.logger.warn(&format!("Route not found: {}", request.uri), "Server");
                         }
                         connection.start_time = Instant::now();
-                        connection.keep_alive = want_keep_alive(request);
+                        connection.keep_alive = connection.keeps_alive_after(&request) && draining.is_none();
                         should_close = !connection.keep_alive;
+                        status::connection_phase(fd, ConnectionPhase::KeepAlive);
                     },
                     ConnectionState::AwaitingRequest => {},
+                    ConnectionState::Invalid(error) => {
+                        self.logger.warn(&format!("Bad request: {}", error), "Server");
+                        if let Some(peer_addr) = connection.peer_addr {
+                            ban::record_failure(peer_addr.ip(), "malformed request");
+                        }
+                        // Nothing of the request can be trusted, the host's page is the
+                        // answer, what was sent only shown in the log
+                        let page = self.hosts[host_index].error_pages.as_ref()
+                            .and_then(|pages| pages.custom_pages.get("400"));
+                        let bad_request = HttpError::BadRequest("Malformed request".to_string());
+                        let mut response = bad_request.page_response(page.map(String::as_str));
+                        response.headers.push(connection_header(false));
+                        let _ = connection.send(&response);
+                        should_close = true;
+                    }
+                    ConnectionState::UnsupportedVersion(error) => {
+                        self.logger.warn(&format!("Bad request: {}", error), "Server");
+                        let mut response = HttpError::HttpVersionNotSupported(error).to_response(None);
+                        response.headers.push(connection_header(false));
+                        let _ = connection.send(&response);
+                        should_close = true;
+                    }
+                    ConnectionState::HeadersTooLarge(error) => {
+                        self.logger.warn(&format!("Bad request: {}", error), "Server");
+                        let mut response = HttpError::RequestHeaderFieldsTooLarge(error).to_response(None);
+                        response.headers.push(connection_header(false));
+                        let _ = connection.send(&response);
+                        should_close = true;
+                    }
+                    ConnectionState::BodyTooLarge(error) => {
+                        self.logger.warn(&format!("Bad request: {}", error), "Server");
+                        let mut response = HttpError::PayloadTooLarge(error).to_response(None);
+                        response.headers.push(connection_header(false));
+                        let _ = connection.send(&response);
+                        should_close = true;
+                    }
                     ConnectionState::Error(error) => {
-                        self.logger.error(&error, "Server");
+                        // Kept-alive clients leave once done, between two requests
+                        if connection.is_idle() {
+                            self.logger.debug(&format!("Client left: {}", error), "Server");
+                        } else {
+                            self.logger.error(&error, "Server");
+                        }
                         should_close = true;
                     }
                 }
@@ -267,7 +698,435 @@ impl Server {
         Ok(())
     }
 
+    /// Watches the upstream socket of `exchange` until its response was sent
+    fn start_exchange(&mut self, exchange: Exchange) -> Result<(), ServerError> {
+        let upstream_fd = exchange.fd();
+        let mut event = epoll_event {
+            events: (EPOLLIN | EPOLLOUT | EPOLLET) as u32,
+            u64: upstream_fd as u64
+        };
+
+        unsafe {
+            if epoll_ctl(self.epoll_fd, EPOLL_CTL_ADD, upstream_fd, &mut event) < 0 {
+                self.logger.error(&format!("Failed to add upstream {} to epoll", upstream_fd), "Server");
+                let progress = Progress::Failed(HttpError::BadGateway("Cannot watch the upstream connection".to_string()));
+                return self.finish_exchange(exchange, progress);
+            }
+        }
+        // The response may come faster than the client takes it
+        self.watch_client(exchange.client_fd, true);
+        self.exchanges.insert(upstream_fd, exchange);
+        Ok(())
+    }
+
+    /// Whether the client is also watched for room to send more of a response
+    fn watch_client(&self, client_fd: RawFd, writable: bool) {
+        let mut event = epoll_event {
+            events: if writable { (EPOLLIN | EPOLLOUT | EPOLLET) as u32 } else { EPOLL_EVENTS },
+            u64: client_fd as u64
+        };
+        unsafe {
+            if epoll_ctl(self.epoll_fd, EPOLL_CTL_MOD, client_fd, &mut event) < 0 {
+                self.logger.error(&format!("Failed to change the events of client {}", client_fd), "Server");
+            }
+        }
+    }
+
+    fn handle_upstream_event(&mut self, upstream_fd: RawFd) -> Result<(), ServerError> {
+        let Some(mut exchange) = self.exchanges.remove(&upstream_fd) else { return Ok(()) };
+        let Some(connection) = self.connections.get_mut(&exchange.client_fd) else {
+            self.unregister_upstream(upstream_fd);
+            return Ok(());
+        };
+
+        let progress = match exchange.advance(connection) {
+            // Frames the client sent along with the handshake wouldn't raise another event
+            Progress::Pending if exchange.is_tunnel() => exchange.relay_from_client(connection),
+            progress => progress,
+        };
+        match progress {
+            Progress::Pending => {
+                self.exchanges.insert(upstream_fd, exchange);
+                Ok(())
+            }
+            progress => {
+                self.unregister_upstream(upstream_fd);
+                self.finish_exchange(exchange, progress)
+            }
+        }
+    }
+
+    /// Sends again the failed requests whose backoff is over
+    fn start_retries(&mut self) {
+        let now = Instant::now();
+        let (due, waiting) = std::mem::take(&mut self.retries).into_iter().partition(|(at, _)| *at <= now);
+        self.retries = waiting;
+        for (_, exchange) in due {
+            let result = match exchange.retry() {
+                Ok(next) => self.start_exchange(next),
+                Err(error) => self.finish_exchange(exchange, Progress::Failed(error)),
+            };
+            if let Err(e) = result {
+                self.logger.error(&format!("Retry error: {:?}", e), "Server");
+            }
+        }
+    }
+
+    /// Longest wait for events, shortened for the next request to send again
+    /// and the next deferred response to time out
+    fn epoll_timeout(&self) -> i32 {
+        if self.outputs.values().any(|output| output.ready) {
+            return 0;
+        }
+        let now = Instant::now();
+        self.retries.iter().map(|(at, _)| *at)
+            .chain(self.parked.values().map(|parked| parked.deadline))
+            .chain(self.draining)
+            .map(|at| at.saturating_duration_since(now).as_millis().min(EPOLL_TIMEOUT_MS as u128) as i32)
+            .min()
+            .unwrap_or(EPOLL_TIMEOUT_MS)
+    }
+
+    /// Whether the request of `client_fd` was forwarded and not answered yet
+    fn is_waiting_for_upstream(&self, client_fd: RawFd) -> bool {
+        self.exchanges.values().chain(self.retries.iter().map(|(_, exchange)| exchange))
+            .any(|exchange| exchange.client_fd == client_fd)
+    }
+
+    /// The upstream socket of the request `client_fd` is waiting for
+    fn exchange_of_client(&self, client_fd: RawFd) -> Option<RawFd> {
+        self.exchanges.iter()
+            .find(|(_, exchange)| exchange.client_fd == client_fd)
+            .map(|(upstream_fd, _)| *upstream_fd)
+    }
+
+    /// Passes what a WebSocket client sent to its handler, and sends it the
+    /// frames waiting for it
+    fn handle_socket_event(&mut self, client_fd: RawFd) -> Result<(), ServerError> {
+        let (Some(session), Some(connection)) = (self.sockets.get_mut(&client_fd), self.connections.get_mut(&client_fd)) else {
+            return Ok(());
+        };
+        match session.ready(connection) {
+            Ok(()) if !session.is_closed() => Ok(()),
+            Ok(()) => self.close_connection(client_fd),
+            Err(e) => {
+                self.logger.warn(&format!("WebSocket {} failed: {}", session.id(), e), "WebSocket");
+                self.close_connection(client_fd)
+            }
+        }
+    }
+
+    /// Sends an event stream client the events published since, and notices
+    /// when it leaves
+    fn handle_stream_event(&mut self, client_fd: RawFd) -> Result<(), ServerError> {
+        let (Some(subscription), Some(connection)) = (self.streams.get_mut(&client_fd), self.connections.get_mut(&client_fd)) else {
+            return Ok(());
+        };
+        // The client sends nothing more, reading only tells when it's gone
+        let mut buffer = [0; 512];
+        let gone = loop {
+            match connection.reader.read_raw(&mut buffer) {
+                Ok(0) => break true,
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break false,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break true,
+            }
+        };
+        if gone {
+            return self.close_connection(client_fd);
+        }
+        if let Err(e) = send_events(subscription, connection) {
+            self.logger.warn(&format!("Event stream of client {} ended: {}", client_fd, e), "Server");
+            return self.close_connection(client_fd);
+        }
+        Ok(())
+    }
+
+    /// Sends the event streams their new events and heartbeats
+    fn check_streams(&mut self) -> Result<(), ServerError> {
+        let mut ended = Vec::new();
+        for (client_fd, subscription) in self.streams.iter_mut() {
+            let Some(connection) = self.connections.get_mut(client_fd) else { continue };
+            if let Err(e) = send_events(subscription, connection) {
+                self.logger.warn(&format!("Event stream of client {} ended: {}", client_fd, e), "Server");
+                ended.push(*client_fd);
+            }
+        }
+        for client_fd in ended {
+            self.close_connection(client_fd)?;
+        }
+        Ok(())
+    }
+
+    /// Lets go of a connection switched to the protocol of `upgrade`, its
+    /// socket now owned by the upgrade's handler
+    fn hand_over(&mut self, client_fd: RawFd, request: Request, upgrade: Upgrade) -> Result<(), ServerError> {
+        unsafe {
+            if epoll_ctl(self.epoll_fd, EPOLL_CTL_DEL, client_fd, std::ptr::null_mut()) < 0 {
+                self.logger.error(&format!("Failed to remove client {} from epoll", client_fd), "Server");
+            }
+        }
+        status::connection_closed(client_fd);
+        let Some(connection) = self.connections.remove(&client_fd) else {
+            return Ok(());
+        };
+        self.logger.info(&format!(
+            "Connection upgraded to {} - Host: {} Client fd: {}",
+            upgrade.protocol, connection.host_name, client_fd
+        ), "Server");
+        match Upgraded::new(request, connection.reader.into_socket(), client_fd) {
+            Ok(upgraded) => upgrade.handler.on_upgrade(upgraded),
+            // Dropping the socket closes the connection
+            Err(e) => self.logger.error(&format!("Failed to hand over client {}: {}", client_fd, e), "Server"),
+        }
+        Ok(())
+    }
+
+    /// Notices when a parked client leaves, its request then abandoned
+    fn handle_parked_event(&mut self, client_fd: RawFd) -> Result<(), ServerError> {
+        if has_left(client_fd) {
+            return self.close_connection(client_fd);
+        }
+        Ok(())
+    }
+
+    /// Sends the parked clients the responses given since, or the timeout
+    /// response of the ones that waited too long
+    fn check_parked(&mut self) -> Result<(), ServerError> {
+        let now = Instant::now();
+        let ready: Vec<(RawFd, Response)> = self.parked.iter()
+            .filter_map(|(client_fd, parked)| {
+                let response = parked.deferred.take()
+                    .or_else(|| (now >= parked.deadline).then(|| parked.deferred.expire()).flatten())?;
+                Some((*client_fd, response))
+            })
+            .collect();
+        for (client_fd, mut response) in ready {
+            let Some(parked) = self.parked.remove(&client_fd) else { continue };
+            let Some(connection) = self.connections.get_mut(&client_fd) else { continue };
+            let chunked = chunks_for(&parked.request, &mut response);
+            let keep_alive = parked.keep_alive && (chunked || !matches!(response.body, Some(Body::Stream(_))));
+            response.headers.push(connection_header(keep_alive));
+            response.headers.extend(parked.headers);
+            if let Some(host) = self.hosts.iter().find(|host| host.server_name == parked.host_name) {
+                let info = RequestInfo::new(&parked.request, &parked.host_name, Some(&parked.route_path), parked.started);
+                host.hooks.response(&info, &mut response);
+            }
+            status::record_route(&parked.host_name, &parked.route_path, response.status_code.clone() as u16, parked.started.elapsed());
+            log_access(&self.logger, &parked.request, &parked.host_name, &response);
+            let body = match &response.body {
+                Some(Body::Stream(body)) => Some(body.clone()),
+                _ => None,
+            };
+            if let Err(e) = connection.send(&response) {
+                self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                self.close_connection(client_fd)?;
+                continue;
+            }
+            if let Some(body) = body {
+                self.start_output(client_fd, body, keep_alive, chunked)?;
+                continue;
+            }
+            if !keep_alive {
+                self.close_connection(client_fd)?;
+                continue;
+            }
+            connection.reset();
+            status::connection_phase(client_fd, ConnectionPhase::KeepAlive);
+        }
+        Ok(())
+    }
+
+    /// Sends the body of a response whose head was sent, as it's produced
+    fn start_output(&mut self, client_fd: RawFd, body: BodyStream, keep_alive: bool, chunked: bool) -> Result<(), ServerError> {
+        status::connection_phase(client_fd, ConnectionPhase::Writing);
+        self.outputs.insert(client_fd, Output { body, pending: Vec::new(), keep_alive, chunked, ended: false, ready: false });
+        // Chunks may wait for a slow client
+        self.watch_client(client_fd, true);
+        self.handle_output_event(client_fd)
+    }
+
+    /// Sends a streamed body the chunks produced since, and notices when its
+    /// client leaves
+    fn handle_output_event(&mut self, client_fd: RawFd) -> Result<(), ServerError> {
+        if has_left(client_fd) {
+            return self.close_connection(client_fd);
+        }
+        let (Some(output), Some(connection)) = (self.outputs.get_mut(&client_fd), self.connections.get_mut(&client_fd)) else {
+            return Ok(());
+        };
+        let sent = send_output(output, connection);
+        self.finish_output(client_fd, sent)
+    }
+
+    /// Sends the streamed bodies the chunks produced since
+    fn check_outputs(&mut self) -> Result<(), ServerError> {
+        let mut progress = Vec::new();
+        for (client_fd, output) in self.outputs.iter_mut() {
+            let Some(connection) = self.connections.get_mut(client_fd) else { continue };
+            progress.push((*client_fd, send_output(output, connection)));
+        }
+        for (client_fd, sent) in progress {
+            self.finish_output(client_fd, sent)?;
+        }
+        Ok(())
+    }
+
+    /// Lets the connection of a streamed body take its next request once the
+    /// body was sent, closing it when it failed
+    fn finish_output(&mut self, client_fd: RawFd, sent: std::io::Result<bool>) -> Result<(), ServerError> {
+        match sent {
+            Ok(false) => Ok(()),
+            Ok(true) => {
+                let Some(output) = self.outputs.remove(&client_fd) else { return Ok(()) };
+                let Some(connection) = self.connections.get_mut(&client_fd) else { return Ok(()) };
+                if !output.keep_alive || self.draining.is_some() {
+                    return self.close_connection(client_fd);
+                }
+                connection.reset();
+                connection.start_time = Instant::now();
+                self.watch_client(client_fd, false);
+                status::connection_phase(client_fd, ConnectionPhase::KeepAlive);
+                Ok(())
+            }
+            Err(e) => {
+                // Without its last chunk, the client knows the body is incomplete
+                self.logger.warn(&format!("Streamed body of client {} cut short: {}", client_fd, e), "Server");
+                self.close_connection(client_fd)
+            }
+        }
+    }
+
+    /// Pings the WebSocket clients silent for too long, and closes the ones
+    /// that are done
+    fn check_sockets(&mut self) -> Result<(), ServerError> {
+        let mut closed = Vec::new();
+        for (client_fd, session) in self.sockets.iter_mut() {
+            let Some(connection) = self.connections.get_mut(client_fd) else { continue };
+            if let Err(e) = session.tick(connection) {
+                self.logger.warn(&format!("WebSocket {} failed: {}", session.id(), e), "WebSocket");
+                closed.push(*client_fd);
+            } else if session.is_closed() {
+                closed.push(*client_fd);
+            }
+        }
+        for client_fd in closed {
+            self.close_connection(client_fd)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a client the response that waited for room, and passes what a
+    /// WebSocket client sent on to its upstream
+    fn handle_client_event(&mut self, upstream_fd: RawFd) -> Result<(), ServerError> {
+        let Some(exchange) = self.exchanges.get_mut(&upstream_fd) else { return Ok(()) };
+        let Some(connection) = self.connections.get_mut(&exchange.client_fd) else { return Ok(()) };
+
+        match exchange.client_ready(connection) {
+            Progress::Pending => Ok(()),
+            progress => match self.exchanges.remove(&upstream_fd) {
+                Some(exchange) => {
+                    self.unregister_upstream(upstream_fd);
+                    self.finish_exchange(exchange, progress)
+                }
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Ends a forwarded request whose upstream socket is no longer watched:
+    /// the client gets the error when the upstream failed, and its connection
+    /// is kept open when the response allows it. A request that may be sent
+    /// again waits for its backoff instead.
+    fn finish_exchange(&mut self, mut exchange: Exchange, progress: Progress) -> Result<(), ServerError> {
+        if let Progress::Retry(problem) = progress {
+            let backoff = exchange.backoff();
+            self.logger.warn(&format!("{}, retrying in {}ms", problem, backoff.as_millis()), "Proxy");
+            self.retries.push((Instant::now() + backoff, exchange));
+            return Ok(());
+        }
+        let client_fd = exchange.client_fd;
+        let Some(connection) = self.connections.get_mut(&client_fd) else { return Ok(()) };
+        let host = connection.host_name.clone();
+
+        let (status, keep_alive) = match progress {
+            Progress::Pending | Progress::Retry(_) => return Ok(()),
+            Progress::Done { keep_alive } => {
+                exchange.cache_response();
+                (exchange.status.unwrap_or_default(), keep_alive)
+            }
+            Progress::Failed(error) => {
+                self.logger.error(&error.to_string(), "Server");
+                let mut response = error.to_response(None);
+                response.headers.push(connection_header(false));
+                if let Some(host) = self.hosts.iter().find(|candidate| candidate.server_name == host) {
+                    let info = RequestInfo::new(&exchange.request, &host.server_name, Some(&exchange.route_path), exchange.started);
+                    host.hooks.error(&info, &ServerError::from(error));
+                    host.hooks.response(&info, &mut response);
+                }
+                let status = response.status_code.clone() as u16;
+                if let Err(e) = connection.send(&response) {
+                    if e.kind() != std::io::ErrorKind::WouldBlock {
+                        self.logger.error(&format!("Failed to send response: {}", e), "Server");
+                    }
+                }
+                (status, false)
+            }
+            Progress::Aborted(message) => {
+                self.logger.error(&message, "Server");
+                (exchange.status.unwrap_or_default(), false)
+            }
+        };
+
+        status::record_route(&host, &exchange.route_path, status, exchange.started.elapsed());
+        record_access(&self.logger, AccessEntry {
+            client: exchange.request.client_addr,
+            host: &host,
+            method: exchange.request.method.to_string(),
+            uri: &exchange.request.uri,
+            status,
+            bytes: exchange.bytes,
+        });
+
+        if keep_alive {
+            connection.reset();
+            status::connection_phase(client_fd, ConnectionPhase::KeepAlive);
+            self.watch_client(client_fd, false);
+            Ok(())
+        } else {
+            self.close_connection(client_fd)
+        }
+    }
+
+    fn unregister_upstream(&self, upstream_fd: RawFd) {
+        unsafe {
+            if epoll_ctl(self.epoll_fd, EPOLL_CTL_DEL, upstream_fd, std::ptr::null_mut()) < 0 {
+                self.logger.error(&format!("Failed to remove upstream {} from epoll", upstream_fd), "Server");
+            }
+        }
+    }
+
     fn close_connection(&mut self, client_fd: RawFd) -> Result<(), ServerError> {
+        let upstream_fds: Vec<RawFd> = self.exchanges.iter()
+            .filter(|(_, exchange)| exchange.client_fd == client_fd)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for upstream_fd in upstream_fds {
+            self.unregister_upstream(upstream_fd);
+            self.exchanges.remove(&upstream_fd);
+        }
+        self.retries.retain(|(_, exchange)| exchange.client_fd != client_fd);
+        self.streams.remove(&client_fd);
+        self.outputs.remove(&client_fd);
+        if let Some(parked) = self.parked.remove(&client_fd) {
+            parked.deferred.abandon();
+        }
+        if let Some(mut session) = self.sockets.remove(&client_fd) {
+            session.abandon();
+            self.logger.info(&format!("WebSocket {} closed", session.id()), "WebSocket");
+        }
+
         unsafe {
             if epoll_ctl(self.epoll_fd, EPOLL_CTL_DEL, client_fd, std::ptr::null_mut()) < 0 {
                 self.logger.error(&format!(
@@ -277,6 +1136,7 @@ impl Server {
             }
         }
 
+        status::connection_closed(client_fd);
         if let Some(connection) = self.connections.remove(&client_fd) {
             self.logger.info(&format!(
                 "Connection closed - Host: {} Client fd: {}", 
@@ -288,11 +1148,29 @@ impl Server {
     }
 
     fn cleanup_timeouts(&mut self) -> Result<(), ServerError> {
+        let late_upstreams: Vec<RawFd> = self.exchanges.iter()
+            .filter(|(_, exchange)| exchange.timed_out())
+            .map(|(fd, _)| *fd)
+            .collect();
+        for upstream_fd in late_upstreams {
+            if let Some(exchange) = self.exchanges.remove(&upstream_fd) {
+                self.unregister_upstream(upstream_fd);
+                let progress = exchange.time_out();
+                self.finish_exchange(exchange, progress)?;
+            }
+        }
+
         let timed_out: Vec<RawFd> = self
             .connections
             .iter()
+            // Clients waiting for an upstream are timed out with it, WebSocket
+            // clients by their pings, event streams last as long as the client,
+            // parked clients get their timeout response
+            .filter(|(fd, _)| !self.is_waiting_for_upstream(**fd) && !self.sockets.contains_key(*fd)
+                && !self.streams.contains_key(*fd) && !self.parked.contains_key(*fd)
+                && !self.outputs.contains_key(*fd))
             .filter(|(_, conn)| {
-                let is_timeout = Instant::now().duration_since(conn.start_time) > TIMEOUT_DURATION;
+                let is_timeout = Instant::now().duration_since(conn.start_time) > conn.idle_timeout;
                 if is_timeout {
                     self.logger.warn(&format!(
                         "Connection timeout - Host: {} Client fd: {}", 
@@ -310,8 +1188,73 @@ impl Server {
         Ok(())
     }
 
+    /// Deletes the uploads older than their TTL, at most once per interval
+    fn purge_expired_uploads(&mut self) {
+        if self.last_upload_purge.elapsed() < UPLOAD_PURGE_INTERVAL {
+            return;
+        }
+        self.last_upload_purge = Instant::now();
+
+        let uploaders = self.uploader.iter_mut().chain(self.hosts.iter_mut().flat_map(|host| {
+            host.uploader.iter_mut().chain(host.routes.iter_mut().filter_map(|route| route.uploader.as_mut()))
+        }));
+
+        for uploader in uploaders {
+            match uploader.purge_expired() {
+                Ok(purged) => {
+                    for file in purged {
+                        self.logger.info(&format!("Purged expired upload {}", file.path.display()), "Server");
+                    }
+                }
+                Err(e) => self.logger.error(&format!("Upload purge error: {:?}", e), "Server"),
+            }
+        }
+    }
+
+    /// Starts the upstream probes that are due, and closes the upstream
+    /// connections idle for too long
+    fn check_upstreams(&self) {
+        let proxies = self.hosts.iter().flat_map(|host| host.routes.iter().filter_map(|route| route.proxy.as_ref()));
+        for proxy in proxies {
+            proxy.check_health();
+            proxy.close_idle();
+        }
+    }
+
+    /// Raises the alerts of the hosts, at most once per interval
+    fn check_alerts(&mut self) {
+        let Some(notifier) = self.notifier.as_mut() else { return };
+        if self.last_alert_check.elapsed() < ALERT_CHECK_INTERVAL {
+            return;
+        }
+        self.last_alert_check = Instant::now();
+        notifier.check(&self.hosts);
+    }
+
+    /// Serves the hosts until a handle shuts the server down
     pub fn run(&mut self) -> Result<(), ServerError> {
         self.logger.info("Starting server...", "Server");
+        let result = self.serve();
+        self.stop();
+        result
+    }
+
+    /// A handle stopping the server from another thread
+    pub fn handle(&self) -> ServerHandle {
+        let addrs = self.hosts.iter()
+            .flat_map(|host| &host.listeners)
+            .map(|listener| listener.socket.local_addr().unwrap_or_else(|_| listener.addr.clone()))
+            .collect();
+        ServerHandle::new(self.shutdown.clone(), addrs)
+    }
+
+    /// How long connections may take to finish on shutdown, 30 seconds by
+    /// default
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    fn serve(&mut self) -> Result<(), ServerError> {
         let mut events = vec![epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
 
         loop {
@@ -320,11 +1263,17 @@ impl Server {
                     self.epoll_fd,
                     events.as_mut_ptr(),
                     MAX_EVENTS as i32,
-                    -1
+                    self.epoll_timeout()
                 )
             };
 
             if num_events < 0 {
+                // A signal, like SIGHUP, interrupted the wait
+                if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                    self.reload_if_requested();
+                    self.stop_if_requested();
+                    continue;
+                }
                 self.logger.error("Failed to wait for events", "Server");
                 return Err(ServerError::EpollError("Failed to wait for events"));
             }
@@ -333,18 +1282,56 @@ impl Server {
             for event in &events[..num_events as usize] {
                 let fd = event.u64 as RawFd;
 
-                if let Some(_) = self.find_host_by_fd(fd) {
+                if fd == self.waker_fd {
+                    // Published events and given responses are sent by check_streams
+                    // and check_parked, once these are handled
+                    let mut count = [0u8; 8];
+                    unsafe { libc::read(fd, count.as_mut_ptr() as *mut libc::c_void, 8) };
+                } else if self.find_host_by_fd(fd).is_some() {
                     if let Err(e) = self.handle_new_connection(fd) {
                         self.logger.error(&format!("New connection error: {:?}", e), "Server");
                     }
+                } else if self.exchanges.contains_key(&fd) {
+                    if let Err(e) = self.handle_upstream_event(fd) {
+                        self.logger.error(&format!("Upstream event error: {:?}", e), "Server");
+                    }
+                } else if self.streams.contains_key(&fd) {
+                    if let Err(e) = self.handle_stream_event(fd) {
+                        self.logger.error(&format!("Event stream error: {:?}", e), "Server");
+                    }
+                } else if self.outputs.contains_key(&fd) {
+                    if let Err(e) = self.handle_output_event(fd) {
+                        self.logger.error(&format!("Streamed body error: {:?}", e), "Server");
+                    }
+                } else if self.parked.contains_key(&fd) {
+                    if let Err(e) = self.handle_parked_event(fd) {
+                        self.logger.error(&format!("Parked connection error: {:?}", e), "Server");
+                    }
+                } else if self.sockets.contains_key(&fd) {
+                    if let Err(e) = self.handle_socket_event(fd) {
+                        self.logger.error(&format!("WebSocket event error: {:?}", e), "Server");
+                    }
+                } else if let Some(upstream_fd) = self.exchange_of_client(fd) {
+                    // The client's next request is read once the response was sent,
+                    // WebSocket frames are passed on right away
+                    if let Err(e) = self.handle_client_event(upstream_fd) {
+                        self.logger.error(&format!("Client event error: {:?}", e), "Server");
+                    }
+                } else if self.is_waiting_for_upstream(fd) {
+                    // Waiting for its request to be sent again
                 } else {
-                    let host_name = self.connections.get(&fd)
-                        .map(|conn| conn.host_name.clone())
-                        .ok_or_else(|| ServerError::ConnectionError("Connection not found".to_string()))?;
-                    
-                    let host_index = self.hosts.iter()
-                        .position(|h| h.server_name == host_name)
-                        .ok_or_else(|| ServerError::ConnectionError("Host not found".to_string()))?;
+                    // Closed earlier in this round, like an upstream done with
+                    let Some(host_name) = self.connections.get(&fd).map(|conn| conn.host_name.clone()) else {
+                        continue;
+                    };
+
+                    // The host may have been removed by a reload
+                    let Some(host_index) = self.hosts.iter().position(|h| h.server_name == host_name) else {
+                        if let Err(e) = self.close_connection(fd) {
+                            self.logger.error(&format!("Connection close error: {:?}", e), "Server");
+                        }
+                        continue;
+                    };
 
                     if let Err(e) = self.handle_connection_event(fd, event.events, host_index) {
                         self.logger.error(&format!("Connection event error: {:?}", e), "Server");
@@ -352,19 +1339,272 @@ impl Server {
                 }
             }
 
+            if self.shutdown.is_requested() {
+                if self.draining.is_none() {
+                    self.start_draining();
+                }
+                if self.drain() {
+                    return Ok(());
+                }
+            }
+
             // Cleanup timeouts
             if let Err(e) = self.cleanup_timeouts() {
                 self.logger.error(&format!("Timeout cleanup error: {:?}", e), "Server");
             }
+
+            if let Err(e) = self.check_streams() {
+                self.logger.error(&format!("Event stream check error: {:?}", e), "Server");
+            }
+            if let Err(e) = self.check_parked() {
+                self.logger.error(&format!("Parked connection check error: {:?}", e), "Server");
+            }
+            if let Err(e) = self.check_outputs() {
+                self.logger.error(&format!("Streamed body check error: {:?}", e), "Server");
+            }
+            if let Err(e) = self.check_sockets() {
+                self.logger.error(&format!("WebSocket check error: {:?}", e), "Server");
+            }
+            self.start_retries();
+            self.purge_expired_uploads();
+            self.check_upstreams();
+            self.check_alerts();
+            self.reload_if_requested();
+            self.stop_if_requested();
+        }
+    }
+
+    /// Stops accepting connections, the ones open closed once answered
+    fn start_draining(&mut self) {
+        let listeners: Vec<HostListener> = self.hosts.iter_mut()
+            .flat_map(|host| host.listeners.drain(..))
+            .collect();
+        for listener in listeners {
+            self.unregister_listener(&listener);
+            self.logger.info(&format!("Stopped listening on {}", listener.addr), "Server");
+        }
+        for connection in self.connections.values_mut() {
+            connection.keep_alive = false;
+        }
+        for parked in self.parked.values_mut() {
+            parked.keep_alive = false;
+        }
+        self.draining = Some(Instant::now() + self.shutdown_timeout);
+        self.logger.info(&format!("Shutting down, {} connections open", self.connections.len()), "Server");
+    }
+
+    /// Closes the connections done with, returning whether none is left or
+    /// the shutdown timeout passed
+    fn drain(&mut self) -> bool {
+        if self.draining.is_some_and(|deadline| Instant::now() >= deadline) {
+            return true;
+        }
+        for session in self.sockets.values_mut() {
+            session.go_away();
+        }
+        // Event stream clients reconnect to another server
+        let done: Vec<RawFd> = self.connections.iter()
+            .filter(|(fd, connection)| self.streams.contains_key(*fd)
+                || (connection.is_idle() && !self.sockets.contains_key(*fd) && !self.parked.contains_key(*fd)))
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in done {
+            if let Err(e) = self.close_connection(fd) {
+                self.logger.error(&format!("Connection close error: {:?}", e), "Server");
+            }
+        }
+        self.connections.is_empty() && self.exchanges.is_empty() && self.retries.is_empty()
+    }
+
+    /// Closes what is left open, then lets the handles know the server stopped
+    fn stop(&mut self) {
+        let open: Vec<RawFd> = self.connections.keys().copied().collect();
+        for fd in open {
+            let _ = self.close_connection(fd);
+        }
+        let listeners: Vec<HostListener> = self.hosts.iter_mut()
+            .flat_map(|host| host.listeners.drain(..))
+            .collect();
+        for listener in listeners {
+            self.unregister_listener(&listener);
+        }
+        self.logger.info("Server stopped", "Server");
+        logger::flush();
+        self.shutdown.stopped();
+    }
+
+    /// Starts shutting down once SIGTERM or SIGINT was received
+    fn stop_if_requested(&mut self) {
+        if STOP_REQUESTED.swap(false, Ordering::SeqCst) {
+            self.logger.info("Received a signal to stop", "Server");
+            self.shutdown.request();
+        }
+    }
+
+    fn reload_if_requested(&mut self) {
+        if !RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let Some(reloader) = self.reloader.as_mut() else { return };
+
+        self.logger.info("Reloading configuration", "Server");
+        let error = match reloader() {
+            Ok(hosts) => {
+                self.reload(hosts);
+                None
+            }
+            Err(e) => {
+                self.logger.error(&format!("Reload failed, keeping the current configuration: {}", e), "Server");
+                Some(e.to_string())
+            }
+        };
+        logger::audit(AuditEntry { action: "config.reload".to_string(), error, ..AuditEntry::default() });
+    }
+}
+
+/// Applies the request size and header limits of `host` and streams its uploads to disk
+/// instead of buffering them whole
+fn configure_reader<S: Read + Write>(
+    mut reader: UnifiedReader<S>,
+    host: &Host,
+    default_uploader: Option<&Uploader>,
+) -> UnifiedReader<S> {
+    if let Some(size) = host.max_request_size {
+        reader.set_max_request_size(size);
+    }
+    if let Some(limits) = host.header_limits {
+        reader.set_header_limits(limits);
+    }
+
+    let upload_route = host.get_route("/api/files/upload");
+    // Multipart bodies the route refuses are answered with 415, not spooled
+    if upload_route.is_some_and(|route| !route.accepts_content_type(Some("multipart/form-data"))) {
+        return reader;
+    }
+    let uploader = upload_route.and_then(|route| host.uploader_for(route))
+        .or(host.uploader.as_ref())
+        .or(default_uploader);
+    if let Some(uploader) = uploader {
+        let upload_dir = uploader.upload_dir.clone();
+        let max_file_size = uploader.max_file_size
+            .or(upload_route.and_then(|route| route.max_body_size))
+            .or(host.max_request_size);
+        reader.set_body_sink_factory(Box::new(move |head: &[u8]| {
+            MultipartSpooler::for_request(head, &upload_dir, max_file_size)
+                .map(|spooler| Box::new(spooler) as Box<dyn BodySink>)
+        }));
+    }
+    reader
+}
+
+/// Sends the chunks of `output` until the client or the body has to be
+/// waited for, returning whether the body was all sent
+fn send_output(output: &mut Output, connection: &mut Connection) -> std::io::Result<bool> {
+    output.ready = false;
+    let mut taken = 0;
+    loop {
+        while !output.pending.is_empty() {
+            match connection.reader.write_some(&output.pending) {
+                Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "The client stopped taking the body")),
+                Ok(written) => { output.pending.drain(..written); }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if output.ended {
+            return Ok(true);
         }
+        if taken == CHUNKS_PER_ROUND {
+            output.ready = true;
+            return Ok(false);
+        }
+        taken += 1;
+        match output.body.next_chunk() {
+            Chunk::Data(data) if output.chunked => output.pending = encode_chunk(&data),
+            Chunk::Data(data) => output.pending = data,
+            Chunk::Pending => return Ok(false),
+            Chunk::End => {
+                if output.chunked {
+                    output.pending = LAST_CHUNK.to_vec();
+                }
+                output.ended = true;
+            }
+            Chunk::Failed(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether the client of `client_fd` closed its connection. Peeking leaves a
+/// pipelined request to be read once the response was sent.
+fn has_left(client_fd: RawFd) -> bool {
+    let mut byte = [0u8; 1];
+    let read = unsafe {
+        libc::recv(client_fd, byte.as_mut_ptr() as *mut libc::c_void, 1, libc::MSG_PEEK | libc::MSG_DONTWAIT)
+    };
+    read == 0 || (read < 0 && std::io::Error::last_os_error().kind() != std::io::ErrorKind::WouldBlock)
+}
+
+/// Sends the client of `subscription` what waits for it, failing when it
+/// can't keep up
+fn send_events(subscription: &mut Subscription, connection: &mut Connection) -> std::io::Result<()> {
+    subscription.poll();
+    while !subscription.pending().is_empty() {
+        match connection.reader.write_some(subscription.pending()) {
+            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "The client stopped taking events")),
+            Ok(written) => subscription.sent(written),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if subscription.is_lagging() {
+        return Err(std::io::Error::other("The client takes events slower than they come"));
     }
+    Ok(())
+}
+
+/// Writes `response` to the access log and counts it for the status page
+/// Records `response` to the access log, for the responses that don't leave
+/// through the middlewares of a host, where its `AccessLog` records them
+pub(crate) fn log_access(logger: &Logger, request: &Request, host: &str, response: &Response) {
+    record_access(logger, access_entry(request, host, response));
+}
+
+fn record_access(logger: &Logger, entry: AccessEntry) {
+    status::record_request(entry.host, entry.status);
+    if let (Some(client), 400..=499) = (entry.client, entry.status) {
+        ban::record_failure(client, &format!("{} on {}", entry.status, entry.uri));
+    }
+    logger.access(&entry, "Server");
+}
+
+fn access_entry<'a>(request: &'a Request, host: &'a str, response: &Response) -> AccessEntry<'a> {
+    AccessEntry {
+        client: request.client_addr,
+        host,
+        method: request.method.to_string(),
+        uri: &request.uri,
+        status: response.status_code.clone() as u16,
+        // The body of a HEAD response is only dropped once logged
+        bytes: response.body.as_ref().filter(|_| request.method != HttpMethod::HEAD).map(|body| body.body_len()).unwrap_or(0),
+    }
+}
+
+/// The `Connection` header telling the client whether its connection stays open
+fn connection_header(keep_alive: bool) -> Header {
+    Header::from_str("Connection", if keep_alive { "keep-alive" } else { "close" })
 }
 
-fn want_keep_alive(request: Request) -> bool {
-    match request.get_header(HeaderName::Connection) {
-        Some(header) => header.value.value.to_lowercase() == "keep-alive",
-        None => true
+/// Whether the streamed body of `response` is sent in chunks. HTTP/1.0
+/// clients don't know them and get the body as it comes instead, until the
+/// connection closes.
+fn chunks_for(request: &Request, response: &mut Response) -> bool {
+    if request.version != "HTTP/1.0" {
+        return true;
     }
+    response.headers.retain(|header| header.name != HeaderName::TransferEncoding);
+    false
 }
 
 /// Host lookup implementation
@@ -383,7 +1623,10 @@ impl Server {
 impl Drop for Server {
     fn drop(&mut self) {
         // Clean up epoll file descriptor
+        self.shutdown.stopped();
+        waker::clear(self.waker_fd);
         unsafe {
+            libc::close(self.waker_fd);
             libc::close(self.epoll_fd);
         }
     }
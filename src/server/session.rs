@@ -2,16 +2,23 @@ pub mod session {
     use colored::*;
     use uuid::Uuid;
     use std::collections::HashMap;
+    use std::sync::{Arc, Mutex, MutexGuard};
     use std::time::{SystemTime, Duration};
+    use sha2::{Digest, Sha256};
+    use crate::server::digest::constant_time_eq;
     use crate::server::errors::{ServerError, SessionError};
     use crate::http::{
         request::Request,
-        header::HeaderName,
+        header::{CookieJar, Header, HeaderName, Headers},
     };
 
+    /// Default lifetime of a remember-me token (30 days)
+    const DEFAULT_REMEMBER_MAX_AGE: u64 = 30 * 24 * 60 * 60;
+
     #[derive(Debug, Clone)]
     pub struct Session {
         pub id: String,
+        pub user: Option<String>,
         pub data: HashMap<String, String>,
         pub created_at: SystemTime,
         pub expires_at: Option<SystemTime>,
@@ -23,6 +30,7 @@ pub mod session {
             let expires_at = max_age.map(|age| now + Duration::from_secs(age));
             Session {
                 id: String::new(),
+                user: None,
                 data: HashMap::new(),
                 created_at: now,
                 expires_at,
@@ -36,9 +44,13 @@ pub mod session {
         pub fn set_id(&mut self, id: String) {
             self.id = id;
         }
+
+        pub fn set_user(&mut self, user: &str) {
+            self.user = Some(user.to_string());
+        }
     }
 
-    pub trait SessionStore {
+    pub trait SessionStore: Send {
         fn get(&self, id: &str) -> Result<Option<Session>, ServerError>;
         fn set(&mut self, session: Session) -> Result<(), ServerError>;
         fn delete(&mut self, id: &str) -> Result<(), ServerError>;
@@ -51,6 +63,9 @@ pub mod session {
             for session in self.list_sessions()? {
                 println!("{}", "═".repeat(50).cyan());
                 println!("Session ID: {}", session.id.yellow());
+                if let Some(user) = &session.user {
+                    println!("User: {}", user);
+                }
                 println!("Created at: {}", session.created_at
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .unwrap_or_default()
@@ -74,6 +89,53 @@ pub mod session {
         }
     }
 
+    /// Session store shared by every clone of a `SessionManager`, so sessions
+    /// created while handling one request are visible to the next one.
+    pub type SharedSessionStore = Arc<Mutex<dyn SessionStore>>;
+
+    /// Long-lived token that can mint a new session for `user` once the
+    /// short-lived session cookie is gone
+    #[derive(Debug, Clone)]
+    pub struct RememberToken {
+        /// SHA-256 of the token, the token itself only known to the client
+        pub hash: String,
+        pub user: String,
+        pub created_at: SystemTime,
+        pub expires_at: SystemTime,
+    }
+
+    impl RememberToken {
+        /// A new token for `user` and the value its cookie carries
+        pub fn new(user: &str, max_age: u64) -> (Self, String) {
+            let now = SystemTime::now();
+            let value = generate_id();
+            let token = RememberToken {
+                hash: hash_token(&value),
+                user: user.to_string(),
+                created_at: now,
+                expires_at: now + Duration::from_secs(max_age),
+            };
+            (token, value)
+        }
+
+        pub fn is_expired(&self) -> bool {
+            SystemTime::now() > self.expires_at
+        }
+    }
+
+    /// Storage for remember-me tokens, kept apart from the session store so
+    /// tokens survive session expiry. Tokens are kept and looked up by their
+    /// hash, so reading the store doesn't give a way to log in.
+    pub trait RememberTokenStore: Send {
+        fn get(&self, hash: &str) -> Result<Option<RememberToken>, ServerError>;
+        fn set(&mut self, token: RememberToken) -> Result<(), ServerError>;
+        fn delete(&mut self, hash: &str) -> Result<(), ServerError>;
+        /// Revokes every token of `user`, returning how many were removed
+        fn delete_for_user(&mut self, user: &str) -> Result<usize, ServerError>;
+    }
+
+    pub type SharedRememberTokenStore = Arc<Mutex<dyn RememberTokenStore>>;
+
     pub mod store_session {
         use super::*;
 
@@ -118,28 +180,101 @@ pub mod session {
                 Ok(self.sessions.values().cloned().collect())
             }
         }
+
+        #[derive(Debug, Clone, Default)]
+        pub struct MemoryRememberTokenStore {
+            tokens: HashMap<String, RememberToken>
+        }
+
+        impl MemoryRememberTokenStore {
+            pub fn new() -> Self {
+                MemoryRememberTokenStore::default()
+            }
+        }
+
+        impl RememberTokenStore for MemoryRememberTokenStore {
+            fn get(&self, hash: &str) -> Result<Option<RememberToken>, ServerError> {
+                Ok(self.tokens.get(hash).cloned())
+            }
+
+            fn set(&mut self, token: RememberToken) -> Result<(), ServerError> {
+                self.tokens.insert(token.hash.clone(), token);
+                Ok(())
+            }
+
+            fn delete(&mut self, hash: &str) -> Result<(), ServerError> {
+                self.tokens.remove(hash);
+                Ok(())
+            }
+
+            fn delete_for_user(&mut self, user: &str) -> Result<usize, ServerError> {
+                let before = self.tokens.len();
+                self.tokens.retain(|_, token| token.user != user);
+                Ok(before - self.tokens.len())
+            }
+        }
     }
 
     pub mod session_manager {
         use super::*;
         use crate::config::config::SessionConfig;
-        use crate::http::header::{Header, Cookie, CookieOptions, SameSitePolicy};
+        use crate::http::header::{Cookie, CookieOptions, SameSitePolicy};
+        use crate::server::logger::logger;
 
         #[derive(Clone)]
         pub struct SessionManager {
             pub config: SessionConfig,
-            pub store: Box<dyn SessionStore>,
+            pub store: SharedSessionStore,
+            pub remember_store: SharedRememberTokenStore,
         }
 
         impl SessionManager {
-            pub fn new(config: SessionConfig, store: Box<dyn SessionStore>) -> Self {
-                SessionManager { config, store }
+            pub fn new<S: SessionStore + 'static>(config: SessionConfig, store: S) -> Self {
+                SessionManager::with_shared_store(config, Arc::new(Mutex::new(store)))
             }
 
-            pub fn create_session(&mut self) -> Result<(Session, Header), ServerError> {
-                let option_config = self.config.options.clone();
-                let id = generate_id();
-                let cookie = if let Some(opts) = option_config {
+            /// Builds a manager on top of an existing shared store
+            pub fn with_shared_store(config: SessionConfig, store: SharedSessionStore) -> Self {
+                SessionManager {
+                    config,
+                    store,
+                    remember_store: Arc::new(Mutex::new(MemoryRememberTokenStore::new())),
+                }
+            }
+
+            /// Replaces the remember-me token store
+            pub fn with_remember_store<R: RememberTokenStore + 'static>(mut self, store: R) -> Self {
+                self.remember_store = Arc::new(Mutex::new(store));
+                self
+            }
+
+            /// Locks the shared store for the duration of one operation
+            pub fn store(&self) -> Result<MutexGuard<'_, dyn SessionStore + 'static>, ServerError> {
+                self.store.lock().map_err(|_| {
+                    SessionError::SessionStorageError("Session store lock poisoned".to_string()).into()
+                })
+            }
+
+            fn remember_store(&self) -> Result<MutexGuard<'_, dyn RememberTokenStore + 'static>, ServerError> {
+                self.remember_store.lock().map_err(|_| {
+                    SessionError::SessionStorageError("Remember-me store lock poisoned".to_string()).into()
+                })
+            }
+
+            pub fn create_session(&self) -> Result<(Session, Header), ServerError> {
+                self.create_session_for(None)
+            }
+
+            /// Creates a session bound to `user`, invalidating the user's oldest
+            /// sessions when `max_sessions_per_user` would otherwise be exceeded.
+            /// `user` must have been authenticated.
+            pub fn create_user_session(&self, user: &str) -> Result<(Session, Header), ServerError> {
+                self.create_session_for(Some(user))
+            }
+
+            /// Builds a cookie carrying the configured session cookie options
+            fn build_cookie(&self, name: &str, value: &str) -> Cookie {
+                if let Some(opts) = self.config.options.clone() {
                     let options = CookieOptions {
                         http_only: opts.http_only.unwrap_or(false),
                         secure: opts.secure.unwrap_or(false),
@@ -154,85 +289,262 @@ pub mod session {
                             _ => SameSitePolicy::Strict,
                         }
                     };
-                    Cookie::with_options(self.config.name.as_deref().unwrap_or(""), &id, options)
+                    Cookie::with_options(name, value, options)
                 } else {
-                    Cookie::new(self.config.name.as_deref().unwrap_or(""), &id)
-                };
+                    Cookie::new(name, value)
+                }
+            }
+
+            fn cookie_name(&self) -> &str {
+                self.config.name.as_deref().unwrap_or("")
+            }
+
+            fn create_session_for(&self, user: Option<&str>) -> Result<(Session, Header), ServerError> {
+                let id = generate_id();
+                let cookie = self.build_cookie(self.cookie_name(), &id);
 
                 let mut session = Session::new(cookie.options.max_age);
                 session.set_id(id.clone());
 
-                self.store.set(session.clone())?;
+                let mut store = self.store()?;
+                if let Some(user) = user {
+                    session.set_user(user);
+                    if let Some(limit) = self.config.max_sessions_per_user {
+                        self.enforce_user_limit(&mut *store, user, limit)?;
+                    }
+                }
+
+                store.set(session.clone())?;
                 let header = Header::from_str("set-cookie", &cookie.to_string());
                 
                 Ok((session, header))
             }
 
-            pub fn get_session(&mut self, cookie_header: Option<&Header>) -> Result<Option<Session>, ServerError> {
-                if let Some(header) = cookie_header {
-                    if let Some(cookie) = Cookie::parse(&header.value.value) {
-                        if let Some(session) = self.store.get(&cookie.value)? {
-                            if !session.is_expired() {
-                                return Ok(Some(session));
-                            }
-                            self.store.delete(&cookie.value)?;
-                            return Err(SessionError::SessionExpired(cookie.value).into());
+            /// Drops the user's oldest sessions so that, once the new one is
+            /// stored, no more than `limit` remain
+            fn enforce_user_limit(&self, store: &mut dyn SessionStore, user: &str, limit: usize) -> Result<(), ServerError> {
+                let mut sessions: Vec<Session> = store.list_sessions()?
+                    .into_iter()
+                    .filter(|s| s.user.as_deref() == Some(user) && !s.is_expired())
+                    .collect();
+
+                if sessions.len() < limit {
+                    return Ok(());
+                }
+
+                sessions.sort_by_key(|s| s.created_at);
+                let excess = sessions.len() + 1 - limit;
+                let logger = logger();
+
+                for old in sessions.iter().take(excess) {
+                    store.delete(&old.id)?;
+                    logger.info(&format!(
+                        "Session limit ({}) reached for user '{}': invalidated oldest session {}",
+                        limit, user, old.id
+                    ), "Session");
+                }
+
+                Ok(())
+            }
+
+            pub fn get_session(&self, cookie_headers: &Headers) -> Result<Option<Session>, ServerError> {
+                if let Some(id) = cookie_value(cookie_headers, self.cookie_name()) {
+                    let mut store = self.store()?;
+                    if let Some(session) = store.get(&id)? {
+                        if !session.is_expired() {
+                            return Ok(Some(session));
                         }
+                        store.delete(&id)?;
+                        return Err(SessionError::SessionExpired(id).into());
                     }
                 }
                 Ok(None)
             }
 
-            pub fn destroy_session(&mut self, session_id: &str) -> Result<Header, ServerError> {                
-                self.store.delete(session_id)?;
-                let mut options = CookieOptions::default();
-                options.max_age = Some(0);
-                let cookie = Cookie::with_options(
-                    self.config.name.as_deref().unwrap_or(""),
-                    "",
-                    options
-                );
-                let header = Header::from_str("set-cookie", &cookie.to_string());
+            /// The session id `Cookie` or `Set-Cookie` headers carry, whether
+            /// or not the session still exists
+            pub fn session_id(&self, cookie_headers: &Headers) -> Option<String> {
+                cookie_value(cookie_headers, self.cookie_name()).filter(|id| !id.is_empty())
+            }
+
+            pub fn destroy_session(&self, session_id: &str) -> Result<Header, ServerError> {
+                let mut store = self.store()?;
+                store.delete(session_id)?;
+                let header = clear_cookie(self.cookie_name());
                 
-                self.store.print_sessions()?;
+                store.print_sessions()?;
                 Ok(header)
             }
+
+            /// Destroys every session and remember-me token of `user`
+            /// ("log out everywhere"), returning the cookie-clearing headers
+            pub fn destroy_user_sessions(&self, user: &str) -> Result<Vec<Header>, ServerError> {
+                let mut store = self.store()?;
+                let sessions = store.list_sessions()?;
+                let mut destroyed = 0;
+                for session in sessions.iter().filter(|s| s.user.as_deref() == Some(user)) {
+                    store.delete(&session.id)?;
+                    destroyed += 1;
+                }
+                let revoked = self.remember_store()?.delete_for_user(user)?;
+
+                logger().info(&format!(
+                    "Logged out '{}' everywhere: {} session(s), {} remember-me token(s) revoked",
+                    user, destroyed, revoked
+                ), "Session");
+
+                let mut headers = vec![clear_cookie(self.cookie_name())];
+                if self.remember_enabled() {
+                    headers.push(clear_cookie(&self.remember_cookie_name()));
+                }
+                Ok(headers)
+            }
+
+            pub fn remember_enabled(&self) -> bool {
+                self.config.remember_me.as_ref()
+                    .map(|r| r.enabled.unwrap_or(false))
+                    .unwrap_or(false)
+            }
+
+            fn remember_cookie_name(&self) -> String {
+                self.config.remember_me.as_ref()
+                    .and_then(|r| r.name.clone())
+                    .unwrap_or_else(|| "remember_me".to_string())
+            }
+
+            fn remember_max_age(&self) -> u64 {
+                self.config.remember_me.as_ref()
+                    .and_then(|r| r.max_age)
+                    .unwrap_or(DEFAULT_REMEMBER_MAX_AGE)
+            }
+
+            /// Issues a new remember-me token for `user` and returns its cookie.
+            /// `user` must have been authenticated, the token logging in as them.
+            pub fn issue_remember_token(&self, user: &str) -> Result<Header, ServerError> {
+                let (token, value) = RememberToken::new(user, self.remember_max_age());
+                let mut cookie = self.build_cookie(&self.remember_cookie_name(), &value);
+                cookie.options.max_age = Some(self.remember_max_age());
+                cookie.options.expires = None;
+
+                self.remember_store()?.set(token)?;
+                Ok(Header::from_str("set-cookie", &cookie.to_string()))
+            }
+
+            /// Mints a fresh session from a valid remember-me cookie. The token
+            /// is rotated on every use, so the returned headers carry both the
+            /// new session cookie and the replacement remember-me cookie.
+            pub fn restore_session(&self, cookie_headers: &Headers) -> Result<Option<(Session, Vec<Header>)>, ServerError> {
+                if !self.remember_enabled() {
+                    return Ok(None);
+                }
+
+                let value = match cookie_value(cookie_headers, &self.remember_cookie_name()) {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                let hash = hash_token(&value);
+                let token = {
+                    let mut store = self.remember_store()?;
+                    let token = store.get(&hash)?;
+                    store.delete(&hash)?;
+                    token
+                };
+
+                match token {
+                    Some(token) if constant_time_eq(token.hash.as_bytes(), hash.as_bytes()) && !token.is_expired() => {
+                        let (session, session_cookie) = self.create_user_session(&token.user)?;
+                        let remember_cookie = self.issue_remember_token(&token.user)?;
+                        Ok(Some((session, vec![session_cookie, remember_cookie])))
+                    }
+                    _ => Ok(None),
+                }
+            }
+
+            /// Revokes the remember-me token presented by the client, if any,
+            /// and returns the header clearing its cookie
+            pub fn revoke_remember_token(&self, cookie_headers: &Headers) -> Result<Option<Header>, ServerError> {
+                if !self.remember_enabled() {
+                    return Ok(None);
+                }
+
+                let name = self.remember_cookie_name();
+                match cookie_value(cookie_headers, &name) {
+                    Some(value) => {
+                        self.remember_store()?.delete(&hash_token(&value))?;
+                        Ok(Some(clear_cookie(&name)))
+                    }
+                    None => Ok(None),
+                }
+            }
         }
 
         impl Default for SessionManager {
             fn default() -> Self {
-                SessionManager {
-                    config: SessionConfig::default(),
-                    store: Box::new(MemorySessionStore::new()),
-                }
+                SessionManager::new(SessionConfig::default(), MemorySessionStore::new())
             }
         }
+
+        /// Builds a `set-cookie` header that expires the named cookie
+        fn clear_cookie(name: &str) -> Header {
+            let options = CookieOptions { max_age: Some(0), ..Default::default() };
+            let cookie = Cookie::with_options(name, "", options);
+            Header::from_str("set-cookie", &cookie.to_string())
+        }
     }
 
     pub mod session_middleware {
 
         use super::*;
+        use crate::http::request::HttpMethod;
+        use crate::http::response::Response;
+        use crate::server::middleware::{Context, Middleware};
         use crate::server::route::Route;
 
-        pub struct SessionMiddleware {}
+        /// Outcome of the session check for one request
+        #[derive(Debug, Default)]
+        pub struct SessionContext {
+            pub session: Option<Session>,
+            /// Cookies to set on the response, e.g. when a session was minted
+            /// from a remember-me token
+            pub set_cookies: Vec<Header>,
+        }
+
+        /// Checks the session of the requests to the routes requiring one,
+        /// registered on every host keeping sessions
+        pub struct SessionMiddleware {
+            manager: SessionManager,
+        }
 
         impl SessionMiddleware {
-            pub fn process(&self, req: &Request, route: &Route, current_manager: &mut SessionManager) 
-                -> Result<Option<Session>, ServerError> {
-                
+            pub fn new(manager: SessionManager) -> Self {
+                SessionMiddleware { manager }
+            }
+
+            pub fn process(&self, req: &Request, route: &Route) -> Result<SessionContext, ServerError> {
+                let current_manager = &self.manager;
+                // The host answers these, CORS preflights never carry the cookie
+                if req.method == HttpMethod::OPTIONS && route.answers_options() {
+                    return Ok(SessionContext::default());
+                }
                 if let Some(required) = &route.session_required {
                     if !required {
-                        return Ok(None);
+                        return Ok(SessionContext::default());
                     }
                 } else {
-                    return Ok(None);
+                    return Ok(SessionContext::default());
                 }
 
 
 
-                let cookie_header = req.headers.iter().find(|h| h.name == HeaderName::Cookie);
+                let cookie_headers = req.get_headers(HeaderName::Cookie);
+
+                let current = match current_manager.get_session(&cookie_headers) {
+                    Err(ServerError::SessionError(SessionError::SessionExpired(_))) => Ok(None),
+                    other => other,
+                };
 
-                match current_manager.get_session(cookie_header) {
+                match current {
                     Ok(Some(session)) => {
                         if session.is_expired() {
                             current_manager.destroy_session(&session.id)?;
@@ -241,9 +553,13 @@ pub mod session {
                             }
                             return Err(SessionError::SessionExpired(session.id).into());
                         }
-                        Ok(Some(session))
+                        Ok(SessionContext { session: Some(session), set_cookies: Vec::new() })
                     },
                     Ok(None) => {
+                        if let Some((session, set_cookies)) = current_manager.restore_session(&cookie_headers)? {
+                            return Ok(SessionContext { session: Some(session), set_cookies });
+                        }
+
                         if let Some(redirect) = &route.session_redirect {
                             return Err(SessionError::SessionExpiredRedirect(redirect.to_string()).into());
                         } else {
@@ -254,13 +570,40 @@ pub mod session {
                 }   
             }
         }
+
+        impl Middleware for SessionMiddleware {
+            fn before(&self, request: &mut Request, route: &Route, context: &mut Context) -> Result<Option<Response>, ServerError> {
+                match self.process(request, route) {
+                    Ok(session) => {
+                        // Handlers only see the request
+                        request.session = session.session.clone().map(Box::new);
+                        context.session = session.session;
+                        context.headers.extend(session.set_cookies);
+                        Ok(None)
+                    },
+                    // Like the redirect to the login page
+                    Err(e) => Ok(Some(e.to_response())),
+                }
+            }
+        }
     }
 
     fn generate_id() -> String {
         Uuid::new_v4().to_string()
     }
 
+    /// SHA-256 of a remember-me token, in hexadecimal
+    fn hash_token(value: &str) -> String {
+        Sha256::digest(value.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Returns the value of the cookie called `name` from the first of
+    /// `headers` carrying it, as clients may split their cookies over several
+    fn cookie_value(headers: &Headers, name: &str) -> Option<String> {
+        CookieJar::from_headers(headers).get(name).map(str::to_string)
+    }
+
     pub use session_manager::SessionManager;
-    pub use store_session::MemorySessionStore;
-    pub use session_middleware::SessionMiddleware;
+    pub use store_session::{MemorySessionStore, MemoryRememberTokenStore};
+    pub use session_middleware::{SessionMiddleware, SessionContext};
 }
\ No newline at end of file
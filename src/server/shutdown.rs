@@ -0,0 +1,125 @@
+//! Stopping a server from code
+//!
+//! A [`ServerHandle`] asks the event loop of its server to stop, from any
+//! thread. The loop then stops accepting connections and closes the idle ones,
+//! while the others finish their request: they are closed once answered,
+//! WebSocket clients are told the server is going away and event streams end.
+//! Connections still open once the shutdown timeout passed are closed anyway.
+
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use crate::http::waker;
+use crate::server::errors::ServerError;
+use crate::server::listen::ListenAddr;
+
+/// What a server and its handles share
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    state: Mutex<State>,
+    stopped: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Eventfd of the server's loop, closed once it stopped
+    waker_fd: RawFd,
+    requested: bool,
+    stopped: bool,
+}
+
+impl Shutdown {
+    pub(crate) fn new(waker_fd: RawFd) -> Arc<Self> {
+        Arc::new(Shutdown {
+            state: Mutex::new(State { waker_fd, requested: false, stopped: false }),
+            stopped: Condvar::new(),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn request(&self) {
+        let mut state = self.state();
+        state.requested = true;
+        // The eventfd is only written while the server still has it open
+        if !state.stopped {
+            waker::wake_fd(state.waker_fd);
+        }
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        self.state().requested
+    }
+
+    /// Lets the handles know the loop is over, before its eventfd is closed
+    pub(crate) fn stopped(&self) {
+        self.state().stopped = true;
+        self.stopped.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.state().stopped
+    }
+
+    fn wait(&self) {
+        let mut state = self.state();
+        while !state.stopped {
+            state = self.stopped.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// The thread serving a spawned server, and how its loop ended
+type ServerThread = JoinHandle<Result<(), ServerError>>;
+
+/// Stops a server, from any thread
+#[derive(Clone, Debug)]
+pub struct ServerHandle {
+    shutdown: Arc<Shutdown>,
+    /// The addresses the server listened on when the handle was taken
+    addrs: Vec<ListenAddr>,
+    /// The thread serving, for the servers started by `ServerBuilder::spawn`
+    thread: Arc<Mutex<Option<ServerThread>>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(shutdown: Arc<Shutdown>, addrs: Vec<ListenAddr>) -> Self {
+        ServerHandle { shutdown, addrs, thread: Arc::new(Mutex::new(None)) }
+    }
+
+    pub(crate) fn with_thread(self, thread: ServerThread) -> Self {
+        *self.thread.lock().unwrap_or_else(|e| e.into_inner()) = Some(thread);
+        self
+    }
+
+    /// The addresses bound, in the order of the hosts and their listeners.
+    /// Ports given as 0 are the ones the system picked.
+    pub fn local_addrs(&self) -> &[ListenAddr] {
+        &self.addrs
+    }
+
+    /// Asks the server to stop once its connections are done with, returning
+    /// right away
+    pub fn shutdown(&self) {
+        self.shutdown.request();
+    }
+
+    /// Whether the server stopped serving, or never started
+    pub fn is_stopped(&self) -> bool {
+        self.shutdown.is_stopped()
+    }
+
+    /// Blocks until the server stopped, returning the error it stopped on.
+    /// The thread of a spawned server is joined, by the first caller.
+    pub fn wait(&self) -> Result<(), ServerError> {
+        self.shutdown.wait();
+        let thread = self.thread.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match thread.map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(ServerError::ConnectionError("The server thread panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Date and Server headers
+//!
+//! Every response the server makes says when it was sent in `Date`, as RFC
+//! 7231 has origin servers do, and names the software in `Server`. The name
+//! is `server_header` when the config sets one, an empty one leaving the
+//! header out for those who'd rather not tell. Responses carrying either
+//! header already, like those of CGI scripts, keep theirs. Proxied responses
+//! keep the upstream's.
+
+use std::sync::RwLock;
+use std::time::SystemTime;
+use crate::http::header::{Header, HeaderName};
+use crate::http::response::Response;
+
+/// `Server` of the responses when the config names none
+pub const DEFAULT_SERVER: &str = concat!("localhost/", env!("CARGO_PKG_VERSION"));
+
+static SERVER: RwLock<Option<String>> = RwLock::new(None);
+
+/// Replaces the name sent in `Server`, `None` going back to the default
+pub fn configure(server: Option<String>) {
+    *SERVER.write().unwrap_or_else(|e| e.into_inner()) = server;
+}
+
+/// The name sent in `Server`, `None` when the header is left out
+pub fn server() -> Option<String> {
+    let server = SERVER.read().unwrap_or_else(|e| e.into_inner());
+    match server.as_deref() {
+        Some("") => None,
+        Some(name) => Some(name.to_string()),
+        None => Some(DEFAULT_SERVER.to_string()),
+    }
+}
+
+/// The `Date` and `Server` headers `response` lacks
+pub fn missing(response: &Response) -> Vec<Header> {
+    let has = |name: &HeaderName| response.headers.iter().any(|header| header.name == *name);
+    let mut headers = Vec::with_capacity(2);
+    if !has(&HeaderName::Date) {
+        headers.push(Header::from_str("date", &httpdate::fmt_http_date(SystemTime::now())));
+    }
+    if !has(&HeaderName::Server) {
+        if let Some(server) = server() {
+            headers.push(Header::from_str("server", &server));
+        }
+    }
+    headers
+}
@@ -1,14 +1,25 @@
 use std::{
-    collections::HashMap, fs, io::{self, Read}, path::{Path, PathBuf}, env
+    collections::HashMap, fs, io::{self, Read}, path::{Path, PathBuf}, env, sync::RwLock, time::SystemTime
 };
-use mime_guess::from_path;
 use serde_json::{json, Value};
 use crate::server::errors::ServerError;
+use crate::server::file_types::mime_type_of;
+use crate::http::escape;
 
-// sites directory prefix
+/// Set by embedders serving their sites from elsewhere
+static SITES_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
 
+/// Directory relative site paths are under, `~/.cargo/localhost-cli/sites`
+/// unless set with `set_sites_dir`
 pub fn sites_dir() -> String {
-    format!("{}/.cargo/localhost-cli/sites", env!("HOME"))
+    if let Some(dir) = SITES_DIR.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return dir.display().to_string();
+    }
+    format!("{}/.cargo/localhost-cli/sites", env::var("HOME").unwrap_or_default())
+}
+
+pub fn set_sites_dir(dir: impl Into<PathBuf>) {
+    *SITES_DIR.write().unwrap_or_else(|e| e.into_inner()) = Some(dir.into());
 }
 
 /// Type alias for MIME type strings
@@ -36,6 +47,10 @@ pub struct ServerStaticFiles {
     pub allow_directory_listing: bool,
     pub error_pages: Option<ErrorPages>,
     pub status: FileStatus,
+    /// Modification time of the file last served
+    pub modified: Option<SystemTime>,
+    /// Weak entity tag of the file last served, from its modification time and size
+    pub etag: Option<String>,
 }
 
 /// Core implementation
@@ -63,9 +78,12 @@ impl ServerStaticFiles {
 
         let value = env::var("LOCALHOST_RESOURCES").unwrap_or_else(|_| "src/.default".to_string());
         let src = std::path::PathBuf::from(value);
-        copy_default_dir(&src, &default_dir).map_err(|e| 
-            ServerError::DirectoryListingError(format!("Failed to copy default directory: {}", e))
-        )?;
+        // Embedders running away from the sources go without the listing's assets
+        if src.exists() {
+            copy_default_dir(&src, &default_dir).map_err(|e|
+                ServerError::DirectoryListingError(format!("Failed to copy default directory: {}", e))
+            )?;
+        }
 
         Ok(ServerStaticFiles {
             directory,
@@ -73,35 +91,51 @@ impl ServerStaticFiles {
             allow_directory_listing,
             error_pages,
             status: FileStatus::Raw,
+            modified: None,
+            etag: None,
         })
     }
 
     pub fn serve_static(&mut self, path: &str) -> Result<(Vec<u8>, Option<mime>, FileStatus), ServerError> {
-        let defaultPath = self.directory.join(".default/index.html");
-
-
-        let path = path.trim_start_matches('/');
-        let full_path = self.directory.join(path);
+        match self.file_for(path) {
+            Some(file) => self.serve_file(&file),
+            None => self.serve_directory(&self.directory.join(path.trim_start_matches('/'))),
+        }
+    }
 
+    /// Sets `modified` and `etag` to those of the file served at `path`
+    /// without reading it, returning whether there is such a file
+    pub fn stat(&mut self, path: &str) -> bool {
+        let metadata = self.file_for(path)
+            .and_then(|file| fs::metadata(file).ok())
+            .filter(|metadata| metadata.is_file());
+        let Some(metadata) = metadata else {
+            return false;
+        };
+        self.modified = metadata.modified().ok();
+        self.etag = self.modified.map(|modified| weak_etag(modified, metadata.len()));
+        true
+    }
 
-        if full_path.is_dir() {
-            if self.allow_directory_listing {
-                return self.serve_directory(&full_path);
-            }
+    /// The file served at `path`, `None` when it's a directory listed
+    fn file_for(&self, path: &str) -> Option<PathBuf> {
+        let full_path = self.directory.join(path.trim_start_matches('/'));
+        if full_path.is_dir() && self.allow_directory_listing {
+            return None;
         }
 
         if let Some(index) = &self.index  {
             let index_path = full_path.join(index);
-            if index_path.is_file() && !self.allow_directory_listing {
-                return self.serve_file(&index_path);
+            if index_path.is_file() {
+                return Some(index_path);
             }
         }
 
         if self.index.is_none() && full_path == self.directory {
-            return self.serve_file(&defaultPath);
+            return Some(self.directory.join(".default/index.html"));
         }
 
-        self.serve_file(&full_path)
+        Some(full_path)
     }
 
     pub fn is_directory_contain_file(&self, path: &Path) -> bool {
@@ -134,6 +168,8 @@ impl ServerStaticFiles {
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)
             .map_err(ServerError::from)?;
+        self.modified = file.metadata().and_then(|metadata| metadata.modified()).ok();
+        self.etag = self.modified.map(|modified| weak_etag(modified, buffer.len() as u64));
         
         let mime = self.get_mime_type(path);
         Ok((buffer, Some(mime), self.status.clone()))
@@ -141,7 +177,7 @@ impl ServerStaticFiles {
 
     /// Gets MIME type for a file path
     fn get_mime_type(&self, path: &Path) -> mime {
-        from_path(path).first_or_octet_stream().to_string()
+        mime_type_of(path)
     }
 }
 
@@ -219,9 +255,7 @@ impl ServerStaticFiles {
         // Create data.js content
         let js_content = format!(
             "// Generated directory structure\nexport const directoryData = {};",
-            serde_json::to_string_pretty(&structure).map_err(|e| 
-                ServerError::DirectoryListingError(format!("Failed to serialize directory data: {}", e))
-            )?
+            escape::js_value(&structure)
         );
 
         let data_js_path = self.directory.join(".default/js/directory/data.js");
@@ -245,6 +279,13 @@ impl ServerStaticFiles {
     }
 }
 
+/// `W/"<modification time>-<size>"`, in hexadecimal, the time in nanoseconds
+/// since the epoch so that a file rewritten within a second gets another tag
+fn weak_etag(modified: SystemTime, size: u64) -> String {
+    let nanos = modified.duration_since(SystemTime::UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or(0);
+    format!("W/\"{:x}-{:x}\"", nanos, size)
+}
+
 pub fn copy_default_dir(src: &Path, dst: &Path) -> Result<(), io::Error> {
     if !dst.exists() {
         fs::create_dir(dst)?;
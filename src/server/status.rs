@@ -0,0 +1,380 @@
+//! Server status
+//!
+//! The admin status page tells what the server is doing, like Apache's
+//! mod_status: the open connections, the requests each host answered over the
+//! last minute, the last errors logged and a summary of the hosts served. The
+//! server records them here as it runs, and the page renders a snapshot as JSON
+//! or as an HTML page refreshing itself.
+//!
+//! The latency and status classes of the last requests of every route are kept
+//! too, for the stats API, which also tells how the upstreams of proxy routes are.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use crate::http::escape;
+use crate::server::host::Host;
+use crate::server::proxy::Proxy;
+use crate::server::server;
+
+/// Errors kept for the status page
+const RECENT_ERRORS: usize = 50;
+/// Seconds over which request rates are averaged
+const RATE_WINDOW: u64 = 60;
+/// Latencies kept per route for its percentiles
+const LATENCY_SAMPLES: usize = 1024;
+
+/// What a connection is doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionPhase {
+    /// Receiving its first request
+    Reading,
+    /// Answering a request
+    Writing,
+    /// Waiting for the next request
+    KeepAlive,
+    /// Upgraded to a WebSocket served by the route
+    WebSocket,
+    /// Streaming the events of a channel
+    EventStream,
+    /// Waiting for a deferred response
+    Parked,
+}
+
+impl ConnectionPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionPhase::Reading => "reading",
+            ConnectionPhase::Writing => "writing",
+            ConnectionPhase::KeepAlive => "keep-alive",
+            ConnectionPhase::WebSocket => "websocket",
+            ConnectionPhase::EventStream => "event-stream",
+            ConnectionPhase::Parked => "parked",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ConnectionInfo {
+    peer: Option<SocketAddr>,
+    host: String,
+    phase: ConnectionPhase,
+    opened: Instant,
+}
+
+#[derive(Debug)]
+struct HostRequests {
+    total: u64,
+    /// Answered with a 5xx status
+    server_errors: u64,
+    /// Requests counted per second since the status was created, as
+    /// (second, requests, server errors) slots reused every `RATE_WINDOW` seconds
+    seconds: [(u64, u64, u64); RATE_WINDOW as usize],
+}
+
+impl Default for HostRequests {
+    fn default() -> Self {
+        HostRequests { total: 0, server_errors: 0, seconds: [(0, 0, 0); RATE_WINDOW as usize] }
+    }
+}
+
+impl HostRequests {
+    fn record(&mut self, second: u64, server_error: bool) {
+        self.total += 1;
+        if server_error {
+            self.server_errors += 1;
+        }
+        let slot = &mut self.seconds[(second % RATE_WINDOW) as usize];
+        if slot.0 != second {
+            *slot = (second, 0, 0);
+        }
+        slot.1 += 1;
+        if server_error {
+            slot.2 += 1;
+        }
+    }
+
+    /// Requests and server errors over the last window
+    fn recent(&self, second: u64) -> (u64, u64) {
+        self.seconds.iter()
+            .filter(|(slot, _, _)| second - slot < RATE_WINDOW)
+            .fold((0, 0), |(requests, errors), (_, count, failed)| (requests + count, errors + failed))
+    }
+
+    /// Requests per second over the last window
+    fn rate(&self, second: u64) -> f64 {
+        self.recent(second).0 as f64 / RATE_WINDOW as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct RouteStats {
+    requests: u64,
+    /// Responses per status class, 1xx to 5xx
+    classes: [u64; 5],
+    /// Latencies of the last `LATENCY_SAMPLES` requests
+    latencies: VecDeque<Duration>,
+}
+
+impl RouteStats {
+    fn record(&mut self, status_code: u16, latency: Duration) {
+        self.requests += 1;
+        if let Some(count) = (status_code / 100).checked_sub(1).and_then(|class| self.classes.get_mut(class as usize)) {
+            *count += 1;
+        }
+        if self.latencies.len() == LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+
+    fn to_json(&self, host: &str, route: &str) -> Value {
+        let mut latencies: Vec<Duration> = self.latencies.iter().copied().collect();
+        latencies.sort();
+        // Nearest-rank percentile, in milliseconds
+        let percentile = |p: usize| -> f64 {
+            let rank = (latencies.len() * p).div_ceil(100).max(1);
+            latencies.get(rank - 1).map_or(0.0, |latency| latency.as_secs_f64() * 1000.0)
+        };
+        json!({
+            "host": host,
+            "route": route,
+            "requests": self.requests,
+            "latency_ms": { "p50": percentile(50), "p95": percentile(95), "p99": percentile(99) },
+            "status": {
+                "1xx": self.classes[0],
+                "2xx": self.classes[1],
+                "3xx": self.classes[2],
+                "4xx": self.classes[3],
+                "5xx": self.classes[4],
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ErrorEntry {
+    time: DateTime<Local>,
+    host: Option<String>,
+    module: String,
+    message: String,
+}
+
+/// What the status page tells about a host's configuration
+#[derive(Debug)]
+struct HostSummary {
+    name: String,
+    listen: Vec<String>,
+    routes: Vec<String>,
+    tls: bool,
+    sessions: bool,
+    /// Proxies by route path
+    proxies: Vec<(String, Arc<Proxy>)>,
+}
+
+#[derive(Debug)]
+struct Status {
+    created: Instant,
+    connections: HashMap<RawFd, ConnectionInfo>,
+    requests: HashMap<String, HostRequests>,
+    /// Per host and route path
+    routes: HashMap<(String, String), RouteStats>,
+    errors: VecDeque<ErrorEntry>,
+    hosts: Vec<HostSummary>,
+}
+
+static STATUS: OnceLock<Mutex<Status>> = OnceLock::new();
+
+fn status() -> MutexGuard<'static, Status> {
+    STATUS.get_or_init(|| Mutex::new(Status {
+        created: Instant::now(),
+        connections: HashMap::new(),
+        requests: HashMap::new(),
+        routes: HashMap::new(),
+        errors: VecDeque::new(),
+        hosts: Vec::new(),
+    }))
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn connection_opened(fd: RawFd, peer: Option<SocketAddr>, host: &str) {
+    let info = ConnectionInfo { peer, host: host.to_string(), phase: ConnectionPhase::Reading, opened: Instant::now() };
+    status().connections.insert(fd, info);
+}
+
+pub fn connection_phase(fd: RawFd, phase: ConnectionPhase) {
+    if let Some(connection) = status().connections.get_mut(&fd) {
+        connection.phase = phase;
+    }
+}
+
+pub fn connection_closed(fd: RawFd) {
+    status().connections.remove(&fd);
+}
+
+/// Counts a request answered by `host` with `status_code`
+pub fn record_request(host: &str, status_code: u16) {
+    let mut status = status();
+    let second = status.created.elapsed().as_secs();
+    status.requests.entry(host.to_string()).or_default().record(second, status_code >= 500);
+}
+
+/// Counts a request of `route` answered with `status_code` after `latency`
+pub fn record_route(host: &str, route: &str, status_code: u16, latency: Duration) {
+    status().routes.entry((host.to_string(), route.to_string())).or_default().record(status_code, latency);
+}
+
+/// Latency percentiles and status classes of the recent requests of every route
+pub fn route_stats() -> Value {
+    let status = status();
+    let mut routes: Vec<_> = status.routes.iter().collect();
+    routes.sort_by_key(|(key, _)| *key);
+    let routes: Vec<Value> = routes.into_iter()
+        .map(|((host, route), stats)| stats.to_json(host, route))
+        .collect();
+    let upstreams: Vec<Value> = status.hosts.iter()
+        .flat_map(|host| host.proxies.iter().flat_map(move |(route, proxy)| {
+            proxy.upstreams.iter().map(move |upstream| {
+                let mut upstream = upstream.to_json();
+                upstream["host"] = json!(host.name);
+                upstream["route"] = json!(route);
+                upstream
+            })
+        }))
+        .collect();
+    json!({ "routes": routes, "upstreams": upstreams })
+}
+
+/// Requests `host` answered over the last minute, and how many of them with a 5xx status
+pub fn recent_requests(host: &str) -> (u64, u64) {
+    let status = status();
+    let second = status.created.elapsed().as_secs();
+    status.requests.get(host).map_or((0, 0), |requests| requests.recent(second))
+}
+
+/// Keeps an error message for the status page, dropping the oldest past `RECENT_ERRORS`
+pub fn record_error(host: Option<&str>, module: &str, message: &str) {
+    let mut status = status();
+    if status.errors.len() == RECENT_ERRORS {
+        status.errors.pop_front();
+    }
+    status.errors.push_back(ErrorEntry {
+        time: Local::now(),
+        host: host.map(str::to_string),
+        module: module.to_string(),
+        message: message.to_string(),
+    });
+}
+
+/// Replaces the summary of the hosts served
+pub fn set_hosts(hosts: &[Host]) {
+    status().hosts = hosts.iter()
+        .map(|host| HostSummary {
+            name: host.server_name.clone(),
+            listen: host.listen.iter().map(|addr| addr.to_string()).collect(),
+            routes: host.routes.iter().map(|route| route.path.clone()).collect(),
+            tls: host.tls.is_some(),
+            sessions: host.session_manager.is_some(),
+            proxies: host.routes.iter()
+                .filter_map(|route| Some((route.path.clone(), route.proxy.clone()?)))
+                .collect(),
+        })
+        .collect();
+}
+
+/// The current status, newest errors first
+pub fn snapshot() -> Value {
+    let status = status();
+    let second = status.created.elapsed().as_secs();
+
+    let mut connections: Vec<_> = status.connections.iter().collect();
+    connections.sort_by_key(|(fd, _)| **fd);
+    let connections: Vec<Value> = connections.into_iter()
+        .map(|(fd, connection)| json!({
+            "fd": fd,
+            "peer": connection.peer.map(|peer| peer.to_string()),
+            "host": connection.host,
+            "state": connection.phase.as_str(),
+            "age_secs": connection.opened.elapsed().as_secs(),
+        }))
+        .collect();
+
+    let hosts: Vec<Value> = status.hosts.iter()
+        .map(|host| {
+            let requests = status.requests.get(&host.name);
+            json!({
+                "name": host.name,
+                "listen": host.listen,
+                "routes": host.routes,
+                "tls": host.tls,
+                "sessions": host.sessions,
+                "requests": requests.map_or(0, |requests| requests.total),
+                "server_errors": requests.map_or(0, |requests| requests.server_errors),
+                "requests_per_sec": requests.map_or(0.0, |requests| requests.rate(second)),
+            })
+        })
+        .collect();
+
+    let errors: Vec<Value> = status.errors.iter().rev()
+        .map(|error| json!({
+            "time": error.time.to_rfc3339(),
+            "host": error.host,
+            "module": error.module,
+            "message": error.message,
+        }))
+        .collect();
+
+    json!({
+        "uptime_secs": server::uptime().as_secs(),
+        "connections": connections,
+        "hosts": hosts,
+        "recent_errors": errors,
+    })
+}
+
+/// Renders a snapshot as a page reloading itself every few seconds
+pub fn render_html(snapshot: &Value) -> String {
+    let rows = |key: &str, columns: &[&str]| -> String {
+        snapshot[key].as_array().into_iter().flatten()
+            .map(|row| {
+                let cells: String = columns.iter()
+                    .map(|column| format!("<td>{}</td>", escape::html(&cell(&row[*column]))))
+                    .collect();
+                format!("<tr>{}</tr>\n", cells)
+            })
+            .collect()
+    };
+    let header = |columns: &[&str]| -> String {
+        columns.iter().map(|column| format!("<th>{}</th>", column)).collect()
+    };
+
+    const CONNECTIONS: &[&str] = &["fd", "peer", "host", "state", "age_secs"];
+    const HOSTS: &[&str] = &["name", "listen", "routes", "tls", "sessions", "requests", "server_errors", "requests_per_sec"];
+    const ERRORS: &[&str] = &["time", "host", "module", "message"];
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"5\">\n\
+         <title>Server status</title>\n</head>\n<body>\n<h1>Server status</h1>\n<p>Up for {} seconds</p>\n\
+         <h2>Connections</h2>\n<table>\n<tr>{}</tr>\n{}</table>\n\
+         <h2>Hosts</h2>\n<table>\n<tr>{}</tr>\n{}</table>\n\
+         <h2>Recent errors</h2>\n<table>\n<tr>{}</tr>\n{}</table>\n</body>\n</html>\n",
+        snapshot["uptime_secs"],
+        header(CONNECTIONS), rows("connections", CONNECTIONS),
+        header(HOSTS), rows("hosts", HOSTS),
+        header(ERRORS), rows("recent_errors", ERRORS),
+    )
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(", "),
+        Value::Number(number) if number.is_f64() => format!("{:.2}", number.as_f64().unwrap_or_default()),
+        other => other.to_string(),
+    }
+}
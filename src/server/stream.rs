@@ -6,11 +6,59 @@
 
 pub mod request_stream {
     use std::io;
+    use crate::http::body::Body;
+    use crate::http::request::{check_header_limits, check_version, validate_head, MAX_HEADERS};
+    use crate::http::upgrade::Socket;
 
     /// Size of the read buffer for processing requests
     const BUFFER_SIZE: usize = 8192;
     /// Maximum allowed size for a complete request
     const MAX_REQUEST_SIZE: usize = 10 * 1024 * 1024; // 10MB
+    /// Capacity past which the bytes of a request aren't kept to read the next
+    const MAX_SPARE_CAPACITY: usize = 8 * BUFFER_SIZE;
+    /// Default size of a request's headers, its request line included
+    pub const DEFAULT_MAX_HEADER_SIZE: usize = 16 * 1024;
+
+    /// How large and how many the headers of a request may be
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct HeaderLimits {
+        /// Bytes of the head, the request line included
+        pub max_size: usize,
+        /// Header lines, at most `MAX_HEADERS`
+        pub max_count: usize,
+    }
+
+    impl Default for HeaderLimits {
+        fn default() -> Self {
+            HeaderLimits { max_size: DEFAULT_MAX_HEADER_SIZE, max_count: MAX_HEADERS }
+        }
+    }
+
+    /// Error of a request whose headers are past its `HeaderLimits`, answered
+    /// with `431`
+    #[derive(Debug)]
+    pub struct HeadersTooLarge(pub String);
+
+    impl std::fmt::Display for HeadersTooLarge {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl std::error::Error for HeadersTooLarge {}
+
+    /// Error of a request whose body is past the maximum request size, answered
+    /// with `413`
+    #[derive(Debug)]
+    pub struct BodyTooLarge(pub String);
+
+    impl std::fmt::Display for BodyTooLarge {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl std::error::Error for BodyTooLarge {}
 
     /// Represents the complete request data including headers and body
     #[derive(Debug, Clone)]
@@ -19,6 +67,8 @@ pub mod request_stream {
         pub data: Vec<u8>,
         /// Position marking the end of headers and start of body
         headers_end: usize,
+        /// Body already consumed by a `BodySink`; `data` then only holds the headers
+        pub body: Option<Body>,
     }
 
     /// Methods for accessing request data components
@@ -34,6 +84,18 @@ pub mod request_stream {
         }
     }
 
+    /// Receives a request body incrementally instead of having it buffered
+    pub trait BodySink {
+        /// Consumes the next decoded slice of the body
+        fn write_body(&mut self, data: &[u8]) -> io::Result<()>;
+
+        /// Called once the whole body was received, returns the resulting body
+        fn finish(&mut self) -> io::Result<Body>;
+    }
+
+    /// Decides from the raw request head whether its body should be streamed
+    pub type BodySinkFactory = Box<dyn Fn(&[u8]) -> Option<Box<dyn BodySink>>>;
+
     /// Represents different types of request body handling
     #[derive(Debug)]
     enum ReaderType {
@@ -77,9 +139,28 @@ pub mod request_stream {
         
         /// Resets the stream state for processing a new request
         fn reset(&mut self);
+
+        /// Gives back the bytes of a completed request, for the next one to
+        /// be read into them
+        fn recycle(&mut self, _data: Vec<u8>) {}
         
         /// Returns true if a complete request has been received
         fn is_complete(&self) -> bool;
+
+        /// Returns true until the first byte of the next request comes
+        fn is_idle(&self) -> bool;
+
+        /// Reads bytes as they come, for connections no longer speaking HTTP
+        /// once upgraded
+        fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+        /// Writes as much of `buf` as the stream takes without blocking,
+        /// returning how much that was
+        fn write_some(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+        /// Gives up the client socket, for a connection leaving the server
+        /// once upgraded
+        fn into_socket(self: Box<Self>) -> Box<dyn Socket>;
     }
 
     /// Implementation of unified request reading with support for both
@@ -104,6 +185,18 @@ pub mod request_stream {
             current_chunk_size: Option<usize>,
             /// Maximum allowed size for a complete request
             max_request_size: usize,
+            /// Size and number of headers allowed
+            header_limits: HeaderLimits,
+            /// Optional factory used to stream bodies instead of buffering them
+            body_sink_factory: Option<BodySinkFactory>,
+            /// Sink receiving the body of the current request, if streaming
+            body_sink: Option<Box<dyn BodySink>>,
+            /// Number of body bytes already handed to the sink
+            streamed_len: usize,
+            /// Bytes of a completed request, reused to read the next one
+            spare: Vec<u8>,
+            /// Whether a request was completed since the last reset
+            completed: bool,
         }
 
         /// Implementation of UnifiedReader for handling HTTP request streams
@@ -113,11 +206,13 @@ pub mod request_stream {
         /// 
         /// # Examples
         /// ```
-        /// use std::net::TcpStream;
+        /// use std::os::unix::net::UnixStream;
+        /// use localhost::server::stream::request_stream::unifiedReader::UnifiedReader;
         /// 
-        /// let stream = TcpStream::new();
+        /// let (stream, _client) = UnixStream::pair().unwrap();
         /// let reader = UnifiedReader::new(stream);
         /// // Or with custom max request size:
+        /// let (stream, _client) = UnixStream::pair().unwrap();
         /// let reader = UnifiedReader::with_max_size(stream, 5 * 1024 * 1024); // 5MB
         /// ```
         /// 
@@ -157,8 +252,22 @@ pub mod request_stream {
                     temp_chunk_headers: None,
                     current_chunk_size: None,
                     max_request_size,
+                    header_limits: HeaderLimits::default(),
+                    body_sink_factory: None,
+                    body_sink: None,
+                    streamed_len: 0,
+                    spare: Vec::new(),
+                    completed: false,
                 }
             }
+
+            /// Registers a factory deciding which request bodies are streamed
+            ///
+            /// Bodies handed to a sink are not kept in memory; the completed
+            /// `RequestData` carries the sink's result in `body` instead.
+            pub fn set_body_sink_factory(&mut self, factory: BodySinkFactory) {
+                self.body_sink_factory = Some(factory);
+            }
             
             /// Sets a new maximum request size
             /// 
@@ -173,39 +282,59 @@ pub mod request_stream {
                 self.max_request_size
             }
 
+            /// Sets how large and how many the headers of a request may be,
+            /// those past them being refused before the head is complete
+            pub fn set_header_limits(&mut self, limits: HeaderLimits) {
+                self.header_limits = limits;
+            }
+
             fn determine_reader_type(data: &[u8], headers_end: usize) -> ReaderType {
-                if let Ok(headers_str) = String::from_utf8(data[..headers_end].to_vec()) {
-                    if headers_str.lines().any(|line| line.to_lowercase().contains("transfer-encoding: chunked")) {
+                if let Ok(headers_str) = std::str::from_utf8(&data[..headers_end]) {
+                    // Looked up without a lowercased copy of every line
+                    let header = |name: &str| headers_str.lines()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(line_name, _)| line_name.eq_ignore_ascii_case(name))
+                        .map(|(_, value)| value.trim());
+
+                    if header("transfer-encoding").and_then(|value| value.get(..7)).is_some_and(|value| value.eq_ignore_ascii_case("chunked")) {
                         return ReaderType::Chunked;
                     }
 
-                    if let Some(content_length) = headers_str.lines()
-                        .find(|line| line.to_lowercase().starts_with("content-length:"))
-                        .and_then(|line| line.split(':').nth(1))
-                        .and_then(|len| len.trim().parse::<usize>().ok()) {
+                    if let Some(content_length) = header("content-length")
+                        .and_then(|len| len.parse::<usize>().ok()) {
                         return ReaderType::Standard { content_length };
                     }
                 }
                 ReaderType::Standard { content_length: 0 }
             }
 
+            /// Hands out a completed request, its bytes leaving the reader
+            fn complete(&mut self, data: RequestData) -> io::Result<RequestState> {
+                self.completed = true;
+                Ok(RequestState::Complete(data))
+            }
+
             fn process_standard_body(
                 &mut self,
                 mut accumulated_data: Vec<u8>,
                 headers_end: usize,
                 content_length: usize,
             ) -> io::Result<RequestState> {
-                let total_expected = headers_end + content_length;
-
-                // Check if expected total size exceeds maximum
-                if total_expected > self.max_request_size {
-                    return Err(io::Error::new(
+                // Check if expected total size exceeds maximum, a length past
+                // what can be counted being too large as well
+                let total_expected = match headers_end.checked_add(content_length) {
+                    Some(total) if total <= self.max_request_size => total,
+                    _ => return Err(io::Error::new(
                         io::ErrorKind::InvalidData,
-                        format!("Request size of {} bytes exceeds maximum of {} bytes", 
-                                total_expected, self.max_request_size)
-                    ));
-                }
+                        BodyTooLarge(format!("Request size of {} bytes exceeds maximum of {} bytes",
+                                headers_end as u128 + content_length as u128, self.max_request_size))
+                    )),
+                };
                 
+                if self.body_sink.is_some() {
+                    return self.stream_standard_body(accumulated_data, headers_end, content_length);
+                }
+
                 // Try to read as much as possible in one function call
                 while accumulated_data.len() < total_expected {
                     let mut temp_buffer = [0u8; BUFFER_SIZE];
@@ -229,23 +358,72 @@ pub mod request_stream {
                     }
                 }
                 
-                // If we get here, we have all the data we need
-                let request_data = RequestData {
-                    data: accumulated_data[..total_expected].to_vec(),
+                // If we get here, we have all the data we need. Any excess
+                // data is saved for the next request.
+                self.buffer.clear();
+                self.buffer.extend_from_slice(&accumulated_data[total_expected..]);
+                accumulated_data.truncate(total_expected);
+
+                self.complete(RequestData {
+                    data: accumulated_data,
                     headers_end,
+                    body: None,
+                })
+            }
+
+            /// Hands a Content-Length body to the body sink as it arrives
+            fn stream_standard_body(
+                &mut self,
+                mut accumulated_data: Vec<u8>,
+                headers_end: usize,
+                content_length: usize,
+            ) -> io::Result<RequestState> {
+                // Body bytes read along with the headers
+                let mut pending = accumulated_data.split_off(headers_end);
+
+                loop {
+                    let remaining = content_length - self.streamed_len;
+                    let take = remaining.min(pending.len());
+                    if take > 0 {
+                        self.sink_body(&pending[..take])?;
+                    }
+                    if take == remaining {
+                        // Save any excess data for the next request
+                        self.buffer = pending[take..].to_vec();
+                        return self.complete_streamed(accumulated_data, headers_end);
+                    }
+
+                    let mut temp_buffer = [0u8; BUFFER_SIZE];
+                    match self.stream.read(&mut temp_buffer) {
+                        Ok(0) => return Ok(RequestState::EndOfStream),
+                        Ok(bytes_read) => pending = temp_buffer[..bytes_read].to_vec(),
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                            self.state = RequestState::ProcessingBody {
+                                accumulated_data,
+                                headers_end,
+                            };
+                            return Ok(self.state.clone());
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+
+            fn sink_body(&mut self, data: &[u8]) -> io::Result<()> {
+                if let Some(sink) = self.body_sink.as_mut() {
+                    sink.write_body(data)?;
+                }
+                self.streamed_len += data.len();
+                Ok(())
+            }
+
+            fn complete_streamed(&mut self, data: Vec<u8>, headers_end: usize) -> io::Result<RequestState> {
+                let body = match self.body_sink.take() {
+                    Some(mut sink) => Some(sink.finish()?),
+                    None => None,
                 };
-                
-                // Save any excess data for the next request
-                self.buffer = if accumulated_data.len() > total_expected {
-                    accumulated_data[total_expected..].to_vec()
-                } else {
-                    Vec::new()
-                };
-                
-                self.state = RequestState::Complete(request_data);
-                Ok(self.state.clone())
+                self.complete(RequestData { data, headers_end, body })
             }
-            
 
             fn process_chunked_body(
                 &mut self,
@@ -253,56 +431,73 @@ pub mod request_stream {
                 headers_end: usize,
             ) -> io::Result<RequestState> {
                 loop {
-                    // Read chunk size if necessary
-                    if self.current_chunk_size.is_none() {
-                        if let Some(line_end) = find_line_end(&self.buffer) {
-                            let size_line = &self.buffer[..line_end - 2];
-                            if let Some(size) = parse_chunk_size(size_line) {
-                                // Check if adding this chunk would exceed the maximum request size
-                                if accumulated_data.len() + size > self.max_request_size {
+                    match self.current_chunk_size {
+                        // Read chunk size if necessary
+                        None => {
+                            if let Some(line_end) = find_line_end(&self.buffer) {
+                                let size = parse_chunk_size(&self.buffer[..line_end - 2]).ok_or_else(|| io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    "Invalid chunk size line"
+                                ))?;
+
+                                // Check if adding this chunk would exceed the maximum request size,
+                                // the size being the client's it can be past what can be counted
+                                let total = accumulated_data.len()
+                                    .checked_add(self.streamed_len)
+                                    .and_then(|total| total.checked_add(size));
+                                if total.is_none_or(|total| total > self.max_request_size) {
                                     return Err(io::Error::new(
                                         io::ErrorKind::InvalidData,
-                                        format!("Chunked request exceeds maximum size of {} bytes", 
-                                                self.max_request_size)
+                                        BodyTooLarge(format!("Chunked request exceeds maximum size of {} bytes",
+                                                self.max_request_size))
                                     ));
                                 }
-                                
+
+                                self.buffer.drain(..line_end);
                                 if size == 0 {
                                     // Final chunk - complete request
-                                    self.state = RequestState::Complete(RequestData {
-                                        data: accumulated_data,
-                                        headers_end,
-                                    });
-                                    return Ok(self.state.clone());
+                                    if self.buffer.starts_with(b"\r\n") {
+                                        self.buffer.drain(..2);
+                                    }
+                                    return self.complete_streamed(accumulated_data, headers_end);
                                 }
                                 self.current_chunk_size = Some(size);
-                                self.buffer = self.buffer[line_end..].to_vec();
+                                continue;
                             }
                         }
-                    }
-            
-                    if let Some(chunk_size) = self.current_chunk_size {
-                        if self.buffer.len() >= chunk_size + 2 {
-                            // Append chunk data to accumulated data
-                            accumulated_data.extend_from_slice(&self.buffer[..chunk_size]);
-                            self.buffer = self.buffer[chunk_size + 2..].to_vec();
-                            self.current_chunk_size = None;
-                            
-                        } else {
-                            let mut temp_buffer = [0u8; BUFFER_SIZE];
-                            let bytes_read = self.stream.read(&mut temp_buffer)?;
-                            if bytes_read == 0 {
-                                return Ok(RequestState::EndOfStream);
+                        Some(chunk_size) if self.buffer.len() >= chunk_size + 2 => {
+                            // Append chunk data to accumulated data, or stream it out
+                            let chunk: Vec<u8> = self.buffer.drain(..chunk_size + 2).take(chunk_size).collect();
+                            if self.body_sink.is_some() {
+                                self.sink_body(&chunk)?;
+                            } else {
+                                accumulated_data.extend_from_slice(&chunk);
                             }
-                            self.buffer.extend_from_slice(&temp_buffer[..bytes_read]);
+                            self.current_chunk_size = None;
+                            continue;
                         }
+                        Some(_) => {}
+                    }
+
+                    let mut temp_buffer = [0u8; BUFFER_SIZE];
+                    match self.stream.read(&mut temp_buffer) {
+                        Ok(0) => return Ok(RequestState::EndOfStream),
+                        Ok(bytes_read) => self.buffer.extend_from_slice(&temp_buffer[..bytes_read]),
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                            // Keep the decoded data so far for the next call
+                            self.state = RequestState::ProcessingBody {
+                                accumulated_data,
+                                headers_end,
+                            };
+                            return Ok(self.state.clone());
+                        },
+                        Err(e) => return Err(e),
                     }
                 }
             }
-            
         }
 
-        impl<S: Read + Write> RequestStream for UnifiedReader<S> {
+        impl<S: Read + Write + Send + 'static> RequestStream for UnifiedReader<S> {
             /// Reads the next chunk of data from the stream and processes it according to the current state
             /// 
             /// # Returns
@@ -310,12 +505,16 @@ pub mod request_stream {
             /// - `Err(io::Error)` - If an error occurs during reading or processing
             fn read_next(&mut self) -> io::Result<RequestState> {
                 let mut temp_buffer = [0u8; BUFFER_SIZE];
-                match self.state.clone() {
-                    // When awaiting headers, try to read until we find the header boundary
-                    RequestState::AwaitingHeaders => {
-                        match self.stream.read(&mut temp_buffer)? {
-                            0 => Ok(RequestState::EndOfStream),
-                            n => {
+                // Taken rather than cloned, the body read so far can be large
+                match std::mem::replace(&mut self.state, RequestState::AwaitingHeaders) {
+                    // When awaiting headers, read until we find the header boundary or
+                    // the stream has nothing more, the next event coming with more data
+                    RequestState::AwaitingHeaders => loop {
+                        match self.stream.read(&mut temp_buffer) {
+                            Ok(0) => return Ok(RequestState::EndOfStream),
+                            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(RequestState::AwaitingHeaders),
+                            Err(e) => return Err(e),
+                            Ok(n) => {
                                 self.buffer.extend_from_slice(&temp_buffer[..n]);
                                 
                                 // Check if headers exceed maximum size
@@ -326,25 +525,45 @@ pub mod request_stream {
                                     ));
                                 }
                                 
-                                if let Some(headers_end) = find_headers_end(&self.buffer) {
-                                    let accumulated_data = self.buffer.clone();
-                                    self.reader_type = Self::determine_reader_type(&accumulated_data, headers_end);
-                                    self.buffer = self.buffer[headers_end..].to_vec();
+                                let headers_end = find_headers_end(&self.buffer);
+                                // Checked as the head comes, so that a client never ending it
+                                // is refused once past the limits
+                                let head = &self.buffer[..headers_end.unwrap_or(self.buffer.len())];
+                                check_header_limits(head, self.header_limits.max_size, self.header_limits.max_count)
+                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, HeadersTooLarge(e)))?;
+
+                                if let Some(headers_end) = headers_end {
+                                    check_version(&self.buffer[..headers_end])
+                                        .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, e))?;
+                                    // The body length is read from the headers, they must be unambiguous
+                                    validate_head(&self.buffer[..headers_end])
+                                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                                    self.reader_type = Self::determine_reader_type(&self.buffer, headers_end);
+                                    if let Some(factory) = &self.body_sink_factory {
+                                        self.body_sink = factory(&self.buffer[..headers_end]);
+                                    }
+                                    // The bytes read so far are the request's, the spare
+                                    // buffer takes what comes next
+                                    let spare = std::mem::take(&mut self.spare);
+                                    let mut accumulated_data = std::mem::replace(&mut self.buffer, spare);
+                                    // Chunked bodies are decoded from `buffer` into the accumulated data
+                                    if matches!(self.reader_type, ReaderType::Chunked) {
+                                        self.buffer.extend_from_slice(&accumulated_data[headers_end..]);
+                                        accumulated_data.truncate(headers_end);
+                                    }
                                     self.state = RequestState::ProcessingBody { 
                                         accumulated_data,
                                         headers_end,
                                     };
-                                    self.read_next()
-                                } else {
-                                    Ok(RequestState::AwaitingHeaders)
+                                    return self.read_next();
                                 }
                             }
                         }
-                    }
+                    },
 
                     // When processing body, handle according to transfer type (chunked or standard)
                     RequestState::ProcessingBody { accumulated_data, headers_end } => {
-                        match self.reader_type {
+                        let state = match self.reader_type {
                             ReaderType::Unknown => Ok(RequestState::EndOfStream),
                             ReaderType::Standard { content_length } => {
                                 self.process_standard_body(accumulated_data, headers_end, content_length)
@@ -352,14 +571,22 @@ pub mod request_stream {
                             ReaderType::Chunked => {
                                 self.process_chunked_body(accumulated_data, headers_end)
                             }
+                        }?;
+                        // A client leaving midway isn't mistaken for an idle one
+                        if matches!(state, RequestState::EndOfStream) {
+                            self.state = RequestState::EndOfStream;
                         }
+                        Ok(state)
                     }
 
-                    // For completed requests, just return the current state
+                    // Completed requests are handed out, never kept as the state
                     RequestState::Complete(data) => Ok(RequestState::Complete(data)),
 
                     // For end of stream, return the end state
-                    RequestState::EndOfStream => Ok(RequestState::EndOfStream),
+                    RequestState::EndOfStream => {
+                        self.state = RequestState::EndOfStream;
+                        Ok(RequestState::EndOfStream)
+                    }
                 }
             }
 
@@ -404,11 +631,42 @@ pub mod request_stream {
                 self.reader_type = ReaderType::Unknown;
                 self.current_chunk_size = None;
                 self.temp_chunk_headers = None;
+                self.body_sink = None;
+                self.streamed_len = 0;
+                self.completed = false;
+            }
+
+            fn recycle(&mut self, mut data: Vec<u8>) {
+                // A large request's bytes aren't kept for the whole connection
+                if data.capacity() <= MAX_SPARE_CAPACITY {
+                    data.clear();
+                    self.spare = data;
+                }
             }
 
             /// Returns true if a complete request has been received
             fn is_complete(&self) -> bool {
-                matches!(self.state, RequestState::Complete(_))
+                self.completed
+            }
+
+            fn is_idle(&self) -> bool {
+                matches!(self.state, RequestState::AwaitingHeaders) && self.buffer.is_empty()
+            }
+
+            fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.stream.read(buf)
+            }
+
+            fn write_some(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let written = self.stream.write(buf)?;
+                match self.stream.flush() {
+                    Err(e) if e.kind() != ErrorKind::WouldBlock => Err(e),
+                    _ => Ok(written),
+                }
+            }
+
+            fn into_socket(self: Box<Self>) -> Box<dyn Socket> {
+                Box::new(self.stream)
             }
         }
     }
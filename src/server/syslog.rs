@@ -0,0 +1,186 @@
+//! System log outputs
+//!
+//! The logger can hand its messages to the system instead of the standard
+//! output: to a syslog daemon as RFC 5424 messages, through a Unix datagram
+//! socket like `/dev/log` or over UDP, or to journald through its native
+//! protocol, which keeps the module of each message as a field.
+
+use std::io;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Local, SecondsFormat};
+use crate::server::logger::LogLevel;
+
+/// Socket of the local syslog daemon
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+/// The `daemon` facility
+pub const DEFAULT_FACILITY: u8 = 3;
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Facility names and codes of RFC 5424
+const FACILITIES: [(&str, u8); 20] = [
+    ("kern", 0), ("user", 1), ("mail", 2), ("daemon", 3), ("auth", 4), ("syslog", 5),
+    ("lpr", 6), ("news", 7), ("uucp", 8), ("cron", 9), ("authpriv", 10), ("ftp", 11),
+    ("local0", 16), ("local1", 17), ("local2", 18), ("local3", 19),
+    ("local4", 20), ("local5", 21), ("local6", 22), ("local7", 23),
+];
+
+/// Where syslog messages are sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyslogAddress {
+    Unix(PathBuf),
+    Udp(String),
+}
+
+impl SyslogAddress {
+    /// Parses `unix:<path>`, a bare absolute path, or `udp:<host>:<port>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:").or(value.starts_with('/').then_some(value)) {
+            return Ok(SyslogAddress::Unix(PathBuf::from(path)));
+        }
+        match value.strip_prefix("udp:") {
+            Some(address) if address.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) => {
+                Ok(SyslogAddress::Udp(address.to_string()))
+            }
+            _ => Err(format!("'{}' is not a syslog address, expected unix:<path> or udp:<host>:<port>", value)),
+        }
+    }
+}
+
+/// Code of a facility name like "daemon" or "local0"
+pub fn parse_facility(name: &str) -> Option<u8> {
+    let name = name.trim().to_ascii_lowercase();
+    FACILITIES.iter().find(|(known, _)| *known == name).map(|(_, code)| *code)
+}
+
+/// Syslog severity of a level, TRACE sharing `debug` with DEBUG
+fn severity(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::ERROR => 3,
+        LogLevel::WARN => 4,
+        LogLevel::INFO => 6,
+        LogLevel::DEBUG | LogLevel::TRACE => 7,
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+#[derive(Debug)]
+pub struct Syslog {
+    transport: Transport,
+    facility: u8,
+    app_name: String,
+    hostname: String,
+}
+
+impl Syslog {
+    pub fn connect(address: &SyslogAddress, facility: u8, app_name: &str) -> io::Result<Self> {
+        let transport = match address {
+            SyslogAddress::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Transport::Unix(socket)
+            }
+            SyslogAddress::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(address)?;
+                Transport::Udp(socket)
+            }
+        };
+        Ok(Syslog { transport, facility, app_name: header_field(app_name, 48), hostname: hostname() })
+    }
+
+    /// The RFC 5424 message of `message`, its module as the MSGID
+    pub fn format(&self, level: LogLevel, module: &str, message: &str, time: DateTime<Local>) -> String {
+        format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            self.facility * 8 + severity(level),
+            time.to_rfc3339_opts(SecondsFormat::Millis, false),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            header_field(module, 32),
+            message,
+        )
+    }
+
+    pub fn send(&self, level: LogLevel, module: &str, message: &str, time: DateTime<Local>) -> io::Result<()> {
+        let line = self.format(level, module, message, time);
+        match &self.transport {
+            Transport::Unix(socket) => socket.send(line.as_bytes()),
+            Transport::Udp(socket) => socket.send(line.as_bytes()),
+        }
+        .map(|_| ())
+    }
+}
+
+#[derive(Debug)]
+pub struct Journald {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl Journald {
+    pub fn connect(identifier: &str) -> io::Result<Self> {
+        Self::connect_to(Path::new(JOURNALD_SOCKET), identifier)
+    }
+
+    pub fn connect_to(path: &Path, identifier: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Journald { socket, identifier: identifier.to_string() })
+    }
+
+    /// The fields of a message in the journal native protocol, values holding a
+    /// newline being sent with their length
+    pub fn encode(&self, level: LogLevel, module: &str, message: &str) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        let priority = severity(level).to_string();
+        for (name, value) in [
+            ("MESSAGE", message),
+            ("PRIORITY", priority.as_str()),
+            ("SYSLOG_IDENTIFIER", self.identifier.as_str()),
+            ("CODE_MODULE", module),
+        ] {
+            datagram.extend_from_slice(name.as_bytes());
+            if value.contains('\n') {
+                datagram.push(b'\n');
+                datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                datagram.push(b'=');
+            }
+            datagram.extend_from_slice(value.as_bytes());
+            datagram.push(b'\n');
+        }
+        datagram
+    }
+
+    pub fn send(&self, level: LogLevel, module: &str, message: &str) -> io::Result<()> {
+        self.socket.send(&self.encode(level, module, message)).map(|_| ())
+    }
+}
+
+/// A header field of RFC 5424: printable ASCII without spaces, "-" when empty
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value.chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if field.is_empty() { "-".to_string() } else { field }
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return "-".to_string();
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    header_field(&String::from_utf8_lossy(&buffer[..len]), 255)
+}
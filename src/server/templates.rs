@@ -0,0 +1,127 @@
+//! HTML pages rendered from Handlebars templates
+//!
+//! A [`TemplateHandler`] renders its template with the JSON of its data
+//! source: a file, the output of a CGI script or the response of another
+//! handler. Templates are read again when they change, so pages can be edited
+//! without restarting the server. Besides the data, templates see the request
+//! under `request`: its `method`, `path`, `query` and `params`, and the
+//! `session` data on the routes requiring a session. Values are escaped as
+//! HTML unless written with triple braces.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use handlebars::Handlebars;
+use serde_json::{json, Map, Value};
+use crate::config::config::ConfigError;
+use crate::http::body::Body;
+use crate::http::response::Response;
+use crate::http::request::Request;
+use crate::http::status::HttpStatusCode;
+use crate::server::cgi::CGIConfig;
+use crate::server::errors::{HttpError, ServerError};
+use crate::server::extract::{extract, PathParams, Query};
+use crate::server::handlers::handlers::cgi_api::CGIHandler;
+use crate::server::handlers::handlers::{Handler, RouteHandler};
+use crate::server::route::Route;
+
+/// Name the page template is registered under
+const PAGE: &str = "page";
+
+/// Where the data of a template comes from
+#[derive(Clone, Debug)]
+pub enum TemplateData {
+    /// Nothing but the request
+    None,
+    /// A JSON file, read on every request
+    File(PathBuf),
+    /// The JSON a CGI script prints
+    Cgi(CGIConfig),
+    /// The JSON body a handler answers with
+    Handler(RouteHandler),
+}
+
+/// Answers with its template rendered as HTML
+pub struct TemplateHandler {
+    registry: Handlebars<'static>,
+    data: TemplateData,
+}
+
+impl TemplateHandler {
+    pub fn new(template: impl AsRef<Path>, data: TemplateData) -> Result<Self, ServerError> {
+        let mut registry = Handlebars::new();
+        registry.set_dev_mode(true);
+        registry.register_template_file(PAGE, template.as_ref())
+            .map_err(|e| invalid(template.as_ref(), e))?;
+        Ok(TemplateHandler { registry, data })
+    }
+
+    /// Registers the `.hbs` files of `dir` as partials, named after their
+    /// file, like `{{> header}}` for `header.hbs`
+    pub fn with_partials(mut self, dir: impl AsRef<Path>) -> Result<Self, ServerError> {
+        let entries = fs::read_dir(dir.as_ref()).map_err(|e| invalid(dir.as_ref(), e))?;
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension().is_some_and(|extension| extension == "hbs") {
+                let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+                self.registry.register_template_file(name, &path).map_err(|e| invalid(&path, e))?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// The data of the template, unless its source answered with an error
+    fn data(&self, request: &Request, route: &Route) -> Result<Result<Value, Response>, ServerError> {
+        let response = match &self.data {
+            TemplateData::None => return Ok(Ok(Value::Object(Map::new()))),
+            TemplateData::File(path) => {
+                let file = fs::read(path)?;
+                return serde_json::from_slice(&file)
+                    .map(Ok)
+                    .map_err(|e| HttpError::InternalServerError(format!("Invalid data in {}: {}", path.display(), e)).into());
+            }
+            TemplateData::Cgi(config) => CGIHandler::new(config.clone()).serve_http(request, route)?,
+            TemplateData::Handler(handler) => handler.clone().serve_http(request, route)?,
+        };
+        if response.status_code.clone() as u16 >= 400 {
+            return Ok(Err(response));
+        }
+        let data = match &response.body {
+            Some(Body::Json(value)) => Ok(value.clone()),
+            Some(Body::Text(text)) => serde_json::from_str(text),
+            Some(Body::Binary(data)) => serde_json::from_slice(data),
+            _ => Ok(Value::Object(Map::new())),
+        };
+        data.map(Ok).map_err(|e| HttpError::BadGateway(format!("The data of the template isn't JSON: {}", e)).into())
+    }
+}
+
+impl Handler for TemplateHandler {
+    fn serve_http(&mut self, request: &Request, route: &Route) -> Result<Response, ServerError> {
+        let mut data = match self.data(request, route)? {
+            Ok(data) => data,
+            Err(response) => return Ok(response),
+        };
+        if let Value::Object(fields) = &mut data {
+            fields.entry("request").or_insert_with(|| request_data(request, route));
+        }
+        let page = self.registry.render(PAGE, &data)
+            .map_err(|e| HttpError::InternalServerError(format!("Cannot render the template: {}", e)))?;
+        Ok(Response::response_with_html(&page, HttpStatusCode::Ok))
+    }
+}
+
+/// What templates know of the request
+fn request_data(request: &Request, route: &Route) -> Value {
+    let Query(query) = extract::<Query<Map<String, Value>>>(request, route).unwrap_or(Query(Map::new()));
+    let PathParams(params) = extract::<PathParams>(request, route).unwrap_or_default();
+    json!({
+        "method": request.method.to_string(),
+        "path": request.uri.split('?').next().unwrap_or_default(),
+        "query": query,
+        "params": params,
+        "session": request.session.as_ref().map(|session| &session.data),
+    })
+}
+
+fn invalid(path: &Path, error: impl std::fmt::Display) -> ServerError {
+    ServerError::ConfigError(ConfigError::Critical(format!("Template {}: {}", path.display(), error)))
+}
@@ -0,0 +1,151 @@
+//! HTTPS
+//!
+//! Hosts with a `tls` block serve HTTPS on their ports. The first byte a client
+//! sends tells a TLS handshake from a plain HTTP request, so the same port can
+//! answer plain requests with a redirect to HTTPS when `redirect_http` is set.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned, SupportedProtocolVersion};
+
+/// First byte of a TLS record carrying a handshake
+const HANDSHAKE_RECORD: u8 = 0x16;
+
+static TLS13_ONLY: [&SupportedProtocolVersion; 1] = [&rustls::version::TLS13];
+
+/// Builds the rustls configuration of a host, failing when the files can't be
+/// read, the key doesn't match the certificate or no cipher suite is left
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    min_version: Option<&str>,
+    ciphers: Option<&[String]>,
+) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Cannot read certificate {}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert.display()));
+    }
+    let key_der = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("Cannot read private key {}: {}", key.display(), e))?;
+
+    let mut provider = ring::default_provider();
+    if let Some(ciphers) = ciphers {
+        let unknown: Vec<_> = ciphers.iter()
+            .filter(|name| !provider.cipher_suites.iter().any(|suite| suite_name(suite) == name.as_str()))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!("Unknown cipher suites {:?}", unknown));
+        }
+        provider.cipher_suites.retain(|suite| ciphers.iter().any(|name| name == &suite_name(suite)));
+    }
+
+    let versions = protocol_versions(min_version)
+        .ok_or_else(|| format!("min_version '{}' is not 1.2 or 1.3", min_version.unwrap_or_default()))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .map_err(|e| format!("No usable cipher suite: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key_der)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+    Ok(Arc::new(config))
+}
+
+/// Names of the cipher suites that `ciphers` may list
+pub fn supported_ciphers() -> Vec<String> {
+    ring::default_provider().cipher_suites.iter().map(suite_name).collect()
+}
+
+fn suite_name(suite: &rustls::SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+fn protocol_versions(min_version: Option<&str>) -> Option<&'static [&'static SupportedProtocolVersion]> {
+    match min_version.map(str::trim) {
+        None | Some("1.2") => Some(rustls::ALL_VERSIONS),
+        Some("1.3") => Some(&TLS13_ONLY),
+        Some(_) => None,
+    }
+}
+
+/// A client stream of a TLS host, encrypted once the client opens with a handshake
+pub enum HostStream {
+    Detecting { stream: TcpStream, config: Arc<ServerConfig>, secure: Arc<AtomicBool> },
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+    Plain(TcpStream),
+    /// Left behind when setting up TLS failed
+    Closed,
+}
+
+impl HostStream {
+    /// `secure` is set once the client turns out to speak TLS
+    pub fn new(stream: TcpStream, config: Arc<ServerConfig>, secure: Arc<AtomicBool>) -> Self {
+        HostStream::Detecting { stream, config, secure }
+    }
+
+    /// Picks the protocol from the first byte sent, without consuming it.
+    /// Returns false when the client closed the connection before sending anything.
+    fn detect(&mut self) -> io::Result<bool> {
+        let HostStream::Detecting { stream, .. } = self else {
+            return Ok(true);
+        };
+        let mut first = [0u8; 1];
+        if stream.peek(&mut first)? == 0 {
+            return Ok(false);
+        }
+
+        let HostStream::Detecting { stream, config, secure } = std::mem::replace(self, HostStream::Closed) else {
+            unreachable!("matched above");
+        };
+        if first[0] == HANDSHAKE_RECORD {
+            let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+            secure.store(true, Ordering::SeqCst);
+            *self = HostStream::Tls(Box::new(StreamOwned::new(connection, stream)));
+        } else {
+            *self = HostStream::Plain(stream);
+        }
+        Ok(true)
+    }
+
+    fn closed() -> io::Error {
+        io::Error::new(io::ErrorKind::NotConnected, "TLS setup failed")
+    }
+}
+
+impl Read for HostStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.detect()? {
+            return Ok(0);
+        }
+        match self {
+            HostStream::Tls(stream) => stream.read(buf),
+            HostStream::Plain(stream) => stream.read(buf),
+            HostStream::Detecting { .. } | HostStream::Closed => Err(Self::closed()),
+        }
+    }
+}
+
+impl Write for HostStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            HostStream::Tls(stream) => stream.write(buf),
+            HostStream::Plain(stream) | HostStream::Detecting { stream, .. } => stream.write(buf),
+            HostStream::Closed => Err(Self::closed()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            HostStream::Tls(stream) => stream.flush(),
+            HostStream::Plain(stream) | HostStream::Detecting { stream, .. } => stream.flush(),
+            HostStream::Closed => Err(Self::closed()),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Upload validation hooks
+//!
+//! Validators run once a file has been fully received and before it is committed
+//! to the upload directory. Rejections are `UploaderError`s: an unsupported type
+//! maps to 415, any other refusal to 422.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::server::errors::UploaderError;
+use crate::server::file_types::{UploadTypePolicy, SNIFF_LEN};
+
+/// Size of the chunks streamed to clamd
+const CLAMAV_CHUNK_SIZE: usize = 64 * 1024;
+
+enum Content<'a> {
+    Memory(&'a [u8]),
+    Disk(&'a Path),
+}
+
+/// A received file waiting to be stored
+pub struct UploadCandidate<'a> {
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub size: u64,
+    content: Content<'a>,
+}
+
+impl<'a> UploadCandidate<'a> {
+    pub fn in_memory(filename: &'a str, content_type: &'a str, data: &'a [u8]) -> Self {
+        UploadCandidate { filename, content_type, size: data.len() as u64, content: Content::Memory(data) }
+    }
+
+    pub fn on_disk(filename: &'a str, content_type: &'a str, path: &'a Path) -> io::Result<Self> {
+        let size = fs::metadata(path)?.len();
+        Ok(UploadCandidate { filename, content_type, size, content: Content::Disk(path) })
+    }
+
+    /// Opens the content of the file from the start
+    pub fn reader(&self) -> io::Result<Box<dyn Read + 'a>> {
+        match self.content {
+            Content::Memory(data) => Ok(Box::new(data)),
+            Content::Disk(path) => Ok(Box::new(fs::File::open(path)?)),
+        }
+    }
+
+    /// Reads up to `len` leading bytes of the content
+    pub fn head(&self, len: usize) -> io::Result<Vec<u8>> {
+        let mut head = Vec::with_capacity(len);
+        self.reader()?.take(len as u64).read_to_end(&mut head)?;
+        Ok(head)
+    }
+}
+
+/// Decides whether a received file may be stored
+pub trait UploadValidator: fmt::Debug + Send + Sync {
+    /// Name reported in rejections
+    fn name(&self) -> &str;
+    fn validate(&self, file: &UploadCandidate) -> Result<(), UploaderError>;
+}
+
+/// Checks extensions and declared types, and sniffs the content's magic bytes
+impl UploadValidator for UploadTypePolicy {
+    fn name(&self) -> &str {
+        "type_policy"
+    }
+
+    fn validate(&self, file: &UploadCandidate) -> Result<(), UploaderError> {
+        let head = file.head(SNIFF_LEN).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to read uploaded file: {}", e))
+        )?;
+        self.check(file.filename, file.content_type, &head)
+    }
+}
+
+/// Rejects files larger than `max_size` bytes
+#[derive(Debug, Clone)]
+pub struct MaxSizeValidator {
+    pub max_size: u64,
+}
+
+impl UploadValidator for MaxSizeValidator {
+    fn name(&self) -> &str {
+        "max_size"
+    }
+
+    fn validate(&self, file: &UploadCandidate) -> Result<(), UploaderError> {
+        if file.size > self.max_size {
+            return Err(UploaderError::FileTooLarge { size: file.size as usize, max_size: self.max_size as usize });
+        }
+        Ok(())
+    }
+}
+
+/// Where clamd listens
+#[derive(Debug, Clone)]
+pub enum ClamAvAddress {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+/// Scans files with a ClamAV daemon through its `INSTREAM` command
+#[derive(Debug, Clone)]
+pub struct ClamAvValidator {
+    pub address: ClamAvAddress,
+    pub timeout: Duration,
+}
+
+impl ClamAvValidator {
+    pub fn new(address: ClamAvAddress) -> Self {
+        ClamAvValidator { address, timeout: Duration::from_secs(30) }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn scan(&self, file: &UploadCandidate) -> io::Result<String> {
+        match &self.address {
+            ClamAvAddress::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                instream(stream, file)
+            }
+            ClamAvAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                instream(stream, file)
+            }
+        }
+    }
+}
+
+impl UploadValidator for ClamAvValidator {
+    fn name(&self) -> &str {
+        "clamav"
+    }
+
+    fn validate(&self, file: &UploadCandidate) -> Result<(), UploaderError> {
+        // An unreachable scanner fails closed
+        let reply = self.scan(file).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Virus scan failed: {}", e))
+        )?;
+
+        let result = reply.trim_end_matches('\0').trim();
+        let result = result.strip_prefix("stream:").unwrap_or(result).trim();
+        match result.strip_suffix("FOUND") {
+            None if result == "OK" => Ok(()),
+            Some(signature) => Err(UploaderError::ValidationFailed {
+                validator: self.name().to_string(),
+                reason: format!("{} found", signature.trim()),
+            }),
+            None => Err(UploaderError::UploadProcessingError(format!("Virus scan failed: {}", result))),
+        }
+    }
+}
+
+/// Streams the file to clamd as length prefixed chunks and returns its verdict
+fn instream<S: Read + Write>(mut stream: S, file: &UploadCandidate) -> io::Result<String> {
+    stream.write_all(b"zINSTREAM\0")?;
+
+    let mut reader = file.reader()?;
+    let mut buffer = vec![0u8; CLAMAV_CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&(read as u32).to_be_bytes())?;
+        stream.write_all(&buffer[..read])?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    stream.flush()?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
@@ -1,8 +1,83 @@
 use std::{
-    fs::{self, read_dir},
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    sync::Arc,
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::http::body::{Body, BodyError, MultipartFile, MultipartForm};
+use crate::http::header::{ContentType, HeaderName};
+use crate::http::multipart::{MultipartParser, PartInfo, PartSink};
+use crate::http::request::{parse_request, Request};
 use crate::server::errors::{ServerError, UploaderError};
+use crate::server::file_types::{self, UploadTypePolicy};
+use crate::server::upload_validator::{UploadCandidate, UploadValidator};
+use crate::server::stream::request_stream::BodySink;
+
+/// Prefix of the temporary files multipart uploads are streamed into
+const SPOOL_PREFIX: &str = ".upload-";
+/// Sidecar file holding the metadata of the stored files
+const METADATA_FILE: &str = ".metadata.json";
+/// Name of the metadata file kept in every chunked upload directory
+const CHUNK_MANIFEST: &str = "manifest.json";
+
+/// A chunked upload in progress, persisted next to its chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkedUpload {
+    pub id: String,
+    pub filename: String,
+    pub content_type: String,
+    /// Total size announced by the client
+    pub size: usize,
+    /// Hex encoded SHA-256 of the whole file, checked on finalize when given
+    pub sha256: Option<String>,
+}
+
+/// Metadata persisted for a stored file, keyed by its name on disk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileMetadata {
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+    /// User, or session id for anonymous uploads, that stored the file
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Upload time, in seconds since the Unix epoch
+    #[serde(default)]
+    pub uploaded_at: Option<u64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Changes applied by `Uploader::update_file`, unset fields are left as they are
+#[derive(Debug, Clone, Default)]
+pub struct FileUpdate {
+    pub name: Option<String>,
+    /// New description, an empty one removes it
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Storage limits of an upload directory, overall and per session
+#[derive(Debug, Clone, Default)]
+pub struct UploadQuota {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+    pub session_max_bytes: Option<u64>,
+    pub session_max_files: Option<usize>,
+}
+
+/// Space used by a set of stored files
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuotaUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
 
 #[derive(Debug, Clone)]
 pub struct File {
@@ -10,24 +85,146 @@ pub struct File {
     pub name: String,
     pub path: PathBuf,
     pub size: u64,
+    pub sha256: Option<String>,
+    pub owner: Option<String>,
+    pub uploaded_at: SystemTime,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Uploader {
     database: Vec<File>,
     pub upload_dir: PathBuf,
+    /// Types and extensions accepted for upload
+    pub type_policy: UploadTypePolicy,
+    /// Extra checks run before a file is stored, after the type policy
+    pub validators: Vec<Arc<dyn UploadValidator>>,
+    /// Store identical content once, as hard links to the first copy
+    pub deduplicate: bool,
+    pub quota: Option<UploadQuota>,
+    /// Time after which stored files are purged
+    pub ttl: Option<Duration>,
+    /// Largest file accepted in bytes, the body size limit applies when unset
+    pub max_file_size: Option<usize>,
+    /// Owner recorded for the files added by this instance
+    owner: Option<String>,
 }
 
 impl Uploader {
     pub fn new(upload_dir: PathBuf) -> Self {
-        let list_files = list_files(&upload_dir);
-        Uploader { 
-            database: match list_files {
-                Ok(files) => files,
-                Err(_) => Vec::new()
-            }, 
-            upload_dir 
+        let mut uploader = Uploader { 
+            database: Vec::new(),
+            upload_dir,
+            type_policy: UploadTypePolicy::default(),
+            validators: Vec::new(),
+            deduplicate: false,
+            quota: None,
+            ttl: None,
+            max_file_size: None,
+            owner: None,
+        };
+        let _ = uploader.sync_database();
+        uploader
+    }
+
+    pub fn with_validator(mut self, validator: Arc<dyn UploadValidator>) -> Self {
+        self.validators.push(validator);
+        self
+    }
+
+    pub fn with_deduplication(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    pub fn with_quota(mut self, quota: Option<UploadQuota>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: Option<usize>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Returns when `file` gets purged, if the uploader has a TTL
+    pub fn expires_at(&self, file: &File) -> Option<SystemTime> {
+        self.ttl.map(|ttl| file.uploaded_at + ttl)
+    }
+
+    /// Deletes the files older than the TTL, returning them, along with the
+    /// chunked uploads left unfinished for that long
+    pub fn purge_expired(&mut self) -> Result<Vec<File>, ServerError> {
+        let Some(ttl) = self.ttl else {
+            return Ok(Vec::new());
+        };
+        self.sync_database()?;
+
+        let now = SystemTime::now();
+        self.purge_abandoned_uploads(now, ttl);
+        let expired: Vec<i32> = self.database.iter()
+            .filter(|f| self.expires_at(f).is_some_and(|expires| expires <= now))
+            .map(|f| f.id)
+            .collect();
+
+        expired.into_iter().map(|id| self.delete_file(id)).collect()
+    }
+
+    /// Attributes the files added from now on to `owner`
+    pub fn with_owner(mut self, owner: Option<String>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    /// Returns the space used by every stored file, or only by `owner`'s
+    pub fn usage(&self, owner: Option<&str>) -> QuotaUsage {
+        self.database.iter()
+            .filter(|f| owner.is_none() || f.owner.as_deref() == owner)
+            .fold(QuotaUsage::default(), |usage, f| QuotaUsage {
+                files: usage.files + 1,
+                bytes: usage.bytes + f.size,
+            })
+    }
+
+    /// Checks that storing `files` more files totalling `bytes` stays within the quota
+    pub fn check_quota(&mut self, files: usize, bytes: u64) -> Result<(), ServerError> {
+        let Some(quota) = self.quota.clone() else { return Ok(()) };
+        self.sync_database()?;
+
+        let mut scopes = vec![("host".to_string(), self.usage(None), quota.max_bytes, quota.max_files)];
+        if let Some(owner) = self.owner.as_deref() {
+            scopes.push(("session".to_string(), self.usage(Some(owner)), quota.session_max_bytes, quota.session_max_files));
+        }
+
+        for (scope, usage, max_bytes, max_files) in scopes {
+            if let Some(limit) = max_files {
+                if usage.files + files > limit {
+                    return Err(UploaderError::FileQuotaExceeded { scope, used: usage.files + files, limit }.into());
+                }
+            }
+            if let Some(limit) = max_bytes {
+                if usage.bytes + bytes > limit {
+                    return Err(UploaderError::StorageQuotaExceeded { scope, used: usage.bytes + bytes, limit }.into());
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Replaces the default type and extension policy
+    pub fn with_type_policy(mut self, type_policy: UploadTypePolicy) -> Self {
+        self.type_policy = type_policy;
+        self
     }
 
     // Core business logic methods
@@ -36,7 +233,7 @@ impl Uploader {
         self.sync_database()?;
         let clean_name = name.trim_matches('"').to_string();
         
-        let file_path = self.generate_unique_path(&clean_name);
+        let file_path = self.generate_unique_path(&clean_name, None);
         
         if let Some(parent) = file_path.parent() {
             fs::create_dir_all(parent).map_err(|e| 
@@ -44,15 +241,66 @@ impl Uploader {
             )?;
         }
         
-        fs::write(&file_path, data).map_err(|e| 
-            UploaderError::UploadProcessingError(format!("Failed to write file: {}", e))
+        let sha256 = to_hex(&Sha256::digest(data));
+        if !self.link_duplicate(&sha256, &file_path) {
+            fs::write(&file_path, data).map_err(|e| 
+                UploaderError::UploadProcessingError(format!("Failed to write file: {}", e))
+            )?;
+        }
+
+        self.register(clean_name, file_path, sha256)
+    }
+
+    /// Registers a file that was already streamed into the upload directory
+    pub fn add_spooled_file(&mut self, name: String, spooled: &Path) -> Result<File, ServerError> {
+        self.sync_database()?;
+        let clean_name = name.trim_matches('"').to_string();
+
+        let sha256 = sha256_file(spooled).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to hash uploaded file: {}", e))
         )?;
 
+        let file_path = self.generate_unique_path(&clean_name, None);
+        if self.link_duplicate(&sha256, &file_path) {
+            let _ = fs::remove_file(spooled);
+        } else {
+            fs::rename(spooled, &file_path).map_err(|e|
+                UploaderError::UploadProcessingError(format!("Failed to move uploaded file: {}", e))
+            )?;
+        }
+
+        self.register(clean_name, file_path, sha256)
+    }
+
+    /// Hard links `target` to a stored file with the same content when deduplicating
+    fn link_duplicate(&self, sha256: &str, target: &Path) -> bool {
+        self.deduplicate && self.database.iter()
+            .filter(|f| f.sha256.as_deref() == Some(sha256))
+            .any(|f| fs::hard_link(&f.path, target).is_ok())
+    }
+
+    /// Records a file written to `path` in the database and the metadata sidecar
+    fn register(&mut self, name: String, path: PathBuf, sha256: String) -> Result<File, ServerError> {
+        let mut metadata = load_metadata(&self.upload_dir);
+        let uploaded_at = SystemTime::now();
+        metadata.insert(file_key(&path), FileMetadata {
+            sha256: sha256.clone(),
+            owner: self.owner.clone(),
+            uploaded_at: Some(unix_seconds(uploaded_at)),
+            ..FileMetadata::default()
+        });
+        save_metadata(&self.upload_dir, &metadata)?;
+
         let new_file = File {
             id: self.generate_next_id(),
-            name: clean_name,
-            size: data.len() as u64,
-            path: file_path,
+            name,
+            size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            path,
+            sha256: Some(sha256),
+            owner: self.owner.clone(),
+            uploaded_at,
+            description: None,
+            tags: Vec::new(),
         };
 
         self.database.push(new_file.clone());
@@ -60,6 +308,13 @@ impl Uploader {
         Ok(new_file)
     }
 
+    /// Removes the spooled files of a form that won't be stored
+    pub fn discard_spooled(form: &MultipartForm) {
+        for path in form.files.values().filter_map(|f| f.path.as_ref()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
     pub fn delete_file(&mut self, file_id: i32) -> Result<File, ServerError> {
         self.sync_database()?;
         let file_index = self.database.iter()
@@ -71,10 +326,75 @@ impl Uploader {
             UploaderError::DeleteError(file_id)
         )?;
         self.database.remove(file_index);
+
+        let mut metadata = load_metadata(&self.upload_dir);
+        if metadata.remove(&file_key(&file.path)).is_some() {
+            save_metadata(&self.upload_dir, &metadata)?;
+        }
         
         Ok(file)
     }
 
+    /// Renames a file and updates its description and tags
+    pub fn update_file(&mut self, file_id: i32, update: FileUpdate) -> Result<File, ServerError> {
+        self.sync_database()?;
+        let file_index = self.database.iter()
+            .position(|f| f.id == file_id)
+            .ok_or(UploaderError::FileNotFound(file_id))?;
+
+        let mut file = self.database[file_index].clone();
+        if file.owner.is_some() && file.owner != self.owner {
+            return Err(UploaderError::NotOwner(file_id).into());
+        }
+        let mut metadata = load_metadata(&self.upload_dir);
+        let mut entry = metadata.remove(&file_key(&file.path)).unwrap_or_default();
+
+        if let Some(name) = update.name {
+            let clean_name = name.trim().trim_matches('"').to_string();
+            let on_disk = file.path.file_name().and_then(|n| n.to_str());
+            if on_disk != Some(clean_name.as_str()) {
+                self.validate_new_name(&clean_name)?;
+                // The file already holding a suffixed form of this name keeps it
+                let new_path = self.generate_unique_path(&clean_name, Some(&file.path));
+                if new_path != file.path {
+                    fs::rename(&file.path, &new_path).map_err(|e|
+                        UploaderError::UploadProcessingError(format!("Failed to rename file: {}", e))
+                    )?;
+                    file.name = file_key(&new_path);
+                    file.path = new_path;
+                }
+            }
+        }
+        if let Some(description) = update.description {
+            file.description = Some(description).filter(|d| !d.trim().is_empty());
+        }
+        if let Some(tags) = update.tags {
+            file.tags = tags.into_iter()
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+        }
+
+        entry.description = file.description.clone();
+        entry.tags = file.tags.clone();
+        metadata.insert(file_key(&file.path), entry);
+        save_metadata(&self.upload_dir, &metadata)?;
+
+        self.database[file_index] = file.clone();
+        Ok(file)
+    }
+
+    /// Rejects names that would leave the upload directory, be hidden or bypass the type policy
+    fn validate_new_name(&self, name: &str) -> Result<(), ServerError> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(UploaderError::InvalidFileName(name.to_string()).into());
+        }
+        if !self.type_policy.is_allowed_extension(name) {
+            return Err(UploaderError::UnsupportedFileType(name.to_string()).into());
+        }
+        Ok(())
+    }
+
     pub fn list_files(&self) -> Vec<&File> {
         self.database.iter().collect()
     }
@@ -83,6 +403,9 @@ impl Uploader {
         self.database.retain(|file| file.path.exists());
 
         if self.upload_dir.exists() {
+            let mut metadata = load_metadata(&self.upload_dir);
+            let mut changed = false;
+
             for entry in fs::read_dir(&self.upload_dir).map_err(|e| 
                 UploaderError::DatabaseSyncError(format!("Failed to read upload directory: {}", e))
             )? {
@@ -90,36 +413,202 @@ impl Uploader {
                     UploaderError::DatabaseSyncError(format!("Failed to read directory entry: {}", e))
                 )?;
                 let path = entry.path();
+                if !is_stored_file(&entry) {
+                    continue;
+                }
                 
                 if !self.database.iter().any(|f| f.path == path) {
-                    let metadata = entry.metadata().map_err(|e| 
+                    let file_metadata = entry.metadata().map_err(|e| 
                         UploaderError::DatabaseSyncError(format!("Failed to read metadata: {}", e))
                     )?;
+                    // Files dropped in the directory by hand get hashed once
+                    let key = file_key(&path);
+                    let modified = file_metadata.modified().unwrap_or_else(|_| SystemTime::now());
+                    if !metadata.contains_key(&key) {
+                        if let Ok(sha256) = sha256_file(&path) {
+                            metadata.insert(key.clone(), FileMetadata {
+                                sha256,
+                                uploaded_at: Some(unix_seconds(modified)),
+                                ..FileMetadata::default()
+                            });
+                            changed = true;
+                        }
+                    }
+
                     self.database.push(File {
                         id: self.generate_next_id(),
-                        name: entry.file_name().to_string_lossy().into_owned(),
+                        name: entry.file_name().to_string_lossy().trim_matches('"').to_string(),
+                        size: file_metadata.len(),
+                        sha256: metadata.get(&key).map(|m| m.sha256.clone()),
+                        owner: metadata.get(&key).and_then(|m| m.owner.clone()),
+                        uploaded_at: metadata.get(&key)
+                            .and_then(|m| m.uploaded_at)
+                            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                            .unwrap_or(modified),
+                        description: metadata.get(&key).and_then(|m| m.description.clone()),
+                        tags: metadata.get(&key).map(|m| m.tags.clone()).unwrap_or_default(),
                         path,
-                        size: metadata.len(),
                     });
                 }
             }
+
+            // Forget files removed from the directory by hand
+            let stored: Vec<String> = self.database.iter().map(|f| file_key(&f.path)).collect();
+            let before = metadata.len();
+            metadata.retain(|key, _| stored.contains(key));
+            if changed || metadata.len() != before {
+                save_metadata(&self.upload_dir, &metadata)?;
+            }
         }
         Ok(())
     }
 
+    // Chunked uploads
+    pub fn start_chunked_upload(
+        &self,
+        filename: String,
+        content_type: String,
+        size: usize,
+        sha256: Option<String>,
+    ) -> Result<ChunkedUpload, ServerError> {
+        let upload = ChunkedUpload {
+            id: Uuid::new_v4().simple().to_string(),
+            filename: filename.trim_matches('"').to_string(),
+            content_type,
+            size,
+            sha256: sha256.map(|hash| hash.to_lowercase()),
+        };
+
+        let dir = self.chunk_dir(&upload.id);
+        fs::create_dir_all(&dir).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to create upload directory: {}", e))
+        )?;
+        let manifest = serde_json::to_vec(&upload).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to save upload: {}", e))
+        )?;
+        fs::write(dir.join(CHUNK_MANIFEST), manifest).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to save upload: {}", e))
+        )?;
+
+        Ok(upload)
+    }
+
+    pub fn get_chunked_upload(&self, upload_id: &str) -> Result<ChunkedUpload, ServerError> {
+        // Ids are generated by us, anything else could escape the upload directory
+        if upload_id.is_empty() || !upload_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(UploaderError::UploadNotFound(upload_id.to_string()).into());
+        }
+
+        let manifest = fs::read(self.chunk_dir(upload_id).join(CHUNK_MANIFEST))
+            .map_err(|_| UploaderError::UploadNotFound(upload_id.to_string()))?;
+        serde_json::from_slice(&manifest).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Corrupted upload manifest: {}", e)).into()
+        )
+    }
+
+    /// Stores chunk `index`; sending the same index again replaces it
+    pub fn write_chunk(&self, upload_id: &str, index: usize, data: &[u8]) -> Result<usize, ServerError> {
+        let upload = self.get_chunked_upload(upload_id)?;
+        let dir = self.chunk_dir(upload_id);
+
+        let chunk_path = dir.join(index.to_string());
+        let _ = fs::remove_file(&chunk_path);
+        let received = self.received_bytes(&dir) + data.len();
+        if received > upload.size {
+            return Err(UploaderError::IntegrityError(format!(
+                "received {} bytes for an upload of {} bytes", received, upload.size
+            )).into());
+        }
+
+        fs::write(&chunk_path, data).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to write chunk: {}", e))
+        )?;
+        Ok(received)
+    }
+
+    /// Assembles the chunks in order and stores the result as a regular file
+    pub fn finalize_chunked_upload(&mut self, upload_id: &str) -> Result<File, ServerError> {
+        let upload = self.get_chunked_upload(upload_id)?;
+        let dir = self.chunk_dir(upload_id);
+
+        let assembled_path = self.upload_dir.join(format!("{}{}.part", SPOOL_PREFIX, upload.id));
+        let result = assemble_chunks(&dir, &assembled_path, &upload)
+            .and_then(|_| self.validate_assembled(&upload, &assembled_path));
+        let file = result.and_then(|_| self.add_spooled_file(upload.filename.clone(), &assembled_path));
+        if file.is_err() {
+            let _ = fs::remove_file(&assembled_path);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+        file
+    }
+
+    fn validate_assembled(&self, upload: &ChunkedUpload, path: &Path) -> Result<(), ServerError> {
+        let candidate = UploadCandidate::on_disk(&upload.filename, &upload.content_type, path).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to read uploaded file: {}", e))
+        )?;
+        self.validate(&candidate)
+    }
+
+    pub fn abort_chunked_upload(&self, upload_id: &str) -> Result<(), ServerError> {
+        self.get_chunked_upload(upload_id)?;
+        fs::remove_dir_all(self.chunk_dir(upload_id)).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to remove upload: {}", e)).into()
+        )
+    }
+
+    /// Removes the chunked upload directories without a new chunk for `ttl`
+    fn purge_abandoned_uploads(&self, now: SystemTime, ttl: Duration) {
+        let Ok(entries) = fs::read_dir(&self.upload_dir) else { return };
+        for entry in entries.flatten() {
+            let is_upload = entry.file_name().to_string_lossy().starts_with(SPOOL_PREFIX);
+            let Ok(metadata) = entry.metadata() else { continue };
+            // Writing a chunk replaces its file, which touches the directory
+            let idle = metadata.modified().ok()
+                .and_then(|modified| now.duration_since(modified).ok());
+            if is_upload && metadata.is_dir() && idle.is_some_and(|idle| idle >= ttl) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    fn chunk_dir(&self, upload_id: &str) -> PathBuf {
+        self.upload_dir.join(format!("{}{}", SPOOL_PREFIX, upload_id))
+    }
+
+    fn received_bytes(&self, dir: &Path) -> usize {
+        chunk_indexes(dir).iter()
+            .filter_map(|index| fs::metadata(dir.join(index.to_string())).ok())
+            .map(|metadata| metadata.len() as usize)
+            .sum()
+    }
+
     // File validation methods
     pub fn is_allowed_mime_type(&self, mime_type: &str) -> bool {
-        const ALLOWED_TYPES: [&str; 8] = [
-            "text/", "image/", "application/pdf", "application/json",
-            "application/msword", "application/vnd.openxmlformats-officedocument",
-            "audio/", "video/"
-        ];
-        ALLOWED_TYPES.iter().any(|&allowed| mime_type.starts_with(allowed))
+        self.type_policy.is_allowed_mime_type(mime_type)
     }
 
-    pub fn validate_mime_type(&self, mime_type: &str) -> Result<(), ServerError> {
-        if (!self.is_allowed_mime_type(mime_type)) {
-            return Err(UploaderError::UnsupportedFileType(mime_type.to_string()).into());
+    /// Checks a file's name and declared type against the policy
+    pub fn validate_file_type(&self, filename: &str, mime_type: &str) -> Result<(), ServerError> {
+        Ok(self.type_policy.check_declared(filename, mime_type)?)
+    }
+
+    /// Checks a multipart file against the policy, sniffing its content
+    pub fn validate_multipart_file(&self, file: &MultipartFile) -> Result<(), ServerError> {
+        let candidate = match &file.path {
+            Some(path) => UploadCandidate::on_disk(&file.filename, &file.content_type, path).map_err(|e|
+                UploaderError::UploadProcessingError(format!("Failed to read uploaded file: {}", e))
+            )?,
+            None => UploadCandidate::in_memory(&file.filename, &file.content_type, &file.data),
+        };
+        self.validate(&candidate)
+    }
+
+    /// Runs the type policy, then the configured validators, before a file is stored
+    pub fn validate(&self, candidate: &UploadCandidate) -> Result<(), ServerError> {
+        self.type_policy.validate(candidate)?;
+        for validator in &self.validators {
+            validator.validate(candidate)?;
         }
         Ok(())
     }
@@ -129,12 +618,15 @@ impl Uploader {
         self.database.iter().map(|f| f.id).max().unwrap_or(-1) + 1
     }
 
-    fn generate_unique_path(&self, original_name: &str) -> PathBuf {
+    /// Finds a free path for `original_name`, suffixing its stem when taken.
+    /// `current` is the path of the file being renamed, which doesn't count as taken
+    fn generate_unique_path(&self, original_name: &str, current: Option<&Path>) -> PathBuf {
         let mut counter = 0;
         let ext = Path::new(original_name)
             .extension()
             .and_then(|e| e.to_str())
-            .unwrap_or("");
+            .map(|e| format!(".{}", e))
+            .unwrap_or_default();
         let base_name = Path::new(original_name)
             .file_stem()
             .and_then(|s| s.to_str())
@@ -142,13 +634,13 @@ impl Uploader {
 
         loop {
             let filename = if counter == 0 {
-                format!("{}.{}", base_name, ext)
+                format!("{}{}", base_name, ext)
             } else {
-                format!("{}_{}.{}", base_name, counter, ext)
+                format!("{}_{}{}", base_name, counter, ext)
             };
             
             let full_path = self.upload_dir.join(&filename);
-            if !full_path.exists() {
+            if !full_path.exists() || current == Some(full_path.as_path()) {
                 return full_path;
             }
             counter += 1;
@@ -166,38 +658,267 @@ impl Uploader {
     }
 }
 
-fn list_files(dir_path: &Path) -> Result<Vec<File>, ServerError> {
-    let mut files = Vec::new();
-    let mut id = 0;
+/// Reads the metadata sidecar of `upload_dir`, keyed by stored file name
+fn load_metadata(upload_dir: &Path) -> HashMap<String, FileMetadata> {
+    fs::read(upload_dir.join(METADATA_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
 
-    if dir_path.exists() {
-        for entry in read_dir(dir_path).map_err(|e| 
-            UploaderError::DatabaseSyncError(format!("Failed to read directory: {}", e))
-        )? {
-            let entry = entry.map_err(|e| 
-                UploaderError::DatabaseSyncError(format!("Failed to read directory entry: {}", e))
-            )?;
-            let path = entry.path();
-            let metadata = entry.metadata().map_err(|e| 
-                UploaderError::DatabaseSyncError(format!("Failed to read metadata: {}", e))
-            )?;
+fn save_metadata(upload_dir: &Path, metadata: &HashMap<String, FileMetadata>) -> Result<(), ServerError> {
+    let data = serde_json::to_vec_pretty(metadata).map_err(|e|
+        UploaderError::DatabaseSyncError(format!("Failed to serialize metadata: {}", e))
+    )?;
+    fs::write(upload_dir.join(METADATA_FILE), data).map_err(|e|
+        UploaderError::DatabaseSyncError(format!("Failed to write metadata: {}", e)).into()
+    )
+}
+
+fn file_key(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Returns the sorted indexes of the chunks stored in `dir`
+fn chunk_indexes(dir: &Path) -> Vec<usize> {
+    let mut indexes: Vec<usize> = fs::read_dir(dir)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok()))
+            .collect())
+        .unwrap_or_default();
+    indexes.sort_unstable();
+    indexes
+}
+
+fn assemble_chunks(dir: &Path, target: &Path, upload: &ChunkedUpload) -> Result<(), ServerError> {
+    let indexes = chunk_indexes(dir);
+    if let Some(missing) = indexes.iter().enumerate().find(|(i, index)| i != *index).map(|(i, _)| i) {
+        return Err(UploaderError::IntegrityError(format!("chunk {} is missing", missing)).into());
+    }
+
+    let mut output = fs::File::create(target).map_err(|e|
+        UploaderError::UploadProcessingError(format!("Failed to create file: {}", e))
+    )?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+
+    for index in indexes {
+        let chunk = fs::read(dir.join(index.to_string())).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to read chunk {}: {}", index, e))
+        )?;
+        hasher.update(&chunk);
+        size += chunk.len();
+        output.write_all(&chunk).map_err(|e|
+            UploaderError::UploadProcessingError(format!("Failed to write file: {}", e))
+        )?;
+    }
+
+    if size != upload.size {
+        return Err(UploaderError::IntegrityError(format!(
+            "expected {} bytes, received {}", upload.size, size
+        )).into());
+    }
+
+    if let Some(expected) = &upload.sha256 {
+        let actual = to_hex(&hasher.finalize());
+        if &actual != expected {
+            return Err(UploaderError::IntegrityError(format!(
+                "expected SHA-256 {}, computed {}", expected, actual
+            )).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Skips directories, in-progress uploads and the metadata sidecar when listing the upload directory
+fn is_stored_file(entry: &fs::DirEntry) -> bool {
+    let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+    is_file && !entry.file_name().to_string_lossy().starts_with('.')
+}
+
+enum SpoolPart {
+    File { info: PartInfo, path: PathBuf, file: fs::File, size: usize },
+    Field { info: PartInfo, data: Vec<u8> },
+}
 
-            let name = entry.file_name()
-                .into_string()
-                .unwrap_or_default()
-                .trim_matches('"')
-                .to_string();
+/// Collects multipart parts, writing file contents straight to the upload directory
+struct SpoolState {
+    upload_dir: PathBuf,
+    max_file_size: Option<usize>,
+    form: MultipartForm,
+    current: Option<SpoolPart>,
+}
+
+impl PartSink for SpoolState {
+    fn begin_part(&mut self, part: &PartInfo) -> Result<(), BodyError> {
+        if !part.is_file() {
+            self.current = Some(SpoolPart::Field { info: part.clone(), data: Vec::new() });
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.upload_dir)
+            .map_err(|e| BodyError::MultipartError(format!("Failed to create upload directory: {}", e)))?;
+        let path = self.upload_dir.join(format!("{}{}.part", SPOOL_PREFIX, Uuid::new_v4()));
+        let file = fs::File::create(&path)
+            .map_err(|e| BodyError::MultipartError(format!("Failed to create upload file: {}", e)))?;
+
+        self.current = Some(SpoolPart::File { info: part.clone(), path, file, size: 0 });
+        Ok(())
+    }
 
-            files.push(File {
-                id,
-                name,
-                path,
-                size: metadata.len(),
-            });
+    fn part_data(&mut self, data: &[u8]) -> Result<(), BodyError> {
+        match self.current.as_mut() {
+            Some(SpoolPart::File { file, size, .. }) => {
+                *size += data.len();
+                if let Some(max_size) = self.max_file_size {
+                    if *size > max_size {
+                        return Err(BodyError::MultipartError(format!(
+                            "File size of {} bytes exceeds maximum of {} bytes", size, max_size
+                        )));
+                    }
+                }
+                file.write_all(data)
+                    .map_err(|e| BodyError::MultipartError(format!("Failed to write upload file: {}", e)))
+            }
+            Some(SpoolPart::Field { data: buffer, .. }) => {
+                buffer.extend_from_slice(data);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
 
-            id += 1;
+    fn end_part(&mut self) -> Result<(), BodyError> {
+        match self.current.take() {
+            Some(SpoolPart::File { info, path, size, .. }) => {
+                let (Some(name), Some(filename)) = (info.name, info.filename) else {
+                    let _ = fs::remove_file(&path);
+                    return Ok(());
+                };
+                // A second part of the same name would replace the first, left on disk
+                if self.form.files.contains_key(&name) {
+                    let _ = fs::remove_file(&path);
+                    return Err(BodyError::MultipartError(format!("Duplicate file field {}", name)));
+                }
+                self.form.add_file(&name, MultipartFile {
+                    content_type: file_types::resolve_content_type(&filename, info.content_type.as_deref()),
+                    filename,
+                    data: Vec::new(),
+                    size,
+                    path: Some(path),
+                });
+            }
+            Some(SpoolPart::Field { info, data }) => {
+                if let Some(name) = info.name {
+                    let text = String::from_utf8(data)
+                        .map_err(|_| BodyError::InvalidUtf8(format!("field {}", name)))?;
+                    self.form.fields.insert(name, text);
+                }
+            }
+            None => {}
         }
+        Ok(())
     }
+}
 
-    Ok(files)
-}
\ No newline at end of file
+impl Drop for SpoolState {
+    fn drop(&mut self) {
+        // An upload cut short leaves its partial file behind otherwise, along
+        // with the parts spooled before it, until `finish` hands them over
+        if let Some(SpoolPart::File { path, .. }) = &self.current {
+            let _ = fs::remove_file(path);
+        }
+        Uploader::discard_spooled(&self.form);
+    }
+}
+
+/// The spooled parts of a request, removed once it was answered whatever its
+/// handler did. Those it stored were moved away already.
+pub struct SpooledParts(Vec<PathBuf>);
+
+impl SpooledParts {
+    pub fn of(request: &Request) -> Self {
+        let paths = match &request.body {
+            Some(Body::Multipart(form)) => form.files.values().filter_map(|file| file.path.clone()).collect(),
+            _ => Vec::new(),
+        };
+        SpooledParts(paths)
+    }
+}
+
+impl Drop for SpooledParts {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Streams multipart upload bodies into the upload directory as they are read
+pub struct MultipartSpooler {
+    parser: MultipartParser,
+    state: SpoolState,
+}
+
+impl MultipartSpooler {
+    pub fn new(boundary: &str, upload_dir: PathBuf, max_file_size: Option<usize>) -> Self {
+        MultipartSpooler {
+            parser: MultipartParser::new(boundary),
+            state: SpoolState {
+                upload_dir,
+                max_file_size,
+                form: MultipartForm::new(),
+                current: None,
+            },
+        }
+    }
+
+    /// Returns a spooler for multipart requests to the upload endpoint
+    pub fn for_request(head: &[u8], upload_dir: &Path, max_file_size: Option<usize>) -> Option<Self> {
+        let request = parse_request(head)?;
+        if request.uri != "/api/files/upload" {
+            return None;
+        }
+
+        let content_type = request.get_header(HeaderName::ContentType)?;
+        let parsed = ContentType::parse_content_type(&content_type)?;
+        if parsed.mime != "multipart/form-data" {
+            return None;
+        }
+        let boundary = parsed.params.get("boundary")?;
+
+        Some(Self::new(boundary.trim_matches('"'), upload_dir.to_path_buf(), max_file_size))
+    }
+}
+
+impl BodySink for MultipartSpooler {
+    fn write_body(&mut self, data: &[u8]) -> io::Result<()> {
+        self.parser.feed(data, &mut self.state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn finish(&mut self) -> io::Result<Body> {
+        if let Err(e) = self.parser.finish() {
+            Uploader::discard_spooled(&self.state.form);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+        }
+        Ok(Body::Multipart(std::mem::replace(&mut self.state.form, MultipartForm::new())))
+    }
+}
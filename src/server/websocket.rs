@@ -0,0 +1,617 @@
+//! WebSocket endpoints (RFC 6455)
+//!
+//! A route with a [`WebSocketEndpoint`] answers the opening handshake itself,
+//! after which its connection belongs to a [`Session`] driven by the event
+//! loop: frames are parsed as they come, whole messages are passed to the
+//! route's [`WebSocketHandler`], and idle connections are pinged, those not
+//! answering being dropped. Messages sent from elsewhere, like another
+//! connection's handler, go through a [`WebSocketSender`], queued until the
+//! event loop sends them.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use base64::Engine;
+use crate::http::{
+    header::Header,
+    request::{HttpMethod, Request},
+    response::{Response, ResponseBuilder},
+    status::HttpStatusCode,
+    waker,
+};
+use crate::server::connection::Connection;
+use crate::server::errors::HttpError;
+
+/// Appended to the client's key to compute `Sec-WebSocket-Accept`
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const READ_SIZE: usize = 16 * 1024;
+/// Largest payload of a ping, pong or close frame
+const MAX_CONTROL_SIZE: u64 = 125;
+
+/// Close codes
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+/// Reported to the handler when the close frame had no code
+pub const NO_STATUS: u16 = 1005;
+/// Reported to the handler when the connection ended without a close frame
+pub const ABNORMAL_CLOSURE: u16 = 1006;
+pub const INVALID_PAYLOAD: u16 = 1007;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Application side of a WebSocket route. The same handler serves every
+/// connection of the route, which `WebSocket::id` tells apart.
+pub trait WebSocketHandler: Send + Sync {
+    fn on_open(&self, _socket: &mut WebSocket) {}
+
+    fn on_message(&self, socket: &mut WebSocket, message: Message);
+
+    /// Called once, with the code of the close frame or [`ABNORMAL_CLOSURE`]
+    /// when the connection was lost
+    fn on_close(&self, _socket: &mut WebSocket, _code: u16, _reason: &str) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WebSocketSettings {
+    /// Largest message, fragments put together, closing with 1009 past it
+    pub max_message_size: usize,
+    /// Silence after which the client is pinged
+    pub ping_interval: Duration,
+    /// Wait for the pong, or for the client's close frame, before dropping it
+    pub pong_timeout: Duration,
+    /// Bytes of the messages sent through a `WebSocketSender` that may wait
+    /// for the client, the next ones being refused
+    pub max_queued: usize,
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> Self {
+        WebSocketSettings {
+            max_message_size: 1024 * 1024,
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+            max_queued: 1024 * 1024,
+        }
+    }
+}
+
+/// What a route answers WebSocket handshakes with
+#[derive(Clone)]
+pub struct WebSocketEndpoint {
+    pub handler: Arc<dyn WebSocketHandler>,
+    pub settings: WebSocketSettings,
+}
+
+impl WebSocketEndpoint {
+    pub fn new(handler: impl WebSocketHandler + 'static) -> Self {
+        WebSocketEndpoint { handler: Arc::new(handler), settings: WebSocketSettings::default() }
+    }
+
+    pub fn with_settings(mut self, settings: WebSocketSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+}
+
+impl fmt::Debug for WebSocketEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketEndpoint").field("settings", &self.settings).finish_non_exhaustive()
+    }
+}
+
+/// Why a `WebSocketSender` refused a message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SendError {
+    /// The client takes its messages slower than they come
+    Full,
+    /// The connection is closing or closed
+    Closed,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full => write!(f, "The WebSocket's queue is full"),
+            SendError::Closed => write!(f, "The WebSocket is closed"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[derive(Debug, Default)]
+struct Queue {
+    frames: VecDeque<Vec<u8>>,
+    /// Bytes of `frames`
+    len: usize,
+    closed: bool,
+}
+
+/// Frames sent from outside the event loop, waiting for it
+#[derive(Debug)]
+struct Outbox {
+    queue: Mutex<Queue>,
+    max_queued: usize,
+}
+
+impl Outbox {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn shut(&self) {
+        let mut queue = self.lock();
+        queue.closed = true;
+        queue.frames.clear();
+        queue.len = 0;
+    }
+}
+
+/// Sends messages to a connection from any thread, as long as it's open
+#[derive(Debug, Clone)]
+pub struct WebSocketSender {
+    id: u64,
+    outbox: Arc<Outbox>,
+}
+
+impl WebSocketSender {
+    /// The id of the connection
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Queues `message`, refused once the connection closed or when the
+    /// client didn't take the messages before it
+    pub fn send(&self, message: Message) -> Result<(), SendError> {
+        let frame = match message {
+            Message::Text(text) => encode_frame(TEXT, text.as_bytes()),
+            Message::Binary(data) => encode_frame(BINARY, &data),
+        };
+        let mut queue = self.outbox.lock();
+        if queue.closed {
+            return Err(SendError::Closed);
+        }
+        // A message bigger than the whole queue still goes once it's empty
+        if queue.len > 0 && queue.len + frame.len() > self.outbox.max_queued {
+            return Err(SendError::Full);
+        }
+        queue.len += frame.len();
+        queue.frames.push_back(frame);
+        drop(queue);
+        waker::wake();
+        Ok(())
+    }
+
+    pub fn send_text(&self, text: &str) -> Result<(), SendError> {
+        self.send(Message::Text(text.to_string()))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.outbox.lock().closed
+    }
+}
+
+/// One end of an open connection, as handlers see it
+pub struct WebSocket {
+    id: u64,
+    request: Request,
+    /// Frames the client didn't take yet
+    outgoing: Vec<u8>,
+    /// When the close frame was sent, nothing being sent after it
+    close_sent: Option<Instant>,
+    outbox: Arc<Outbox>,
+}
+
+impl WebSocket {
+    fn new(request: Request, max_queued: usize) -> Self {
+        WebSocket {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            request,
+            outgoing: Vec::new(),
+            close_sent: None,
+            outbox: Arc::new(Outbox { queue: Mutex::new(Queue::default()), max_queued }),
+        }
+    }
+
+    /// Identifies the connection among the ones of the server
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// A handle sending messages to the connection from outside the
+    /// handler's callbacks
+    pub fn sender(&self) -> WebSocketSender {
+        WebSocketSender { id: self.id, outbox: self.outbox.clone() }
+    }
+
+    /// The handshake request, with the identity its route authenticated
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    pub fn send(&mut self, message: Message) {
+        match message {
+            Message::Text(text) => self.send_frame(TEXT, text.as_bytes()),
+            Message::Binary(data) => self.send_frame(BINARY, &data),
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) {
+        self.send_frame(TEXT, text.as_bytes());
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) {
+        self.send_frame(BINARY, data);
+    }
+
+    /// Starts the closing handshake, the connection ending once the client
+    /// answered it
+    pub fn close(&mut self, code: u16, reason: &str) {
+        let mut payload = code.to_be_bytes().to_vec();
+        // The reason has to fit in a control frame
+        let mut end = reason.len().min(MAX_CONTROL_SIZE as usize - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.send_frame(CLOSE, &payload);
+        self.close_sent = Some(Instant::now());
+        self.outbox.shut();
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.close_sent.is_some()
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) {
+        if self.close_sent.is_none() {
+            self.outgoing.extend_from_slice(&encode_frame(opcode, payload));
+        }
+    }
+}
+
+/// The parts of a frame head
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FrameHead {
+    pub fin: bool,
+    /// The reserved bits, no extension being negotiated
+    pub rsv: u8,
+    pub opcode: u8,
+    pub mask: Option<[u8; 4]>,
+    pub payload_len: u64,
+    /// Bytes the head takes
+    pub len: usize,
+}
+
+/// Reads the head at the start of `data`, once it's complete
+pub(crate) fn frame_head(data: &[u8]) -> Option<FrameHead> {
+    let (&first, &second) = (data.first()?, data.get(1)?);
+    let (length_size, short_len) = match second & 0x7f {
+        126 => (2, None),
+        127 => (8, None),
+        len => (0, Some(len as u64)),
+    };
+    let mask_size = if second & 0x80 != 0 { 4 } else { 0 };
+    let len = 2 + length_size + mask_size;
+    if data.len() < len {
+        return None;
+    }
+    let payload_len = short_len.unwrap_or_else(|| {
+        data[2..2 + length_size].iter().fold(0, |len, &byte| (len << 8) | byte as u64)
+    });
+    let mask = (mask_size > 0).then(|| {
+        let at = 2 + length_size;
+        [data[at], data[at + 1], data[at + 2], data[at + 3]]
+    });
+    Some(FrameHead { fin: first & 0x80 != 0, rsv: (first >> 4) & 0x7, opcode: first & 0x0f, mask, payload_len, len })
+}
+
+/// An unfragmented, unmasked frame, as servers send them
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The `Sec-WebSocket-Accept` answering the client's `key`
+pub fn accept_key(key: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key.trim(), GUID).as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(digest.as_ref())
+}
+
+/// The `101` accepting `request` as an opening handshake
+pub fn handshake(request: &Request) -> Result<Response, HttpError> {
+    let header = |name: &str| request.headers.iter()
+        .find(|header| header.name.to_string().eq_ignore_ascii_case(name))
+        .map(|header| header.value.value.as_str());
+    let has_token = |name: &str, token: &str| header(name)
+        .is_some_and(|value| value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)));
+
+    if request.method != HttpMethod::GET {
+        return Err(HttpError::MethodNotAllowed(format!("WebSocket handshakes use GET, not {}", request.method)));
+    }
+    if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+        return Err(HttpError::BadRequest(format!("Route {} only takes WebSocket connections", request.uri)));
+    }
+    if header("sec-websocket-version").map(str::trim) != Some("13") {
+        return Err(HttpError::BadRequest("Only version 13 of the WebSocket protocol is supported".to_string()));
+    }
+    let key = header("sec-websocket-key").unwrap_or_default();
+    let decoded = base64::engine::general_purpose::STANDARD.decode(key.trim()).unwrap_or_default();
+    if decoded.len() != 16 {
+        return Err(HttpError::BadRequest("The WebSocket key isn't 16 bytes in base64".to_string()));
+    }
+
+    Ok(ResponseBuilder::new()
+        .status_code(HttpStatusCode::SwitchingProtocols)
+        .header(Header::from_str("upgrade", "websocket"))
+        .header(Header::from_str("connection", "Upgrade"))
+        .header(Header::from_str("sec-websocket-accept", &accept_key(key)))
+        .build())
+}
+
+/// An open connection of a WebSocket route
+pub struct Session {
+    socket: WebSocket,
+    handler: Arc<dyn WebSocketHandler>,
+    settings: WebSocketSettings,
+    /// Bytes of the frame being received
+    received: Vec<u8>,
+    /// The opcode and payload so far of a fragmented message
+    fragments: Option<(u8, Vec<u8>)>,
+    last_seen: Instant,
+    ping_sent: Option<Instant>,
+    /// Set once the handler was told of the close
+    ended: bool,
+    /// Set once the connection is to be closed, its frames sent
+    closed: bool,
+}
+
+impl Session {
+    /// Opens the connection the handshake `request` upgraded
+    pub fn open(endpoint: &WebSocketEndpoint, request: Request) -> Self {
+        let mut session = Session {
+            socket: WebSocket::new(request, endpoint.settings.max_queued),
+            handler: endpoint.handler.clone(),
+            settings: endpoint.settings,
+            received: Vec::new(),
+            fragments: None,
+            last_seen: Instant::now(),
+            ping_sent: None,
+            ended: false,
+            closed: false,
+        };
+        session.handler.on_open(&mut session.socket);
+        session
+    }
+
+    pub fn id(&self) -> u64 {
+        self.socket.id
+    }
+
+    /// Whether the connection is over, its last frames sent or dropped
+    pub fn is_closed(&self) -> bool {
+        self.closed && self.socket.outgoing.is_empty()
+    }
+
+    /// Reads what the client sent and sends it what waits for it
+    pub fn ready(&mut self, client: &mut Connection) -> io::Result<()> {
+        let mut buffer = [0; READ_SIZE];
+        while !self.closed {
+            match client.reader.read_raw(&mut buffer) {
+                Ok(0) => {
+                    self.end(ABNORMAL_CLOSURE, "The client closed the connection");
+                    self.closed = true;
+                    self.socket.outgoing.clear();
+                }
+                Ok(read) => self.receive(&buffer[..read]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.end(ABNORMAL_CLOSURE, &e.to_string());
+                    return Err(e);
+                }
+            }
+        }
+        self.flush(client)
+    }
+
+    /// Pings the client once it was silent too long, and drops it when it
+    /// doesn't answer
+    pub fn tick(&mut self, client: &mut Connection) -> io::Result<()> {
+        let now = Instant::now();
+        let waited = |since: Instant| now.duration_since(since) > self.settings.pong_timeout;
+        if self.ping_sent.is_some_and(waited) || self.socket.close_sent.is_some_and(waited) {
+            self.end(ABNORMAL_CLOSURE, "The client stopped answering");
+            self.closed = true;
+            self.socket.outgoing.clear();
+            return Ok(());
+        }
+        if self.ping_sent.is_none() && now.duration_since(self.last_seen) >= self.settings.ping_interval {
+            self.socket.send_frame(PING, b"");
+            self.ping_sent = Some(now);
+        }
+        self.flush(client)
+    }
+
+    /// Starts the closing handshake with `1001`, the server going away
+    pub fn go_away(&mut self) {
+        if !self.closed && !self.socket.is_closing() {
+            self.socket.close(GOING_AWAY, "The server is shutting down");
+        }
+    }
+
+    /// Tells the handler the connection was lost, unless it knows already
+    pub fn abandon(&mut self) {
+        self.end(ABNORMAL_CLOSURE, "The connection was closed");
+    }
+
+    fn flush(&mut self, client: &mut Connection) -> io::Result<()> {
+        // Queued frames are taken while the client keeps up
+        if self.socket.close_sent.is_none() {
+            let mut queue = self.socket.outbox.lock();
+            while self.socket.outgoing.len() < self.settings.max_queued {
+                let Some(frame) = queue.frames.pop_front() else { break };
+                queue.len -= frame.len();
+                self.socket.outgoing.extend_from_slice(&frame);
+            }
+        }
+        let outgoing = &mut self.socket.outgoing;
+        while !outgoing.is_empty() {
+            match client.reader.write_some(outgoing) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "The client stopped taking frames")),
+                Ok(written) => {
+                    outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, code: u16, reason: &str) {
+        self.socket.outbox.shut();
+        if !self.ended {
+            self.ended = true;
+            self.handler.on_close(&mut self.socket, code, reason);
+        }
+    }
+
+    /// Sends a close frame with `code` and ends the connection
+    fn fail(&mut self, code: u16, reason: &str) {
+        self.socket.close(code, reason);
+        self.end(code, reason);
+        self.closed = true;
+    }
+
+    fn receive(&mut self, data: &[u8]) {
+        self.last_seen = Instant::now();
+        self.received.extend_from_slice(data);
+        while !self.closed {
+            let Some(head) = frame_head(&self.received) else { return };
+            if let Err((code, reason)) = self.check(&head) {
+                return self.fail(code, reason);
+            }
+            let end = head.len + head.payload_len as usize;
+            if self.received.len() < end {
+                return;
+            }
+            let mut payload: Vec<u8> = self.received.drain(..end).skip(head.len).collect();
+            if let Some(mask) = head.mask {
+                payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= mask[i % 4]);
+            }
+            self.handle_frame(head, payload);
+        }
+    }
+
+    /// Refuses the frames breaking the protocol or the size limit, as soon as
+    /// their head came
+    fn check(&self, head: &FrameHead) -> Result<(), (u16, &'static str)> {
+        if head.rsv != 0 {
+            return Err((PROTOCOL_ERROR, "Reserved bits are set but no extension was negotiated"));
+        }
+        if head.mask.is_none() {
+            return Err((PROTOCOL_ERROR, "Client frames must be masked"));
+        }
+        match head.opcode {
+            CLOSE | PING | PONG if !head.fin || head.payload_len > MAX_CONTROL_SIZE => {
+                Err((PROTOCOL_ERROR, "Control frames can't be fragmented or over 125 bytes"))
+            }
+            CLOSE | PING | PONG => Ok(()),
+            CONTINUATION if self.fragments.is_none() => Err((PROTOCOL_ERROR, "No fragmented message to continue")),
+            TEXT | BINARY if self.fragments.is_some() => Err((PROTOCOL_ERROR, "A fragmented message wasn't finished")),
+            CONTINUATION | TEXT | BINARY => {
+                let so_far = self.fragments.as_ref().map_or(0, |(_, payload)| payload.len()) as u64;
+                if so_far + head.payload_len > self.settings.max_message_size as u64 {
+                    return Err((MESSAGE_TOO_BIG, "The message is too big"));
+                }
+                Ok(())
+            }
+            _ => Err((PROTOCOL_ERROR, "Unknown opcode")),
+        }
+    }
+
+    fn handle_frame(&mut self, head: FrameHead, payload: Vec<u8>) {
+        match head.opcode {
+            PING => self.socket.send_frame(PONG, &payload),
+            PONG => self.ping_sent = None,
+            CLOSE => self.receive_close(&payload),
+            opcode => {
+                let (opcode, payload) = match self.fragments.take() {
+                    Some((first, mut message)) => {
+                        message.extend_from_slice(&payload);
+                        (first, message)
+                    }
+                    None => (opcode, payload),
+                };
+                if !head.fin {
+                    self.fragments = Some((opcode, payload));
+                    return;
+                }
+                let message = match opcode {
+                    TEXT => match String::from_utf8(payload) {
+                        Ok(text) => Message::Text(text),
+                        Err(_) => return self.fail(INVALID_PAYLOAD, "Text messages must be UTF-8"),
+                    },
+                    _ => Message::Binary(payload),
+                };
+                // Messages still coming after the close was sent are dropped
+                if !self.socket.is_closing() {
+                    self.handler.on_message(&mut self.socket, message);
+                }
+            }
+        }
+    }
+
+    /// Answers the client's close frame, unless it answers the server's
+    fn receive_close(&mut self, payload: &[u8]) {
+        let (code, reason) = match payload {
+            [] => (NO_STATUS, ""),
+            [_] => return self.fail(PROTOCOL_ERROR, "The close frame's code is cut short"),
+            [high, low, reason @ ..] => match std::str::from_utf8(reason) {
+                Ok(reason) => (u16::from_be_bytes([*high, *low]), reason),
+                Err(_) => return self.fail(INVALID_PAYLOAD, "Close reasons must be UTF-8"),
+            },
+        };
+        if !self.socket.is_closing() {
+            match code {
+                NO_STATUS => self.socket.send_frame(CLOSE, b""),
+                code => self.socket.send_frame(CLOSE, &code.to_be_bytes()),
+            }
+            self.socket.close_sent = Some(Instant::now());
+        }
+        self.end(code, reason);
+        self.closed = true;
+    }
+}
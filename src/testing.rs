@@ -0,0 +1,574 @@
+//! Servers started by tests
+//!
+//! A [`TestServer`] serves a host built in code on a port the system picks,
+//! so that tests run side by side without a config file, a server started by
+//! hand or entries written to `/etc/hosts`. Files the host serves go in a
+//! [`TempDir`], and requests are sent with a [`TestClient`]:
+//!
+//! ```no_run
+//! use localhost::HostBuilder;
+//! use localhost::testing::{TempDir, TestServer};
+//!
+//! let site = TempDir::new().unwrap();
+//! site.write("index.html", "<h1>Hello</h1>").unwrap();
+//! let server = TestServer::start(HostBuilder::new("test.local").static_files("/", site.path(), Some("index.html"))).unwrap();
+//!
+//! let response = server.get("/").unwrap();
+//! assert_eq!(response.status, 200);
+//! assert_eq!(response.text(), "<h1>Hello</h1>");
+//! ```
+//!
+//! Readers and connections are tested without sockets with a [`MockStream`],
+//! whose reads are scripted, down to the `WouldBlock` a non-blocking socket
+//! returns between two events.
+//!
+//! A [`LoadTest`] keeps connections busy with a request for a while, and
+//! reports the throughput and latencies seen, like `wrk` would.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::server::builder::{HostBuilder, ServerBuilder};
+use crate::server::errors::ServerError;
+use crate::server::http_client::read_chunked;
+use crate::server::listen::ListenAddr;
+use crate::server::shutdown::ServerHandle;
+
+/// How long a client waits for the server before failing
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A directory removed with everything in it once dropped
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("localhost-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&path)?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to the file at `relative`, creating its directories
+    pub fn write(&self, relative: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<PathBuf> {
+        let path = self.path.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)?;
+        Ok(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A server running on a thread of its own, stopped once dropped
+#[derive(Debug)]
+pub struct TestServer {
+    handle: ServerHandle,
+    addr: SocketAddr,
+}
+
+impl TestServer {
+    /// Serves `host` on a free port of the loopback interface, besides the
+    /// addresses it listens on already
+    pub fn start(host: HostBuilder) -> Result<Self, ServerError> {
+        let host = host.listen("127.0.0.1:0").build()?;
+        let handle = ServerBuilder::new()
+            .host(host)
+            .shutdown_timeout(Duration::from_secs(1))
+            .spawn()?;
+        // The host's own addresses are bound before the one added last
+        let addr = handle.local_addrs().iter().rev()
+            .find_map(|addr| match addr {
+                ListenAddr::Tcp(addr) => Some(*addr),
+                ListenAddr::Unix(_) => None,
+            })
+            .ok_or_else(|| ServerError::ConnectionError("The test server listens on no TCP port".to_string()))?;
+        Ok(TestServer { handle, addr })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Like `http://127.0.0.1:41234`
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url(), path)
+    }
+
+    pub fn handle(&self) -> &ServerHandle {
+        &self.handle
+    }
+
+    pub fn client(&self) -> TestClient {
+        TestClient::new(self.addr)
+    }
+
+    pub fn get(&self, path: &str) -> io::Result<TestResponse> {
+        self.client().send(&TestRequest::new("GET", path))
+    }
+
+    /// Sends `body` of `content_type` with a POST request
+    pub fn post(&self, path: &str, content_type: &str, body: impl Into<Vec<u8>>) -> io::Result<TestResponse> {
+        self.client().send(&TestRequest::new("POST", path).header("Content-Type", content_type).body(body))
+    }
+
+    /// Stops the server, returning the error it stopped on
+    pub fn shutdown(self) -> Result<(), ServerError> {
+        self.handle.shutdown();
+        self.handle.wait()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+        let _ = self.handle.wait();
+    }
+}
+
+/// A request a [`TestClient`] sends, with a `Host` header naming the server
+/// unless it sets one
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn new(method: &str, path: &str) -> Self {
+        TestRequest { method: method.to_string(), path: path.to_string(), headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the body, sent with its `Content-Length`
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.iter().any(|(own, _)| own.eq_ignore_ascii_case(name))
+    }
+}
+
+/// A response read whole, its chunked body decoded
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// The first header called `name`
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(own, _)| own.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The body as text, invalid UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Sends each request on a connection of its own, closed once answered
+#[derive(Debug, Clone)]
+pub struct TestClient {
+    addr: SocketAddr,
+}
+
+impl TestClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        TestClient { addr }
+    }
+
+    pub fn send(&self, request: &TestRequest) -> io::Result<TestResponse> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.write_all(&self.encode(request))?;
+        read_response(BufReader::new(stream), request.method.eq_ignore_ascii_case("HEAD"))
+    }
+
+    fn encode(&self, request: &TestRequest) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.path);
+        if !request.has_header("Host") {
+            head.push_str(&format!("Host: {}\r\n", self.addr));
+        }
+        for (name, value) in &request.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !request.body.is_empty() && !request.has_header("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+        }
+        if !request.has_header("Connection") {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        let mut encoded = head.into_bytes();
+        encoded.extend_from_slice(&request.body);
+        encoded
+    }
+}
+
+fn read_response<R: BufRead>(mut reader: R, head_only: bool) -> io::Result<TestResponse> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("invalid status line '{}'", status_line.trim())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut response = TestResponse { status, headers, body: Vec::new() };
+
+    let chunked = response.header("Transfer-Encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    let length = response.header("Content-Length").and_then(|value| value.parse::<usize>().ok());
+    if head_only || status == 204 || status == 304 {
+        return Ok(response);
+    }
+    response.body = if chunked {
+        read_chunked(&mut reader)?
+    } else if let Some(length) = length {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        body
+    } else {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        body
+    };
+    Ok(response)
+}
+
+/// Requests for one path, sent by every connection as soon as the last one
+/// was answered, until the duration is over
+#[derive(Debug, Clone)]
+pub struct LoadTest {
+    pub path: String,
+    pub connections: usize,
+    pub duration: Duration,
+    /// Whether connections are reused, otherwise one is opened per request
+    pub keep_alive: bool,
+}
+
+impl LoadTest {
+    pub fn new(path: &str) -> Self {
+        LoadTest { path: path.to_string(), connections: 16, duration: Duration::from_secs(10), keep_alive: true }
+    }
+
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Loads the server at `addr`, returning once every connection is done
+    pub fn run(&self, addr: SocketAddr) -> LoadReport {
+        let started = Instant::now();
+        let deadline = started + self.duration;
+        let workers: Vec<_> = (0..self.connections)
+            .map(|_| {
+                let test = self.clone();
+                thread::spawn(move || test.drive(addr, deadline))
+            })
+            .collect();
+
+        let mut report = LoadReport::default();
+        for worker in workers {
+            // A worker that panicked counts as one error
+            let (latencies, errors) = worker.join().unwrap_or_else(|_| (Vec::new(), 1));
+            report.latencies.extend(latencies);
+            report.errors += errors;
+        }
+        report.elapsed = started.elapsed();
+        report.latencies.sort();
+        report
+    }
+
+    /// Sends requests over one connection until `deadline`, returning the
+    /// latencies of those answered and the number that failed
+    fn drive(&self, addr: SocketAddr, deadline: Instant) -> (Vec<Duration>, usize) {
+        let connection = if self.keep_alive { "keep-alive" } else { "close" };
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: {}\r\n\r\n", self.path, addr, connection);
+        let mut latencies = Vec::new();
+        let mut errors = 0;
+        let mut stream: Option<BufReader<TcpStream>> = None;
+
+        while Instant::now() < deadline {
+            let sent = Instant::now();
+            let answered = match stream.take() {
+                Some(reader) => Ok(reader),
+                None => connect(addr),
+            }
+            .and_then(|mut reader| {
+                reader.get_mut().write_all(request.as_bytes())?;
+                let response = read_response(&mut reader, false)?;
+                Ok((reader, response))
+            });
+            match answered {
+                Ok((reader, response)) => {
+                    latencies.push(sent.elapsed());
+                    if response.status >= 500 {
+                        errors += 1;
+                    }
+                    let closed = response.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"));
+                    if self.keep_alive && !closed {
+                        stream = Some(reader);
+                    }
+                }
+                // The connection is opened again for the next request
+                Err(_) => errors += 1,
+            }
+        }
+        (latencies, errors)
+    }
+}
+
+fn connect(addr: SocketAddr) -> io::Result<BufReader<TcpStream>> {
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(BufReader::new(stream))
+}
+
+/// What a [`LoadTest`] saw: responses with a `5xx` status count as errors,
+/// along with the requests that got no response
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub errors: usize,
+    pub elapsed: Duration,
+    /// Of the requests answered, sorted
+    latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    /// Number of requests answered, errors included
+    pub fn requests(&self) -> usize {
+        self.latencies.len()
+    }
+
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// The latency `percent` of the requests answered didn't exceed
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * (self.latencies.len() - 1) as f64).round() as usize;
+        self.latencies[rank]
+    }
+
+    pub fn max_latency(&self) -> Duration {
+        self.latencies.last().copied().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  requests  {} in {:.1?}, {:.0} req/s", self.requests(), self.elapsed, self.requests_per_second())?;
+        writeln!(f, "  errors    {}", self.errors)?;
+        write!(
+            f,
+            "  latency   p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.max_latency(),
+        )
+    }
+}
+
+/// What a [`MockStream`] returns to its next read
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockRead {
+    /// Bytes, handed over in as many reads as the buffers take
+    Data(Vec<u8>),
+    /// No data for now, like a non-blocking socket between two events
+    WouldBlock,
+    /// A failed read
+    Error(io::ErrorKind),
+    /// The peer closed its side, every later read returning 0
+    Eof,
+}
+
+/// A stream whose reads follow a script, and whose writes are kept
+///
+/// Clones share the script and the writes, so a test keeps one to add reads
+/// and check writes while a reader owns another. Once the script runs out,
+/// reads return `WouldBlock` until more is added.
+///
+/// ```
+/// use std::io::Read;
+/// use localhost::testing::MockStream;
+///
+/// let stream = MockStream::new().data("GET / HT").would_block().data("TP/1.1\r\n\r\n");
+/// let mut reader = stream.clone();
+/// let mut buffer = [0; 64];
+/// assert_eq!(reader.read(&mut buffer).unwrap(), 8);
+/// assert_eq!(reader.read(&mut buffer).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+/// assert_eq!(reader.read(&mut buffer).unwrap(), 10);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockStream {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    reads: VecDeque<MockRead>,
+    written: Vec<u8>,
+    /// Bytes read at most at once, to split data as a socket would
+    max_read: Option<usize>,
+}
+
+impl MockStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_reads(reads: impl IntoIterator<Item = MockRead>) -> Self {
+        let stream = Self::new();
+        stream.state().reads.extend(reads);
+        stream
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds `read` to the end of the script
+    pub fn push(&self, read: MockRead) {
+        self.state().reads.push_back(read);
+    }
+
+    /// Adds `data` to read, unless empty since a read of nothing means the
+    /// stream ended
+    pub fn data(self, data: impl AsRef<[u8]>) -> Self {
+        if !data.as_ref().is_empty() {
+            self.push(MockRead::Data(data.as_ref().to_vec()));
+        }
+        self
+    }
+
+    pub fn would_block(self) -> Self {
+        self.push(MockRead::WouldBlock);
+        self
+    }
+
+    pub fn error(self, kind: io::ErrorKind) -> Self {
+        self.push(MockRead::Error(kind));
+        self
+    }
+
+    pub fn eof(self) -> Self {
+        self.push(MockRead::Eof);
+        self
+    }
+
+    /// Hands over at most `bytes` per read, the rest of the data being
+    /// read next
+    pub fn max_read(self, bytes: usize) -> Self {
+        self.state().max_read = Some(bytes.max(1));
+        self
+    }
+
+    /// Whether every scripted read was made
+    pub fn is_drained(&self) -> bool {
+        self.state().reads.iter().all(|read| *read == MockRead::Eof)
+    }
+
+    /// Everything written so far
+    pub fn written(&self) -> Vec<u8> {
+        self.state().written.clone()
+    }
+
+    /// Everything written since the last call
+    pub fn take_written(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state().written)
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state();
+        let limit = state.max_read.unwrap_or(usize::MAX).min(buf.len());
+        match state.reads.pop_front() {
+            None | Some(MockRead::WouldBlock) => Err(io::ErrorKind::WouldBlock.into()),
+            Some(MockRead::Error(kind)) => Err(kind.into()),
+            Some(MockRead::Eof) => {
+                state.reads.push_front(MockRead::Eof);
+                Ok(0)
+            }
+            Some(MockRead::Data(mut data)) => {
+                let read = limit.min(data.len());
+                buf[..read].copy_from_slice(&data[..read]);
+                if read < data.len() {
+                    state.reads.push_front(MockRead::Data(data.split_off(read)));
+                }
+                Ok(read)
+            }
+        }
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.state().written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
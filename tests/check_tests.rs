@@ -0,0 +1,57 @@
+use std::process::Command;
+
+const VALID: &str = r#"
+servers:
+  - server_name: a.home
+    server_address: 127.0.0.1
+    ports: ["8080"]
+    routes:
+      - { path: /, methods: [GET], root: a.home, default_page: index.html }
+"#;
+
+/// A method no route may list
+const INVALID: &str = r#"
+servers:
+  - server_name: a.home
+    server_address: 127.0.0.1
+    ports: ["8080"]
+    routes:
+      - { path: /, methods: [FETCH], root: a.home }
+"#;
+
+fn check(flag: &str, config: &str) -> std::process::Output {
+    let dir = std::env::temp_dir().join(format!("localhost-check-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.yaml");
+    std::fs::write(&path, config).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_localhost-server"))
+        .arg(flag)
+        .arg("--config")
+        .arg(&path)
+        .env_remove("LOCALHOST_CONFIG")
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    output
+}
+
+#[test]
+fn test_check_accepts_a_valid_config_without_serving_it() {
+    for flag in ["--check", "-t"] {
+        let output = check(flag, VALID);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}: {}{}", flag, stdout, String::from_utf8_lossy(&output.stderr));
+        assert!(stdout.contains("is valid"), "{}", stdout);
+    }
+}
+
+#[test]
+fn test_check_fails_on_an_invalid_config() {
+    for flag in ["--check", "-t"] {
+        let output = check(flag, INVALID);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "{}: {}", flag, stderr);
+        assert!(stderr.contains("'FETCH'"), "{}", stderr);
+        assert!(stderr.contains("is invalid: 1 error(s)"), "{}", stderr);
+    }
+}
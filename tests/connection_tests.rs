@@ -0,0 +1,327 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use localhost::http::body::Body;
+use localhost::http::body_stream::BodyStream;
+use localhost::http::request::HttpMethod;
+use localhost::http::header::Header;
+use localhost::http::response::Response;
+use localhost::http::status::HttpStatusCode;
+use localhost::server::handlers::handlers::RouteHandler;
+use localhost::server::stream::request_stream::HeaderLimits;
+use localhost::testing::{LoadTest, TempDir, TestRequest, TestServer};
+use localhost::HostBuilder;
+
+fn echo() -> RouteHandler {
+    RouteHandler::from_fn(|request, _route| {
+        let body = request.raw_body.as_ref().map(|body| body.to_vec()).unwrap_or_default();
+        Ok(Response::new(HttpStatusCode::Ok, Vec::new(), Some(Body::text(&String::from_utf8_lossy(&body)))))
+    })
+}
+
+#[test]
+fn test_static_files_are_served() {
+    let site = TempDir::new().unwrap();
+    site.write("index.html", "<h1>Hello</h1>").unwrap();
+    site.write("css/site.css", "h1 { color: red }").unwrap();
+    let server = TestServer::start(HostBuilder::new("static.test").static_files("/", site.path(), Some("index.html"))).unwrap();
+
+    let index = server.get("/").unwrap();
+    assert_eq!(index.status, 200);
+    assert_eq!(index.text(), "<h1>Hello</h1>");
+    assert!(index.header("content-type").unwrap().starts_with("text/html"));
+
+    let sent = httpdate::parse_http_date(index.header("date").unwrap()).unwrap();
+    assert!(sent.elapsed().unwrap_or_default() < Duration::from_secs(60));
+    assert!(index.header("server").unwrap().starts_with("localhost/"));
+
+    let css = server.get("/css/site.css").unwrap();
+    assert_eq!((css.status, css.text().as_str()), (200, "h1 { color: red }"));
+    assert_eq!(server.get("/missing.html").unwrap().status, 404);
+}
+
+#[test]
+fn test_request_bodies_reach_handlers() {
+    let server = TestServer::start(HostBuilder::new("echo.test").handler("/echo", vec![HttpMethod::POST], echo())).unwrap();
+
+    let response = server.post("/echo", "text/plain", "ping").unwrap();
+    assert_eq!((response.status, response.text().as_str()), (200, "ping"));
+    // A larger body, read over several events
+    let large = "x".repeat(256 * 1024);
+    assert_eq!(server.post("/echo", "text/plain", large.clone()).unwrap().text(), large);
+    assert_eq!(server.client().send(&TestRequest::new("DELETE", "/echo")).unwrap().status, 405);
+}
+
+#[test]
+fn test_connections_are_kept_alive_between_requests() {
+    let server = TestServer::start(HostBuilder::new("alive.test").handler("/echo", vec![HttpMethod::POST], echo())).unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    for body in ["one", "two"] {
+        write!(stream, "POST /echo HTTP/1.1\r\nHost: alive.test\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&response).ends_with(body) {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "the connection was closed");
+            response.extend_from_slice(&buffer[..read]);
+        }
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200"));
+    }
+}
+
+#[test]
+fn test_connections_close_after_their_max_requests() {
+    let host = HostBuilder::new("max.test")
+        .handler("/echo", vec![HttpMethod::POST], echo())
+        .request_timeout(Duration::from_secs(30))
+        .keep_alive_requests(3);
+    let server = TestServer::start(host).unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    for (body, left) in [("one", Some(2)), ("two", Some(1)), ("six", None)] {
+        write!(stream, "POST /echo HTTP/1.1\r\nHost: max.test\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&response).ends_with(body) {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "the connection was closed");
+            response.extend_from_slice(&buffer[..read]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        match left {
+            Some(left) => {
+                assert!(response.contains("Connection: keep-alive\r\n"), "{}", response);
+                assert!(response.contains(&format!("Keep-Alive: timeout=30, max={}\r\n", left)), "{}", response);
+            }
+            None => {
+                assert!(response.contains("Connection: close\r\n"), "{}", response);
+                assert!(!response.contains("Keep-Alive"), "{}", response);
+            }
+        }
+    }
+    let mut rest = Vec::new();
+    assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0, "the connection was left open");
+}
+
+#[test]
+fn test_streamed_bodies_arrive_whole() {
+    let stream = RouteHandler::from_fn(|_request, _route| {
+        let chunks = (1..=3).map(|n| Ok(format!("line {}\n", n).into_bytes()));
+        Ok(Response::stream("text/plain", BodyStream::new(chunks)))
+    });
+    let server = TestServer::start(HostBuilder::new("stream.test").handler("/lines", vec![HttpMethod::GET], stream)).unwrap();
+
+    let response = server.get("/lines").unwrap();
+    assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+    assert_eq!(response.text(), "line 1\nline 2\nline 3\n");
+}
+
+#[test]
+fn test_requests_are_answered_per_their_http_version() {
+    let lines = RouteHandler::from_fn(|_request, _route| {
+        let chunks = (1..=3).map(|n| Ok(format!("line {}\n", n).into_bytes()));
+        Ok(Response::stream("text/plain", BodyStream::new(chunks)))
+    });
+    let host = HostBuilder::new("versions.test")
+        .handler("/echo", vec![HttpMethod::POST], echo())
+        .handler("/lines", vec![HttpMethod::GET], lines);
+    let server = TestServer::start(host).unwrap();
+    let connect = || {
+        let stream = TcpStream::connect(server.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    };
+    // Read until the server closes the connection
+    let exchange = |request: &str| {
+        let mut stream = connect();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let unsupported = exchange("GET /lines HTTP/2.0\r\nHost: versions.test\r\n\r\n");
+    assert!(unsupported.starts_with("HTTP/1.1 505"), "{}", unsupported);
+
+    // Without chunks, the end of the body is the end of the connection
+    let streamed = exchange("GET /lines HTTP/1.0\r\n\r\n");
+    assert!(streamed.starts_with("HTTP/1.1 200"), "{}", streamed);
+    assert!(streamed.contains("Connection: close\r\n"));
+    assert!(!streamed.to_ascii_lowercase().contains("transfer-encoding"));
+    assert!(streamed.ends_with("\r\n\r\nline 1\nline 2\nline 3\n"));
+
+    // HTTP/1.0 connections are kept alive on request only
+    let mut stream = connect();
+    for body in ["one", "two"] {
+        write!(stream, "POST /echo HTTP/1.0\r\nConnection: keep-alive\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&response).ends_with(body) {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "the connection was closed");
+            response.extend_from_slice(&buffer[..read]);
+        }
+        assert!(String::from_utf8_lossy(&response).contains("Connection: keep-alive\r\n"));
+    }
+    let closed = exchange("POST /echo HTTP/1.0\r\nContent-Length: 3\r\n\r\nbye");
+    assert!(closed.contains("Connection: close\r\n") && closed.ends_with("bye"), "{}", closed);
+}
+
+#[test]
+fn test_error_responses_say_whether_the_connection_stays_open() {
+    let server = TestServer::start(HostBuilder::new("errors.test").handler("/echo", vec![HttpMethod::POST], echo())).unwrap();
+    let connect = || {
+        let stream = TcpStream::connect(server.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    };
+
+    // An HTTP/1.0 client asking for keep-alive is told its connection stays open
+    let mut stream = connect();
+    for _ in 0..2 {
+        stream.write_all(b"GET /missing HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&response).contains("</html>") {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "the connection was closed");
+            response.extend_from_slice(&buffer[..read]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        assert!(response.contains("Connection: keep-alive\r\n"), "{}", response);
+    }
+
+    // Otherwise it's closed once answered, as are the ones of bad requests
+    for request in ["GET /missing HTTP/1.0\r\n\r\n", "GET /echo HTTP/2.0\r\nHost: errors.test\r\n\r\n"] {
+        let mut stream = connect();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Connection: close\r\n"), "{}", response);
+    }
+}
+
+#[test]
+fn test_requests_past_the_header_limits_get_431() {
+    let host = HostBuilder::new("limits.test")
+        .handler("/echo", vec![HttpMethod::POST], echo())
+        .header_limits(HeaderLimits { max_size: 1024, max_count: 4 });
+    let server = TestServer::start(host).unwrap();
+    // Read until the server closes the connection
+    let exchange = |request: &[u8]| {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let within = exchange(b"POST /echo HTTP/1.1\r\nA: 1\r\nB: 2\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok");
+    assert!(within.starts_with("HTTP/1.1 200") && within.ends_with("ok"), "{}", within);
+
+    let many = exchange(b"POST /echo HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\nContent-Length: 0\r\n\r\n");
+    assert!(many.starts_with("HTTP/1.1 431"), "{}", many);
+
+    // A head never ending is refused once past the size, not waited for
+    let large = exchange(format!("GET /echo HTTP/1.1\r\nX-Large: {}", "a".repeat(2048)).as_bytes());
+    assert!(large.starts_with("HTTP/1.1 431"), "{}", large);
+}
+
+#[test]
+fn test_requests_past_the_max_request_size_get_413() {
+    let host = HostBuilder::new("size.test")
+        .handler("/echo", vec![HttpMethod::POST], echo())
+        .max_request_size(256);
+    let server = TestServer::start(host).unwrap();
+    // Read until the server closes the connection
+    let exchange = |request: &[u8]| {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let within = exchange(b"POST /echo HTTP/1.1\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok");
+    assert!(within.starts_with("HTTP/1.1 200") && within.ends_with("ok"), "{}", within);
+
+    // Refused from its announced length, before the body is read
+    let declared = exchange(b"POST /echo HTTP/1.1\r\nContent-Length: 1024\r\n\r\n");
+    assert!(declared.starts_with("HTTP/1.1 413"), "{}", declared);
+
+    let chunked = exchange(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n400\r\n");
+    assert!(chunked.starts_with("HTTP/1.1 413"), "{}", chunked);
+
+    // Lengths past what can be counted are too large as well
+    let overflowing = exchange(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n");
+    assert!(overflowing.starts_with("HTTP/1.1 413"), "{}", overflowing);
+    let declared = exchange(b"POST /echo HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n");
+    assert!(declared.starts_with("HTTP/1.1 413"), "{}", declared);
+}
+
+#[test]
+fn test_unparseable_requests_get_a_400_page() {
+    let server = TestServer::start(HostBuilder::new("malformed.test").handler("/echo", vec![HttpMethod::POST], echo())).unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"G<E>T\x01 / HTTP/1.1\r\nHost: malformed.test\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    assert!(response.to_ascii_lowercase().contains("content-type: text/html"));
+    // What was sent is only logged, never sent back
+    assert!(response.contains("Malformed request"), "{}", response);
+    assert!(!response.contains("Unparseable") && !response.contains("G&lt;E&gt;T"), "{}", response);
+}
+
+#[test]
+fn test_servers_run_side_by_side() {
+    let servers: Vec<TestServer> = (0..3)
+        .map(|n| {
+            let name = format!("side{}.test", n);
+            let hello = RouteHandler::from_fn(move |_request, _route| {
+                Ok(Response::new(HttpStatusCode::Ok, Vec::new(), Some(Body::text(&n.to_string()))))
+            });
+            TestServer::start(HostBuilder::new(&name).handler("/", vec![HttpMethod::GET], hello)).unwrap()
+        })
+        .collect();
+
+    thread::scope(|scope| {
+        for (n, server) in servers.iter().enumerate() {
+            scope.spawn(move || assert_eq!(server.get("/").unwrap().text(), n.to_string()));
+        }
+    });
+
+    let last = servers.into_iter().last().unwrap();
+    let addr = last.addr();
+    last.shutdown().unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_load_tests_keep_every_connection_busy() {
+    let hello = RouteHandler::from_fn(|_request, _route| {
+        Ok(Response::new(HttpStatusCode::Ok, vec![Header::from_str("content-length", "5")], Some(Body::text("hello"))))
+    });
+    let server = TestServer::start(HostBuilder::new("load.test").handler("/hello", vec![HttpMethod::GET], hello)).unwrap();
+
+    // Connections opened at once are all accepted
+    for keep_alive in [true, false] {
+        let report = LoadTest::new("/hello").connections(4).duration(Duration::from_millis(300)).keep_alive(keep_alive).run(server.addr());
+        assert!(report.requests() >= 4, "{}", report);
+        assert_eq!(report.errors, 0, "{}", report);
+        assert!(report.percentile(50.0) <= report.percentile(99.0));
+        assert_eq!(report.percentile(100.0), report.max_latency());
+    }
+
+    // Only the answers of a failing server count as errors
+    let report = LoadTest::new("/missing").connections(1).duration(Duration::from_millis(100)).run(server.addr());
+    assert_eq!(report.errors, 0, "a 404 is an answer");
+}